@@ -2,6 +2,45 @@
 
 #include "tikv_client.h"
 #include <iostream>
+#include <stdexcept>
+#include <string>
+#include <vector>
+
+// Drives an externally coordinated two-phase commit the way FISCO-BCOS does:
+// one transaction per participant, the first one holding the primary key.
+// All the keys must be visible at the commit version and none before it.
+void two_phase_commit(tikv_client::TransactionClient &client, size_t participants) {
+    std::vector<tikv_client::Transaction> txns;
+    for (size_t i = 0; i < participants; ++i) {
+        txns.push_back(client.begin());
+        txns[i].put("2pc_k" + std::to_string(i), "v" + std::to_string(i));
+    }
+
+    auto primary = txns[0].prewrite_primary("");
+    for (size_t i = 1; i < participants; ++i) {
+        auto result = txns[i].prewrite_secondary(primary.first, primary.second);
+        std::cout << "secondary " << i << " prewrote " << result.mutation_count
+                  << " mutations" << std::endl;
+    }
+    auto commit_ts = txns[0].commit_primary();
+    for (size_t i = 1; i < participants; ++i) {
+        txns[i].commit_secondary(commit_ts);
+    }
+
+    auto before = client.snapshot(commit_ts - 1);
+    auto after = client.snapshot(commit_ts);
+    for (size_t i = 0; i < participants; ++i) {
+        auto key = "2pc_k" + std::to_string(i);
+        if (before->get(key)) {
+            throw std::runtime_error(key + " visible before the commit version");
+        }
+        if (after->get(key) != "v" + std::to_string(i)) {
+            throw std::runtime_error(key + " not visible at the commit version");
+        }
+    }
+    std::cout << "2pc committed " << participants << " participants at " << commit_ts
+              << std::endl;
+}
 
 int main() {
     auto client = tikv_client::TransactionClient({"127.0.0.1:2379"});
@@ -23,5 +62,7 @@ int main() {
 
     txn.commit();
 
+    two_phase_commit(client, 4);
+
     return 0;
 }
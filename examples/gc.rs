@@ -0,0 +1,39 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Advances the GC safepoint to a fresh timestamp, after which versions
+//! older than it may be collected; the latest version of every key stays.
+//!
+//! Usage: `cargo run --example gc -- 127.0.0.1:2379 [more PD endpoints]`
+//!
+//! Run it against a test cluster only: it lets GC collect the history of
+//! the whole cluster.
+
+use std::env;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use tikv_client_rust::sync::TransactionClient;
+
+fn main() -> Result<()> {
+    let pd_endpoints: Vec<String> = env::args().skip(1).collect();
+    ensure!(!pd_endpoints.is_empty(), "usage: gc <PD endpoint>...");
+    let client = TransactionClient::new(pd_endpoints, ".", Duration::from_secs(3))?;
+    let key = format!("example/gc/{}", client.current_timestamp()?);
+
+    let mut txn = client.begin_optimistic()?;
+    txn.put(key.as_bytes(), b"latest")?;
+    txn.commit()?;
+
+    let safepoint = client.current_timestamp()?;
+    let advanced = client.gc(safepoint)?;
+    println!("gc to {}: safepoint advanced: {}", safepoint, advanced);
+
+    let mut snapshot = client.snapshot()?;
+    ensure!(snapshot.get(key.as_bytes())?.as_deref() == Some(&b"latest"[..]));
+
+    let mut txn = client.begin_optimistic()?;
+    txn.delete(key.as_bytes())?;
+    txn.commit()?;
+    println!("ok");
+    Ok(())
+}
@@ -0,0 +1,52 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Reads at past versions: every commit version stays readable through a
+//! snapshot until GC collects it.
+//!
+//! Usage: `cargo run --example historical_snapshot -- 127.0.0.1:2379 [more PD endpoints]`
+
+use std::env;
+use std::ops::Bound;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use tikv_client_rust::sync::TransactionClient;
+
+fn main() -> Result<()> {
+    let pd_endpoints: Vec<String> = env::args().skip(1).collect();
+    ensure!(
+        !pd_endpoints.is_empty(),
+        "usage: historical_snapshot <PD endpoint>..."
+    );
+    let client = TransactionClient::new(pd_endpoints, ".", Duration::from_secs(3))?;
+    let prefix = format!("example/history/{}/", client.current_timestamp()?);
+    let key = format!("{}k", prefix);
+
+    let mut versions = Vec::new();
+    for value in &["v1", "v2", "v3"] {
+        let mut txn = client.begin_optimistic()?;
+        txn.put(key.as_bytes(), value.as_bytes())?;
+        versions.push((txn.commit()?, *value));
+    }
+
+    for &(version, value) in &versions {
+        let mut snapshot = client.snapshot_at(version)?;
+        let read = snapshot.get(key.as_bytes())?;
+        ensure!(
+            read.as_deref() == Some(value.as_bytes()),
+            "read {:?} at {}, expected {}",
+            read,
+            version,
+            value
+        );
+        let pairs = snapshot.scan(Bound::Included(prefix.as_bytes()), Bound::Unbounded, 10)?;
+        ensure!(pairs.len() == 1);
+        println!("{} = {} at {}", key, value, version);
+    }
+
+    let mut txn = client.begin_optimistic()?;
+    txn.delete(key.as_bytes())?;
+    txn.commit()?;
+    println!("ok");
+    Ok(())
+}
@@ -0,0 +1,36 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A basic optimistic transaction: write, read back, commit and check.
+//!
+//! Usage: `cargo run --example optimistic -- 127.0.0.1:2379 [more PD endpoints]`
+
+use std::env;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use tikv_client_rust::sync::TransactionClient;
+
+fn main() -> Result<()> {
+    let pd_endpoints: Vec<String> = env::args().skip(1).collect();
+    ensure!(
+        !pd_endpoints.is_empty(),
+        "usage: optimistic <PD endpoint>..."
+    );
+    let client = TransactionClient::new(pd_endpoints, ".", Duration::from_secs(3))?;
+    let key = format!("example/optimistic/{}", client.current_timestamp()?);
+
+    let mut txn = client.begin_optimistic()?;
+    txn.put(key.as_bytes(), b"v1")?;
+    ensure!(txn.get(key.as_bytes())?.as_deref() == Some(&b"v1"[..]));
+    let commit_ts = txn.commit()?;
+    println!("committed {} at {}", key, commit_ts);
+
+    let mut snapshot = client.snapshot()?;
+    ensure!(snapshot.get(key.as_bytes())?.as_deref() == Some(&b"v1"[..]));
+
+    let mut txn = client.begin_optimistic()?;
+    txn.delete(key.as_bytes())?;
+    txn.commit()?;
+    println!("ok");
+    Ok(())
+}
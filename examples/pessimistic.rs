@@ -0,0 +1,50 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A pessimistic read-modify-write: `get_for_update` locks the key, so the
+//! increment cannot be lost to a concurrent writer.
+//!
+//! Usage: `cargo run --example pessimistic -- 127.0.0.1:2379 [more PD endpoints]`
+
+use std::env;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use tikv_client_rust::sync::TransactionClient;
+
+fn increment(client: &TransactionClient, key: &[u8]) -> Result<u64> {
+    let mut txn = client.begin_pessimistic()?;
+    let current = match txn.get_for_update(key)? {
+        Some(value) => String::from_utf8(value)?.parse()?,
+        None => 0,
+    };
+    txn.put(key, (current + 1).to_string().as_bytes())?;
+    txn.commit()?;
+    Ok(current + 1)
+}
+
+fn main() -> Result<()> {
+    let pd_endpoints: Vec<String> = env::args().skip(1).collect();
+    ensure!(
+        !pd_endpoints.is_empty(),
+        "usage: pessimistic <PD endpoint>..."
+    );
+    let client = TransactionClient::new(pd_endpoints, ".", Duration::from_secs(3))?;
+    let key = format!("example/pessimistic/{}", client.current_timestamp()?);
+
+    for expected in 1..=3 {
+        let counter = increment(&client, key.as_bytes())?;
+        ensure!(
+            counter == expected,
+            "counter is {}, expected {}",
+            counter,
+            expected
+        );
+    }
+    println!("counter {} reached 3", key);
+
+    let mut txn = client.begin_pessimistic()?;
+    txn.delete(key.as_bytes())?;
+    txn.commit()?;
+    println!("ok");
+    Ok(())
+}
@@ -0,0 +1,58 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! An externally driven two-phase commit across two transactions, the way
+//! FISCO-BCOS commits the writes of several parties atomically: one holds
+//! the primary key, the other prewrites its writes as secondaries of it.
+//!
+//! Usage: `cargo run --example two_phase_commit -- 127.0.0.1:2379 [more PD endpoints]`
+
+use std::env;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use tikv_client_rust::sync::TransactionClient;
+
+fn main() -> Result<()> {
+    let pd_endpoints: Vec<String> = env::args().skip(1).collect();
+    ensure!(
+        !pd_endpoints.is_empty(),
+        "usage: two_phase_commit <PD endpoint>..."
+    );
+    let client = TransactionClient::new(pd_endpoints, ".", Duration::from_secs(3))?;
+    let prefix = format!("example/2pc/{}/", client.current_timestamp()?);
+    let primary_key = format!("{}primary", prefix);
+    let secondary_key = format!("{}secondary", prefix);
+
+    let mut primary = client.begin_optimistic()?;
+    let mut secondary = client.begin_optimistic()?;
+    primary.put(primary_key.as_bytes(), b"p")?;
+    secondary.put(secondary_key.as_bytes(), b"s")?;
+
+    let (key, start_ts) = primary.prewrite_primary(Some(primary_key.as_bytes()))?;
+    let prewritten = secondary.prewrite_secondary(<&[u8]>::from(&key), start_ts, false)?;
+    println!(
+        "prewrote {} secondary mutations at {}",
+        prewritten.mutation_count, start_ts
+    );
+    let commit_ts = primary.commit_primary()?;
+    secondary.commit_secondary(commit_ts);
+
+    let mut before = client.snapshot_at(commit_ts - 1)?;
+    let mut after = client.snapshot_at(commit_ts)?;
+    for key in &[&primary_key, &secondary_key] {
+        ensure!(
+            before.get(key.as_bytes())?.is_none(),
+            "{} visible too early",
+            key
+        );
+        ensure!(after.get(key.as_bytes())?.is_some(), "{} not visible", key);
+    }
+    println!("committed both at {}", commit_ts);
+
+    let mut txn = client.begin_optimistic()?;
+    txn.delete(primary_key.as_bytes())?;
+    txn.delete(secondary_key.as_bytes())?;
+    txn.commit()?;
+    println!("ok");
+    Ok(())
+}
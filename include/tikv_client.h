@@ -4,6 +4,7 @@
 #define _TIKV_CLIENT_H_
 
 #include "tikv_client_glue.h"
+#include <functional>
 #include <iostream>
 #include <map>
 #include <memory>
@@ -18,28 +19,333 @@ struct KvPair final {
   KvPair(std::string &&key, std::string &&value);
 };
 
+// A key whose value differs between the two versions of
+// TransactionClient::range_diff.
+struct DiffEntry final {
+  std::string key;
+  DiffChange change;
+  // The length of the value at the newer version, 0 when removed.
+  uint64_t new_value_len;
+};
+
+// A key a transaction put or deleted, see Transaction::written_keys.
+struct WrittenKey final {
+  std::string key;
+  WriteType write_type;
+};
+
+// How Snapshot::batch_get_hashes hashes values: Crc64 gives the big-endian
+// crc64 of the value, 8 bytes, Sha256 its SHA-256, 32 bytes.
+enum class HashAlgorithm : uint8_t {
+  Crc64 = 0,
+  Sha256 = 1,
+};
+
+// What stood in the way of a transaction on a key.
+  // Another transaction committed the key after this one started.
+  WriteConflict = 0,
+  // Another transaction held a lock on the key.
+  Locked = 1,
+  Deadlock = 2,
+};
+
+// A contended key, see Transaction::conflict_report; `other_start_ts` is the
+// start version of the transaction it was contended with, 0 when not
+// reported.
+struct ConflictEntry final {
+  std::string key;
+  ConflictKind kind;
+  uint64_t other_start_ts;
+};
+
+struct MvccVersion final {
+  uint64_t commit_ts;
+  WriteType write_type;
+  // std::nullopt for a delete.
+  std::optional<std::string> value;
+};
+
+// A scan result which tells whether the range goes on after `last_key`, the
+// key of the last pair; scan again from it, excluded, to resume.
+struct ScanResult final {
+  std::vector<KvPair> pairs;
+  bool truncated;
+  std::string last_key;
+};
+
+// The pairs of a Snapshot::scan_until; unless `completed`, the range goes on
+// from `resume_key`, included, where to scan again.
+struct ScanUntilResult final {
+  std::vector<KvPair> pairs;
+  bool completed;
+  std::string resume_key;
+};
+
+// The pairs of a batch_get_limited, in the order of the keys, which stops
+// before the pair going over the byte budget unless it is the first;
+// `next_index` is the index of the first key not processed, where to resume,
+// and the number of keys once `completed`.
+struct BatchGetLimitedResult final {
+  std::vector<KvPair> pairs;
+  bool completed;
+  uint64_t next_index;
+};
+
+// How far a chunked operation got, returned rather than thrown: on success
+// `first_error` and `resume_token` are empty, else run the operation again
+// over the same range starting at `resume_token`, included; the operations
+// returning it are idempotent, so redone work is harmless.
+struct PartialOutcome final {
+  uint64_t succeeded_items;
+  uint64_t failed_items;
+  std::string first_error;
+  std::string resume_token;
+};
+
+struct SkippingScanResult final {
+  std::vector<KvPair> pairs;
+  // Keys whose value is too large to be read, in key order.
+  std::vector<std::string> skipped;
+};
+
+// The value of a key at one version; `error` is empty unless that read
+// failed.
+struct VersionedValue final {
+  std::optional<std::string> value;
+  std::string error;
+};
+
+// Where Transaction::get_traced got its value from.
+enum class ValueSource : uint8_t {
+  Buffer = 0,
+  Network = 1,
+};
+
+struct TracedValue final {
+  std::optional<std::string> value;
+  ValueSource source;
+  uint64_t elapsed_us;
+  // The region that served a network read, 0 when unknown; the pinned
+  // tikv-client does not report it, so always 0 for now.
+  uint64_t region_id;
+};
+
+// Values of Transaction::state(). Once a transaction is no longer Active,
+// its operations throw "transaction finished (state=...)"; after a Failed
+// commit only rollback is allowed.
+//
+// A transaction is committed by commit() or by the two-phase commit
+// functions, never both. A prewrite moves it to ExternalTwoPhase, where
+// every other call, commit() and rollback() included, throws "transaction is
+// under external 2PC control"; a failed two-phase commit function moves it to
+// Failed, to be rolled back. Once commit() has started, the two-phase commit
+// functions throw "transaction is being committed by commit()".
+enum class TransactionState : uint8_t {
+  Active = 0,
+  Committed = 1,
+  RolledBack = 2,
+  Failed = 3,
+  ExternalTwoPhase = 4,
+};
+
+class Snapshot;
+class TransactionClient;
+
+// Threads: a TransactionClient, RoutedClient or FailoverClient may be used
+// from several threads at once. Every other handle, Transaction and Snapshot
+// included, may be handed from thread to thread but used by one at a time; a
+// call made while another is in flight on a Transaction, Snapshot or their
+// routed kinds throws "... is busy". Debug builds of the library log, at
+// debug, each call made on another thread than the one before, to audit such
+// hand-offs.
 class Transaction {
 public:
   Transaction(::rust::cxxbridge1::Box<tikv_client_glue::Transaction> txn);
   std::optional<std::string> get(const std::string &key);
+  // Like get, also telling whether the value came from this transaction's
+  // own buffered put or delete or was read from TiKV, and how long it took.
+  // A key only locked by get_for_update counts as read from TiKV.
+  TracedValue get_traced(const std::string &key);
+  // The version this transaction reads at, fetched when it began.
+  uint64_t start_version();
+  // A snapshot at start_version(), e.g. for another thread to validate
+  // against without the transaction. With include_buffered, its get,
+  // batch_get and scan also see the puts and deletes buffered so far (not
+  // those made afterwards); scan_keys and the raw reads do not. It never
+  // sees what was committed after the start. `client` must be the one
+  // this transaction was begun from, or share its connection.
+  Snapshot fork_snapshot(const TransactionClient &client,
+                         bool include_buffered);
   std::optional<std::string> get_for_update(const std::string &key);
+  // Like get_for_update, only locking the key if it exists, so that
+  // concurrent creators of an absent key do not wait on each other; needs a
+  // pessimistic transaction. Existence is checked at the start timestamp of
+  // the transaction before locking: a key created since is left unlocked
+  // and returns std::nullopt, a key deleted since stays locked and returns
+  // std::nullopt, as read under the lock.
+  std::optional<std::string> get_and_lock_if_present(const std::string &key);
+  // Length of the value of `key`; the value itself is still fetched from
+  // TiKV, but not copied across the FFI boundary.
+  std::optional<uint64_t> get_value_length(const std::string &key);
+  // Copies the value of `key` into the caller's buffer without allocating.
+  // `length` is the full value length; when it exceeds `buf_len` the copy is
+  // truncated and `truncated` is set, the caller may retry with a larger
+  // buffer.
+  GetIntoResult get_into(const std::string &key, uint8_t *buf,
+                         size_t buf_len);
+  // Pairs of the keys that exist, sorted by key.
   std::vector<KvPair> batch_get(const std::vector<std::string> &keys);
+  // Pairs of the keys that exist, in the order of `keys`, as many as fit in
+  // `max_bytes` of keys and values; every key is still read.
+  BatchGetLimitedResult batch_get_limited(const std::vector<std::string> &keys,
+                                          uint64_t max_bytes);
+  // Every scan takes up to `limit` pairs; 0 scans the whole range, page by
+  // page, and limits over UINT32_MAX throw.
   std::vector<KvPair>
   batch_get_for_update(const std::vector<std::string> &keys);
+  // Makes commit fail with a write conflict if another transaction changes
+  // any of `keys`, without reading their values; a pessimistic transaction
+  // locks them right away instead.
+  void assert_unchanged(const std::vector<std::string> &keys);
+  // The number of regions the buffered keys span, from the region cache of
+  // `client`; throws with any key buffered, as the tikv-client in use keeps
+  // its region cache private.
+  uint64_t region_span(const TransactionClient &client);
+  // The keys this transaction put or deleted, in key order with their latest
+  // mutation, locks left out; still available after commit, e.g. to
+  // invalidate caches.
+  std::vector<WrittenKey> written_keys();
+  // The contended keys met by the reads, locks and commit of this
+  // transaction so far, as reported by their errors, in the order first met;
+  // still available after a failed commit, e.g. to lock exactly those keys
+  // up front on retry.
+  std::vector<ConflictEntry> conflict_report();
+  // Releases the pessimistic locks held on `keys`, keys not locked being
+  // skipped. The tikv-client in use cannot release a single lock, so this
+  // throws as soon as one is held, leaving the transaction unchanged.
+  void pessimistic_rollback_keys(const std::vector<std::string> &keys);
   std::vector<KvPair> scan(const std::string &start, Bound start_bound,
                            const std::string &end, Bound end_bound,
-                           std::uint32_t limit);
+                           std::uint64_t limit);
   std::vector<std::string> scan_keys(const std::string &start,
                                      Bound start_bound, const std::string &end,
-                                     Bound end_bound, std::uint32_t limit);
+                                     Bound end_bound, std::uint64_t limit);
+  ScanResult scan_with_truncation(const std::string &start, Bound start_bound,
+                                  const std::string &end, Bound end_bound,
+                                  std::uint64_t limit);
+  // Like scan, for keys stored with the memcomparable bytes encoding of
+  // encode_bytes: with `bounds_are_decoded` the bounds are given decoded and
+  // encoded before scanning, with `decode_keys` the keys returned are
+  // decoded, their first component decoded and the rest kept as stored; a
+  // key that does not decode throws. The pairs stay in ascending order
+  // either way. An included end bound leaves out the keys with components
+  // after it; end the range at the next value, excluded, to take them in.
+  std::vector<KvPair> scan_with_codec(const std::string &start,
+                                      Bound start_bound,
+                                      const std::string &end, Bound end_bound,
+                                      std::uint64_t limit,
+                                      bool bounds_are_decoded,
+                                      bool decode_keys);
+  // Like scan, with every key and value packed into `data`; pair i is at
+  // offsets[4 * i .. 4 * i + 4] = {key_off, key_len, value_off, value_len}.
+  PackedScanResult scan_packed(const std::string &start, Bound start_bound,
+                               const std::string &end, Bound end_bound,
+                               std::uint64_t limit);
   void put(const std::string &key, const std::string &value);
+  // Puts `value` into `key` and returns the value it replaces, std::nullopt
+  // if the key did not exist, in one call; a pessimistic transaction locks
+  // the key while reading it, so the answer holds until commit.
+  std::optional<std::string> put_get_prev(const std::string &key,
+                                          const std::string &value);
   void batch_put(const std::vector<KvPair> &kvs);
   void remove(const std::string &key);
-  void commit();
+  // Writes `value` wrapped with its expiry, plus an entry in an expiry index
+  // under the reserved prefix "\x00__tikv_cpp__". Expiry times are opaque,
+  // e.g. Unix seconds, and compared with `now` at reads and purges; an
+  // `expire_at` of 0 never expires and writes no index entry.
+  void put_with_expiry(const std::string &key, const std::string &value,
+                       uint64_t expire_at);
+  // Reads a key written by put_with_expiry, std::nullopt once its expiry is
+  // at most `now` and not 0; throws for values written without an expiry.
+  std::optional<std::string> get_with_expiry(const std::string &key,
+                                             uint64_t now);
+  // Conditional writes: throw an error starting with "precondition failed"
+  // and buffer nothing unless the current value of `key` is `expected`. A
+  // concurrent change to the key still makes the commit fail.
+  void put_if_equals(const std::string &key, const std::string &expected,
+                     const std::string &new_value);
+  void remove_if_equals(const std::string &key, const std::string &expected);
+  // Caps the key and value bytes this transaction may buffer; mutations
+  // going over throw an error starting with "transaction too large". 0 means
+  // no limit.
+  void set_memory_limit(uint64_t bytes);
+  uint64_t buffered_bytes() const;
+  // Whether nothing has been buffered yet: no put, delete or lock.
+  bool is_readonly() const;
+  TransactionState state() const;
+  // Returns the commit timestamp, or 0 for a read-only transaction, whose
+  // commit sends nothing to TiKV.
+  uint64_t commit();
+  // With commit stats on, commit drives the two-phase commit itself and
+  // times its phases; last_commit_stats is all zero until such a commit.
+  // tikv-client fetches the commit timestamp from PD within commit_primary,
+  // so get_commit_ts_ms times an extra fetch made right before it, an
+  // estimate of the one counted in commit_primary_ms.
+  void set_commit_stats(bool enabled);
+  // Records the latest `max_entries` reads, writes, commits and rollbacks;
+  // a failed commit then reports a summary of them in its error.
+  void enable_op_log(uint32_t max_entries);
+  ::rust::Vec<OpLogEntry> dump_op_log() const;
+  // Names the transaction in the warnings of strict mode, e.g. after the
+  // call site that began it; an empty tag removes it.
+  void set_tag(const std::string &tag);
+  // Makes every later operation but rollback throw "transaction deadline
+  // exceeded" once `deadline_unix_ms` has passed, without sending anything,
+  // and cuts short the one running then; 0 removes the deadline.
+  void set_deadline(uint64_t deadline_unix_ms);
+  // Makes put read the key first and drop the write when the value is the
+  // one already buffered or stored, counted in metrics().skipped_noop_writes.
+  // Every put then costs a read, from TiKV for a key not yet mutated; a
+  // skipped key is locked, so a concurrent write of it still conflicts.
+  void enable_skip_noop_writes();
+  CommitStats last_commit_stats() const;
+  // How the transaction was committed, see CommitInfo; throws until a
+  // commit succeeded. The pinned tikv-client always uses two-phase commit.
+  CommitInfo commit_info() const;
+  // Applies the buffered mutations in independent transactions of up to
+  // `keys_per_txn` keys, in key order, when atomicity is not needed. A failed
+  // chunk throws a "chunk failed" error naming its first key: the keys
+  // before it are committed, the rest are not. Throws on pessimistic
+  // transactions holding locks.
+  ChunkedCommitResult commit_chunked(uint32_t keys_per_txn);
   void rollback();
+  // Request priority overrides; only Priority::Normal is supported by the
+  // pinned tikv-client, others throw.
+  void set_priority(Priority priority);
+  // The timestamp pessimistic locks are taken at, and moving it to a fresh
+  // one for later locks. The pinned tikv-client already uses a fresh one for
+  // every lock request and does not expose it, so both throw.
+  uint64_t for_update_ts() const;
+  uint64_t refresh_for_update_ts();
   std::pair<std::string, uint64_t>
   prewrite_primary(const std::string &primary_key);
-  void prewrite_secondary(const std::string &primary_key, uint64_t start_ts);
+  // Like prewrite_primary, with the primary key picked deterministically
+  // among the buffered mutations; `primary_key` is only used with
+  // PrimarySelection::Explicit.
+  std::pair<std::string, uint64_t>
+  prewrite_primary(PrimarySelection strategy,
+                   const std::string &primary_key = "");
+  // On a transaction without mutations prewrite_primary returns an empty key
+  // and version 0, commit_primary returns 0 and commit_secondary does
+  // nothing, none of them sending a request. prewrite_secondary throws on
+  // such a transaction unless `allow_empty` is set, and then does nothing.
+  // Otherwise commit_primary throws until the primary key was prewritten.
+  // commit_secondary throws unless the primary was committed, by this
+  // transaction or, on one that only called prewrite_secondary, by another;
+  // on the latter it is what finishes the transaction.
+  SecondaryPrewriteResult prewrite_secondary(const std::string &primary_key,
+                                             uint64_t start_ts,
+                                             bool allow_empty = false);
   uint64_t commit_primary();
   void commit_secondary(uint64_t commit_ts);
 
@@ -51,40 +357,630 @@ class Snapshot {
 public:
   Snapshot(::rust::cxxbridge1::Box<tikv_client_glue::Snapshot> snapshot);
   std::optional<std::string> get(const std::string &key);
+  std::optional<uint64_t> get_value_length(const std::string &key);
+  GetIntoResult get_into(const std::string &key, uint8_t *buf,
+                         size_t buf_len);
+  void set_priority(Priority priority);
   std::map<std::string, std::string>
   batch_get(const std::vector<std::string> &keys);
+  // The values of `keys`, in the same order, std::nullopt for those that
+  // do not exist. Dense runs of keys, of the same length and differing in
+  // their last 8 bytes read as big-endian integers, are read by one scan
+  // each, the other keys by one batch get; metrics() counts the keys each
+  // served in smart_get_scanned_keys and smart_get_batched_keys.
+  std::vector<std::optional<std::string>>
+  smart_get(const std::vector<std::string> &keys);
+  // A run is dense when it has at least min_run_keys keys filling at least
+  // density_percent (1 to 100) of the integers it spans; defaults to 4 keys
+  // and 50.
+  void set_smart_get_options(uint32_t density_percent, uint32_t min_run_keys);
+  // The hashes of the values of `keys`, in the same order, std::nullopt for
+  // those that do not exist; the values are read and hashed on the Rust
+  // side, on a worker thread, and never cross to C++, for verifiers
+  // comparing what nodes read.
+  std::vector<std::optional<std::string>>
+  batch_get_hashes(const std::vector<std::string> &keys, HashAlgorithm algo);
+  // See Transaction::batch_get_limited.
+  BatchGetLimitedResult batch_get_limited(const std::vector<std::string> &keys,
+                                          uint64_t max_bytes);
   std::vector<KvPair> scan(const std::string &start, Bound start_bound,
                            const std::string &end, Bound end_bound,
-                           std::uint32_t limit);
+                           std::uint64_t limit);
   std::vector<std::string> scan_keys(const std::string &start,
                                      Bound start_bound, const std::string &end,
-                                     Bound end_bound, std::uint32_t limit);
+                                     Bound end_bound, std::uint64_t limit);
+  ScanResult scan_with_truncation(const std::string &start, Bound start_bound,
+                                  const std::string &end, Bound end_bound,
+                                  std::uint64_t limit);
+  // See Transaction::scan_with_codec.
+  std::vector<KvPair> scan_with_codec(const std::string &start,
+                                      Bound start_bound,
+                                      const std::string &end, Bound end_bound,
+                                      std::uint64_t limit,
+                                      bool bounds_are_decoded,
+                                      bool decode_keys);
+  // Like scan, reading page by page and stopping after the first page that
+  // ends at or past deadline_ms, in Unix milliseconds, so that a scan bound
+  // to a time window returns what it read so far instead of throwing, with
+  // whether it read the whole range and where to resume if not.
+  ScanUntilResult scan_until(const std::string &start, Bound start_bound,
+                             const std::string &end, Bound end_bound,
+                             std::uint64_t limit, uint64_t deadline_ms);
+  PackedScanResult scan_packed(const std::string &start, Bound start_bound,
+                               const std::string &end, Bound end_bound,
+                               std::uint64_t limit);
+  // Like scan, but a value too large for a gRPC message only has its key
+  // reported in `skipped` instead of failing the whole scan; the failing
+  // reads are narrowed down by bisection, which costs extra requests.
+  SkippingScanResult scan_skipping_oversized(const std::string &start,
+                                             Bound start_bound,
+                                             const std::string &end,
+                                             Bound end_bound,
+                                             std::uint64_t limit);
+  // Starts fetching `keys` in the background at the version of the snapshot,
+  // so that the get and batch_get of them that follow are served from a
+  // cache once the fetch is done, without a round trip; reads before that go
+  // to TiKV as usual. The cache holds up to set_prefetch_cache_bytes bytes of
+  // keys and values, 64 MiB by default.
+  void prefetch(const std::vector<std::string> &keys);
+  void set_prefetch_cache_bytes(uint64_t bytes);
+  PrefetchStats prefetch_stats();
+  // Like get and scan, but the values are returned as stored, without
+  // opening the envelopes of values written under set_compression. scan_raw
+  // reads a single page, so a limit of 0 throws.
+  std::optional<std::string> get_raw(const std::string &key);
+  std::vector<KvPair> scan_raw(const std::string &start, Bound start_bound,
+                               const std::string &end, Bound end_bound,
+                               std::uint64_t limit);
+  // Keys of the range with the length of their values. The values are still
+  // fetched from TiKV, this only saves copying them.
+  std::vector<std::pair<std::string, uint64_t>>
+  scan_key_sizes(const std::string &start, Bound start_bound,
+                 const std::string &end, Bound end_bound,
+                 std::uint64_t limit);
+  // Scans two ranges of this snapshot in one call, e.g. a table and its
+  // index. All reads of a snapshot are at its timestamp, so the results are
+  // consistent with each other.
+  std::pair<std::vector<KvPair>, std::vector<KvPair>>
+  scan_pair(const std::string &start_a, Bound start_a_bound,
+            const std::string &end_a, Bound end_a_bound,
+            const std::string &start_b, Bound start_b_bound,
+            const std::string &end_b, Bound end_b_bound, std::uint64_t limit);
+  // XOR of the crc64 of every key and value in the range, compatible with
+  // TiKV's checksum. The range is scanned by the client, page by page.
+  ChecksumResult checksum_range(const std::string &start, Bound start_bound,
+                                const std::string &end, Bound end_bound);
+  // Streams the range to `file_path` and returns the number of pairs
+  // written; see TransactionClient::import_file.
+  uint64_t export_range(const std::string &start, Bound start_bound,
+                        const std::string &end, Bound end_bound,
+                        const std::string &file_path);
 
 private:
+  friend class TransactionClient;
   ::rust::cxxbridge1::Box<tikv_client_glue::Snapshot> _snapshot;
 };
 
+// Holds back GC for a service while alive, see
+// TransactionClient::register_service_safepoint.
+class SafepointGuard {
+public:
+  SafepointGuard(
+      ::rust::cxxbridge1::Box<tikv_client_glue::SafepointGuard> guard);
+  bool healthy() const;
+  void release();
+
+private:
+  ::rust::cxxbridge1::Box<tikv_client_glue::SafepointGuard> _guard;
+};
+
+// A bulk write session for keys known not to exist yet: pushed pairs are
+// committed `keys_per_txn` at a time by up to `concurrency` transactions in
+// parallel, without any read, push blocking while all of them are in flight.
+// finish writes the last partial chunk, waits for all of them and returns
+// the chunks that failed, with their pairs to push again; the session can
+// go on afterwards.
+class BulkWriter {
+public:
+  BulkWriter(::rust::cxxbridge1::Box<tikv_client_glue::BulkWriter> writer);
+  void push(const std::vector<std::string> &keys,
+            const std::vector<std::string> &values);
+  BulkWriteProgress progress() const;
+  ::rust::Vec<BulkWriteFailure> finish();
+
+private:
+  ::rust::cxxbridge1::Box<tikv_client_glue::BulkWriter> _writer;
+};
+
 class TransactionClient {
 public:
+  // A positive `max_live_snapshots` limits the snapshots created through
+  // this client and the ones derived from it that are not destroyed yet:
+  // creating one more throws "too many snapshots", with the ages of the
+  // oldest. 0 leaves them unlimited.
   TransactionClient(const std::vector<std::string> &pd_endpoints,
-                    const std::string &log_path, uint32_t grpc_timeout = 3);
+                    const std::string &log_path, uint32_t grpc_timeout = 3,
+                    uint32_t max_live_snapshots = 0);
   TransactionClient(const std::vector<std::string> &pd_endpoints,
                     const std::string &log_path, const std::string &ca_path,
                     const std::string &cert_path, const std::string &key_path,
-                    uint32_t timeout = 3);
+                    uint32_t timeout = 3, Priority priority = Priority::Normal,
+                    uint32_t max_live_snapshots = 0);
+  TransactionClient(
+      ::rust::cxxbridge1::Box<tikv_client_glue::TransactionClient> client);
+  // Connects to a cluster with API v2 enabled, within its keyspace named
+  // `keyspace`, which every key and range of the client is encoded into.
+  // Needs Capability::ApiV2, throwing "unsupported: ..." without it.
+  static TransactionClient in_keyspace(
+      const std::vector<std::string> &pd_endpoints,
+      const std::string &log_path, const std::string &keyspace,
+      uint32_t grpc_timeout = 3, uint32_t max_live_snapshots = 0);
+  // Returns a client on the same connection for which every key is
+  // transparently prefixed with `prefix`; scans never leave the prefix.
+  TransactionClient with_prefix(const std::string &prefix);
   Transaction begin();
   std::shared_ptr<Transaction> new_optimistic_transaction();
   std::shared_ptr<Transaction> new_optimistic_transaction(uint32_t retry_limit);
+  // A transaction that only reads: put and remove throw "transaction is
+  // read-only", and commit sends nothing and returns 0.
+  Transaction begin_read_only();
   Transaction begin_pessimistic();
+  // Under IsolationLevel::ReadCommitted plain reads see the latest committed
+  // data plus the transaction's own writes, instead of the data at its start
+  // timestamp; locking reads are unaffected.
+  Transaction begin_pessimistic(IsolationLevel isolation);
+  // Pipelined locking (`pipelined_locking`) is not supported by the pinned
+  // tikv-client and throws; keep it false.
+  Transaction begin_pessimistic(const PessimisticOptions &options);
+  // A pessimistic transaction whose lock backoff waits from `base_ms`,
+  // doubling up to `max_ms`, for `attempts` retries, regardless of the
+  // default transaction options.
+  Transaction begin_pessimistic_with_backoff(uint64_t base_ms, uint64_t max_ms,
+                                             uint32_t attempts);
   std::shared_ptr<Snapshot> snapshot();
   std::shared_ptr<Snapshot> snapshot(uint64_t timestamp);
+  // A snapshot at a fresh timestamp whose get throws "key locked" at once on
+  // a key locked by a live transaction, instead of backing off.
+  std::shared_ptr<Snapshot> snapshot_fail_fast();
+  // Registers `provider`, returning the version snapshot_pinned() reads at,
+  // e.g. the commit timestamp of the last complete block; it replaces the
+  // one set before and is shared by the clients derived with with_prefix
+  // from then on. It is called synchronously by snapshot_pinned() only,
+  // never per read, from whichever threads create pinned snapshots, so it
+  // must be thread-safe; its exceptions are rethrown from snapshot_pinned().
+  void set_read_ts_provider(std::function<uint64_t()> provider);
+  void clear_read_ts_provider();
+  // A snapshot at the version of the read timestamp provider rather than a
+  // fresh one from PD; a provider returning 0 falls back to a fresh one with
+  // a logged warning. Throws when no provider is set.
+  std::shared_ptr<Snapshot> snapshot_pinned();
   uint64_t current_timestamp();
   void gc(uint64_t safe_point);
+  // The PD endpoint the client is connected to, and how many PD leader
+  // changes it has seen. The pinned tikv-client does not report them: both
+  // currently always throw.
+  std::string current_pd();
+  uint64_t pd_leader_changes();
+  // Caps the keys and values written by the transactions begun from then on,
+  // which fail right away when over instead of at commit. The limits default
+  // to TiKV's own, 8 KiB keys and 8 MiB values; 0 disables a check, for
+  // clusters whose limits were raised.
+  void set_size_limits(uint64_t max_key_bytes, uint64_t max_value_bytes);
+  // With OversizedValues::Chunk, a put of a value over the value limit
+  // stores it as chunks under `<key>\0chunk_0`, `<key>\0chunk_1` and on,
+  // with a manifest at the key itself, instead of throwing "entry too large".
+  // The transactions begun and snapshots created from then on read chunked
+  // values back whole, throwing "chunked value corrupt: ..." when a chunk
+  // is missing, and hide chunk keys from scans; deleting or overwriting a
+  // chunked value deletes its chunks, at the cost of a read of the key on
+  // every put and delete. Clients reading chunked values must be set to
+  // Chunk too, otherwise they see the manifests and chunks as stored. Bulk
+  // writes and the raw reads never chunk. Defaults to Reject.
+  void set_oversized_values(OversizedValues policy);
+  // Point operations (get, put, delete, batch_get...) throw on the empty key,
+  // most often an uninitialized string, unless this is set; it applies to the
+  // transactions and snapshots started from then on. Empty scan bounds are
+  // always valid.
+  void set_allow_empty_keys(bool allow);
+  // Keys starting with "\x00__tikv_cpp__" are reserved for the wrapper's own
+  // bookkeeping, such as the expiry index. With the guard on, point
+  // operations on such keys throw and scans skip their range, for the
+  // transactions and snapshots started from then on.
+  void set_guard_reserved(bool guard);
+  // How current_timestamp, begin* and snapshot* retry fetching a timestamp
+  // while PD fails, e.g. during a leader failover: up to max_attempts
+  // attempts within budget_ms, with doubling waits. Defaults to 3 attempts
+  // within 500ms; a max_attempts of 0 throws.
+  void set_timestamp_retry(uint32_t max_attempts, uint64_t budget_ms);
+  // Makes the calls taking a version, e.g. snapshot(timestamp) and gc, throw
+  // on one whose physical part is more than within_years from now, such as
+  // Unix milliseconds not converted with version_from_unix_ms; 0, the
+  // default, checks nothing.
+  void set_strict_versions(uint32_t within_years);
+  // How the reads of the snapshots created from then on are sent again when
+  // they fail on a region error, e.g. not-leader while regions rebalance:
+  // up to max_attempts more times, waiting from base_delay_ms doubling up to
+  // max_delay_ms; 0 attempts disables it. Defaults to 10 retries from 2ms up
+  // to 500ms, counted in metrics().snapshot_read_retries.
+  void set_snapshot_read_retry(uint32_t max_attempts, uint64_t base_delay_ms,
+                               uint64_t max_delay_ms);
+  // Calls `callback` with PressureLevel::High once the transaction and
+  // snapshot calls in flight on this connection reach high_watermark, and
+  // with PressureLevel::Normal once they fall back to low_watermark, so that
+  // load can be shed before requests time out. The callback runs on a
+  // thread of its own, at most once every 500ms, and replaces the one set
+  // before; throws unless low_watermark < high_watermark.
+  void set_pressure_callback(std::function<void(PressureLevel)> callback,
+                             uint64_t high_watermark, uint64_t low_watermark);
+  void clear_pressure_callback();
+  // Replays sample_rate_percent out of every hundred snapshot reads of the
+  // kinds in ops_mask, an or of MirrorOp values, against the cluster of
+  // `mirror`, counting in metrics().mirror_matches and the counters after it
+  // whether both returned the same. The replay runs in the background after
+  // the read returned and never changes its latency or outcome; it reads at
+  // a fresh timestamp of the mirror cluster, so writes in between show as
+  // mismatches, which are logged at warn at most once every 10s.
+  // Transaction reads and writes are not mirrored. Applies to the
+  // snapshots created from then on.
+  void set_mirror(const TransactionClient &mirror,
+                  uint32_t sample_rate_percent, uint32_t ops_mask);
+  void clear_mirror();
+  // Makes the commits of the transactions begun from then on append one
+  // JSON line per put or delete, then one per commit, to the file at
+  // `path`, tagged with client_tag, and return once they are written, and
+  // synced with AuditFsync::EveryCommit. Keys are recorded as SHA-256
+  // hashes and values by length only; every record carries the format
+  // version in "v". The file is rotated to `<path>.1` and on past
+  // max_file_bytes, keeping max_rotated_files of them; 0 bytes never
+  // rotates. A commit whose records fail to be written throws
+  // "audit log write failed: ...", though it did commit. Clients derived
+  // with with_prefix from then on share the trail; bulk writes are not
+  // recorded.
+  void set_audit_log(const std::string &path, const std::string &client_tag,
+                     uint64_t max_file_bytes, uint32_t max_rotated_files,
+                     AuditFsync fsync);
+  void clear_audit_log();
+  // Strict mode, for the transactions begun from then on: beginning one
+  // while more than max_active_transactions (0 for no limit) are active
+  // logs them at warn with their tags and ages; committing one that
+  // buffered nothing is allowed, warned about, or throws "empty commit:
+  // ..." as empty_commits says, read-only transactions aside; and every
+  // transaction still active after report_age_ms (0 for never) is logged
+  // once, with the summary of its op log. A transaction is active until it
+  // is committed, rolled back or destroyed, so a leaked one shows. Clients
+  // derived with with_prefix from then on count in and copy the options.
+  void set_strict_mode(uint32_t max_active_transactions,
+                       EmptyCommits empty_commits, uint64_t report_age_ms);
+  void clear_strict_mode();
+  // The transactions of this client and the ones derived from it that are
+  // active, as strict mode counts them.
+  uint64_t active_transaction_count() const;
+  // The snapshots of this client and the ones derived from it not destroyed
+  // yet, the age of the oldest and how many were ever created, whether
+  // max_live_snapshots is set or not; those of transactions do not count.
+  SnapshotStats snapshot_stats() const;
+  // Values written from then on are stored in an envelope, LZ4-compressed
+  // when they have at least min_value_bytes and that saves space; while it
+  // is set, reads open the envelopes, so enveloped and plain values can be
+  // mixed. Compression::None turns it off, and reads then return enveloped
+  // values as stored, so a client reading them must set compression too.
+  void set_compression(Compression compression, uint64_t min_value_bytes);
+  // Sets the options of the transactions begun from then on by begin() and
+  // begin_pessimistic(), e.g. a tuned lock backoff; safe to call while other
+  // threads begin transactions. A lock_backoff_max_attempts of 0 keeps the
+  // backoff of tikv-client. drop_check says what dropping a transaction that
+  // was neither committed nor rolled back does; DropCheck::Panic aborts the
+  // process when the drop happens while an exception unwinds.
+  // fail_fast_on_locks makes reads throw on the first lock they meet.
+  void set_default_txn_options(const TxnOptions &options);
+  TxnOptions default_txn_options();
+  // Compares the physical part of a fresh PD timestamp with the local clock;
+  // skew_ms is positive when PD is ahead.
+  ClockSkewReport check_clock_skew(uint64_t max_skew_ms);
+  // Commits the primary of the transaction (primary_key, start_ts) at
+  // commit_ts, or reports it already was, for a coordinator replaying its
+  // commit intents after a crash. The tikv-client in use cannot send these
+  // requests on its own, so this throws after checking its arguments.
+  FinalizeResult finalize_commit(const std::string &primary_key,
+                                 uint64_t start_ts, uint64_t commit_ts);
+  // Errors per TiKV store, for the errors naming a store, shared by the
+  // clients of the same connection; requests and p99_us are not reported by
+  // the pinned tikv-client and stay 0.
+  ::rust::Vec<StoreMetric> store_metrics();
+  BulkWriter bulk_write(uint32_t keys_per_txn, uint32_t concurrency);
+  // Running totals of the keys and bytes read by snapshots and transactions
+  // and written by committed transactions, shared by the clients of the same
+  // connection; reset_metrics zeroes them, but not failed_log_writes. The
+  // region error counts only cover the errors that made an operation fail:
+  // tikv-client retries the others internally without reporting them, and
+  // the snapshot reads set_snapshot_read_retry sent again are counted in
+  // snapshot_read_retries instead. in_flight_operations is a gauge of the
+  // transaction and snapshot calls running, left alone by reset_metrics.
+  // prewrites counts the prewrites sent, none for a commit of a transaction
+  // that buffered nothing.
+  MetricsSnapshot metrics();
+  // What the logger of the connection does with the records it fails to
+  // write to its file, e.g. when the disk is full: drop them (the default)
+  // or write them to stderr. Failures never abort; failed_log_writes of
+  // metrics counts them.
+  void set_log_failure_mode(LogFailureMode mode);
+  void reset_metrics();
+  // Logs a warning when more than `per_minute` region errors are counted
+  // within a minute, at most once a minute; 0, the default, disables it.
+  void set_region_error_warn_threshold(uint64_t per_minute);
+  // Checks each PD endpoint (DNS, TCP), fetching a PD timestamp and reading
+  // from a TiKV store, each within `timeout_ms`, and with `write_probe` also
+  // writes and deletes a key under the reserved prefix. Every
+  // check runs and reports its outcome; print the report when operations
+  // fail with unclear errors.
+  ::rust::Vec<CheckItem> self_check(uint64_t timeout_ms,
+                                    bool write_probe = false);
+  // Registers a service GC safepoint with PD, renewed every `ttl_secs` for as
+  // long as the guard lives. Not supported by the pinned tikv-client yet:
+  // this currently always throws.
+  SafepointGuard register_service_safepoint(const std::string &service_id,
+                                            uint64_t safepoint_ts,
+                                            uint64_t ttl_secs);
+  // Reads `key`, passes its value to `modify` and writes back the result in
+  // an optimistic transaction, retried from scratch on conflicts up to
+  // `max_retries` times; `modify` sees the freshly read value every time and
+  // returns std::nullopt to abort. Returns the committed value.
+  std::string read_modify_write(
+      const std::string &key,
+      const std::function<std::optional<std::string>(
+          const std::optional<std::string> &)> &modify,
+      uint32_t max_retries);
+  // Runs `ops` in a fresh optimistic transaction and commits it, retrying
+  // both on conflicts for up to `max_attempts` attempts with exponential
+  // backoff, and returns the commit timestamp. `ops` works on the glue
+  // transaction through the tikv_client_glue functions, must not keep it past
+  // the call, and returns false to abort without retrying.
+  uint64_t run_optimistic(
+      const std::function<bool(tikv_client_glue::Transaction &)> &ops,
+      uint32_t max_attempts, uint64_t backoff_base_ms = 10,
+      uint64_t backoff_max_ms = 1000);
+  // Deletes every key in the range, `keys_per_txn` keys per transaction, and
+  // returns how many were deleted. Chunks are retried on conflicts; chunks
+  // committed before an error stay deleted, so calling again with the same
+  // range resumes where it stopped.
+  uint64_t delete_range_chunked(const std::string &start, Bound start_bound,
+                                const std::string &end, Bound end_bound,
+                                uint32_t keys_per_txn,
+                                bool pessimistic = false);
+  // Like delete_range_chunked, returning the keys deleted and where to resume
+  // instead of throwing.
+  PartialOutcome delete_range_partial(const std::string &start,
+                                      Bound start_bound, const std::string &end,
+                                      Bound end_bound, uint32_t keys_per_txn,
+                                      bool pessimistic = false);
+  // Copies the pairs of a range of `source`, e.g. a snapshot of another
+  // cluster, into this client's cluster and returns how many were copied.
+  // The range is scanned page by page and written like a bulk_writer of
+  // keys_per_txn and concurrency; chunks committed before an error stay
+  // committed, and copying again is idempotent.
+  uint64_t copy_range(Snapshot &source, const std::string &start,
+                      Bound start_bound, const std::string &end,
+                      Bound end_bound, uint32_t keys_per_txn,
+                      uint32_t concurrency);
+  // Like copy_range, returning the pairs copied and failed and where to
+  // resume instead of throwing.
+  PartialOutcome copy_range_partial(Snapshot &source, const std::string &start,
+                                    Bound start_bound, const std::string &end,
+                                    Bound end_bound, uint32_t keys_per_txn,
+                                    uint32_t concurrency);
+  // The keys of the range whose value differs between old_version and
+  // new_version, in key order, up to `limit` of them, 0 for all; resume
+  // after the last key. Both versions are scanned page by page and merged,
+  // so only the end states are compared.
+  std::vector<DiffEntry> range_diff(const std::string &start, Bound start_bound,
+                                    const std::string &end, Bound end_bound,
+                                    uint64_t old_version, uint64_t new_version,
+                                    uint64_t limit);
+  // Replays a file written by Snapshot::export_range, `keys_per_txn` pairs
+  // per transaction. The file is verified before anything is written.
+  uint64_t import_file(const std::string &file_path, uint32_t keys_per_txn);
+  // Deletes the keys under `prefix` written by Transaction::put_with_expiry
+  // that have expired at `now`, in chunked transactions, and returns how many
+  // were deleted. Keys overwritten or deleted since are left alone.
+  uint64_t purge_expired(const std::string &prefix, uint64_t now,
+                         uint32_t keys_per_txn);
+  // Splits regions at `sample_keys` before a bulk load, optionally
+  // scattering them, and returns the number of splits. Not supported by the
+  // pinned tikv-client yet: this currently always throws.
+  uint32_t presplit_for_keys(const std::vector<std::string> &sample_keys,
+                             bool scatter);
+  // Version history of `key`, newest first. This is a slow diagnostics call
+  // reconstructing the history from snapshot reads, which can miss versions.
+  std::vector<MvccVersion> get_mvcc_versions(const std::string &key,
+                                             uint32_t max_versions);
+  // Reads `key` at each of `timestamps` concurrently; the results are in the
+  // order of `timestamps`. A read failing, e.g. below the GC safepoint, only
+  // sets the error of its own entry.
+  std::vector<VersionedValue>
+  multi_version_get(const std::string &key,
+                    const std::vector<uint64_t> &timestamps);
 
 private:
+  friend class Transaction;
+  friend class RoutedClient;
+  friend class FailoverClient;
   ::rust::cxxbridge1::Box<tikv_client_glue::TransactionClient> _client;
 };
 
+// A transaction of a RoutedClient, begun on the client owning the first key
+// it touches. An operation on a key of another client throws
+// "cross-route transaction: ...", and one on a key no client owns throws
+// "no route: ...". Scans must stay within one client's prefix. Committing
+// a transaction that touched no key returns 0.
+class RoutedTransaction {
+public:
+  RoutedTransaction(
+      ::rust::cxxbridge1::Box<tikv_client_glue::RoutedTransaction> txn);
+  std::optional<std::string> get(const std::string &key);
+  void put(const std::string &key, const std::string &value);
+  void remove(const std::string &key);
+  std::vector<KvPair> scan(const std::string &start, Bound start_bound,
+                           const std::string &end, Bound end_bound,
+                           std::uint64_t limit);
+  uint64_t commit();
+  void rollback();
+
+private:
+  ::rust::cxxbridge1::Box<tikv_client_glue::RoutedTransaction> _txn;
+};
+
+// A snapshot of every client of a RoutedClient, each at a fresh timestamp
+// of its own cluster, so reads across clients are not of one point in time.
+class RoutedSnapshot {
+public:
+  RoutedSnapshot(
+      ::rust::cxxbridge1::Box<tikv_client_glue::RoutedSnapshot> snapshot);
+  std::optional<std::string> get(const std::string &key);
+  std::map<std::string, std::string>
+  batch_get(const std::vector<std::string> &keys);
+  // A range spanning the prefixes of several clients is scanned client by
+  // client and merged when the RoutedClient splits scans; otherwise it
+  // throws.
+  std::vector<KvPair> scan(const std::string &start, Bound start_bound,
+                           const std::string &end, Bound end_bound,
+                           std::uint64_t limit);
+
+private:
+  ::rust::cxxbridge1::Box<tikv_client_glue::RoutedSnapshot> _snapshot;
+};
+
+// Sends every operation to the client owning the prefix of its keys, e.g.
+// to split data across clusters. Keys are passed on as they are, prefix
+// included. Transactions cannot span clients: atomicity across clusters is
+// out of scope.
+class RoutedClient {
+public:
+  explicit RoutedClient(bool split_scans = false);
+  // Takes `client` over for the keys under `prefix`. Throws if the prefix
+  // starts, or starts with, the prefix of another route, or while
+  // transactions or snapshots of this client are open.
+  void add_route(const std::string &prefix, TransactionClient &&client);
+  RoutedTransaction begin();
+  RoutedTransaction begin_pessimistic();
+  RoutedSnapshot snapshot();
+
+private:
+  ::rust::cxxbridge1::Box<tikv_client_glue::RoutedClient> _client;
+};
+
+enum class FailoverTarget : uint8_t {
+  Primary = 0,
+  Standby = 1,
+};
+
+// Where a FailoverClient stands: its target, the switches made, the failures
+// in a row of the primary and the operations and failures of its current
+// window, and why the last switch was made, empty before any.
+struct FailoverStatus final {
+  FailoverTarget target;
+  uint64_t switches;
+  uint32_t consecutive_failures;
+  uint32_t window_operations;
+  uint32_t window_failures;
+  std::string last_reason;
+};
+
+// A primary client and a warm standby one, every transaction and snapshot
+// begun on the primary until it fails `max_consecutive_failures` times in a
+// row, or `max_error_percent` of the last `window` operations, then, once
+// `confirm` agrees, if set, on the standby. Only cluster errors and deadlines
+// count as failures, not conflicts, locks or the errors of the wrapper. After
+// a switch, the transactions begun on the other cluster throw "failed over:
+// ..." on every call but rollback; snapshots keep reading their cluster.
+// Keeping the data of the two clusters consistent is up to the caller.
+class FailoverClient {
+public:
+  FailoverClient(TransactionClient &&primary, TransactionClient &&standby,
+                 const FailoverPolicy &policy = FailoverPolicy{5, 50, 100},
+                 std::function<bool(uint32_t, uint32_t)> confirm = nullptr);
+  Transaction begin();
+  Transaction begin_pessimistic();
+  std::shared_ptr<Snapshot> snapshot();
+  // Switches at once, without calling `confirm`; a no-op when `target` is
+  // the current one.
+  void switch_to(FailoverTarget target);
+  FailoverStatus status() const;
+
+private:
+  ::rust::cxxbridge1::Box<tikv_client_glue::FailoverClient> _client;
+};
+
+// Marks the Rust runtime as gone, for process teardown, e.g. at the end of
+// main or from an atexit handler: every later call throws "runtime shut
+// down" at once instead of waiting on threads the exit tears down. Dropping
+// handles never blocks, before or after; this cannot be undone.
+void shutdown_runtime();
+
+// A request that the pinned tikv-client does not send and a patched fork may.
+// The functions that need it are always declared. Without it they throw
+// "unsupported: ..." after checking their arguments.
+enum class Capability : uint8_t {
+  // TransactionClient::finalize_commit.
+  CheckTxnStatus = 0,
+  // TransactionClient::presplit_for_keys.
+  RegionSplit = 1,
+  // Transaction::region_span.
+  RegionLookup = 2,
+  // TransactionClient::register_service_safepoint.
+  ServiceSafepoint = 3,
+  // TransactionClient::current_pd and pd_leader_changes.
+  PdLeader = 4,
+  // Transaction::for_update_ts and refresh_for_update_ts.
+  ForUpdateTs = 5,
+  // Transaction::pessimistic_rollback_keys.
+  PessimisticRollback = 6,
+  // PessimisticOptions::pipelined_locking.
+  PipelinedLocking = 7,
+  // Request priorities other than Priority::Normal.
+  RequestPriority = 8,
+  // TransactionClient::in_keyspace.
+  ApiV2 = 9,
+};
+
+// The capabilities the Rust side was built with, in ascending order.
+std::vector<Capability> capabilities();
+bool has_capability(Capability capability);
+
+// Formats a key exactly like the errors and logs of the Rust side: "0x" and
+// its bytes in lowercase hex, cut after 32 bytes with a trailing "...".
+// parse_key_hex reverses it, throwing on a key that was cut.
+std::string format_key_hex(const std::string &key);
+std::string parse_key_hex(const std::string &text);
+
+// The canonical 32-byte SHA-256 of a set of pairs, computed on the Rust side
+// so that every node hashes a read the same way: for each pair in ascending
+// key order, the key length as a big-endian uint64, the key, the value
+// length likewise, then the value. The order of `pairs` does not matter;
+// scans and batch_get already return theirs sorted by key.
+std::string hash_kv_pairs(const std::vector<KvPair> &pairs);
+std::string hash_kv_pairs(const ScanResult &result);
+
+// Conversions between Unix milliseconds and TSO versions, whose physical
+// part is the milliseconds shifted left by 18 bits; use them rather than
+// passing milliseconds where a version is expected.
+uint64_t version_from_unix_ms(uint64_t ms);
+uint64_t unix_ms_of_version(uint64_t version);
+
+// Memcomparable encodings of key components, compatible with TiKV's codec:
+// comparing encoded strings orders them like the decoded values. The decode
+// functions read from the start of `data` and throw on malformed input;
+// decode_bytes also returns how many bytes it consumed.
+std::string encode_u64_asc(uint64_t value);
+std::string encode_u64_desc(uint64_t value);
+std::string encode_i64_asc(int64_t value);
+std::string encode_i64_desc(int64_t value);
+uint64_t decode_u64_asc(const std::string &data);
+uint64_t decode_u64_desc(const std::string &data);
+int64_t decode_i64_asc(const std::string &data);
+int64_t decode_i64_desc(const std::string &data);
+std::string encode_bytes(const std::string &data);
+std::pair<std::string, size_t> decode_bytes(const std::string &data);
+
 } // namespace tikv_client
 
 #endif //_TIKV_CLIENT_H_
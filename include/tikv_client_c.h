@@ -0,0 +1,127 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+#ifndef _TIKV_CLIENT_C_H_
+#define _TIKV_CLIENT_C_H_
+
+// Flat C API, available when the crate is built with `--features c-api`.
+//
+// Every function returns one of the TIKV_* codes; on failure the message is
+// available from tikv_last_error() on the same thread. Buffers returned by
+// the library must be released with tikv_buffer_free / tikv_kv_pairs_free.
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TIKV_OK 0
+#define TIKV_ERR_INVALID_ARGUMENT 1
+#define TIKV_ERR_CLIENT 2
+#define TIKV_ERR_PANIC 3
+
+#define TIKV_BOUND_INCLUDED 0
+#define TIKV_BOUND_EXCLUDED 1
+#define TIKV_BOUND_UNBOUNDED 2
+
+typedef struct tikv_client tikv_client_t;
+typedef struct tikv_transaction tikv_transaction_t;
+typedef struct tikv_snapshot tikv_snapshot_t;
+
+typedef struct {
+  uint8_t *data;
+  size_t len;
+} tikv_buffer_t;
+
+typedef struct {
+  tikv_buffer_t key;
+  tikv_buffer_t value;
+} tikv_kv_pair_t;
+
+typedef struct {
+  tikv_kv_pair_t *pairs;
+  size_t len;
+} tikv_kv_pairs_t;
+
+const char *tikv_last_error(void);
+// Makes every later call fail at once, for process teardown; the handles
+// can still be freed, which never blocks.
+void tikv_shutdown_runtime(void);
+void tikv_buffer_free(tikv_buffer_t *buffer);
+void tikv_kv_pairs_free(tikv_kv_pairs_t *pairs);
+
+int tikv_client_new(const char *const *pd_endpoints, size_t pd_endpoints_len,
+                    const char *log_dir, uint32_t timeout_secs,
+                    tikv_client_t **out_client);
+void tikv_client_free(tikv_client_t *client);
+int tikv_client_current_timestamp(const tikv_client_t *client,
+                                  uint64_t *out_version);
+int tikv_client_gc(const tikv_client_t *client, uint64_t safepoint,
+                   bool *out_done);
+
+int tikv_transaction_begin(const tikv_client_t *client, bool pessimistic,
+                           tikv_transaction_t **out_transaction);
+void tikv_transaction_free(tikv_transaction_t *transaction);
+int tikv_transaction_get(tikv_transaction_t *transaction, const uint8_t *key,
+                         size_t key_len, tikv_buffer_t *out_value,
+                         bool *out_found);
+int tikv_transaction_get_for_update(tikv_transaction_t *transaction,
+                                    const uint8_t *key, size_t key_len,
+                                    tikv_buffer_t *out_value, bool *out_found);
+int tikv_transaction_batch_get(tikv_transaction_t *transaction,
+                               const uint8_t *const *keys,
+                               const size_t *key_lens, size_t count,
+                               tikv_kv_pairs_t *out_pairs);
+// A `limit` of 0 scans the whole range, page by page.
+int tikv_transaction_scan(tikv_transaction_t *transaction,
+                          const uint8_t *start, size_t start_len,
+                          int start_bound, const uint8_t *end, size_t end_len,
+                          int end_bound, uint32_t limit,
+                          tikv_kv_pairs_t *out_pairs);
+int tikv_transaction_put(tikv_transaction_t *transaction, const uint8_t *key,
+                         size_t key_len, const uint8_t *value,
+                         size_t value_len);
+int tikv_transaction_delete(tikv_transaction_t *transaction,
+                            const uint8_t *key, size_t key_len);
+int tikv_transaction_commit(tikv_transaction_t *transaction);
+int tikv_transaction_rollback(tikv_transaction_t *transaction);
+
+// On a transaction without mutations, prewrite_primary returns an empty key
+// and a start_ts of 0, commit_primary a commit_ts of 0, and the other calls do
+// nothing; none of them sends a request.
+int tikv_transaction_prewrite_primary(tikv_transaction_t *transaction,
+                                      const uint8_t *primary_key,
+                                      size_t primary_key_len,
+                                      tikv_buffer_t *out_primary_key,
+                                      uint64_t *out_start_ts);
+int tikv_transaction_prewrite_secondary(tikv_transaction_t *transaction,
+                                        const uint8_t *primary_key,
+                                        size_t primary_key_len,
+                                        uint64_t start_ts);
+int tikv_transaction_commit_primary(tikv_transaction_t *transaction,
+                                    uint64_t *out_commit_ts);
+int tikv_transaction_commit_secondary(tikv_transaction_t *transaction,
+                                      uint64_t commit_ts);
+
+// A version of 0 creates the snapshot at the current timestamp.
+int tikv_snapshot_new(const tikv_client_t *client, uint64_t version,
+                      tikv_snapshot_t **out_snapshot);
+void tikv_snapshot_free(tikv_snapshot_t *snapshot);
+int tikv_snapshot_get(tikv_snapshot_t *snapshot, const uint8_t *key,
+                      size_t key_len, tikv_buffer_t *out_value,
+                      bool *out_found);
+int tikv_snapshot_batch_get(tikv_snapshot_t *snapshot,
+                            const uint8_t *const *keys, const size_t *key_lens,
+                            size_t count, tikv_kv_pairs_t *out_pairs);
+int tikv_snapshot_scan(tikv_snapshot_t *snapshot, const uint8_t *start,
+                       size_t start_len, int start_bound, const uint8_t *end,
+                       size_t end_len, int end_bound, uint32_t limit,
+                       tikv_kv_pairs_t *out_pairs);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif
+
+#endif //_TIKV_CLIENT_C_H_
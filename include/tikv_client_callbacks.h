@@ -0,0 +1,68 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+#ifndef _TIKV_CLIENT_CALLBACKS_H_
+#define _TIKV_CLIENT_CALLBACKS_H_
+
+// C++ interfaces invoked from the Rust side of the bridge. This header is
+// included by the generated tikv_client_glue.h before the shared structs are
+// defined, so they are only forward declared here.
+
+#include <cstdint>
+
+struct OptionalValue;
+struct RmwDecision;
+enum class PressureLevel : ::std::uint8_t;
+
+namespace tikv_client_glue {
+
+struct Transaction;
+
+class RmwCallback {
+public:
+  virtual ~RmwCallback() = default;
+  // Returns the value to write given the current one, or a decision with
+  // `abort` set. Exceptions abort the read-modify-write and are rethrown.
+  virtual RmwDecision modify(const OptionalValue &current) const = 0;
+};
+
+class TransactionCallback {
+public:
+  virtual ~TransactionCallback() = default;
+  // Performs the reads and writes of one attempt on `transaction`, which is
+  // only valid during the call. Returns false to abort without retrying;
+  // exceptions abort too, unless they report a retryable conflict.
+  virtual bool run(Transaction &transaction) const = 0;
+};
+
+class PressureCallback {
+public:
+  virtual ~PressureCallback() = default;
+  // Called from a thread of the client's own with the new level, at most
+  // once every 500ms; it must not block for long. Exceptions are logged.
+  virtual void on_pressure(PressureLevel level) const = 0;
+};
+
+class ReadTsProvider {
+public:
+  virtual ~ReadTsProvider() = default;
+  // Returns the version pinned snapshots read at, 0 for a fresh one. Called
+  // on the thread creating the snapshot, possibly from several at once.
+  // Exceptions fail the snapshot creation and are rethrown.
+  virtual std::uint64_t read_ts() const = 0;
+};
+
+class FailoverConfirmation {
+public:
+  virtual ~FailoverConfirmation() = default;
+  // Returns whether a FailoverClient whose primary tripped its policy, with
+  // `consecutive_failures` in a row and `error_percent` of its window, is to
+  // switch to the standby. Called on the thread whose operation tripped it,
+  // one call at a time; the pair keeps serving the primary meanwhile.
+  // Exceptions are logged and keep the primary.
+  virtual bool confirm_failover(std::uint32_t consecutive_failures,
+                                std::uint32_t error_percent) const = 0;
+};
+
+} // namespace tikv_client_glue
+
+#endif //_TIKV_CLIENT_CALLBACKS_H_
@@ -0,0 +1,303 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Drives the same code paths the C++ bridge uses (the `sync` module), so that
+//! numbers measured from C++ can be compared against a Rust-side baseline.
+//!
+//! ```bash
+//! cargo run --release --bin bench -- --pd 127.0.0.1:2379 --workload commit \
+//!     --threads 8 --ops 10000 --value-size 256
+//! ```
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    ops::Bound,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Barrier,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use tikv_client_rust::{packed, sync::TransactionClient};
+
+/// Counts heap allocations, to compare the allocation cost of workloads.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const USAGE: &str = "usage: bench --pd <host:port>[,<host:port>...] [options]
+
+options:
+    --workload <put|batch-get|scan|scan-packed|commit>
+                                            workload to run (default: commit)
+    --threads <N>                           worker threads (default: 4)
+    --ops <N>                               operations per thread (default: 1000)
+    --value-size <V>                        value size in bytes (default: 64)
+    --batch-keys <K>                        keys per batch_get (default: 16)
+    --scan-limit <L>                        limit per scan (default: 100)
+    --key-prefix <P>                        prefix for generated keys (default: bench)
+    --log-dir <DIR>                         directory for the client log (default: .)
+    --timeout <SECS>                        grpc timeout in seconds (default: 3)";
+
+#[derive(Clone, Copy)]
+enum Workload {
+    Put,
+    BatchGet,
+    Scan,
+    /// A scan whose result is packed as `*_scan_packed` returns it.
+    ScanPacked,
+    Commit,
+}
+
+struct Options {
+    pd_endpoints: Vec<String>,
+    workload: Workload,
+    threads: usize,
+    ops: usize,
+    value_size: usize,
+    batch_keys: usize,
+    scan_limit: u32,
+    key_prefix: String,
+    log_dir: String,
+    timeout: u32,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options> {
+        let mut options = Options {
+            pd_endpoints: Vec::new(),
+            workload: Workload::Commit,
+            threads: 4,
+            ops: 1000,
+            value_size: 64,
+            batch_keys: 16,
+            scan_limit: 100,
+            key_prefix: "bench".to_owned(),
+            log_dir: ".".to_owned(),
+            timeout: 3,
+        };
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow!("missing value for {}", flag))
+            };
+            match flag.as_str() {
+                "--pd" => options
+                    .pd_endpoints
+                    .extend(value()?.split(',').map(ToOwned::to_owned)),
+                "--workload" => {
+                    options.workload = match value()?.as_str() {
+                        "put" => Workload::Put,
+                        "batch-get" => Workload::BatchGet,
+                        "scan" => Workload::Scan,
+                        "scan-packed" => Workload::ScanPacked,
+                        "commit" => Workload::Commit,
+                        other => bail!("unknown workload {}", other),
+                    }
+                }
+                "--threads" => options.threads = value()?.parse()?,
+                "--ops" => options.ops = value()?.parse()?,
+                "--value-size" => options.value_size = value()?.parse()?,
+                "--batch-keys" => options.batch_keys = value()?.parse()?,
+                "--scan-limit" => options.scan_limit = value()?.parse()?,
+                "--key-prefix" => options.key_prefix = value()?,
+                "--log-dir" => options.log_dir = value()?,
+                "--timeout" => options.timeout = value()?.parse()?,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                other => bail!("unknown flag {}", other),
+            }
+        }
+        if options.pd_endpoints.is_empty() {
+            bail!("at least one --pd endpoint is required");
+        }
+        if options.threads == 0 || options.ops == 0 {
+            bail!("--threads and --ops must be positive");
+        }
+        Ok(options)
+    }
+
+    fn key(&self, thread: usize, index: usize) -> Vec<u8> {
+        format!("{}/{:04}/{:010}", self.key_prefix, thread, index).into_bytes()
+    }
+}
+
+/// Writes the keys that the read workloads will touch, one transaction per
+/// thread range.
+fn prepare(client: &TransactionClient, options: &Options) -> Result<()> {
+    let value = vec![b'v'; options.value_size];
+    for thread in 0..options.threads {
+        let mut txn = client.begin_optimistic()?;
+        for index in 0..options.ops {
+            txn.put(&options.key(thread, index), &value)?;
+        }
+        txn.commit()?;
+    }
+    Ok(())
+}
+
+fn run_thread(
+    client: &TransactionClient,
+    options: &Options,
+    thread: usize,
+) -> Result<Vec<Duration>> {
+    let mut latencies = Vec::with_capacity(options.ops);
+    let value = vec![b'v'; options.value_size];
+    match options.workload {
+        Workload::Put => {
+            let mut txn = client.begin_optimistic()?;
+            for index in 0..options.ops {
+                let key = options.key(thread, index);
+                let start = Instant::now();
+                txn.put(&key, &value)?;
+                latencies.push(start.elapsed());
+            }
+            txn.rollback()?;
+        }
+        Workload::BatchGet => {
+            let mut txn = client.begin_optimistic()?;
+            for index in 0..options.ops {
+                let keys = (0..options.batch_keys)
+                    .map(|i| options.key(thread, (index + i) % options.ops))
+                    .collect::<Vec<_>>();
+                let start = Instant::now();
+                txn.batch_get(keys.iter().map(Vec::as_slice))?;
+                latencies.push(start.elapsed());
+            }
+            txn.rollback()?;
+        }
+        Workload::Scan => {
+            let mut txn = client.begin_optimistic()?;
+            for index in 0..options.ops {
+                let key = options.key(thread, index);
+                let start = Instant::now();
+                txn.scan(
+                    Bound::Included(key.as_slice()),
+                    Bound::Unbounded,
+                    options.scan_limit,
+                )?;
+                latencies.push(start.elapsed());
+            }
+            txn.rollback()?;
+        }
+        Workload::ScanPacked => {
+            let mut txn = client.begin_optimistic()?;
+            for index in 0..options.ops {
+                let key = options.key(thread, index);
+                let start = Instant::now();
+                let kv_pairs = txn.scan(
+                    Bound::Included(key.as_slice()),
+                    Bound::Unbounded,
+                    options.scan_limit,
+                )?;
+                packed::pack(kv_pairs);
+                latencies.push(start.elapsed());
+            }
+            txn.rollback()?;
+        }
+        Workload::Commit => {
+            for index in 0..options.ops {
+                let key = options.key(thread, index);
+                let start = Instant::now();
+                let mut txn = client.begin_optimistic()?;
+                txn.put(&key, &value)?;
+                txn.commit()?;
+                latencies.push(start.elapsed());
+            }
+        }
+    }
+    Ok(latencies)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((sorted.len() as f64) * p).ceil() as usize;
+    sorted[rank.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+fn main() -> Result<()> {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => Arc::new(options),
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let client = Arc::new(TransactionClient::new(
+        options.pd_endpoints.clone(),
+        &options.log_dir,
+        Duration::from_secs(options.timeout as u64),
+    )?);
+
+    if matches!(
+        options.workload,
+        Workload::BatchGet | Workload::Scan | Workload::ScanPacked
+    ) {
+        prepare(&client, &options)?;
+    }
+
+    let barrier = Arc::new(Barrier::new(options.threads + 1));
+    let handles = (0..options.threads)
+        .map(|thread| {
+            let client = client.clone();
+            let options = options.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                run_thread(&client, &options, thread)
+            })
+        })
+        .collect::<Vec<_>>();
+    barrier.wait();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut latencies = Vec::with_capacity(options.threads * options.ops);
+    for handle in handles {
+        let thread_latencies = handle
+            .join()
+            .map_err(|_| anyhow!("bench thread panicked"))??;
+        latencies.extend(thread_latencies);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    latencies.sort_unstable();
+
+    println!(
+        "ops: {}, elapsed: {:?}, throughput: {:.1} ops/s",
+        latencies.len(),
+        elapsed,
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "latency p50: {:?}, p90: {:?}, p99: {:?}, p999: {:?}, max: {:?}",
+        percentile(&latencies, 0.50),
+        percentile(&latencies, 0.90),
+        percentile(&latencies, 0.99),
+        percentile(&latencies, 0.999),
+        latencies[latencies.len() - 1]
+    );
+    println!(
+        "allocations per op: {:.1}",
+        allocations as f64 / latencies.len() as f64
+    );
+    Ok(())
+}
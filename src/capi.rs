@@ -0,0 +1,602 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Flat C ABI over [`sync`](crate::sync), for callers which can't use the
+//! cxx-generated C++ headers. Declared in `include/tikv_client_c.h`.
+//!
+//! Every function returns one of the `TIKV_*` codes. When it is not
+//! `TIKV_OK`, a description of the error is available from
+//! `tikv_last_error` on the same thread. Buffers handed out by this module
+//! must be released with the matching `*_free` function.
+//!
+//! All pointer arguments must be NULL or valid for the duration of the call;
+//! key and value pointers must be readable for the given length.
+
+#![allow(clippy::missing_safety_doc, clippy::too_many_arguments)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::ops::Bound;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+use std::{ptr, slice};
+
+use anyhow::{bail, Result};
+
+use crate::sync::{Snapshot, Transaction, TransactionClient};
+
+pub const TIKV_OK: c_int = 0;
+pub const TIKV_ERR_INVALID_ARGUMENT: c_int = 1;
+pub const TIKV_ERR_CLIENT: c_int = 2;
+pub const TIKV_ERR_PANIC: c_int = 3;
+
+pub const TIKV_BOUND_INCLUDED: c_int = 0;
+pub const TIKV_BOUND_EXCLUDED: c_int = 1;
+pub const TIKV_BOUND_UNBOUNDED: c_int = 2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// A byte buffer owned by this library.
+#[repr(C)]
+pub struct TikvBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+#[repr(C)]
+pub struct TikvKvPair {
+    pub key: TikvBuffer,
+    pub value: TikvBuffer,
+}
+
+/// An array of pairs owned by this library.
+#[repr(C)]
+pub struct TikvKvPairs {
+    pub pairs: *mut TikvKvPair,
+    pub len: usize,
+}
+
+impl TikvBuffer {
+    fn empty() -> Self {
+        TikvBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    fn from_vec(value: Vec<u8>) -> Self {
+        let len = value.len();
+        let data = Box::into_raw(value.into_boxed_slice()) as *mut u8;
+        TikvBuffer { data, len }
+    }
+
+    unsafe fn release(&mut self) {
+        if !self.data.is_null() {
+            drop(Box::from_raw(slice::from_raw_parts_mut(
+                self.data, self.len,
+            )));
+        }
+        *self = TikvBuffer::empty();
+    }
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', "\\0")).expect("nul bytes are escaped");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning errors and panics into error codes so that neither
+/// crosses the C boundary.
+fn ffi_call(f: impl FnOnce() -> Result<()>) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => TIKV_OK,
+        Ok(Err(e)) => {
+            let code = if e.is::<InvalidArgument>() {
+                TIKV_ERR_INVALID_ARGUMENT
+            } else {
+                TIKV_ERR_CLIENT
+            };
+            set_last_error(format!("{:#}", e));
+            code
+        }
+        Err(_) => {
+            set_last_error("panic in tikv client".to_owned());
+            TIKV_ERR_PANIC
+        }
+    }
+}
+
+#[derive(Debug)]
+struct InvalidArgument(&'static str);
+
+impl std::fmt::Display for InvalidArgument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid argument: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidArgument {}
+
+unsafe fn as_ref<'a, T>(ptr: *const T, name: &'static str) -> Result<&'a T> {
+    ptr.as_ref().ok_or_else(|| InvalidArgument(name).into())
+}
+
+unsafe fn as_mut<'a, T>(ptr: *mut T, name: &'static str) -> Result<&'a mut T> {
+    ptr.as_mut().ok_or_else(|| InvalidArgument(name).into())
+}
+
+unsafe fn as_bytes<'a>(data: *const u8, len: usize, name: &'static str) -> Result<&'a [u8]> {
+    if len == 0 {
+        Ok(&[])
+    } else if data.is_null() {
+        Err(InvalidArgument(name).into())
+    } else {
+        Ok(slice::from_raw_parts(data, len))
+    }
+}
+
+unsafe fn as_keys<'a>(
+    keys: *const *const u8,
+    key_lens: *const usize,
+    count: usize,
+) -> Result<Vec<&'a [u8]>> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    if keys.is_null() || key_lens.is_null() {
+        bail!(InvalidArgument("keys"));
+    }
+    (0..count)
+        .map(|i| as_bytes(*keys.add(i), *key_lens.add(i), "keys"))
+        .collect()
+}
+
+unsafe fn as_str<'a>(s: *const c_char, name: &'static str) -> Result<&'a str> {
+    if s.is_null() {
+        bail!(InvalidArgument(name));
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+unsafe fn as_bound<'a>(
+    bound: c_int,
+    key: *const u8,
+    key_len: usize,
+    name: &'static str,
+) -> Result<Bound<&'a [u8]>> {
+    match bound {
+        TIKV_BOUND_INCLUDED => Ok(Bound::Included(as_bytes(key, key_len, name)?)),
+        TIKV_BOUND_EXCLUDED => Ok(Bound::Excluded(as_bytes(key, key_len, name)?)),
+        TIKV_BOUND_UNBOUNDED => Ok(Bound::Unbounded),
+        _ => Err(InvalidArgument("bound").into()),
+    }
+}
+
+unsafe fn write_value(
+    value: Option<Vec<u8>>,
+    out_value: *mut TikvBuffer,
+    out_found: *mut bool,
+) -> Result<()> {
+    let out_value = as_mut(out_value, "out_value")?;
+    let out_found = as_mut(out_found, "out_found")?;
+    *out_found = value.is_some();
+    *out_value = value.map_or_else(TikvBuffer::empty, TikvBuffer::from_vec);
+    Ok(())
+}
+
+unsafe fn write_pairs(pairs: Vec<tikv_client::KvPair>, out: *mut TikvKvPairs) -> Result<()> {
+    let out = as_mut(out, "out_pairs")?;
+    let pairs = pairs
+        .into_iter()
+        .map(|tikv_client::KvPair(key, value)| TikvKvPair {
+            key: TikvBuffer::from_vec(key.into()),
+            value: TikvBuffer::from_vec(value),
+        })
+        .collect::<Vec<_>>()
+        .into_boxed_slice();
+    out.len = pairs.len();
+    out.pairs = Box::into_raw(pairs) as *mut TikvKvPair;
+    Ok(())
+}
+
+/// Returns the message of the last error on this thread, or NULL. The
+/// pointer is valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn tikv_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// See [`sync::shutdown_runtime`](crate::sync::shutdown_runtime).
+#[no_mangle]
+pub extern "C" fn tikv_shutdown_runtime() {
+    crate::sync::shutdown_runtime();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_buffer_free(buffer: *mut TikvBuffer) {
+    if let Some(buffer) = buffer.as_mut() {
+        buffer.release();
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_kv_pairs_free(pairs: *mut TikvKvPairs) {
+    if let Some(pairs) = pairs.as_mut() {
+        if !pairs.pairs.is_null() {
+            let mut boxed = Box::from_raw(slice::from_raw_parts_mut(pairs.pairs, pairs.len));
+            for pair in boxed.iter_mut() {
+                pair.key.release();
+                pair.value.release();
+            }
+        }
+        pairs.pairs = ptr::null_mut();
+        pairs.len = 0;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_client_new(
+    pd_endpoints: *const *const c_char,
+    pd_endpoints_len: usize,
+    log_dir: *const c_char,
+    timeout_secs: u32,
+    out_client: *mut *mut TransactionClient,
+) -> c_int {
+    ffi_call(|| {
+        let out_client = as_mut(out_client, "out_client")?;
+        if pd_endpoints.is_null() && pd_endpoints_len > 0 {
+            bail!(InvalidArgument("pd_endpoints"));
+        }
+        let pd_endpoints = (0..pd_endpoints_len)
+            .map(|i| as_str(*pd_endpoints.add(i), "pd_endpoints").map(ToOwned::to_owned))
+            .collect::<Result<Vec<_>>>()?;
+        let client = TransactionClient::new(
+            pd_endpoints,
+            as_str(log_dir, "log_dir")?,
+            Duration::from_secs(timeout_secs as u64),
+        )?;
+        *out_client = Box::into_raw(Box::new(client));
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_client_free(client: *mut TransactionClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_client_current_timestamp(
+    client: *const TransactionClient,
+    out_version: *mut u64,
+) -> c_int {
+    ffi_call(|| {
+        *as_mut(out_version, "out_version")? = as_ref(client, "client")?.current_timestamp()?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_client_gc(
+    client: *const TransactionClient,
+    safepoint: u64,
+    out_done: *mut bool,
+) -> c_int {
+    ffi_call(|| {
+        *as_mut(out_done, "out_done")? = as_ref(client, "client")?.gc(safepoint)?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_begin(
+    client: *const TransactionClient,
+    pessimistic: bool,
+    out_transaction: *mut *mut Transaction,
+) -> c_int {
+    ffi_call(|| {
+        let out_transaction = as_mut(out_transaction, "out_transaction")?;
+        let client = as_ref(client, "client")?;
+        let transaction = if pessimistic {
+            client.begin_pessimistic()?
+        } else {
+            client.begin_optimistic()?
+        };
+        *out_transaction = Box::into_raw(Box::new(transaction));
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_free(transaction: *mut Transaction) {
+    if !transaction.is_null() {
+        drop(Box::from_raw(transaction));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_get(
+    transaction: *mut Transaction,
+    key: *const u8,
+    key_len: usize,
+    out_value: *mut TikvBuffer,
+    out_found: *mut bool,
+) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        let value = transaction.get(as_bytes(key, key_len, "key")?)?;
+        write_value(value, out_value, out_found)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_get_for_update(
+    transaction: *mut Transaction,
+    key: *const u8,
+    key_len: usize,
+    out_value: *mut TikvBuffer,
+    out_found: *mut bool,
+) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        let value = transaction.get_for_update(as_bytes(key, key_len, "key")?)?;
+        write_value(value, out_value, out_found)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_batch_get(
+    transaction: *mut Transaction,
+    keys: *const *const u8,
+    key_lens: *const usize,
+    count: usize,
+    out_pairs: *mut TikvKvPairs,
+) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        let pairs = transaction.batch_get(as_keys(keys, key_lens, count)?)?;
+        write_pairs(pairs, out_pairs)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_scan(
+    transaction: *mut Transaction,
+    start: *const u8,
+    start_len: usize,
+    start_bound: c_int,
+    end: *const u8,
+    end_len: usize,
+    end_bound: c_int,
+    limit: u32,
+    out_pairs: *mut TikvKvPairs,
+) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        let pairs = transaction.scan(
+            as_bound(start_bound, start, start_len, "start")?,
+            as_bound(end_bound, end, end_len, "end")?,
+            limit,
+        )?;
+        write_pairs(pairs, out_pairs)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_put(
+    transaction: *mut Transaction,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        transaction.put(
+            as_bytes(key, key_len, "key")?,
+            as_bytes(value, value_len, "value")?,
+        )
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_delete(
+    transaction: *mut Transaction,
+    key: *const u8,
+    key_len: usize,
+) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        transaction.delete(as_bytes(key, key_len, "key")?)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_commit(transaction: *mut Transaction) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        transaction.commit()?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_rollback(transaction: *mut Transaction) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        transaction.rollback()
+    })
+}
+
+/// Prewrites the primary key; an empty `primary_key` lets the client pick one.
+/// The chosen key is written to `out_primary_key`.
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_prewrite_primary(
+    transaction: *mut Transaction,
+    primary_key: *const u8,
+    primary_key_len: usize,
+    out_primary_key: *mut TikvBuffer,
+    out_start_ts: *mut u64,
+) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        let primary_key = as_bytes(primary_key, primary_key_len, "primary_key")?;
+        let out_primary_key = as_mut(out_primary_key, "out_primary_key")?;
+        let out_start_ts = as_mut(out_start_ts, "out_start_ts")?;
+        let primary_key = if primary_key.is_empty() {
+            None
+        } else {
+            Some(primary_key)
+        };
+        let (key, start_ts) = transaction.prewrite_primary(primary_key)?;
+        *out_primary_key = TikvBuffer::from_vec(key.into());
+        *out_start_ts = start_ts;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_prewrite_secondary(
+    transaction: *mut Transaction,
+    primary_key: *const u8,
+    primary_key_len: usize,
+    start_ts: u64,
+) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        transaction.prewrite_secondary(
+            as_bytes(primary_key, primary_key_len, "primary_key")?,
+            start_ts,
+            true,
+        )?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_commit_primary(
+    transaction: *mut Transaction,
+    out_commit_ts: *mut u64,
+) -> c_int {
+    ffi_call(|| {
+        let out_commit_ts = as_mut(out_commit_ts, "out_commit_ts")?;
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        *out_commit_ts = transaction.commit_primary()?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_transaction_commit_secondary(
+    transaction: *mut Transaction,
+    commit_ts: u64,
+) -> c_int {
+    ffi_call(|| {
+        let transaction = as_mut(transaction, "transaction")?;
+        let _busy = transaction.enter()?;
+        transaction.commit_secondary(commit_ts)
+    })
+}
+
+/// Creates a snapshot at `version`, or at the current timestamp when
+/// `version` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn tikv_snapshot_new(
+    client: *const TransactionClient,
+    version: u64,
+    out_snapshot: *mut *mut Snapshot,
+) -> c_int {
+    ffi_call(|| {
+        let out_snapshot = as_mut(out_snapshot, "out_snapshot")?;
+        let client = as_ref(client, "client")?;
+        let snapshot = if version == 0 {
+            client.snapshot()?
+        } else {
+            client.snapshot_at(version)?
+        };
+        *out_snapshot = Box::into_raw(Box::new(snapshot));
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_snapshot_free(snapshot: *mut Snapshot) {
+    if !snapshot.is_null() {
+        drop(Box::from_raw(snapshot));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_snapshot_get(
+    snapshot: *mut Snapshot,
+    key: *const u8,
+    key_len: usize,
+    out_value: *mut TikvBuffer,
+    out_found: *mut bool,
+) -> c_int {
+    ffi_call(|| {
+        let snapshot = as_mut(snapshot, "snapshot")?;
+        let _busy = snapshot.enter()?;
+        let value = snapshot.get(as_bytes(key, key_len, "key")?)?;
+        write_value(value, out_value, out_found)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_snapshot_batch_get(
+    snapshot: *mut Snapshot,
+    keys: *const *const u8,
+    key_lens: *const usize,
+    count: usize,
+    out_pairs: *mut TikvKvPairs,
+) -> c_int {
+    ffi_call(|| {
+        let snapshot = as_mut(snapshot, "snapshot")?;
+        let _busy = snapshot.enter()?;
+        let pairs = snapshot.batch_get(as_keys(keys, key_lens, count)?)?;
+        write_pairs(pairs, out_pairs)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tikv_snapshot_scan(
+    snapshot: *mut Snapshot,
+    start: *const u8,
+    start_len: usize,
+    start_bound: c_int,
+    end: *const u8,
+    end_len: usize,
+    end_bound: c_int,
+    limit: u32,
+    out_pairs: *mut TikvKvPairs,
+) -> c_int {
+    ffi_call(|| {
+        let snapshot = as_mut(snapshot, "snapshot")?;
+        let _busy = snapshot.enter()?;
+        let pairs = snapshot.scan(
+            as_bound(start_bound, start, start_len, "start")?,
+            as_bound(end_bound, end, end_len, "end")?,
+            limit,
+        )?;
+        write_pairs(pairs, out_pairs)
+    })
+}
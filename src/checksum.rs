@@ -0,0 +1,388 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Range checksums compatible with TiKV's coprocessor checksum: the XOR of
+//! the crc64 (ECMA-182, as computed by `crc64fast`) of every key followed by
+//! its value. XOR makes the result independent of the scan order.
+//!
+//! Also the canonical SHA-256 hash of a set of pairs, see [`hash_kv_pairs`],
+//! for nodes that must agree bit for bit on what they read, and the hashes
+//! of single values, see [`HashAlgorithm`].
+
+const CRC64_POLY: u64 = 0xC96C_5795_D787_0F42;
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+const fn crc64_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// An incremental crc64 digest.
+#[derive(Clone, Copy)]
+pub struct Crc64(u64);
+
+impl Default for Crc64 {
+    fn default() -> Self {
+        Crc64(!0)
+    }
+}
+
+impl Crc64 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = CRC64_TABLE[((self.0 ^ byte as u64) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn sum64(&self) -> u64 {
+        !self.0
+    }
+}
+
+/// The checksum of a set of key-value pairs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Checksum {
+    pub checksum: u64,
+    pub total_kvs: u64,
+    pub total_bytes: u64,
+}
+
+impl Checksum {
+    pub fn update(&mut self, key: &[u8], value: &[u8]) {
+        let mut digest = Crc64::new();
+        digest.write(key);
+        digest.write(value);
+        self.checksum ^= digest.sum64();
+        self.total_kvs += 1;
+        self.total_bytes += (key.len() + value.len()) as u64;
+    }
+}
+
+const SHA256_INIT: [u32; 8] = [
+    0x6a09_e667,
+    0xbb67_ae85,
+    0x3c6e_f372,
+    0xa54f_f53a,
+    0x510e_527f,
+    0x9b05_688c,
+    0x1f83_d9ab,
+    0x5be0_cd19,
+];
+
+const SHA256_ROUND_CONSTANTS: [u32; 64] = [
+    0x428a_2f98,
+    0x7137_4491,
+    0xb5c0_fbcf,
+    0xe9b5_dba5,
+    0x3956_c25b,
+    0x59f1_11f1,
+    0x923f_82a4,
+    0xab1c_5ed5,
+    0xd807_aa98,
+    0x1283_5b01,
+    0x2431_85be,
+    0x550c_7dc3,
+    0x72be_5d74,
+    0x80de_b1fe,
+    0x9bdc_06a7,
+    0xc19b_f174,
+    0xe49b_69c1,
+    0xefbe_4786,
+    0x0fc1_9dc6,
+    0x240c_a1cc,
+    0x2de9_2c6f,
+    0x4a74_84aa,
+    0x5cb0_a9dc,
+    0x76f9_88da,
+    0x983e_5152,
+    0xa831_c66d,
+    0xb003_27c8,
+    0xbf59_7fc7,
+    0xc6e0_0bf3,
+    0xd5a7_9147,
+    0x06ca_6351,
+    0x1429_2967,
+    0x27b7_0a85,
+    0x2e1b_2138,
+    0x4d2c_6dfc,
+    0x5338_0d13,
+    0x650a_7354,
+    0x766a_0abb,
+    0x81c2_c92e,
+    0x9272_2c85,
+    0xa2bf_e8a1,
+    0xa81a_664b,
+    0xc24b_8b70,
+    0xc76c_51a3,
+    0xd192_e819,
+    0xd699_0624,
+    0xf40e_3585,
+    0x106a_a070,
+    0x19a4_c116,
+    0x1e37_6c08,
+    0x2748_774c,
+    0x34b0_bcb5,
+    0x391c_0cb3,
+    0x4ed8_aa4a,
+    0x5b9c_ca4f,
+    0x682e_6ff3,
+    0x748f_82ee,
+    0x78a5_636f,
+    0x84c8_7814,
+    0x8cc7_0208,
+    0x90be_fffa,
+    0xa450_6ceb,
+    0xbef9_a3f7,
+    0xc671_78f2,
+];
+
+/// An incremental SHA-256 digest (FIPS 180-4).
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Sha256 {
+            state: SHA256_INIT,
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let len = data.len().min(64 - self.block_len);
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&data[..len]);
+            self.block_len += len;
+            data = &data[len..];
+            if self.block_len == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        self.write(&[0x80]);
+        while self.block_len != 56 {
+            self.write(&[0]);
+        }
+        self.write(&bit_len.to_be_bytes());
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(&self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_ROUND_CONSTANTS[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in self.state.iter_mut().zip(&[a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(*value);
+        }
+    }
+}
+
+/// The canonical hash of a set of key-value pairs, for nodes to compare
+/// what they read: the SHA-256 of, for every pair in ascending key order,
+/// the length of the key as a big-endian `u64`, the key, the length of the
+/// value as a big-endian `u64`, then the value. Pairs with equal keys keep
+/// their given order.
+///
+/// It does not depend on the order of `kv_pairs`, though the scans and
+/// batch gets of the crate already return theirs sorted by key.
+pub fn hash_kv_pairs<'a>(kv_pairs: impl IntoIterator<Item = (&'a [u8], &'a [u8])>) -> [u8; 32] {
+    let mut kv_pairs = kv_pairs.into_iter().collect::<Vec<_>>();
+    kv_pairs.sort_by(|a, b| a.0.cmp(b.0));
+    let mut digest = Sha256::new();
+    for (key, value) in kv_pairs {
+        digest.write(&(key.len() as u64).to_be_bytes());
+        digest.write(key);
+        digest.write(&(value.len() as u64).to_be_bytes());
+        digest.write(value);
+    }
+    digest.finish()
+}
+
+/// How a value is hashed for verification reads, e.g.
+/// [`Snapshot::batch_get_hashes`](crate::sync::Snapshot::batch_get_hashes).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HashAlgorithm {
+    /// The crc64 of the value, as for [`Checksum`], big-endian: 8 bytes.
+    Crc64 = 0,
+    /// The SHA-256 of the value: 32 bytes.
+    Sha256 = 1,
+}
+
+impl HashAlgorithm {
+    pub fn hash(self, value: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Crc64 => {
+                let mut digest = Crc64::new();
+                digest.write(value);
+                digest.sum64().to_be_bytes().to_vec()
+            }
+            HashAlgorithm::Sha256 => {
+                let mut digest = Sha256::new();
+                digest.write(value);
+                digest.finish().to_vec()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        let mut digest = Sha256::new();
+        digest.write(data);
+        hex(&digest.finish())
+    }
+
+    #[test]
+    fn sha256_matches_the_fips_vectors() {
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+
+        // Written in pieces that straddle the blocks.
+        let mut digest = Sha256::new();
+        for _ in 0..10_000 {
+            digest.write(&[b'a'; 100]);
+        }
+        assert_eq!(
+            hex(&digest.finish()),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn crc64_matches_the_check_value() {
+        let mut digest = Crc64::new();
+        digest.write(b"123456789");
+        assert_eq!(digest.sum64(), 0x995d_c9bb_df19_39fa);
+
+        let mut digest = Crc64::new();
+        digest.write(b"1234");
+        digest.write(b"56789");
+        assert_eq!(digest.sum64(), 0x995d_c9bb_df19_39fa);
+        assert_eq!(
+            HashAlgorithm::Crc64.hash(b"123456789"),
+            0x995d_c9bb_df19_39fa_u64.to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn checksums_do_not_depend_on_the_pair_order() {
+        let kv_pairs: [(&[u8], &[u8]); 4] = [(b"a", b"1"), (b"b", b""), (b"ab", b"2"), (b"", b"3")];
+        let expected = hash_kv_pairs(kv_pairs.iter().copied());
+        let mut checksum = Checksum::default();
+        for (key, value) in kv_pairs.iter() {
+            checksum.update(key, value);
+        }
+
+        for reversed in [false, true].iter() {
+            for rotation in 0..kv_pairs.len() {
+                let mut shuffled = kv_pairs;
+                shuffled.rotate_left(rotation);
+                if *reversed {
+                    shuffled.reverse();
+                }
+                assert_eq!(hash_kv_pairs(shuffled.iter().copied()), expected);
+                let mut shuffled_checksum = Checksum::default();
+                for (key, value) in shuffled.iter() {
+                    shuffled_checksum.update(key, value);
+                }
+                assert_eq!(shuffled_checksum, checksum);
+            }
+        }
+
+        // The lengths keep the pairs apart: moving a byte from a key to its
+        // value changes the hash.
+        assert_ne!(
+            hash_kv_pairs(vec![(&b"ab"[..], &b"c"[..])]),
+            hash_kv_pairs(vec![(&b"a"[..], &b"bc"[..])])
+        );
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Memcomparable encodings for composite key components, byte-compatible
+//! with TiKV's `codec::number` and `codec::bytes`: comparing the encoded
+//! bytes gives the same order as comparing the decoded values.
+
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+
+const SIGN_MASK: u64 = 0x8000_0000_0000_0000;
+const ENC_GROUP_SIZE: usize = 8;
+const ENC_MARKER: u8 = 0xFF;
+const ENC_PAD: u8 = 0x00;
+
+pub fn encode_u64_asc(value: u64) -> [u8; 8] {
+    value.to_be_bytes()
+}
+
+pub fn encode_u64_desc(value: u64) -> [u8; 8] {
+    (!value).to_be_bytes()
+}
+
+pub fn encode_i64_asc(value: i64) -> [u8; 8] {
+    encode_u64_asc(value as u64 ^ SIGN_MASK)
+}
+
+pub fn encode_i64_desc(value: i64) -> [u8; 8] {
+    encode_u64_desc(value as u64 ^ SIGN_MASK)
+}
+
+fn read_u64(data: &[u8]) -> Result<u64> {
+    if data.len() < 8 {
+        bail!("need 8 bytes to decode a number, got {}", data.len());
+    }
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&data[..8]);
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Decodes the number at the start of `data`; trailing bytes are ignored.
+pub fn decode_u64_asc(data: &[u8]) -> Result<u64> {
+    read_u64(data)
+}
+
+pub fn decode_u64_desc(data: &[u8]) -> Result<u64> {
+    Ok(!read_u64(data)?)
+}
+
+pub fn decode_i64_asc(data: &[u8]) -> Result<i64> {
+    Ok((decode_u64_asc(data)? ^ SIGN_MASK) as i64)
+}
+
+pub fn decode_i64_desc(data: &[u8]) -> Result<i64> {
+    Ok((decode_u64_desc(data)? ^ SIGN_MASK) as i64)
+}
+
+/// Encodes `data` in groups of 8 bytes, each followed by a marker telling
+/// how many of its bytes are padding; the last group is always padded, so
+/// an exact multiple of 8 gets an extra all-padding group.
+pub fn encode_bytes(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity((data.len() / ENC_GROUP_SIZE + 1) * (ENC_GROUP_SIZE + 1));
+    let mut index = 0;
+    loop {
+        let remain = data.len() - index;
+        if remain >= ENC_GROUP_SIZE {
+            encoded.extend_from_slice(&data[index..index + ENC_GROUP_SIZE]);
+            encoded.push(ENC_MARKER);
+            index += ENC_GROUP_SIZE;
+        } else {
+            let pad = ENC_GROUP_SIZE - remain;
+            encoded.extend_from_slice(&data[index..]);
+            encoded.resize(encoded.len() + pad, ENC_PAD);
+            encoded.push(ENC_MARKER - pad as u8);
+            return encoded;
+        }
+    }
+}
+
+/// Decodes the bytes at the start of `data`, returning them together with
+/// the number of encoded bytes consumed so that following components of a
+/// composite key can be decoded from the rest.
+pub fn decode_bytes(data: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let mut decoded = Vec::with_capacity(data.len() / (ENC_GROUP_SIZE + 1) * ENC_GROUP_SIZE);
+    let mut offset = 0;
+    loop {
+        let group = match data.get(offset..offset + ENC_GROUP_SIZE + 1) {
+            Some(group) => group,
+            None => bail!("insufficient bytes to decode at offset {}", offset),
+        };
+        offset += ENC_GROUP_SIZE + 1;
+        let marker = group[ENC_GROUP_SIZE];
+        let pad = (ENC_MARKER - marker) as usize;
+        if pad > ENC_GROUP_SIZE {
+            bail!(
+                "invalid marker byte {:#04x} at offset {}",
+                marker,
+                offset - 1
+            );
+        }
+        let real = ENC_GROUP_SIZE - pad;
+        decoded.extend_from_slice(&group[..real]);
+        if pad != 0 {
+            if group[real..ENC_GROUP_SIZE].iter().any(|&b| b != ENC_PAD) {
+                bail!("invalid padding in group ending at offset {}", offset);
+            }
+            return Ok((decoded, offset));
+        }
+    }
+}
+
+/// The bound on encoded keys matching `bound` on decoded ones: a key given
+/// is encoded with [`encode_bytes`], which keeps its order.
+///
+/// A composite key sorts after its encoded first component, so an included
+/// end bound leaves out the keys with more components after it; end the
+/// range at the next value, excluded, to take them in.
+pub fn encode_bound(bound: Bound<&[u8]>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(encode_bytes(key)),
+        Bound::Excluded(key) => Bound::Excluded(encode_bytes(key)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Decodes the bytes component at the start of `key`, followed by the rest
+/// of the key as stored, e.g. further components.
+pub fn decode_key(key: &[u8]) -> Result<Vec<u8>> {
+    let (mut decoded, consumed) = decode_bytes(key)?;
+    decoded.extend_from_slice(&key[consumed..]);
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const U64_EDGES: &[u64] = &[
+        0,
+        1,
+        0xff,
+        0x100,
+        SIGN_MASK - 1,
+        SIGN_MASK,
+        u64::MAX - 1,
+        u64::MAX,
+    ];
+    const I64_EDGES: &[i64] = &[
+        i64::MIN,
+        i64::MIN + 1,
+        -256,
+        -1,
+        0,
+        1,
+        255,
+        i64::MAX - 1,
+        i64::MAX,
+    ];
+
+    #[test]
+    fn numbers_round_trip_at_the_edges() {
+        for &value in U64_EDGES {
+            assert_eq!(decode_u64_asc(&encode_u64_asc(value)).unwrap(), value);
+            assert_eq!(decode_u64_desc(&encode_u64_desc(value)).unwrap(), value);
+        }
+        for &value in I64_EDGES {
+            assert_eq!(decode_i64_asc(&encode_i64_asc(value)).unwrap(), value);
+            assert_eq!(decode_i64_desc(&encode_i64_desc(value)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn numbers_keep_their_order_at_the_edges() {
+        for pair in U64_EDGES.windows(2) {
+            assert!(encode_u64_asc(pair[0]) < encode_u64_asc(pair[1]));
+            assert!(encode_u64_desc(pair[0]) > encode_u64_desc(pair[1]));
+        }
+        for pair in I64_EDGES.windows(2) {
+            assert!(encode_i64_asc(pair[0]) < encode_i64_asc(pair[1]));
+            assert!(encode_i64_desc(pair[0]) > encode_i64_desc(pair[1]));
+        }
+    }
+
+    #[test]
+    fn numbers_need_eight_bytes() {
+        assert!(decode_u64_asc(&[0; 7]).is_err());
+        assert!(decode_i64_desc(&[]).is_err());
+        // Trailing bytes are left to the next component.
+        assert_eq!(decode_u64_asc(&[0, 0, 0, 0, 0, 0, 0, 1, 9]).unwrap(), 1);
+    }
+
+    #[test]
+    fn bytes_pad_their_last_group() {
+        assert_eq!(encode_bytes(b""), [0, 0, 0, 0, 0, 0, 0, 0, 0xf7]);
+        assert_eq!(encode_bytes(b"abc"), *b"abc\0\0\0\0\0\xfa");
+        assert_eq!(encode_bytes(b"abcdefg"), *b"abcdefg\0\xfe");
+        // An exact multiple of 8 bytes gets a group of padding only.
+        assert_eq!(
+            encode_bytes(b"abcdefgh"),
+            *b"abcdefgh\xff\0\0\0\0\0\0\0\0\xf7"
+        );
+        assert_eq!(
+            encode_bytes(b"abcdefghabcdefgh"),
+            *b"abcdefgh\xffabcdefgh\xff\0\0\0\0\0\0\0\0\xf7"
+        );
+        assert_eq!(
+            encode_bytes(b"abcdefghi"),
+            *b"abcdefgh\xffi\0\0\0\0\0\0\0\xf8"
+        );
+        for len in 0..=33 {
+            let data = vec![0xab; len];
+            let encoded = encode_bytes(&data);
+            assert_eq!(encoded.len(), (len / 8 + 1) * 9);
+            assert_eq!(decode_bytes(&encoded).unwrap(), (data, encoded.len()));
+        }
+    }
+
+    #[test]
+    fn bytes_reject_bad_groups() {
+        // Short of a group.
+        assert!(decode_bytes(b"abcdefgh").is_err());
+        // A full group with no end.
+        assert!(decode_bytes(b"abcdefgh\xff").is_err());
+        // More than 8 bytes of padding.
+        assert!(decode_bytes(b"\0\0\0\0\0\0\0\0\xf6").is_err());
+        // Padding that is not zero.
+        assert!(decode_bytes(b"abc\0\0\0\0\x01\xfa").is_err());
+    }
+
+    #[test]
+    fn bounds_are_encoded_keys() {
+        assert_eq!(
+            encode_bound(Bound::Included(b"ab")),
+            Bound::Included(encode_bytes(b"ab"))
+        );
+        assert_eq!(
+            encode_bound(Bound::Excluded(b"")),
+            Bound::Excluded(encode_bytes(b""))
+        );
+        assert_eq!(encode_bound(Bound::Unbounded), Bound::Unbounded);
+    }
+
+    #[test]
+    fn keys_decode_their_first_component() {
+        let mut key = encode_bytes(b"abcdefgh");
+        key.extend_from_slice(&encode_u64_asc(7));
+        let mut decoded = b"abcdefgh".to_vec();
+        decoded.extend_from_slice(&encode_u64_asc(7));
+        assert_eq!(decode_key(&key).unwrap(), decoded);
+        assert_eq!(decode_key(&encode_bytes(b"")).unwrap(), b"");
+        assert!(decode_key(b"abc").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn u64_round_trips_in_order(a in any::<u64>(), b in any::<u64>()) {
+            prop_assert_eq!(decode_u64_asc(&encode_u64_asc(a)).unwrap(), a);
+            prop_assert_eq!(decode_u64_desc(&encode_u64_desc(a)).unwrap(), a);
+            prop_assert_eq!(a < b, encode_u64_asc(a) < encode_u64_asc(b));
+            prop_assert_eq!(a < b, encode_u64_desc(a) > encode_u64_desc(b));
+        }
+
+        #[test]
+        fn i64_round_trips_in_order(a in any::<i64>(), b in any::<i64>()) {
+            prop_assert_eq!(decode_i64_asc(&encode_i64_asc(a)).unwrap(), a);
+            prop_assert_eq!(decode_i64_desc(&encode_i64_desc(a)).unwrap(), a);
+            prop_assert_eq!(a < b, encode_i64_asc(a) < encode_i64_asc(b));
+            prop_assert_eq!(a < b, encode_i64_desc(a) > encode_i64_desc(b));
+        }
+
+        #[test]
+        fn bytes_round_trip_in_order(
+            a in prop::collection::vec(0u8..3, 0..20),
+            b in prop::collection::vec(0u8..3, 0..20),
+            rest in prop::collection::vec(any::<u8>(), 0..12),
+        ) {
+            let encoded = encode_bytes(&a);
+            prop_assert_eq!(decode_bytes(&encoded).unwrap(), (a.clone(), encoded.len()));
+            prop_assert_eq!(a < b, encoded < encode_bytes(&b));
+            // What follows the component is left in place.
+            let mut key = encoded.clone();
+            key.extend_from_slice(&rest);
+            prop_assert_eq!(decode_bytes(&key).unwrap(), (a.clone(), encoded.len()));
+            let mut decoded = a.clone();
+            decoded.extend_from_slice(&rest);
+            prop_assert_eq!(decode_key(&key).unwrap(), decoded);
+        }
+    }
+}
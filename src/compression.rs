@@ -0,0 +1,383 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Client-side value compression.
+//!
+//! A client with compression set stores every value it writes in an
+//! envelope: the 4-byte magic `\xffLZ4` and a flag byte, then with
+//! `FLAG_STORED` the value as is, and with `FLAG_LZ4` the length of the
+//! original value as a big-endian `u32` and the value as an LZ4 block. The
+//! value is only compressed when it is long enough and that saves space, but
+//! enveloping the others too means a value of its own starting with the
+//! magic is never mistaken for an envelope.
+//!
+//! Only the clients with compression set open envelopes on reads, every
+//! value carrying the magic, so compressed and plain values written by
+//! other clients can be mixed in one range; a value that only looks like an
+//! envelope, with another flag or not decompressing to exactly its stated
+//! length, is returned as is. Other clients read envelopes as stored, so
+//! that binary values starting with the magic are never rewritten for them.
+
+const ENVELOPE_MAGIC: &[u8; 4] = b"\xffLZ4";
+/// The flag of an envelope holding the value as is.
+const FLAG_STORED: u8 = 0;
+/// The flag of an envelope holding the value as an LZ4 block.
+const FLAG_LZ4: u8 = 1;
+/// The magic and the flag.
+const ENVELOPE_HEADER_LEN: usize = 5;
+/// The magic, the flag and the original length of an LZ4 envelope.
+const LZ4_HEADER_LEN: usize = 9;
+
+/// The codec a client compresses the values it writes with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+/// The stored form of `value`: `value` itself when the compression is off,
+/// otherwise its envelope, compressed when the value has at least `min_len`
+/// bytes and compressing it saves space.
+pub fn compress_value(compression: Compression, min_len: usize, value: &[u8]) -> Vec<u8> {
+    if compression == Compression::None {
+        return value.to_vec();
+    }
+    if value.len() >= min_len && value.len() <= u32::MAX as usize {
+        let block = lz4_compress(value);
+        if LZ4_HEADER_LEN + block.len() < ENVELOPE_HEADER_LEN + value.len() {
+            let mut envelope = Vec::with_capacity(LZ4_HEADER_LEN + block.len());
+            envelope.extend_from_slice(ENVELOPE_MAGIC);
+            envelope.push(FLAG_LZ4);
+            envelope.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            envelope.extend_from_slice(&block);
+            return envelope;
+        }
+    }
+    let mut envelope = Vec::with_capacity(ENVELOPE_HEADER_LEN + value.len());
+    envelope.extend_from_slice(ENVELOPE_MAGIC);
+    envelope.push(FLAG_STORED);
+    envelope.extend_from_slice(value);
+    envelope
+}
+
+/// The original value of a stored one, see [`compress_value`].
+pub fn decompress_value(mut value: Vec<u8>) -> Vec<u8> {
+    if value.len() < ENVELOPE_HEADER_LEN || !value.starts_with(ENVELOPE_MAGIC) {
+        return value;
+    }
+    match value[4] {
+        FLAG_STORED => {
+            value.drain(..ENVELOPE_HEADER_LEN);
+            value
+        }
+        FLAG_LZ4 if value.len() >= LZ4_HEADER_LEN => {
+            let mut len = [0; 4];
+            len.copy_from_slice(&value[ENVELOPE_HEADER_LEN..LZ4_HEADER_LEN]);
+            match lz4_decompress(&value[LZ4_HEADER_LEN..], u32::from_be_bytes(len) as usize) {
+                Some(original) => original,
+                None => value,
+            }
+        }
+        _ => value,
+    }
+}
+
+const LZ4_MIN_MATCH: usize = 4;
+/// A match must start at least this many bytes before the end of the input.
+const LZ4_MF_LIMIT: usize = 12;
+/// The input always ends with at least this many literals.
+const LZ4_LAST_LITERALS: usize = 5;
+const LZ4_MAX_OFFSET: usize = 0xFFFF;
+const LZ4_HASH_LOG: u32 = 16;
+/// How many times its length a block decompresses to at most: a length
+/// byte of 255 stands for 255 bytes of output.
+const LZ4_MAX_RATIO: usize = 255;
+/// How many times its length the output of a block is allocated for up
+/// front, whatever length it claims.
+const LZ4_PREALLOC_RATIO: usize = 4;
+
+/// Compresses `input` into an LZ4 block, with a greedy search for matches.
+pub fn lz4_compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    // Positions plus one of the last occurrence of each hashed 4-byte
+    // sequence, 0 for none.
+    let mut table = vec![0usize; 1 << LZ4_HASH_LOG];
+    let mut anchor = 0;
+    let mut pos = 0;
+    if input.len() > LZ4_MF_LIMIT {
+        let match_limit = input.len() - LZ4_MF_LIMIT;
+        let match_end_limit = input.len() - LZ4_LAST_LITERALS;
+        while pos < match_limit {
+            let sequence = read_u32(input, pos);
+            let hash = (sequence.wrapping_mul(2_654_435_761) >> (32 - LZ4_HASH_LOG)) as usize;
+            let candidate = table[hash];
+            table[hash] = pos + 1;
+            if candidate > 0 {
+                let candidate = candidate - 1;
+                if pos - candidate <= LZ4_MAX_OFFSET && read_u32(input, candidate) == sequence {
+                    let mut end = pos + LZ4_MIN_MATCH;
+                    while end < match_end_limit && input[end] == input[candidate + end - pos] {
+                        end += 1;
+                    }
+                    write_sequence(
+                        &mut out,
+                        &input[anchor..pos],
+                        Some((pos - candidate, end - pos)),
+                    );
+                    pos = end;
+                    anchor = pos;
+                    continue;
+                }
+            }
+            pos += 1;
+        }
+    }
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+/// Decompresses an LZ4 block into exactly `len` bytes, or `None` when it is
+/// malformed or of another length. `len` may come from untrusted data: a
+/// length the block cannot expand to fails at once, and the output grows
+/// with what is decompressed rather than being allocated for `len`.
+pub fn lz4_decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    if len > input.len().saturating_mul(LZ4_MAX_RATIO) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(len.min(input.len().saturating_mul(LZ4_PREALLOC_RATIO)));
+    let mut i = 0;
+    loop {
+        let token = *input.get(i)?;
+        i += 1;
+        let literals = read_length(input, &mut i, (token >> 4) as usize)?;
+        out.extend_from_slice(input.get(i..i.checked_add(literals)?)?);
+        i += literals;
+        if out.len() > len {
+            return None;
+        }
+        if i == input.len() {
+            break;
+        }
+        let offset = u16::from_le_bytes([*input.get(i)?, *input.get(i + 1)?]) as usize;
+        i += 2;
+        if offset == 0 || offset > out.len() {
+            return None;
+        }
+        let match_len = read_length(input, &mut i, (token & 0xF) as usize)? + LZ4_MIN_MATCH;
+        if out.len() + match_len > len {
+            return None;
+        }
+        let start = out.len() - offset;
+        for k in 0..match_len {
+            let byte = out[start + k];
+            out.push(byte);
+        }
+    }
+    if out.len() == len {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]])
+}
+
+/// Writes a sequence of `literals` followed by the match `(offset, len)`, or
+/// by nothing for the last sequence.
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - LZ4_MIN_MATCH);
+    let token = (literals.len().min(15) << 4) | match_len.min(15);
+    out.push(token as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_length(out, match_len - 15);
+        }
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// Reads the rest of a length whose 4 bits in the token were `len`.
+fn read_length(input: &[u8], i: &mut usize, mut len: usize) -> Option<usize> {
+    if len == 15 {
+        loop {
+            let byte = *input.get(*i)?;
+            *i += 1;
+            len = len.checked_add(byte as usize)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn round_trip(input: &[u8]) {
+        let block = lz4_compress(input);
+        assert_eq!(lz4_decompress(&block, input.len()).as_deref(), Some(input));
+        // Any other length fails.
+        assert_eq!(lz4_decompress(&block, input.len() + 1), None);
+        if !input.is_empty() {
+            assert_eq!(lz4_decompress(&block, input.len() - 1), None);
+        }
+    }
+
+    #[test]
+    fn blocks_round_trip() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"abcdefghijk");
+        round_trip(b"abcdabcdabcdabcdabcdabcdabcd");
+        // Runs long enough for literal and match lengths past 15 and 255.
+        round_trip(&[7; 100_000]);
+        let mixed = (0..70_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 1000) as u8)
+            .collect::<Vec<_>>();
+        round_trip(&mixed);
+        let literals = (0..300u32)
+            .map(|i| (i * 7919 % 256) as u8)
+            .collect::<Vec<_>>();
+        round_trip(&literals);
+    }
+
+    fn stored(value: &[u8]) -> Vec<u8> {
+        [&b"\xffLZ4\0"[..], value].concat()
+    }
+
+    #[test]
+    fn values_are_compressed_when_it_saves_space() {
+        let value = vec![b'x'; 1000];
+        let compressed = compress_value(Compression::Lz4, 0, &value);
+        assert!(compressed.starts_with(b"\xffLZ4\x01"));
+        assert!(compressed.len() < value.len());
+        assert_eq!(decompress_value(compressed), value);
+
+        assert_eq!(compress_value(Compression::None, 0, &value), value);
+        // Short and incompressible values are enveloped as they are.
+        assert_eq!(
+            compress_value(Compression::Lz4, 1001, &value),
+            stored(&value)
+        );
+        let random = (0..64u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            compress_value(Compression::Lz4, 0, &random),
+            stored(&random)
+        );
+        assert_eq!(compress_value(Compression::Lz4, 0, b""), stored(b""));
+        assert_eq!(decompress_value(stored(&random)), random);
+    }
+
+    #[test]
+    fn values_of_their_own_looking_like_envelopes_are_enveloped_too() {
+        let value = b"\xffLZ4\x01\0\0\0\x05\x50hello".to_vec();
+        let enveloped = compress_value(Compression::Lz4, 1000, &value);
+        assert_eq!(enveloped, stored(&value));
+        assert_eq!(decompress_value(enveloped), value);
+    }
+
+    #[test]
+    fn values_only_looking_like_envelopes_are_returned_as_stored() {
+        for value in &[
+            b"\xffLZ4".to_vec(),
+            b"\xffLZ4\x01".to_vec(),
+            b"\xffLZ4\x01\0\0\0\x05".to_vec(),
+            b"\xffLZ4\x01\0\0\0\x05hello".to_vec(),
+            b"\xffLZ4\x01\0\0\0\x05\x50hell".to_vec(),
+            // A length the block cannot expand to, which must not be
+            // allocated for.
+            b"\xffLZ4\x01\xff\xff\xff\xff\x0f\xff\xff\xff\xff".to_vec(),
+            // An unknown flag.
+            b"\xffLZ4\x02hello".to_vec(),
+        ] {
+            assert_eq!(decompress_value(value.clone()), *value);
+        }
+        assert_eq!(
+            decompress_value(b"\xffLZ4\x01\0\0\0\x05\x50hello".to_vec()),
+            b"hello"
+        );
+        assert_eq!(decompress_value(stored(b"hello")), b"hello");
+    }
+
+    #[test]
+    fn claimed_lengths_are_bounded_by_the_input() {
+        assert_eq!(lz4_decompress(&[0x10, b'a'], usize::MAX), None);
+        assert_eq!(lz4_decompress(&[], 1), None);
+        assert_eq!(lz4_decompress(&[], 0), None);
+        assert_eq!(lz4_decompress(&[0], 0), Some(Vec::new()));
+    }
+
+    proptest! {
+        #[test]
+        fn any_input_round_trips(input in prop::collection::vec(0u8..4, 0..2000)) {
+            let block = lz4_compress(&input);
+            prop_assert_eq!(lz4_decompress(&block, input.len()), Some(input));
+        }
+
+        #[test]
+        fn any_value_round_trips_through_its_envelope(
+            value in prop_oneof![
+                prop::collection::vec(any::<u8>(), 0..2000),
+                prop::collection::vec(0u8..4, 0..2000),
+                prop::collection::vec(any::<u8>(), 0..20)
+                    .prop_map(|tail| [&ENVELOPE_MAGIC[..], &tail].concat()),
+            ],
+            min_len in 0..3000usize,
+        ) {
+            let envelope = compress_value(Compression::Lz4, min_len, &value);
+            prop_assert!(envelope.starts_with(ENVELOPE_MAGIC));
+            prop_assert!(envelope.len() <= ENVELOPE_HEADER_LEN + value.len());
+            prop_assert_eq!(decompress_value(envelope), value);
+        }
+
+        #[test]
+        fn garbage_never_panics(
+            input in prop::collection::vec(any::<u8>(), 0..64),
+            len in 0..4096usize,
+        ) {
+            if let Some(out) = lz4_decompress(&input, len) {
+                prop_assert_eq!(out.len(), len);
+            }
+        }
+
+        #[test]
+        fn damaged_blocks_never_panic(
+            input in prop::collection::vec(0u8..4, 0..500),
+            cut in any::<usize>(),
+            flip in any::<u8>(),
+        ) {
+            let mut block = lz4_compress(&input);
+            let at = cut % (block.len() + 1);
+            if at < block.len() {
+                block[at] ^= flip;
+            }
+            let _ = lz4_decompress(&block, input.len());
+            let _ = lz4_decompress(&block[..at], input.len());
+        }
+    }
+}
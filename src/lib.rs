@@ -1,32 +1,36 @@
 // Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
 
-use core::panic;
-use std::{ops, path::PathBuf, time::Duration};
-
-use anyhow::Result;
-use cxx::{CxxString, CxxVector};
-// use futures::executor::TOKIO_RUNTIME.block_on;
-use chrono;
-use log::debug;
-use once_cell::sync::{Lazy, OnceCell};
-use slog::{o, Drain};
-use std::fs::OpenOptions;
-use std::sync::Once;
-use tikv_client::{request, Backoff, Config, Timestamp, TimestampExt, TransactionOptions};
-use tokio::runtime::Runtime;
-use tokio::time::Instant;
+//! C++ bindings for tikv-client.
+//!
+//! The functions in the `ffi` bridge are thin adapters over [`sync`], which
+//! holds the actual client logic and can be used directly from Rust.
+
+#[cfg(feature = "c-api")]
+pub mod capi;
+pub mod checksum;
+pub mod codec;
+pub mod compression;
+pub mod logger;
+pub mod packed;
+pub mod sync;
+
+use std::{
+    convert::TryFrom,
+    ops,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use cxx::{CxxString, CxxVector, UniquePtr};
+
+pub use self::sync::{
+    BulkWriter, FailoverClient, RoutedClient, RoutedSnapshot, RoutedTransaction, SafepointGuard,
+    Snapshot, Transaction, TransactionClient,
+};
 
 use self::ffi::*;
 
-static TOKIO_RUNTIME: Lazy<Runtime> = Lazy::new(|| {
-    tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .expect("Failed to create TOKIO_RUNTIME")
-});
-static START: Once = Once::new();
-const DEFAULT_CHAN_SIZE: usize = 4096;
-
 #[cxx::bridge]
 mod ffi {
     struct Key {
@@ -43,27 +47,437 @@ mod ffi {
         version: u64,
     }
 
+    /// What a chunked commit committed; `last_commit_ts` is 0 when there
+    /// was nothing to commit.
+    struct ChunkedCommitResult {
+        chunks: u32,
+        last_commit_ts: u64,
+    }
+
+    /// What a secondary prewrite sent; `region_count` is 0 when unknown,
+    /// which it always is with the pinned tikv-client.
+    struct SecondaryPrewriteResult {
+        mutation_count: u64,
+        region_count: u64,
+    }
+
+    /// A scan that skipped the values too large to be read.
+    struct SkippingScanResult {
+        pairs: Vec<KvPair>,
+        skipped: Vec<Key>,
+    }
+
     struct OptionalValue {
         is_none: bool,
         value: Vec<u8>,
     }
 
+    /// The hash of a value read by `snapshot_batch_get_hashes`.
+    struct OptionalHash {
+        is_none: bool,
+        hash: Vec<u8>,
+    }
+
+    /// A value read by `transaction_get_traced`: `source` is 0 when it came
+    /// from the buffered mutations and 1 when read from TiKV, and
+    /// `region_id` is 0 when unknown, which it always is with the pinned
+    /// tikv-client.
+    struct TracedValue {
+        is_none: bool,
+        value: Vec<u8>,
+        source: u8,
+        elapsed_us: u64,
+        region_id: u64,
+    }
+
+    /// A value read by `client_multi_version_get`; `error` is empty unless
+    /// the read at this version failed.
+    struct VersionedValue {
+        value: OptionalValue,
+        error: String,
+    }
+
+    struct KeySize {
+        key: Vec<u8>,
+        value_len: u64,
+    }
+
+    /// How a transaction was committed: `protocol` is 0 for two-phase
+    /// commit, 1 for async commit, 2 for 1PC and 3 for a read-only commit,
+    /// which sent nothing and has a `commit_ts` of 0.
+    struct CommitInfo {
+        protocol: u8,
+        commit_ts: u64,
+    }
+
+    /// Phase timings of a commit in milliseconds, all zero when none was
+    /// recorded.
+    struct CommitStats {
+        prewrite_ms: f64,
+        get_commit_ts_ms: f64,
+        commit_primary_ms: f64,
+        commit_secondary_ms: f64,
+        total_ms: f64,
+        mutation_count: u64,
+    }
+
+    /// What a client saw of a TiKV store. Only errors naming the store are
+    /// counted; `requests` and `p99_us` are always 0 with the pinned
+    /// tikv-client.
+    struct StoreMetric {
+        store_id: u64,
+        requests: u64,
+        errors: u64,
+        p99_us: u64,
+    }
+
+    /// The read and write totals of a client, see
+    /// `TransactionClient::metrics` on the Rust side.
+    struct MetricsSnapshot {
+        snapshot_read_keys: u64,
+        snapshot_read_bytes: u64,
+        transaction_read_keys: u64,
+        transaction_read_bytes: u64,
+        written_keys: u64,
+        written_bytes: u64,
+        not_leader_errors: u64,
+        epoch_not_match_errors: u64,
+        server_is_busy_errors: u64,
+        snapshot_read_retries: u64,
+        in_flight_operations: u64,
+        mirror_matches: u64,
+        mirror_mismatches: u64,
+        mirror_errors: u64,
+        mirror_dropped: u64,
+        smart_get_scanned_keys: u64,
+        smart_get_batched_keys: u64,
+        smart_get_extra_pairs: u64,
+        skipped_noop_writes: u64,
+        prewrites: u64,
+        failed_log_writes: u64,
+    }
+
+    struct BulkWriteProgress {
+        pending_keys: u64,
+        written_keys: u64,
+        failed_keys: u64,
+    }
+
+    /// A chunk of a bulk write that failed, with its pairs to write again.
+    struct BulkWriteFailure {
+        pairs: Vec<KvPair>,
+        error: String,
+    }
+
+    struct CheckItem {
+        name: String,
+        ok: bool,
+        detail: String,
+    }
+
+    struct PrefetchStats {
+        hits: u64,
+        misses: u64,
+        dropped: u64,
+    }
+
+    struct FinalizeResult {
+        already_committed: bool,
+    }
+
+    struct ClockSkewReport {
+        pd_physical_ms: i64,
+        local_ms: i64,
+        skew_ms: i64,
+        within_bound: bool,
+    }
+
+    /// An operation recorded by a transaction's operation log; `outcome` is
+    /// "ok" or the error message.
+    struct OpLogEntry {
+        op: String,
+        key_hex: String,
+        value_len: u64,
+        timestamp_ms: u64,
+        outcome: String,
+    }
+
+    /// A scan result; `truncated` tells whether the range goes on after
+    /// `last_key`, the key of the last pair, empty when there is none.
+    struct ScanResult {
+        pairs: Vec<KvPair>,
+        truncated: bool,
+        last_key: Vec<u8>,
+    }
+
+    /// A scan stopped by a deadline; `resume_key` is where to scan again
+    /// from, included, unless `completed`.
+    struct ScanUntilResult {
+        pairs: Vec<KvPair>,
+        completed: bool,
+        resume_key: Vec<u8>,
+    }
+
+    /// The snapshots of a client; `oldest_age_ms` is 0 when none is live.
+    struct SnapshotStats {
+        live: u64,
+        oldest_age_ms: u64,
+        created_total: u64,
+    }
+
+    /// The pairs of a byte-budgeted batch get, in the order of the keys;
+    /// `next_index` is the index of the first key not processed.
+    struct BatchGetLimitedResult {
+        pairs: Vec<KvPair>,
+        completed: bool,
+        next_index: u64,
+    }
+
+    /// When `failover_pair_new` switches to the standby; a threshold of 0
+    /// never trips.
+    struct FailoverPolicy {
+        max_consecutive_failures: u32,
+        max_error_percent: u32,
+        window: u32,
+    }
+
+    /// Where a failover pair stands; `target` is a `FailoverTarget` of the
+    /// C++ side, `last_reason` empty before any switch.
+    struct FailoverStatus {
+        target: u8,
+        switches: u64,
+        consecutive_failures: u32,
+        window_operations: u32,
+        window_failures: u32,
+        last_reason: Vec<u8>,
+    }
+
+    /// How far a chunked operation got; `first_error` is empty when it
+    /// completed, else it resumes from `resume_token`, included.
+    struct PartialOutcome {
+        succeeded_items: u64,
+        failed_items: u64,
+        first_error: Vec<u8>,
+        resume_token: Vec<u8>,
+    }
+
+    struct PackedScanResult {
+        data: Vec<u8>,
+        offsets: Vec<u64>,
+        count: u64,
+    }
+
+    struct ScanPair {
+        a: Vec<KvPair>,
+        b: Vec<KvPair>,
+    }
+
+    struct GetIntoResult {
+        found: bool,
+        length: u64,
+        truncated: bool,
+    }
+
+    struct OptionalLength {
+        is_none: bool,
+        length: u64,
+    }
+
+    struct DecodedBytes {
+        value: Vec<u8>,
+        consumed: u64,
+    }
+
+    struct RmwDecision {
+        abort: bool,
+        value: Vec<u8>,
+    }
+
+    struct ChecksumResult {
+        checksum: u64,
+        total_kvs: u64,
+        total_bytes: u64,
+    }
+
+    enum WriteType {
+        Put,
+        Delete,
+    }
+
+    enum DiffChange {
+        Added,
+        Removed,
+        Modified,
+    }
+
+    struct DiffEntry {
+        key: Vec<u8>,
+        change: DiffChange,
+        new_value_len: u64,
+    }
+
+    struct WrittenKey {
+        key: Vec<u8>,
+        write_type: WriteType,
+    }
+
+    /// A key contended by a transaction, see `transaction_conflict_report`:
+    /// `kind` is 0 for a write conflict, 1 for a lock and 2 for a deadlock,
+    /// and `other_start_ts` is 0 when not reported.
+    struct ConflictEntry {
+        key: Vec<u8>,
+        kind: u8,
+        other_start_ts: u64,
+    }
+
+    struct MvccVersion {
+        commit_ts: u64,
+        write_type: WriteType,
+        value: Vec<u8>,
+    }
+
+    enum PrimarySelection {
+        FirstWritten,
+        SmallestKey,
+        LargestKey,
+        Explicit,
+    }
+
+    enum IsolationLevel {
+        SnapshotIsolation,
+        ReadCommitted,
+    }
+
+    struct PessimisticOptions {
+        isolation: IsolationLevel,
+        pipelined_locking: bool,
+    }
+
+    /// The options of the transactions begun by a client by default. A
+    /// `lock_backoff_max_attempts` of 0 keeps the lock backoff of
+    /// tikv-client.
+    struct TxnOptions {
+        lock_backoff_base_ms: u64,
+        lock_backoff_max_ms: u64,
+        lock_backoff_max_attempts: u32,
+        drop_check: DropCheck,
+        /// Reads fail on the first lock they meet, whatever the lock
+        /// backoff.
+        fail_fast_on_locks: bool,
+    }
+
+    /// The level reported to a `PressureCallback`.
+    enum PressureLevel {
+        Normal,
+        High,
+    }
+
+    /// The bits of the `ops_mask` of `client_set_mirror`, one per kind of
+    /// snapshot read replayed against the mirror.
+    enum MirrorOp {
+        Get = 1,
+        BatchGet = 2,
+        Scan = 4,
+    }
+
+    /// What dropping an active transaction does; `Warn`, the default, comes
+    /// first so that a zero-initialized `TxnOptions` gets it.
+    enum DropCheck {
+        Warn,
+        Panic,
+        Ignore,
+    }
+
+    /// When `client_set_audit_log` syncs the audit file to disk.
+    enum AuditFsync {
+        Never,
+        EveryCommit,
+    }
+
+    /// What `client_set_oversized_values` makes puts of values over the
+    /// value size limit do.
+    enum OversizedValues {
+        Reject,
+        Chunk,
+    }
+
+    /// What `client_set_strict_mode` makes committing a transaction that
+    /// buffered nothing do.
+    enum EmptyCommits {
+        Allow,
+        Warn,
+        Reject,
+    }
+
+    /// The codec of `client_set_compression`.
+    enum Compression {
+        None,
+        Lz4,
+    }
+
+    enum LogFailureMode {
+        Drop,
+        Stderr,
+    }
+
+    enum Priority {
+        Normal,
+        Low,
+        High,
+    }
+
     enum Bound {
         Included,
         Excluded,
         Unbounded,
     }
 
+    #[namespace = "tikv_client_glue"]
+    unsafe extern "C++" {
+        include!("tikv_client_callbacks.h");
+
+        type RmwCallback;
+
+        fn modify(self: &RmwCallback, current: &OptionalValue) -> Result<RmwDecision>;
+
+        type TransactionCallback;
+
+        fn run(self: &TransactionCallback, transaction: &mut Transaction) -> Result<bool>;
+
+        type PressureCallback;
+
+        fn on_pressure(self: &PressureCallback, level: PressureLevel) -> Result<()>;
+
+        type ReadTsProvider;
+
+        fn read_ts(self: &ReadTsProvider) -> Result<u64>;
+
+        type FailoverConfirmation;
+
+        fn confirm_failover(
+            self: &FailoverConfirmation,
+            consecutive_failures: u32,
+            error_percent: u32,
+        ) -> Result<bool>;
+    }
+
     #[namespace = "tikv_client_glue"]
     extern "Rust" {
         type TransactionClient;
         type Transaction;
         type Snapshot;
+        type SafepointGuard;
+        type BulkWriter;
+        type RoutedClient;
+        type RoutedTransaction;
+        type RoutedSnapshot;
+        type FailoverClient;
 
         fn transaction_client_new(
             pd_endpoints: &CxxVector<CxxString>,
             logPath: &CxxString,
             timeout: u32,
+            max_live_snapshots: u32,
         ) -> Result<Box<TransactionClient>>;
 
         fn transaction_client_new_with_config(
@@ -73,527 +487,2792 @@ mod ffi {
             cert_path: &CxxString,
             key_path: &CxxString,
             timeout: u32,
+            priority: Priority,
+            max_live_snapshots: u32,
+        ) -> Result<Box<TransactionClient>>;
+
+        fn transaction_client_with_prefix(
+            client: &TransactionClient,
+            prefix: &CxxString,
+        ) -> Box<TransactionClient>;
+
+        fn transaction_client_new_in_keyspace(
+            pd_endpoints: &CxxVector<CxxString>,
+            log_path: &CxxString,
+            keyspace: &CxxString,
+            timeout: u32,
+            max_live_snapshots: u32,
         ) -> Result<Box<TransactionClient>>;
 
         fn transaction_client_begin(client: &TransactionClient) -> Result<Box<Transaction>>;
         fn client_gc(client: &TransactionClient, safeTimpoint: u64) -> Result<bool>;
+        fn client_current_pd(client: &TransactionClient) -> Result<Vec<u8>>;
+        fn client_store_metrics(client: &TransactionClient) -> Vec<StoreMetric>;
+        fn client_bulk_write(
+            client: &TransactionClient,
+            keys_per_txn: u32,
+            concurrency: u32,
+        ) -> Result<Box<BulkWriter>>;
+        fn bulk_write_push(
+            writer: &mut BulkWriter,
+            keys: &CxxVector<CxxString>,
+            values: &CxxVector<CxxString>,
+        ) -> Result<()>;
+        fn bulk_write_progress(writer: &BulkWriter) -> BulkWriteProgress;
+        fn bulk_write_finish(writer: &mut BulkWriter) -> Vec<BulkWriteFailure>;
+        fn client_metrics(client: &TransactionClient) -> MetricsSnapshot;
+        fn client_reset_metrics(client: &TransactionClient);
+        fn client_set_log_failure_mode(client: &TransactionClient, mode: LogFailureMode);
+        fn client_set_region_error_warn_threshold(client: &TransactionClient, per_minute: u64);
+        fn client_self_check(
+            client: &TransactionClient,
+            timeout_ms: u64,
+            write_probe: bool,
+        ) -> Vec<CheckItem>;
+        fn client_pd_leader_changes(client: &TransactionClient) -> Result<u64>;
+        fn client_set_default_txn_options(client: &TransactionClient, options: &TxnOptions);
+        fn client_get_default_txn_options(client: &TransactionClient) -> TxnOptions;
+        fn client_set_allow_empty_keys(client: &mut TransactionClient, allow: bool);
+        fn client_set_guard_reserved(client: &mut TransactionClient, guard: bool);
+        fn client_set_strict_versions(client: &mut TransactionClient, within_years: u32);
+        fn client_set_timestamp_retry(
+            client: &mut TransactionClient,
+            max_attempts: u32,
+            budget_ms: u64,
+        ) -> Result<()>;
+        fn client_set_snapshot_read_retry(
+            client: &mut TransactionClient,
+            max_attempts: u32,
+            base_delay_ms: u64,
+            max_delay_ms: u64,
+        );
+        fn client_set_pressure_callback(
+            client: &mut TransactionClient,
+            callback: UniquePtr<PressureCallback>,
+            high_watermark: u64,
+            low_watermark: u64,
+        ) -> Result<()>;
+        fn client_set_mirror(
+            client: &mut TransactionClient,
+            mirror_client: &TransactionClient,
+            sample_rate_percent: u32,
+            ops_mask: u32,
+        ) -> Result<()>;
+        fn client_clear_mirror(client: &mut TransactionClient);
+        fn client_set_compression(
+            client: &mut TransactionClient,
+            compression: Compression,
+            min_value_bytes: u64,
+        ) -> Result<()>;
+        fn client_set_audit_log(
+            client: &mut TransactionClient,
+            path: &CxxString,
+            client_tag: &CxxString,
+            max_file_bytes: u64,
+            max_rotated_files: u32,
+            fsync: AuditFsync,
+        ) -> Result<()>;
+        fn client_clear_audit_log(client: &mut TransactionClient) -> Result<()>;
+        fn client_set_strict_mode(
+            client: &mut TransactionClient,
+            max_active_transactions: u32,
+            empty_commits: EmptyCommits,
+            report_age_ms: u64,
+        ) -> Result<()>;
+        fn client_clear_strict_mode(client: &mut TransactionClient) -> Result<()>;
+        fn client_active_transaction_count(client: &TransactionClient) -> u64;
+        fn client_snapshot_stats(client: &TransactionClient) -> SnapshotStats;
+        fn client_set_size_limits(
+            client: &mut TransactionClient,
+            max_key_bytes: u64,
+            max_value_bytes: u64,
+        );
+        fn client_set_oversized_values(client: &mut TransactionClient, policy: OversizedValues);
+        fn client_check_clock_skew(
+            client: &TransactionClient,
+            max_skew_ms: u64,
+        ) -> Result<ClockSkewReport>;
+        fn client_finalize_commit(
+            client: &TransactionClient,
+            primary_key: &CxxString,
+            start_ts: u64,
+            commit_ts: u64,
+        ) -> Result<FinalizeResult>;
+        fn client_register_service_safepoint(
+            client: &TransactionClient,
+            service_id: &CxxString,
+            safepoint_ts: u64,
+            ttl_secs: u64,
+        ) -> Result<Box<SafepointGuard>>;
+        fn safepoint_guard_healthy(guard: &SafepointGuard) -> bool;
+        fn safepoint_guard_release(guard: &mut SafepointGuard) -> Result<()>;
+        fn client_rmw(
+            client: &TransactionClient,
+            key: &CxxString,
+            callback: &RmwCallback,
+            max_retries: u32,
+        ) -> Result<Vec<u8>>;
+        fn client_run_optimistic(
+            client: &TransactionClient,
+            callback: &TransactionCallback,
+            max_attempts: u32,
+            backoff_base_ms: u64,
+            backoff_max_ms: u64,
+        ) -> Result<u64>;
+        fn client_delete_range_chunked(
+            client: &TransactionClient,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            keys_per_txn: u32,
+            pessimistic: bool,
+        ) -> Result<u64>;
+        fn client_delete_range_partial(
+            client: &TransactionClient,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            keys_per_txn: u32,
+            pessimistic: bool,
+        ) -> PartialOutcome;
+        fn client_copy_range(
+            client: &TransactionClient,
+            source: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            keys_per_txn: u32,
+            concurrency: u32,
+        ) -> Result<u64>;
+        fn client_copy_range_partial(
+            client: &TransactionClient,
+            source: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            keys_per_txn: u32,
+            concurrency: u32,
+        ) -> PartialOutcome;
+        fn client_range_diff(
+            client: &TransactionClient,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            old_version: u64,
+            new_version: u64,
+            limit: u64,
+        ) -> Result<Vec<DiffEntry>>;
+        fn client_get_mvcc_versions(
+            client: &TransactionClient,
+            key: &CxxString,
+            max_versions: u32,
+        ) -> Result<Vec<MvccVersion>>;
+        fn client_presplit_for_keys(
+            client: &TransactionClient,
+            sample_keys: &CxxVector<CxxString>,
+            scatter: bool,
+        ) -> Result<u32>;
+        fn client_multi_version_get(
+            client: &TransactionClient,
+            key: &CxxString,
+            timestamps: &CxxVector<u64>,
+        ) -> Vec<VersionedValue>;
         fn transaction_client_begin_optimistic_with_option(
             client: &TransactionClient,
             retry: u32,
         ) -> Result<Box<Transaction>>;
 
+        fn transaction_client_begin_pessimistic_with_backoff(
+            client: &TransactionClient,
+            base_ms: u64,
+            max_ms: u64,
+            attempts: u32,
+        ) -> Result<Box<Transaction>>;
+        fn transaction_client_begin_read_only(
+            client: &TransactionClient,
+        ) -> Result<Box<Transaction>>;
         fn transaction_client_begin_pessimistic(
             client: &TransactionClient,
         ) -> Result<Box<Transaction>>;
 
+        fn transaction_client_begin_pessimistic_with_isolation(
+            client: &TransactionClient,
+            isolation: IsolationLevel,
+        ) -> Result<Box<Transaction>>;
+
+        fn transaction_client_begin_pessimistic_with_options(
+            client: &TransactionClient,
+            options: &PessimisticOptions,
+        ) -> Result<Box<Transaction>>;
+
         fn transaction_get(transaction: &mut Transaction, key: &CxxString)
             -> Result<OptionalValue>;
 
+        fn transaction_get_traced(
+            transaction: &mut Transaction,
+            key: &CxxString,
+        ) -> Result<TracedValue>;
+        fn transaction_start_version(transaction: &Transaction) -> u64;
+        fn transaction_fork_snapshot(
+            transaction: &mut Transaction,
+            client: &TransactionClient,
+            include_buffered: bool,
+        ) -> Result<Box<Snapshot>>;
+
         fn transaction_get_for_update(
             transaction: &mut Transaction,
             key: &CxxString,
         ) -> Result<OptionalValue>;
 
+        fn transaction_get_and_lock_if_present(
+            transaction: &mut Transaction,
+            key: &CxxString,
+        ) -> Result<OptionalValue>;
+
+        fn transaction_get_value_length(
+            transaction: &mut Transaction,
+            key: &CxxString,
+        ) -> Result<OptionalLength>;
+
+        fn transaction_get_into(
+            transaction: &mut Transaction,
+            key: &CxxString,
+            buf: &mut [u8],
+        ) -> Result<GetIntoResult>;
+
         fn transaction_batch_get(
             transaction: &mut Transaction,
             keys: &CxxVector<CxxString>,
         ) -> Result<Vec<KvPair>>;
 
+        fn transaction_batch_get_limited(
+            transaction: &mut Transaction,
+            keys: &CxxVector<CxxString>,
+            max_bytes: u64,
+        ) -> Result<BatchGetLimitedResult>;
+
         fn transaction_batch_get_for_update(
             transaction: &mut Transaction,
             keys: &CxxVector<CxxString>,
         ) -> Result<Vec<KvPair>>;
 
+        fn transaction_assert_unchanged(
+            transaction: &mut Transaction,
+            keys: &CxxVector<CxxString>,
+        ) -> Result<()>;
+
+        fn transaction_written_keys(transaction: &Transaction) -> Result<Vec<WrittenKey>>;
+
+        fn transaction_conflict_report(transaction: &Transaction) -> Result<Vec<ConflictEntry>>;
+
+        fn transaction_pessimistic_rollback_keys(
+            transaction: &mut Transaction,
+            keys: &CxxVector<CxxString>,
+        ) -> Result<()>;
+
+        fn transaction_region_span(
+            transaction: &Transaction,
+            client: &TransactionClient,
+        ) -> Result<u64>;
+
         fn transaction_scan(
             transaction: &mut Transaction,
             start: &CxxString,
             start_bound: Bound,
             end: &CxxString,
             end_bound: Bound,
-            limit: u32,
+            limit: u64,
         ) -> Result<Vec<KvPair>>;
 
-        fn transaction_scan_keys(
+        fn transaction_scan_with_codec(
             transaction: &mut Transaction,
             start: &CxxString,
             start_bound: Bound,
             end: &CxxString,
             end_bound: Bound,
-            limit: u32,
-        ) -> Result<Vec<Key>>;
-
-        fn transaction_put(
-            transaction: &mut Transaction,
-            key: &CxxString,
-            val: &CxxString,
-        ) -> Result<()>;
-
-        fn transaction_delete(transaction: &mut Transaction, key: &CxxString) -> Result<()>;
-
-        fn transaction_commit(transaction: &mut Transaction) -> Result<()>;
-        fn transaction_rollback(transaction: &mut Transaction) -> Result<()>;
-        fn current_timestamp(client: &TransactionClient) -> Result<u64>;
-
-        fn snapshot_new(client: &TransactionClient) -> Result<Box<Snapshot>>;
-        fn snapshot_new_with_timestamp(
-            client: &TransactionClient,
-            timestamp: u64,
-        ) -> Result<Box<Snapshot>>;
-
-        fn snapshot_get(snapshot: &mut Snapshot, key: &CxxString) -> Result<OptionalValue>;
-
-        fn snapshot_batch_get(
-            snapshot: &mut Snapshot,
-            keys: &CxxVector<CxxString>,
+            limit: u64,
+            bounds_are_decoded: bool,
+            decode_keys: bool,
         ) -> Result<Vec<KvPair>>;
 
-        fn snapshot_scan(
-            snapshot: &mut Snapshot,
+        fn transaction_scan_with_truncation(
+            transaction: &mut Transaction,
             start: &CxxString,
             start_bound: Bound,
             end: &CxxString,
             end_bound: Bound,
-            limit: u32,
-        ) -> Result<Vec<KvPair>>;
+            limit: u64,
+        ) -> Result<ScanResult>;
 
-        fn snapshot_scan_keys(
-            snapshot: &mut Snapshot,
+        fn transaction_scan_packed(
+            transaction: &mut Transaction,
             start: &CxxString,
             start_bound: Bound,
             end: &CxxString,
             end_bound: Bound,
-            limit: u32,
-        ) -> Result<Vec<Key>>;
+            limit: u64,
+        ) -> Result<PackedScanResult>;
+
+        fn transaction_scan_keys(
+            transaction: &mut Transaction,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<Vec<Key>>;
+
+        fn transaction_put(
+            transaction: &mut Transaction,
+            key: &CxxString,
+            val: &CxxString,
+        ) -> Result<()>;
+
+        fn transaction_put_get_prev(
+            transaction: &mut Transaction,
+            key: &CxxString,
+            val: &CxxString,
+        ) -> Result<OptionalValue>;
+
+        fn transaction_delete(transaction: &mut Transaction, key: &CxxString) -> Result<()>;
+
+        fn transaction_put_with_expiry(
+            transaction: &mut Transaction,
+            key: &CxxString,
+            val: &CxxString,
+            expire_at_ts: u64,
+        ) -> Result<()>;
+
+        fn transaction_get_with_expiry(
+            transaction: &mut Transaction,
+            key: &CxxString,
+            now_ts: u64,
+        ) -> Result<OptionalValue>;
+
+        fn transaction_put_if_equals(
+            transaction: &mut Transaction,
+            key: &CxxString,
+            expected: &CxxString,
+            new_value: &CxxString,
+        ) -> Result<()>;
+
+        fn transaction_delete_if_equals(
+            transaction: &mut Transaction,
+            key: &CxxString,
+            expected: &CxxString,
+        ) -> Result<()>;
+
+        fn transaction_set_memory_limit(transaction: &mut Transaction, bytes: u64) -> Result<()>;
+        fn transaction_buffered_bytes(transaction: &Transaction) -> u64;
+
+        fn transaction_is_readonly(transaction: &Transaction) -> bool;
+        fn transaction_state(transaction: &Transaction) -> u8;
+        fn transaction_set_priority(
+            transaction: &mut Transaction,
+            priority: Priority,
+        ) -> Result<()>;
+        fn transaction_for_update_ts(transaction: &Transaction) -> Result<u64>;
+        fn transaction_refresh_for_update_ts(transaction: &mut Transaction) -> Result<u64>;
+        fn transaction_commit(transaction: &mut Transaction) -> Result<u64>;
+        fn transaction_set_commit_stats(transaction: &mut Transaction, enabled: bool)
+            -> Result<()>;
+        fn transaction_enable_op_log(transaction: &mut Transaction, max_entries: u32)
+            -> Result<()>;
+        fn transaction_set_tag(transaction: &mut Transaction, tag: &CxxString) -> Result<()>;
+        fn transaction_set_deadline(
+            transaction: &mut Transaction,
+            deadline_unix_ms: u64,
+        ) -> Result<()>;
+        fn transaction_enable_skip_noop_writes(transaction: &mut Transaction) -> Result<()>;
+        fn transaction_dump_op_log(transaction: &Transaction) -> Vec<OpLogEntry>;
+        fn transaction_last_commit_stats(transaction: &Transaction) -> CommitStats;
+        fn transaction_commit_info(transaction: &Transaction) -> Result<CommitInfo>;
+        fn transaction_commit_chunked(
+            transaction: &mut Transaction,
+            keys_per_txn: u32,
+        ) -> Result<ChunkedCommitResult>;
+        fn transaction_rollback(transaction: &mut Transaction) -> Result<()>;
+        fn current_timestamp(client: &TransactionClient) -> Result<u64>;
+
+        fn routed_client_new(split_scans: bool) -> Box<RoutedClient>;
+        fn routed_client_add_route(
+            client: &mut RoutedClient,
+            prefix: &CxxString,
+            route_client: Box<TransactionClient>,
+        ) -> Result<()>;
+        fn routed_client_begin(client: &RoutedClient, pessimistic: bool) -> Box<RoutedTransaction>;
+        fn routed_client_snapshot(client: &RoutedClient) -> Result<Box<RoutedSnapshot>>;
+        fn routed_transaction_get(
+            transaction: &mut RoutedTransaction,
+            key: &CxxString,
+        ) -> Result<OptionalValue>;
+        fn routed_transaction_put(
+            transaction: &mut RoutedTransaction,
+            key: &CxxString,
+            val: &CxxString,
+        ) -> Result<()>;
+        fn routed_transaction_delete(
+            transaction: &mut RoutedTransaction,
+            key: &CxxString,
+        ) -> Result<()>;
+        fn routed_transaction_scan(
+            transaction: &mut RoutedTransaction,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<Vec<KvPair>>;
+        fn routed_transaction_commit(transaction: &mut RoutedTransaction) -> Result<u64>;
+        fn routed_transaction_rollback(transaction: &mut RoutedTransaction) -> Result<()>;
+        fn routed_snapshot_get(
+            snapshot: &mut RoutedSnapshot,
+            key: &CxxString,
+        ) -> Result<OptionalValue>;
+        fn routed_snapshot_batch_get(
+            snapshot: &mut RoutedSnapshot,
+            keys: &CxxVector<CxxString>,
+        ) -> Result<Vec<KvPair>>;
+        fn routed_snapshot_scan(
+            snapshot: &mut RoutedSnapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<Vec<KvPair>>;
+
+        fn failover_pair_new(
+            primary_client: Box<TransactionClient>,
+            standby_client: Box<TransactionClient>,
+            policy: FailoverPolicy,
+            confirmation: UniquePtr<FailoverConfirmation>,
+        ) -> Result<Box<FailoverClient>>;
+        fn failover_begin(client: &FailoverClient, pessimistic: bool) -> Result<Box<Transaction>>;
+        fn failover_snapshot(client: &FailoverClient) -> Result<Box<Snapshot>>;
+        fn failover_switch(client: &FailoverClient, target: u8) -> Result<()>;
+        fn failover_status(client: &FailoverClient) -> FailoverStatus;
+
+        fn snapshot_new(client: &TransactionClient) -> Result<Box<Snapshot>>;
+        fn snapshot_new_fail_fast(client: &TransactionClient) -> Result<Box<Snapshot>>;
+        fn snapshot_new_pinned(client: &TransactionClient) -> Result<Box<Snapshot>>;
+        fn client_set_read_ts_provider(
+            client: &mut TransactionClient,
+            provider: UniquePtr<ReadTsProvider>,
+        );
+        fn snapshot_new_with_timestamp(
+            client: &TransactionClient,
+            timestamp: u64,
+        ) -> Result<Box<Snapshot>>;
+
+        fn snapshot_set_priority(snapshot: &mut Snapshot, priority: Priority) -> Result<()>;
+        fn snapshot_get(snapshot: &mut Snapshot, key: &CxxString) -> Result<OptionalValue>;
+        fn snapshot_get_raw(snapshot: &mut Snapshot, key: &CxxString) -> Result<OptionalValue>;
+        fn snapshot_prefetch(snapshot: &mut Snapshot, keys: &CxxVector<CxxString>) -> Result<()>;
+        fn snapshot_set_prefetch_cache_bytes(snapshot: &mut Snapshot, bytes: u64) -> Result<()>;
+        fn snapshot_prefetch_stats(snapshot: &Snapshot) -> PrefetchStats;
+        fn snapshot_scan_raw(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<Vec<KvPair>>;
+
+        fn snapshot_get_value_length(
+            snapshot: &mut Snapshot,
+            key: &CxxString,
+        ) -> Result<OptionalLength>;
+
+        fn snapshot_get_into(
+            snapshot: &mut Snapshot,
+            key: &CxxString,
+            buf: &mut [u8],
+        ) -> Result<GetIntoResult>;
+
+        fn snapshot_batch_get(
+            snapshot: &mut Snapshot,
+            keys: &CxxVector<CxxString>,
+        ) -> Result<Vec<KvPair>>;
+
+        fn snapshot_batch_get_limited(
+            snapshot: &mut Snapshot,
+            keys: &CxxVector<CxxString>,
+            max_bytes: u64,
+        ) -> Result<BatchGetLimitedResult>;
+
+        fn snapshot_smart_get(
+            snapshot: &mut Snapshot,
+            keys: &CxxVector<CxxString>,
+        ) -> Result<Vec<OptionalValue>>;
+
+        fn snapshot_batch_get_hashes(
+            snapshot: &mut Snapshot,
+            keys: &CxxVector<CxxString>,
+            algo: u8,
+        ) -> Result<Vec<OptionalHash>>;
+
+        fn snapshot_set_smart_get_options(
+            snapshot: &mut Snapshot,
+            density_percent: u32,
+            min_run_keys: u32,
+        ) -> Result<()>;
+
+        fn snapshot_scan(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<Vec<KvPair>>;
+
+        fn snapshot_scan_with_codec(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+            bounds_are_decoded: bool,
+            decode_keys: bool,
+        ) -> Result<Vec<KvPair>>;
+
+        fn snapshot_scan_with_truncation(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<ScanResult>;
+
+        fn snapshot_scan_until(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+            deadline_ms: u64,
+        ) -> Result<ScanUntilResult>;
+
+        fn snapshot_scan_skipping_oversized(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<SkippingScanResult>;
+
+        fn snapshot_scan_packed(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<PackedScanResult>;
+
+        fn snapshot_scan_pair(
+            snapshot: &mut Snapshot,
+            start_a: &CxxString,
+            start_a_bound: Bound,
+            end_a: &CxxString,
+            end_a_bound: Bound,
+            start_b: &CxxString,
+            start_b_bound: Bound,
+            end_b: &CxxString,
+            end_b_bound: Bound,
+            limit: u64,
+        ) -> Result<ScanPair>;
+
+        fn snapshot_scan_keys(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<Vec<Key>>;
+
+        fn snapshot_scan_key_sizes(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            limit: u64,
+        ) -> Result<Vec<KeySize>>;
+
+        fn snapshot_checksum_range(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+        ) -> Result<ChecksumResult>;
+
+        fn snapshot_export_range(
+            snapshot: &mut Snapshot,
+            start: &CxxString,
+            start_bound: Bound,
+            end: &CxxString,
+            end_bound: Bound,
+            file_path: &CxxString,
+        ) -> Result<u64>;
+
+        fn client_import_file(
+            client: &TransactionClient,
+            file_path: &CxxString,
+            keys_per_txn: u32,
+        ) -> Result<u64>;
+
+        fn client_purge_expired(
+            client: &TransactionClient,
+            prefix: &CxxString,
+            now_ts: u64,
+            keys_per_txn: u32,
+        ) -> Result<u64>;
 
         fn transaction_prewrite_primary(
             transaction: &mut Transaction,
             primary_key: &CxxString,
         ) -> Result<PrewriteResult>;
 
+        fn transaction_prewrite_primary_with_strategy(
+            transaction: &mut Transaction,
+            strategy: PrimarySelection,
+            primary_key: &CxxString,
+        ) -> Result<PrewriteResult>;
         fn transaction_prewrite_secondary(
             transaction: &mut Transaction,
             primary_key: &CxxString,
             start_ts: u64,
-        ) -> Result<()>;
+            allow_empty: bool,
+        ) -> Result<SecondaryPrewriteResult>;
         fn transaction_commit_primary(transaction: &mut Transaction) -> Result<u64>;
-        fn transaction_commit_secondary(transaction: &mut Transaction, commit_ts: u64);
-
-    }
-}
-
-#[repr(transparent)]
-struct TransactionClient {
-    inner: tikv_client::TransactionClient,
-}
-
-#[repr(transparent)]
-struct Transaction {
-    inner: tikv_client::Transaction,
-}
-
-#[repr(transparent)]
-struct Snapshot {
-    inner: tikv_client::Snapshot,
-}
-
-fn create_slog_logger(log_path: &CxxString) -> Result<slog::Logger> {
-    let mut log_path = log_path.to_str()?.to_string();
-    let log_file_name = chrono::Local::now()
-        .format("/tikv-client-%Y%m%d%H%M%S.log")
-        .to_string();
-    log_path.push_str(&log_file_name);
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(false)
-        .open(log_path)
-        .expect("open log file failed");
-
-    let decorator = slog_term::PlainDecorator::new(file);
-    let drain = slog_term::FullFormat::new(decorator)
-        .use_local_timestamp()
-        .build()
-        .fuse();
-    let drain = slog_async::Async::new(drain)
-        .chan_size(DEFAULT_CHAN_SIZE)
-        .build()
-        .fuse();
-    let logger = slog::Logger::root(drain, o!());
-    static SCOPE_GUARD: OnceCell<slog_scope::GlobalLoggerGuard> = OnceCell::new();
-    #[allow(unused_must_use)]
-    START.call_once(|| {
-        SCOPE_GUARD.set(slog_scope::set_global_logger(logger));
-        slog_stdlog::init().unwrap();
-    });
-    Ok(slog_scope::logger())
+        fn transaction_commit_secondary(
+            transaction: &mut Transaction,
+            commit_ts: u64,
+        ) -> Result<()>;
+
+        fn shutdown_runtime();
+        fn client_capabilities() -> Vec<u8>;
+
+        fn format_key_hex(key: &CxxString) -> String;
+        fn parse_key_hex(text: &CxxString) -> Result<Vec<u8>>;
+        fn version_from_unix_ms(ms: u64) -> u64;
+        fn unix_ms_of_version(version: u64) -> u64;
+        fn hash_kv_pairs(
+            keys: &CxxVector<CxxString>,
+            values: &CxxVector<CxxString>,
+        ) -> Result<Vec<u8>>;
+
+        fn encode_u64_asc(value: u64) -> Vec<u8>;
+        fn encode_u64_desc(value: u64) -> Vec<u8>;
+        fn encode_i64_asc(value: i64) -> Vec<u8>;
+        fn encode_i64_desc(value: i64) -> Vec<u8>;
+        fn decode_u64_asc(data: &CxxString) -> Result<u64>;
+        fn decode_u64_desc(data: &CxxString) -> Result<u64>;
+        fn decode_i64_asc(data: &CxxString) -> Result<i64>;
+        fn decode_i64_desc(data: &CxxString) -> Result<i64>;
+        fn encode_bytes(data: &CxxString) -> Vec<u8>;
+        fn decode_bytes(data: &CxxString) -> Result<DecodedBytes>;
+
+    }
 }
 
-fn transaction_client_new(
-    pd_endpoints: &CxxVector<CxxString>,
-    log_path: &CxxString,
-    timeout: u32,
-) -> Result<Box<TransactionClient>> {
-    // env_logger::init();
-    let config = Config::default();
-    let config = config.with_timeout(Duration::from_secs(timeout as u64));
-    let log = create_slog_logger(log_path)?;
-    let pd_endpoints = pd_endpoints
+// The thread contracts of the handles the C++ side holds, which cxx does not
+// check: a client may be used from several threads at once, every other
+// handle may be moved from thread to thread and used by one at a time, a
+// concurrent call failing with "... is busy" where the handle has a busy
+// flag. Debug builds log the handles moving, see `sync::busy`.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<TransactionClient>();
+    assert_send_sync::<RoutedClient>();
+    assert_send_sync::<FailoverClient>();
+    assert_send::<Transaction>();
+    assert_send::<Snapshot>();
+    assert_send::<RoutedTransaction>();
+    assert_send::<RoutedSnapshot>();
+    assert_send::<BulkWriter>();
+    assert_send::<SafepointGuard>();
+};
+
+// The C++ side documents that a pressure callback is called from a thread of
+// its own, which it is then moved to.
+unsafe impl Send for PressureCallback {}
+
+// The C++ side documents that a read timestamp provider is called from any
+// thread creating a pinned snapshot, concurrently.
+unsafe impl Send for ReadTsProvider {}
+unsafe impl Sync for ReadTsProvider {}
+
+// The C++ side documents that a failover confirmation is called from the
+// thread whose operation tripped the switch, from any thread using the pair.
+unsafe impl Send for FailoverConfirmation {}
+unsafe impl Sync for FailoverConfirmation {}
+
+impl From<Option<tikv_client::Value>> for OptionalValue {
+    fn from(value: Option<tikv_client::Value>) -> Self {
+        match value {
+            Some(value) => OptionalValue {
+                is_none: false,
+                value,
+            },
+            None => OptionalValue {
+                is_none: true,
+                value: Vec::new(),
+            },
+        }
+    }
+}
+
+impl From<Option<Vec<u8>>> for OptionalHash {
+    fn from(hash: Option<Vec<u8>>) -> Self {
+        match hash {
+            Some(hash) => OptionalHash {
+                is_none: false,
+                hash,
+            },
+            None => OptionalHash {
+                is_none: true,
+                hash: Vec::new(),
+            },
+        }
+    }
+}
+
+impl From<Option<u64>> for OptionalLength {
+    fn from(length: Option<u64>) -> Self {
+        OptionalLength {
+            is_none: length.is_none(),
+            length: length.unwrap_or_default(),
+        }
+    }
+}
+
+fn to_get_into_result(length: Option<usize>, buf: &[u8]) -> GetIntoResult {
+    match length {
+        Some(length) => GetIntoResult {
+            found: true,
+            length: length as u64,
+            truncated: length > buf.len(),
+        },
+        None => GetIntoResult {
+            found: false,
+            length: 0,
+            truncated: false,
+        },
+    }
+}
+
+impl From<tikv_client::KvPair> for KvPair {
+    fn from(tikv_client::KvPair(key, value): tikv_client::KvPair) -> Self {
+        KvPair {
+            key: key.into(),
+            value,
+        }
+    }
+}
+
+impl From<sync::CommitStats> for CommitStats {
+    fn from(stats: sync::CommitStats) -> Self {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        CommitStats {
+            prewrite_ms: ms(stats.prewrite),
+            get_commit_ts_ms: ms(stats.get_commit_ts),
+            commit_primary_ms: ms(stats.commit_primary),
+            commit_secondary_ms: ms(stats.commit_secondary),
+            total_ms: ms(stats.total),
+            mutation_count: stats.mutation_count,
+        }
+    }
+}
+
+impl From<sync::MvccVersion> for MvccVersion {
+    fn from(version: sync::MvccVersion) -> Self {
+        MvccVersion {
+            commit_ts: version.commit_ts,
+            write_type: match version.write_type() {
+                sync::WriteType::Put => WriteType::Put,
+                sync::WriteType::Delete => WriteType::Delete,
+            },
+            value: version.value.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<sync::PartialOutcome> for PartialOutcome {
+    fn from(outcome: sync::PartialOutcome) -> Self {
+        PartialOutcome {
+            succeeded_items: outcome.succeeded_items,
+            failed_items: outcome.failed_items,
+            first_error: outcome
+                .first_error
+                .map(String::into_bytes)
+                .unwrap_or_default(),
+            resume_token: outcome.resume_token,
+        }
+    }
+}
+
+impl From<sync::SnapshotStats> for SnapshotStats {
+    fn from(stats: sync::SnapshotStats) -> Self {
+        SnapshotStats {
+            live: stats.live,
+            oldest_age_ms: stats.oldest_age.as_millis() as u64,
+            created_total: stats.created_total,
+        }
+    }
+}
+
+impl From<sync::FailoverStatus> for FailoverStatus {
+    fn from(status: sync::FailoverStatus) -> Self {
+        FailoverStatus {
+            target: status.target as u8,
+            switches: status.switches,
+            consecutive_failures: status.consecutive_failures,
+            window_operations: status.window_operations,
+            window_failures: status.window_failures,
+            last_reason: status.last_reason.unwrap_or_default().into_bytes(),
+        }
+    }
+}
+
+impl From<sync::BatchGetLimitedResult> for BatchGetLimitedResult {
+    fn from(result: sync::BatchGetLimitedResult) -> Self {
+        BatchGetLimitedResult {
+            pairs: result.kv_pairs.into_iter().map(Into::into).collect(),
+            completed: result.completed,
+            next_index: result.next_index,
+        }
+    }
+}
+
+impl From<sync::ScanResult> for ScanResult {
+    fn from(result: sync::ScanResult) -> Self {
+        ScanResult {
+            pairs: result.kv_pairs.into_iter().map(Into::into).collect(),
+            truncated: result.truncated,
+            last_key: result.last_key.map(Into::into).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<packed::PackedKvPairs> for PackedScanResult {
+    fn from(packed: packed::PackedKvPairs) -> Self {
+        PackedScanResult {
+            count: packed.len() as u64,
+            data: packed.data,
+            offsets: packed.offsets,
+        }
+    }
+}
+
+impl From<tikv_client::Key> for Key {
+    fn from(key: tikv_client::Key) -> Self {
+        Key { key: key.into() }
+    }
+}
+
+fn to_pd_endpoints(pd_endpoints: &CxxVector<CxxString>) -> Result<Vec<String>> {
+    Ok(pd_endpoints
         .iter()
         .map(|str| str.to_str().map(ToOwned::to_owned))
-        .collect::<std::result::Result<Vec<_>, _>>()?;
-
-    Ok(Box::new(TransactionClient {
-        inner: TOKIO_RUNTIME.block_on(tikv_client::TransactionClient::new_with_config(
-            pd_endpoints,
-            config,
-            Some(log),
-        ))?,
-    }))
+        .collect::<std::result::Result<Vec<_>, _>>()?)
+}
+
+/// Scan limits cross the bridge as `u64`, 0 meaning no limit; one call can
+/// honor at most `u32::MAX`.
+fn to_scan_limit(limit: u64) -> Result<u32> {
+    u32::try_from(limit).map_err(|_| {
+        anyhow!(
+            "scan limit {} is over the maximum of {} pairs per call, pass 0 to scan the whole range",
+            limit,
+            u32::MAX
+        )
+    })
+}
+
+fn to_bound(key: &CxxString, bound: Bound) -> ops::Bound<&[u8]> {
+    match bound {
+        Bound::Included => ops::Bound::Included(key.as_bytes()),
+        Bound::Excluded => ops::Bound::Excluded(key.as_bytes()),
+        Bound::Unbounded => ops::Bound::Unbounded,
+        _ => panic!("unexpected bound"),
+    }
+}
+
+fn to_priority(priority: Priority) -> sync::Priority {
+    match priority {
+        Priority::Normal => sync::Priority::Normal,
+        Priority::Low => sync::Priority::Low,
+        Priority::High => sync::Priority::High,
+        _ => panic!("unexpected priority"),
+    }
+}
+
+/// A `max_live_snapshots` of 0 leaves the live snapshots unlimited.
+fn transaction_client_new(
+    pd_endpoints: &CxxVector<CxxString>,
+    log_path: &CxxString,
+    timeout: u32,
+    max_live_snapshots: u32,
+) -> Result<Box<TransactionClient>> {
+    let mut client = TransactionClient::new(
+        to_pd_endpoints(pd_endpoints)?,
+        log_path.to_str()?,
+        Duration::from_secs(timeout as u64),
+    )?;
+    client.set_max_live_snapshots(Some(max_live_snapshots).filter(|&max| max != 0))?;
+    Ok(Box::new(client))
+}
+
+fn transaction_client_new_with_config(
+    pd_endpoints: &CxxVector<CxxString>,
+    log_path: &CxxString,
+    ca_path: &CxxString,
+    cert_path: &CxxString,
+    key_path: &CxxString,
+    timeout: u32,
+    priority: Priority,
+    max_live_snapshots: u32,
+) -> Result<Box<TransactionClient>> {
+    let config = tikv_client::Config {
+        ca_path: Some(PathBuf::from(ca_path.to_str()?.to_string())),
+        cert_path: Some(PathBuf::from(cert_path.to_str()?.to_string())),
+        key_path: Some(PathBuf::from(key_path.to_str()?.to_string())),
+        timeout: Duration::from_secs(timeout as u64),
+    };
+    let mut client = TransactionClient::new_with_config(
+        to_pd_endpoints(pd_endpoints)?,
+        log_path.to_str()?,
+        config,
+    )?;
+    client.set_default_priority(to_priority(priority))?;
+    client.set_max_live_snapshots(Some(max_live_snapshots).filter(|&max| max != 0))?;
+    Ok(Box::new(client))
+}
+
+fn transaction_client_with_prefix(
+    client: &TransactionClient,
+    prefix: &CxxString,
+) -> Box<TransactionClient> {
+    Box::new(client.with_prefix(prefix.as_bytes()))
+}
+
+fn transaction_client_new_in_keyspace(
+    pd_endpoints: &CxxVector<CxxString>,
+    log_path: &CxxString,
+    keyspace: &CxxString,
+    timeout: u32,
+    max_live_snapshots: u32,
+) -> Result<Box<TransactionClient>> {
+    let mut client = TransactionClient::new_with_keyspace(
+        to_pd_endpoints(pd_endpoints)?,
+        log_path.to_str()?,
+        tikv_client::Config::default().with_timeout(Duration::from_secs(timeout as u64)),
+        keyspace.to_str()?,
+    )?;
+    client.set_max_live_snapshots(Some(max_live_snapshots).filter(|&max| max != 0))?;
+    Ok(Box::new(client))
+}
+
+fn client_set_default_txn_options(client: &TransactionClient, options: &TxnOptions) {
+    let lock_backoff = Some(sync::BackoffOptions {
+        base_delay_ms: options.lock_backoff_base_ms,
+        max_delay_ms: options.lock_backoff_max_ms,
+        max_attempts: options.lock_backoff_max_attempts,
+    })
+    .filter(|backoff| backoff.max_attempts > 0);
+    let drop_check = match options.drop_check {
+        DropCheck::Warn => sync::DropCheck::Warn,
+        DropCheck::Panic => sync::DropCheck::Panic,
+        DropCheck::Ignore => sync::DropCheck::Ignore,
+        _ => panic!("unexpected drop check"),
+    };
+    client.set_default_txn_options(sync::TxnOptions {
+        lock_backoff,
+        drop_check,
+        fail_fast_on_locks: options.fail_fast_on_locks,
+    });
+}
+
+fn client_get_default_txn_options(client: &TransactionClient) -> TxnOptions {
+    let options = client.default_txn_options();
+    let drop_check = match options.drop_check {
+        sync::DropCheck::Warn => DropCheck::Warn,
+        sync::DropCheck::Panic => DropCheck::Panic,
+        sync::DropCheck::Ignore => DropCheck::Ignore,
+    };
+    match options.lock_backoff {
+        Some(backoff) => TxnOptions {
+            lock_backoff_base_ms: backoff.base_delay_ms,
+            lock_backoff_max_ms: backoff.max_delay_ms,
+            lock_backoff_max_attempts: backoff.max_attempts,
+            drop_check,
+            fail_fast_on_locks: options.fail_fast_on_locks,
+        },
+        None => TxnOptions {
+            lock_backoff_base_ms: 0,
+            lock_backoff_max_ms: 0,
+            lock_backoff_max_attempts: 0,
+            drop_check,
+            fail_fast_on_locks: options.fail_fast_on_locks,
+        },
+    }
+}
+
+fn client_set_strict_versions(client: &mut TransactionClient, within_years: u32) {
+    client.set_strict_versions(Some(within_years).filter(|&years| years > 0));
+}
+
+fn client_set_timestamp_retry(
+    client: &mut TransactionClient,
+    max_attempts: u32,
+    budget_ms: u64,
+) -> Result<()> {
+    client.set_timestamp_retry(sync::TimestampRetry {
+        max_attempts,
+        budget: Duration::from_millis(budget_ms),
+    })
+}
+
+fn client_set_snapshot_read_retry(
+    client: &mut TransactionClient,
+    max_attempts: u32,
+    base_delay_ms: u64,
+    max_delay_ms: u64,
+) {
+    client.set_snapshot_read_retry(sync::BackoffOptions {
+        base_delay_ms,
+        max_delay_ms,
+        max_attempts,
+    });
+}
+
+/// `ops_mask` is an or of `MirrorOp` values.
+fn client_set_mirror(
+    client: &mut TransactionClient,
+    mirror_client: &TransactionClient,
+    sample_rate_percent: u32,
+    ops_mask: u32,
+) -> Result<()> {
+    let known = (MirrorOp::Get.repr | MirrorOp::BatchGet.repr | MirrorOp::Scan.repr) as u32;
+    if ops_mask & !known != 0 {
+        return Err(anyhow!(
+            "unknown mirror operations in the mask {:#x}",
+            ops_mask
+        ));
+    }
+    let has = |op: MirrorOp| ops_mask & op.repr as u32 != 0;
+    client.set_mirror(
+        mirror_client,
+        sample_rate_percent,
+        sync::MirrorOps {
+            get: has(MirrorOp::Get),
+            batch_get: has(MirrorOp::BatchGet),
+            scan: has(MirrorOp::Scan),
+        },
+    )
+}
+
+fn client_clear_mirror(client: &mut TransactionClient) {
+    client.clear_mirror();
+}
+
+/// Calls `callback` from the thread of the pressure monitor; a null one
+/// removes the callback set before.
+fn client_set_pressure_callback(
+    client: &mut TransactionClient,
+    callback: UniquePtr<PressureCallback>,
+    high_watermark: u64,
+    low_watermark: u64,
+) -> Result<()> {
+    if callback.is_null() {
+        return client.set_pressure_callback(None, high_watermark, low_watermark);
+    }
+    let callback: sync::PressureCallback = Box::new(move |level| {
+        let level = match level {
+            sync::PressureLevel::Normal => PressureLevel::Normal,
+            sync::PressureLevel::High => PressureLevel::High,
+        };
+        Ok(callback.on_pressure(level)?)
+    });
+    client.set_pressure_callback(Some(callback), high_watermark, low_watermark)
+}
+
+fn client_set_compression(
+    client: &mut TransactionClient,
+    compression: Compression,
+    min_value_bytes: u64,
+) -> Result<()> {
+    let compression = match compression {
+        Compression::None => compression::Compression::None,
+        Compression::Lz4 => compression::Compression::Lz4,
+        _ => panic!("unexpected compression"),
+    };
+    client.set_compression(compression, usize::try_from(min_value_bytes)?);
+    Ok(())
+}
+
+fn client_set_audit_log(
+    client: &mut TransactionClient,
+    path: &CxxString,
+    client_tag: &CxxString,
+    max_file_bytes: u64,
+    max_rotated_files: u32,
+    fsync: AuditFsync,
+) -> Result<()> {
+    let fsync = match fsync {
+        AuditFsync::Never => sync::AuditFsync::Never,
+        AuditFsync::EveryCommit => sync::AuditFsync::EveryCommit,
+        _ => panic!("unexpected audit fsync policy"),
+    };
+    client.set_audit_log(Some(sync::AuditOptions {
+        path: PathBuf::from(path.to_str()?),
+        client_tag: client_tag.to_str()?.to_owned(),
+        max_file_bytes,
+        max_rotated_files,
+        fsync,
+    }))
+}
+
+fn client_clear_audit_log(client: &mut TransactionClient) -> Result<()> {
+    client.set_audit_log(None)
+}
+
+/// A report age of 0 reports no transaction.
+fn client_set_strict_mode(
+    client: &mut TransactionClient,
+    max_active_transactions: u32,
+    empty_commits: EmptyCommits,
+    report_age_ms: u64,
+) -> Result<()> {
+    let empty_commits = match empty_commits {
+        EmptyCommits::Allow => sync::EmptyCommits::Allow,
+        EmptyCommits::Warn => sync::EmptyCommits::Warn,
+        EmptyCommits::Reject => sync::EmptyCommits::Reject,
+        _ => panic!("unexpected empty commit policy"),
+    };
+    client.set_strict_mode(Some(sync::StrictOptions {
+        max_active_transactions,
+        empty_commits,
+        report_age: Some(Duration::from_millis(report_age_ms)).filter(|_| report_age_ms > 0),
+    }))
+}
+
+fn client_clear_strict_mode(client: &mut TransactionClient) -> Result<()> {
+    client.set_strict_mode(None)
+}
+
+fn client_active_transaction_count(client: &TransactionClient) -> u64 {
+    client.active_transaction_count() as u64
+}
+
+fn client_snapshot_stats(client: &TransactionClient) -> SnapshotStats {
+    client.snapshot_stats().into()
+}
+
+fn client_set_guard_reserved(client: &mut TransactionClient, guard: bool) {
+    client.set_guard_reserved(guard);
+}
+
+fn client_set_allow_empty_keys(client: &mut TransactionClient, allow: bool) {
+    client.set_allow_empty_keys(allow);
+}
+
+/// A limit of 0 disables its check.
+fn client_set_size_limits(
+    client: &mut TransactionClient,
+    max_key_bytes: u64,
+    max_value_bytes: u64,
+) {
+    let to_limit = |limit| Some(limit).filter(|&limit| limit > 0);
+    client.set_size_limits(sync::SizeLimits {
+        max_key_bytes: to_limit(max_key_bytes),
+        max_value_bytes: to_limit(max_value_bytes),
+        ..client.size_limits()
+    });
+}
+
+fn client_set_oversized_values(client: &mut TransactionClient, policy: OversizedValues) {
+    let oversized_values = match policy {
+        OversizedValues::Reject => sync::OversizedValues::Reject,
+        OversizedValues::Chunk => sync::OversizedValues::Chunk,
+        _ => panic!("unexpected oversized value policy"),
+    };
+    client.set_size_limits(sync::SizeLimits {
+        oversized_values,
+        ..client.size_limits()
+    });
+}
+
+fn client_gc(client: &TransactionClient, safepoint: u64) -> Result<bool> {
+    client.gc(safepoint)
+}
+
+fn client_current_pd(client: &TransactionClient) -> Result<Vec<u8>> {
+    Ok(client.current_pd()?.into_bytes())
+}
+
+fn client_pd_leader_changes(client: &TransactionClient) -> Result<u64> {
+    client.pd_leader_changes()
+}
+
+fn client_store_metrics(client: &TransactionClient) -> Vec<StoreMetric> {
+    client
+        .store_metrics()
+        .into_iter()
+        .map(|metric| StoreMetric {
+            store_id: metric.store_id,
+            requests: metric.requests,
+            errors: metric.errors,
+            p99_us: metric.p99.as_micros() as u64,
+        })
+        .collect()
+}
+
+fn client_bulk_write(
+    client: &TransactionClient,
+    keys_per_txn: u32,
+    concurrency: u32,
+) -> Result<Box<BulkWriter>> {
+    Ok(Box::new(client.bulk_writer(keys_per_txn, concurrency)?))
+}
+
+fn bulk_write_push(
+    writer: &mut BulkWriter,
+    keys: &CxxVector<CxxString>,
+    values: &CxxVector<CxxString>,
+) -> Result<()> {
+    if keys.len() != values.len() {
+        return Err(anyhow!(
+            "got {} keys but {} values",
+            keys.len(),
+            values.len()
+        ));
+    }
+    writer.push(
+        keys.iter()
+            .map(CxxString::as_bytes)
+            .zip(values.iter().map(CxxString::as_bytes)),
+    )
+}
+
+fn bulk_write_progress(writer: &BulkWriter) -> BulkWriteProgress {
+    let progress = writer.progress();
+    BulkWriteProgress {
+        pending_keys: progress.pending_keys,
+        written_keys: progress.written_keys,
+        failed_keys: progress.failed_keys,
+    }
+}
+
+fn bulk_write_finish(writer: &mut BulkWriter) -> Vec<BulkWriteFailure> {
+    writer
+        .finish()
+        .into_iter()
+        .map(|failure| BulkWriteFailure {
+            pairs: failure.kv_pairs.into_iter().map(Into::into).collect(),
+            error: failure.error,
+        })
+        .collect()
+}
+
+fn client_metrics(client: &TransactionClient) -> MetricsSnapshot {
+    let metrics = client.metrics();
+    MetricsSnapshot {
+        snapshot_read_keys: metrics.snapshot_read_keys,
+        snapshot_read_bytes: metrics.snapshot_read_bytes,
+        transaction_read_keys: metrics.transaction_read_keys,
+        transaction_read_bytes: metrics.transaction_read_bytes,
+        written_keys: metrics.written_keys,
+        written_bytes: metrics.written_bytes,
+        not_leader_errors: metrics.not_leader_errors,
+        epoch_not_match_errors: metrics.epoch_not_match_errors,
+        server_is_busy_errors: metrics.server_is_busy_errors,
+        snapshot_read_retries: metrics.snapshot_read_retries,
+        in_flight_operations: metrics.in_flight_operations,
+        mirror_matches: metrics.mirror_matches,
+        mirror_mismatches: metrics.mirror_mismatches,
+        mirror_errors: metrics.mirror_errors,
+        mirror_dropped: metrics.mirror_dropped,
+        smart_get_scanned_keys: metrics.smart_get_scanned_keys,
+        smart_get_batched_keys: metrics.smart_get_batched_keys,
+        smart_get_extra_pairs: metrics.smart_get_extra_pairs,
+        skipped_noop_writes: metrics.skipped_noop_writes,
+        prewrites: metrics.prewrites,
+        failed_log_writes: metrics.failed_log_writes,
+    }
+}
+
+fn client_reset_metrics(client: &TransactionClient) {
+    client.reset_metrics();
+}
+
+fn client_set_log_failure_mode(client: &TransactionClient, mode: LogFailureMode) {
+    client.set_log_failure_mode(match mode {
+        LogFailureMode::Drop => logger::LogFailureMode::Drop,
+        LogFailureMode::Stderr => logger::LogFailureMode::Stderr,
+        _ => panic!("unexpected log failure mode"),
+    });
+}
+
+fn client_set_region_error_warn_threshold(client: &TransactionClient, per_minute: u64) {
+    client.set_region_error_warn_threshold(per_minute);
+}
+
+fn client_self_check(
+    client: &TransactionClient,
+    timeout_ms: u64,
+    write_probe: bool,
+) -> Vec<CheckItem> {
+    client
+        .self_check(Duration::from_millis(timeout_ms), write_probe)
+        .into_iter()
+        .map(|item| CheckItem {
+            name: item.name,
+            ok: item.ok,
+            detail: item.detail,
+        })
+        .collect()
+}
+
+fn client_check_clock_skew(
+    client: &TransactionClient,
+    max_skew_ms: u64,
+) -> Result<ClockSkewReport> {
+    let report = client.check_clock_skew(Duration::from_millis(max_skew_ms))?;
+    Ok(ClockSkewReport {
+        pd_physical_ms: report.pd_physical_ms,
+        local_ms: report.local_ms,
+        skew_ms: report.skew_ms,
+        within_bound: report.within_bound,
+    })
+}
+
+fn client_finalize_commit(
+    client: &TransactionClient,
+    primary_key: &CxxString,
+    start_ts: u64,
+    commit_ts: u64,
+) -> Result<FinalizeResult> {
+    let result = client.finalize_commit(primary_key.as_bytes(), start_ts, commit_ts)?;
+    Ok(FinalizeResult {
+        already_committed: result.already_committed,
+    })
+}
+
+fn client_register_service_safepoint(
+    client: &TransactionClient,
+    service_id: &CxxString,
+    safepoint_ts: u64,
+    ttl_secs: u64,
+) -> Result<Box<SafepointGuard>> {
+    let guard = client.register_service_safepoint(
+        service_id.to_str()?,
+        safepoint_ts,
+        Duration::from_secs(ttl_secs),
+    )?;
+    Ok(Box::new(guard))
+}
+
+fn safepoint_guard_healthy(guard: &SafepointGuard) -> bool {
+    guard.healthy()
+}
+
+fn safepoint_guard_release(guard: &mut SafepointGuard) -> Result<()> {
+    guard.release()
+}
+
+fn client_rmw(
+    client: &TransactionClient,
+    key: &CxxString,
+    callback: &RmwCallback,
+    max_retries: u32,
+) -> Result<Vec<u8>> {
+    client.read_modify_write(key.as_bytes(), max_retries, |current| {
+        let decision = callback.modify(&current.map(ToOwned::to_owned).into())?;
+        Ok(if decision.abort {
+            None
+        } else {
+            Some(decision.value)
+        })
+    })
+}
+
+fn client_run_optimistic(
+    client: &TransactionClient,
+    callback: &TransactionCallback,
+    max_attempts: u32,
+    backoff_base_ms: u64,
+    backoff_max_ms: u64,
+) -> Result<u64> {
+    client.run_optimistic(
+        max_attempts,
+        Duration::from_millis(backoff_base_ms),
+        Duration::from_millis(backoff_max_ms),
+        |txn| Ok(callback.run(txn)?),
+    )
+}
+
+fn client_copy_range(
+    client: &TransactionClient,
+    source: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    keys_per_txn: u32,
+    concurrency: u32,
+) -> Result<u64> {
+    let _busy = source.enter()?;
+    client.copy_range(
+        source,
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        keys_per_txn,
+        concurrency,
+    )
+}
+
+fn client_copy_range_partial(
+    client: &TransactionClient,
+    source: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    keys_per_txn: u32,
+    concurrency: u32,
+) -> PartialOutcome {
+    let _busy = match source.enter() {
+        Ok(busy) => busy,
+        Err(e) => {
+            let resume_token = sync::resume_token(to_bound(start, start_bound));
+            return sync::PartialOutcome::new(0, 0, Err(e), resume_token).into();
+        }
+    };
+    client
+        .copy_range_partial(
+            source,
+            to_bound(start, start_bound),
+            to_bound(end, end_bound),
+            keys_per_txn,
+            concurrency,
+        )
+        .into()
+}
+
+fn client_range_diff(
+    client: &TransactionClient,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    old_version: u64,
+    new_version: u64,
+    limit: u64,
+) -> Result<Vec<DiffEntry>> {
+    let diff = client.range_diff(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        old_version,
+        new_version,
+        to_scan_limit(limit)?,
+    )?;
+    Ok(diff
+        .into_iter()
+        .map(|entry| DiffEntry {
+            key: entry.key.into(),
+            change: match entry.change {
+                sync::Change::Added => DiffChange::Added,
+                sync::Change::Removed => DiffChange::Removed,
+                sync::Change::Modified => DiffChange::Modified,
+            },
+            new_value_len: entry.new_value_len,
+        })
+        .collect())
+}
+
+fn client_delete_range_chunked(
+    client: &TransactionClient,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    keys_per_txn: u32,
+    pessimistic: bool,
+) -> Result<u64> {
+    client.delete_range_chunked(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        keys_per_txn,
+        pessimistic,
+    )
+}
+
+fn client_delete_range_partial(
+    client: &TransactionClient,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    keys_per_txn: u32,
+    pessimistic: bool,
+) -> PartialOutcome {
+    client
+        .delete_range_partial(
+            to_bound(start, start_bound),
+            to_bound(end, end_bound),
+            keys_per_txn,
+            pessimistic,
+        )
+        .into()
+}
+
+fn client_get_mvcc_versions(
+    client: &TransactionClient,
+    key: &CxxString,
+    max_versions: u32,
+) -> Result<Vec<MvccVersion>> {
+    let versions = client.get_mvcc_versions(key.as_bytes(), max_versions)?;
+    Ok(versions.into_iter().map(Into::into).collect())
+}
+
+fn client_presplit_for_keys(
+    client: &TransactionClient,
+    sample_keys: &CxxVector<CxxString>,
+    scatter: bool,
+) -> Result<u32> {
+    client.presplit_for_keys(sample_keys.iter().map(CxxString::as_bytes), scatter)
+}
+
+fn client_multi_version_get(
+    client: &TransactionClient,
+    key: &CxxString,
+    timestamps: &CxxVector<u64>,
+) -> Vec<VersionedValue> {
+    let timestamps = timestamps.iter().copied().collect::<Vec<_>>();
+    client
+        .multi_version_get(key.as_bytes(), &timestamps)
+        .into_iter()
+        .map(|result| match result {
+            Ok(value) => VersionedValue {
+                value: value.into(),
+                error: String::new(),
+            },
+            Err(e) => VersionedValue {
+                value: None.into(),
+                error: format!("{:?}", e),
+            },
+        })
+        .collect()
+}
+
+fn transaction_client_begin(client: &TransactionClient) -> Result<Box<Transaction>> {
+    Ok(Box::new(client.begin_optimistic()?))
+}
+
+fn transaction_client_begin_pessimistic_with_backoff(
+    client: &TransactionClient,
+    base_ms: u64,
+    max_ms: u64,
+    attempts: u32,
+) -> Result<Box<Transaction>> {
+    Ok(Box::new(client.begin_pessimistic_with_backoff(
+        sync::BackoffOptions {
+            base_delay_ms: base_ms,
+            max_delay_ms: max_ms,
+            max_attempts: attempts,
+        },
+    )?))
+}
+
+fn transaction_client_begin_read_only(client: &TransactionClient) -> Result<Box<Transaction>> {
+    Ok(Box::new(client.begin_read_only()?))
+}
+
+fn transaction_client_begin_pessimistic(client: &TransactionClient) -> Result<Box<Transaction>> {
+    Ok(Box::new(client.begin_pessimistic()?))
+}
+
+fn transaction_client_begin_pessimistic_with_isolation(
+    client: &TransactionClient,
+    isolation: IsolationLevel,
+) -> Result<Box<Transaction>> {
+    Ok(Box::new(client.begin_pessimistic_with_isolation(
+        to_isolation_level(isolation),
+    )?))
+}
+
+fn transaction_client_begin_pessimistic_with_options(
+    client: &TransactionClient,
+    options: &PessimisticOptions,
+) -> Result<Box<Transaction>> {
+    Ok(Box::new(client.begin_pessimistic_with_options(
+        sync::PessimisticOptions {
+            isolation: to_isolation_level(options.isolation),
+            pipelined_locking: options.pipelined_locking,
+        },
+    )?))
+}
+
+fn to_isolation_level(isolation: IsolationLevel) -> sync::IsolationLevel {
+    match isolation {
+        IsolationLevel::SnapshotIsolation => sync::IsolationLevel::SnapshotIsolation,
+        IsolationLevel::ReadCommitted => sync::IsolationLevel::ReadCommitted,
+        _ => panic!("unexpected isolation level"),
+    }
+}
+
+fn transaction_client_begin_optimistic_with_option(
+    client: &TransactionClient,
+    retry: u32,
+) -> Result<Box<Transaction>> {
+    Ok(Box::new(client.begin_optimistic_with_retry(retry)?))
+}
+
+fn transaction_get(transaction: &mut Transaction, key: &CxxString) -> Result<OptionalValue> {
+    let _busy = transaction.enter()?;
+    Ok(transaction.get(key.as_bytes())?.into())
+}
+
+fn transaction_get_traced(transaction: &mut Transaction, key: &CxxString) -> Result<TracedValue> {
+    let _busy = transaction.enter()?;
+    let traced = transaction.get_traced(key.as_bytes())?;
+    Ok(TracedValue {
+        is_none: traced.value.is_none(),
+        value: traced.value.unwrap_or_default(),
+        source: traced.source as u8,
+        elapsed_us: traced.elapsed.as_micros() as u64,
+        region_id: traced.region_id.unwrap_or(0),
+    })
+}
+
+fn transaction_start_version(transaction: &Transaction) -> u64 {
+    transaction.start_version()
+}
+
+fn transaction_fork_snapshot(
+    transaction: &mut Transaction,
+    client: &TransactionClient,
+    include_buffered: bool,
+) -> Result<Box<Snapshot>> {
+    let _busy = transaction.enter()?;
+    Ok(Box::new(
+        transaction.fork_snapshot(client, include_buffered)?,
+    ))
+}
+
+fn transaction_get_for_update(
+    transaction: &mut Transaction,
+    key: &CxxString,
+) -> Result<OptionalValue> {
+    let _busy = transaction.enter()?;
+    Ok(transaction.get_for_update(key.as_bytes())?.into())
+}
+
+fn transaction_get_and_lock_if_present(
+    transaction: &mut Transaction,
+    key: &CxxString,
+) -> Result<OptionalValue> {
+    let _busy = transaction.enter()?;
+    Ok(transaction.get_and_lock_if_present(key.as_bytes())?.into())
+}
+
+fn transaction_get_value_length(
+    transaction: &mut Transaction,
+    key: &CxxString,
+) -> Result<OptionalLength> {
+    let _busy = transaction.enter()?;
+    Ok(transaction.get_value_length(key.as_bytes())?.into())
+}
+
+fn transaction_get_into(
+    transaction: &mut Transaction,
+    key: &CxxString,
+    buf: &mut [u8],
+) -> Result<GetIntoResult> {
+    let _busy = transaction.enter()?;
+    let length = transaction.get_into(key.as_bytes(), buf)?;
+    Ok(to_get_into_result(length, buf))
+}
+
+fn transaction_batch_get(
+    transaction: &mut Transaction,
+    keys: &CxxVector<CxxString>,
+) -> Result<Vec<KvPair>> {
+    let _busy = transaction.enter()?;
+    let kv_pairs = transaction.batch_get(keys.iter().map(CxxString::as_bytes))?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
+}
+
+fn transaction_batch_get_limited(
+    transaction: &mut Transaction,
+    keys: &CxxVector<CxxString>,
+    max_bytes: u64,
+) -> Result<BatchGetLimitedResult> {
+    let _busy = transaction.enter()?;
+    let keys = keys.iter().map(CxxString::as_bytes).collect::<Vec<_>>();
+    Ok(transaction.batch_get_limited(&keys, max_bytes)?.into())
+}
+
+fn transaction_batch_get_for_update(
+    _transaction: &mut Transaction,
+    _keys: &CxxVector<CxxString>,
+) -> Result<Vec<KvPair>> {
+    unimplemented!("batch_get_for_update is not working properly so far.")
+}
+
+fn transaction_written_keys(transaction: &Transaction) -> Result<Vec<WrittenKey>> {
+    let _busy = transaction.enter()?;
+    Ok(transaction
+        .written_keys()
+        .into_iter()
+        .map(|(key, write_type)| WrittenKey {
+            key: key.into(),
+            write_type: match write_type {
+                sync::WriteType::Put => WriteType::Put,
+                sync::WriteType::Delete => WriteType::Delete,
+            },
+        })
+        .collect())
+}
+
+fn transaction_conflict_report(transaction: &Transaction) -> Result<Vec<ConflictEntry>> {
+    let _busy = transaction.enter()?;
+    Ok(transaction
+        .conflict_report()
+        .into_iter()
+        .map(|entry| ConflictEntry {
+            key: entry.key,
+            kind: entry.kind as u8,
+            other_start_ts: entry.other_start_ts,
+        })
+        .collect())
+}
+
+fn transaction_pessimistic_rollback_keys(
+    transaction: &mut Transaction,
+    keys: &CxxVector<CxxString>,
+) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.pessimistic_rollback_keys(keys.iter().map(CxxString::as_bytes))
+}
+
+fn transaction_region_span(transaction: &Transaction, client: &TransactionClient) -> Result<u64> {
+    let _busy = transaction.enter()?;
+    client.region_span(transaction)
+}
+
+fn transaction_assert_unchanged(
+    transaction: &mut Transaction,
+    keys: &CxxVector<CxxString>,
+) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.assert_unchanged(keys.iter().map(CxxString::as_bytes))
+}
+
+fn transaction_scan(
+    transaction: &mut Transaction,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<Vec<KvPair>> {
+    let _busy = transaction.enter()?;
+    let kv_pairs = transaction.scan(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
+}
+
+fn transaction_scan_with_codec(
+    transaction: &mut Transaction,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+    bounds_are_decoded: bool,
+    decode_keys: bool,
+) -> Result<Vec<KvPair>> {
+    let _busy = transaction.enter()?;
+    let kv_pairs = transaction.scan_with_codec(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+        sync::ScanCodec {
+            bounds_are_decoded,
+            decode_keys,
+        },
+    )?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
+}
+
+fn transaction_scan_with_truncation(
+    transaction: &mut Transaction,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<ScanResult> {
+    let _busy = transaction.enter()?;
+    let result = transaction.scan_with_truncation(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(result.into())
+}
+
+fn transaction_scan_packed(
+    transaction: &mut Transaction,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<PackedScanResult> {
+    let _busy = transaction.enter()?;
+    let kv_pairs = transaction.scan(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(packed::pack(kv_pairs).into())
+}
+
+fn transaction_scan_keys(
+    transaction: &mut Transaction,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<Vec<Key>> {
+    let _busy = transaction.enter()?;
+    let keys = transaction.scan_keys(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(keys.into_iter().map(Into::into).collect())
+}
+
+fn transaction_put(transaction: &mut Transaction, key: &CxxString, val: &CxxString) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.put(key.as_bytes(), val.as_bytes())
+}
+
+fn transaction_put_get_prev(
+    transaction: &mut Transaction,
+    key: &CxxString,
+    val: &CxxString,
+) -> Result<OptionalValue> {
+    let _busy = transaction.enter()?;
+    Ok(transaction
+        .put_get_prev(key.as_bytes(), val.as_bytes())?
+        .into())
+}
+
+fn transaction_delete(transaction: &mut Transaction, key: &CxxString) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.delete(key.as_bytes())
+}
+
+fn transaction_put_with_expiry(
+    transaction: &mut Transaction,
+    key: &CxxString,
+    val: &CxxString,
+    expire_at_ts: u64,
+) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.put_with_expiry(key.as_bytes(), val.as_bytes(), expire_at_ts)
+}
+
+fn transaction_get_with_expiry(
+    transaction: &mut Transaction,
+    key: &CxxString,
+    now_ts: u64,
+) -> Result<OptionalValue> {
+    let _busy = transaction.enter()?;
+    Ok(transaction.get_with_expiry(key.as_bytes(), now_ts)?.into())
+}
+
+fn client_purge_expired(
+    client: &TransactionClient,
+    prefix: &CxxString,
+    now_ts: u64,
+    keys_per_txn: u32,
+) -> Result<u64> {
+    client.purge_expired(prefix.as_bytes(), now_ts, keys_per_txn)
+}
+
+fn transaction_put_if_equals(
+    transaction: &mut Transaction,
+    key: &CxxString,
+    expected: &CxxString,
+    new_value: &CxxString,
+) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.put_if_equals(key.as_bytes(), expected.as_bytes(), new_value.as_bytes())
+}
+
+fn transaction_delete_if_equals(
+    transaction: &mut Transaction,
+    key: &CxxString,
+    expected: &CxxString,
+) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.delete_if_equals(key.as_bytes(), expected.as_bytes())
+}
+
+fn transaction_set_memory_limit(transaction: &mut Transaction, bytes: u64) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.set_memory_limit(if bytes == 0 { None } else { Some(bytes) });
+    Ok(())
+}
+
+fn transaction_buffered_bytes(transaction: &Transaction) -> u64 {
+    transaction.buffered_bytes()
+}
+
+fn transaction_is_readonly(transaction: &Transaction) -> bool {
+    transaction.is_read_only()
+}
+
+fn transaction_state(transaction: &Transaction) -> u8 {
+    transaction.state() as u8
+}
+
+fn transaction_set_priority(transaction: &mut Transaction, priority: Priority) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.set_priority(to_priority(priority))
+}
+
+fn transaction_for_update_ts(transaction: &Transaction) -> Result<u64> {
+    let _busy = transaction.enter()?;
+    transaction.for_update_ts()
+}
+
+fn transaction_refresh_for_update_ts(transaction: &mut Transaction) -> Result<u64> {
+    let _busy = transaction.enter()?;
+    transaction.refresh_for_update_ts()
+}
+
+fn transaction_set_commit_stats(transaction: &mut Transaction, enabled: bool) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.set_commit_stats(enabled);
+    Ok(())
+}
+
+fn transaction_enable_op_log(transaction: &mut Transaction, max_entries: u32) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.set_op_log(Some(max_entries as usize));
+    Ok(())
+}
+
+/// An empty tag removes the one set.
+fn transaction_set_tag(transaction: &mut Transaction, tag: &CxxString) -> Result<()> {
+    let _busy = transaction.enter()?;
+    let tag = tag.to_str()?;
+    transaction.set_tag(Some(tag).filter(|tag| !tag.is_empty()));
+    Ok(())
+}
+
+/// A deadline of 0 removes the one set.
+fn transaction_set_deadline(transaction: &mut Transaction, deadline_unix_ms: u64) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.set_deadline(Some(deadline_unix_ms).filter(|&deadline_ms| deadline_ms > 0));
+    Ok(())
+}
+
+fn transaction_enable_skip_noop_writes(transaction: &mut Transaction) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.set_skip_noop_writes(true);
+    Ok(())
+}
+
+fn transaction_dump_op_log(transaction: &Transaction) -> Vec<OpLogEntry> {
+    transaction
+        .op_log()
+        .into_iter()
+        .map(|entry| OpLogEntry {
+            op: entry.kind.name().to_owned(),
+            key_hex: sync::to_hex(&entry.key, usize::MAX),
+            value_len: entry.value_len,
+            timestamp_ms: entry.timestamp_ms,
+            outcome: entry.error.unwrap_or_else(|| "ok".to_owned()),
+        })
+        .collect()
+}
+
+fn transaction_last_commit_stats(transaction: &Transaction) -> CommitStats {
+    transaction.last_commit_stats().unwrap_or_default().into()
+}
+
+fn transaction_commit_chunked(
+    transaction: &mut Transaction,
+    keys_per_txn: u32,
+) -> Result<ChunkedCommitResult> {
+    let _busy = transaction.enter()?;
+    let result = transaction.commit_chunked(keys_per_txn)?;
+    Ok(ChunkedCommitResult {
+        chunks: result.chunks,
+        last_commit_ts: result.last_commit_ts,
+    })
+}
+
+fn transaction_commit_info(transaction: &Transaction) -> Result<CommitInfo> {
+    let _busy = transaction.enter()?;
+    let commit_info = transaction.commit_info()?;
+    Ok(CommitInfo {
+        protocol: commit_info.protocol as u8,
+        commit_ts: commit_info.commit_ts,
+    })
+}
+
+fn transaction_commit(transaction: &mut Transaction) -> Result<u64> {
+    let _busy = transaction.enter()?;
+    transaction.commit()
+}
+
+fn transaction_rollback(transaction: &mut Transaction) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.rollback()
+}
+
+fn transaction_prewrite_primary(
+    transaction: &mut Transaction,
+    primary_key: &CxxString,
+) -> Result<PrewriteResult> {
+    let _busy = transaction.enter()?;
+    let primary_key = if primary_key.is_empty() {
+        None
+    } else {
+        Some(primary_key.as_bytes())
+    };
+    let (key, version) = transaction.prewrite_primary(primary_key)?;
+    Ok(PrewriteResult {
+        key: key.into(),
+        version,
+    })
+}
+
+fn transaction_prewrite_primary_with_strategy(
+    transaction: &mut Transaction,
+    strategy: PrimarySelection,
+    primary_key: &CxxString,
+) -> Result<PrewriteResult> {
+    let _busy = transaction.enter()?;
+    let selection = match strategy {
+        PrimarySelection::FirstWritten => sync::PrimarySelection::FirstWritten,
+        PrimarySelection::SmallestKey => sync::PrimarySelection::SmallestKey,
+        PrimarySelection::LargestKey => sync::PrimarySelection::LargestKey,
+        PrimarySelection::Explicit => sync::PrimarySelection::Explicit(primary_key.as_bytes()),
+        _ => panic!("unexpected primary selection"),
+    };
+    let (key, version) = transaction.prewrite_primary_with_strategy(selection)?;
+    Ok(PrewriteResult {
+        key: key.into(),
+        version,
+    })
+}
+
+fn transaction_prewrite_secondary(
+    transaction: &mut Transaction,
+    primary_key: &CxxString,
+    start_ts: u64,
+    allow_empty: bool,
+) -> Result<SecondaryPrewriteResult> {
+    let _busy = transaction.enter()?;
+    let result = transaction.prewrite_secondary(primary_key.as_bytes(), start_ts, allow_empty)?;
+    Ok(SecondaryPrewriteResult {
+        mutation_count: result.mutation_count,
+        region_count: result.region_count,
+    })
+}
+
+fn transaction_commit_primary(transaction: &mut Transaction) -> Result<u64> {
+    let _busy = transaction.enter()?;
+    transaction.commit_primary()
+}
+
+fn transaction_commit_secondary(transaction: &mut Transaction, commit_ts: u64) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.commit_secondary(commit_ts)
+}
+
+fn snapshot_new(client: &TransactionClient) -> Result<Box<Snapshot>> {
+    Ok(Box::new(client.snapshot()?))
+}
+
+fn snapshot_new_fail_fast(client: &TransactionClient) -> Result<Box<Snapshot>> {
+    Ok(Box::new(client.snapshot_fail_fast()?))
+}
+
+fn snapshot_new_pinned(client: &TransactionClient) -> Result<Box<Snapshot>> {
+    Ok(Box::new(client.snapshot_pinned()?))
+}
+
+/// A null provider removes the one set.
+fn client_set_read_ts_provider(
+    client: &mut TransactionClient,
+    provider: UniquePtr<ReadTsProvider>,
+) {
+    if provider.is_null() {
+        return client.set_read_ts_provider(None);
+    }
+    client.set_read_ts_provider(Some(Box::new(move || Ok(provider.read_ts()?))));
+}
+
+fn snapshot_new_with_timestamp(
+    client: &TransactionClient,
+    timestamp: u64,
+) -> Result<Box<Snapshot>> {
+    Ok(Box::new(client.snapshot_at(timestamp)?))
+}
+
+fn current_timestamp(client: &TransactionClient) -> Result<u64> {
+    client.current_timestamp()
+}
+
+fn snapshot_set_priority(snapshot: &mut Snapshot, priority: Priority) -> Result<()> {
+    let _busy = snapshot.enter()?;
+    snapshot.set_priority(to_priority(priority))
+}
+
+fn snapshot_get(snapshot: &mut Snapshot, key: &CxxString) -> Result<OptionalValue> {
+    let _busy = snapshot.enter()?;
+    Ok(snapshot.get(key.as_bytes())?.into())
+}
+
+fn snapshot_prefetch(snapshot: &mut Snapshot, keys: &CxxVector<CxxString>) -> Result<()> {
+    let _busy = snapshot.enter()?;
+    snapshot.prefetch(keys.iter().map(CxxString::as_bytes))
+}
+
+fn snapshot_set_prefetch_cache_bytes(snapshot: &mut Snapshot, bytes: u64) -> Result<()> {
+    let _busy = snapshot.enter()?;
+    snapshot.set_prefetch_cache_bytes(bytes);
+    Ok(())
 }
 
-fn transaction_client_new_with_config(
-    pd_endpoints: &CxxVector<CxxString>,
-    log_path: &CxxString,
-    ca_path: &CxxString,
-    cert_path: &CxxString,
-    key_path: &CxxString,
-    timeout: u32,
-) -> Result<Box<TransactionClient>> {
-    let config = tikv_client::Config {
-        ca_path: Some(PathBuf::from(ca_path.to_str()?.to_string())),
-        cert_path: Some(PathBuf::from(cert_path.to_str()?.to_string())),
-        key_path: Some(PathBuf::from(key_path.to_str()?.to_string())),
-        timeout: Duration::from_secs(timeout as u64),
-    };
-    let log = create_slog_logger(log_path)?;
-    let pd_endpoints = pd_endpoints
-        .iter()
-        .map(|str| str.to_str().map(ToOwned::to_owned))
-        .collect::<std::result::Result<Vec<_>, _>>()?;
-
-    Ok(Box::new(TransactionClient {
-        inner: TOKIO_RUNTIME.block_on(tikv_client::TransactionClient::new_with_config(
-            pd_endpoints,
-            config,
-            Some(log),
-        ))?,
-    }))
+fn snapshot_prefetch_stats(snapshot: &Snapshot) -> PrefetchStats {
+    let stats = snapshot.prefetch_stats();
+    PrefetchStats {
+        hits: stats.hits,
+        misses: stats.misses,
+        dropped: stats.dropped,
+    }
 }
 
-fn client_gc(client: &TransactionClient, safepoint: u64) -> Result<bool> {
-    let safepoint = Timestamp::from_version(safepoint);
-    Ok(TOKIO_RUNTIME.block_on(client.inner.gc(safepoint))?)
+fn snapshot_get_raw(snapshot: &mut Snapshot, key: &CxxString) -> Result<OptionalValue> {
+    let _busy = snapshot.enter()?;
+    Ok(snapshot.get_raw(key.as_bytes())?.into())
 }
 
-fn transaction_client_begin(client: &TransactionClient) -> Result<Box<Transaction>> {
-    Ok(Box::new(Transaction {
-        inner: TOKIO_RUNTIME.block_on(client.inner.begin_optimistic())?,
-    }))
+fn snapshot_scan_raw(
+    snapshot: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<Vec<KvPair>> {
+    let _busy = snapshot.enter()?;
+    let kv_pairs = snapshot.scan_raw(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
 }
 
-fn transaction_client_begin_pessimistic(client: &TransactionClient) -> Result<Box<Transaction>> {
-    Ok(Box::new(Transaction {
-        inner: TOKIO_RUNTIME.block_on(client.inner.begin_pessimistic())?,
-    }))
+fn snapshot_get_value_length(snapshot: &mut Snapshot, key: &CxxString) -> Result<OptionalLength> {
+    let _busy = snapshot.enter()?;
+    Ok(snapshot.get_value_length(key.as_bytes())?.into())
 }
 
-fn transaction_client_begin_optimistic_with_option(
-    client: &TransactionClient,
-    retry: u32,
-) -> Result<Box<Transaction>> {
-    let options = TransactionOptions::new_optimistic();
-    let mut retry_options = request::RetryOptions::default_optimistic();
-    retry_options.lock_backoff = Backoff::no_jitter_backoff(2, 500, retry);
-    let options = options.retry_options(retry_options);
-    let timestamp = TOKIO_RUNTIME.block_on(client.inner.current_timestamp())?;
-    Ok(Box::new(Transaction {
-        inner: client
-            .inner
-            .new_transaction_with_options(timestamp, options),
-    }))
+fn snapshot_get_into(
+    snapshot: &mut Snapshot,
+    key: &CxxString,
+    buf: &mut [u8],
+) -> Result<GetIntoResult> {
+    let _busy = snapshot.enter()?;
+    let length = snapshot.get_into(key.as_bytes(), buf)?;
+    Ok(to_get_into_result(length, buf))
 }
 
-fn transaction_get(transaction: &mut Transaction, key: &CxxString) -> Result<OptionalValue> {
-    match TOKIO_RUNTIME.block_on(transaction.inner.get(key.as_bytes().to_owned()))? {
-        Some(value) => Ok(OptionalValue {
-            is_none: false,
-            value,
-        }),
-        None => Ok(OptionalValue {
-            is_none: true,
-            value: Vec::new(),
-        }),
-    }
+fn routed_client_new(split_scans: bool) -> Box<RoutedClient> {
+    Box::new(RoutedClient::new(split_scans))
 }
 
-fn transaction_get_for_update(
-    transaction: &mut Transaction,
+fn routed_client_add_route(
+    client: &mut RoutedClient,
+    prefix: &CxxString,
+    route_client: Box<TransactionClient>,
+) -> Result<()> {
+    client.add_route(prefix.as_bytes(), *route_client)
+}
+
+fn routed_client_begin(client: &RoutedClient, pessimistic: bool) -> Box<RoutedTransaction> {
+    Box::new(if pessimistic {
+        client.begin_pessimistic()
+    } else {
+        client.begin_optimistic()
+    })
+}
+
+fn routed_client_snapshot(client: &RoutedClient) -> Result<Box<RoutedSnapshot>> {
+    Ok(Box::new(client.snapshot()?))
+}
+
+fn routed_transaction_get(
+    transaction: &mut RoutedTransaction,
     key: &CxxString,
 ) -> Result<OptionalValue> {
-    match TOKIO_RUNTIME.block_on(transaction.inner.get_for_update(key.as_bytes().to_owned()))? {
-        Some(value) => Ok(OptionalValue {
-            is_none: false,
-            value,
-        }),
-        None => Ok(OptionalValue {
-            is_none: true,
-            value: Vec::new(),
-        }),
-    }
+    let _busy = transaction.enter()?;
+    Ok(transaction.get(key.as_bytes())?.into())
 }
 
-fn transaction_batch_get(
-    transaction: &mut Transaction,
-    keys: &CxxVector<CxxString>,
-) -> Result<Vec<KvPair>> {
-    let keys = keys.iter().map(|key| key.as_bytes().to_owned());
-    let kv_pairs = TOKIO_RUNTIME
-        .block_on(transaction.inner.batch_get(keys))?
-        .map(|tikv_client::KvPair(key, value)| KvPair {
-            key: key.into(),
-            value,
-        })
-        .collect();
-    Ok(kv_pairs)
+fn routed_transaction_put(
+    transaction: &mut RoutedTransaction,
+    key: &CxxString,
+    val: &CxxString,
+) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.put(key.as_bytes(), val.as_bytes())
 }
 
-fn transaction_batch_get_for_update(
-    _transaction: &mut Transaction,
-    _keys: &CxxVector<CxxString>,
-) -> Result<Vec<KvPair>> {
-    // let keys = keys.iter().map(|key| key.as_bytes().to_owned());
-    // let kv_pairs = TOKIO_RUNTIME.block_on(transaction.inner.batch_get_for_update(keys))?
-    //     .map(|tikv_client::KvPair(key, value)| KvPair {
-    //         key: key.into(),
-    //         value,
-    //     })
-    //     .collect();
-    // Ok(kv_pairs)
-    unimplemented!("batch_get_for_update is not working properly so far.")
+fn routed_transaction_delete(transaction: &mut RoutedTransaction, key: &CxxString) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.delete(key.as_bytes())
 }
 
-fn transaction_scan(
-    transaction: &mut Transaction,
+fn routed_transaction_scan(
+    transaction: &mut RoutedTransaction,
     start: &CxxString,
     start_bound: Bound,
     end: &CxxString,
     end_bound: Bound,
-    limit: u32,
+    limit: u64,
 ) -> Result<Vec<KvPair>> {
-    let range = to_bound_range(start, start_bound, end, end_bound);
-    let kv_pairs = TOKIO_RUNTIME
-        .block_on(transaction.inner.scan(range, limit))?
-        .map(|tikv_client::KvPair(key, value)| KvPair {
-            key: key.into(),
-            value,
-        })
-        .collect();
-    Ok(kv_pairs)
+    let _busy = transaction.enter()?;
+    let kv_pairs = transaction.scan(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
 }
 
-fn transaction_scan_keys(
-    transaction: &mut Transaction,
+fn routed_transaction_commit(transaction: &mut RoutedTransaction) -> Result<u64> {
+    let _busy = transaction.enter()?;
+    transaction.commit()
+}
+
+fn routed_transaction_rollback(transaction: &mut RoutedTransaction) -> Result<()> {
+    let _busy = transaction.enter()?;
+    transaction.rollback()
+}
+
+fn routed_snapshot_get(snapshot: &mut RoutedSnapshot, key: &CxxString) -> Result<OptionalValue> {
+    let _busy = snapshot.enter()?;
+    Ok(snapshot.get(key.as_bytes())?.into())
+}
+
+fn routed_snapshot_batch_get(
+    snapshot: &mut RoutedSnapshot,
+    keys: &CxxVector<CxxString>,
+) -> Result<Vec<KvPair>> {
+    let _busy = snapshot.enter()?;
+    let kv_pairs = snapshot.batch_get(keys.iter().map(CxxString::as_bytes))?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
+}
+
+fn routed_snapshot_scan(
+    snapshot: &mut RoutedSnapshot,
     start: &CxxString,
     start_bound: Bound,
     end: &CxxString,
     end_bound: Bound,
-    limit: u32,
-) -> Result<Vec<Key>> {
-    let range = to_bound_range(start, start_bound, end, end_bound);
-    let keys = TOKIO_RUNTIME
-        .block_on(transaction.inner.scan_keys(range, limit))?
-        .map(|key| Key { key: key.into() })
-        .collect();
-    Ok(keys)
+    limit: u64,
+) -> Result<Vec<KvPair>> {
+    let _busy = snapshot.enter()?;
+    let kv_pairs = snapshot.scan(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
 }
 
-fn transaction_put(transaction: &mut Transaction, key: &CxxString, val: &CxxString) -> Result<()> {
-    TOKIO_RUNTIME.block_on(
-        transaction
-            .inner
-            .put(key.as_bytes().to_owned(), val.as_bytes().to_owned()),
-    )?;
-    Ok(())
+/// A null `confirmation` switches without asking.
+fn failover_pair_new(
+    primary_client: Box<TransactionClient>,
+    standby_client: Box<TransactionClient>,
+    policy: FailoverPolicy,
+    confirmation: UniquePtr<FailoverConfirmation>,
+) -> Result<Box<FailoverClient>> {
+    let policy = sync::FailoverPolicy {
+        max_consecutive_failures: policy.max_consecutive_failures,
+        max_error_percent: policy.max_error_percent,
+        window: policy.window,
+    };
+    let confirmation: Option<sync::FailoverConfirmation> = if confirmation.is_null() {
+        None
+    } else {
+        Some(Box::new(move |consecutive_failures, error_percent| {
+            Ok(confirmation.confirm_failover(consecutive_failures, error_percent)?)
+        }))
+    };
+    Ok(Box::new(FailoverClient::new(
+        *primary_client,
+        *standby_client,
+        policy,
+        confirmation,
+    )?))
 }
 
-fn transaction_delete(transaction: &mut Transaction, key: &CxxString) -> Result<()> {
-    TOKIO_RUNTIME.block_on(transaction.inner.delete(key.as_bytes().to_owned()))?;
-    Ok(())
+fn failover_begin(client: &FailoverClient, pessimistic: bool) -> Result<Box<Transaction>> {
+    Ok(Box::new(if pessimistic {
+        client.begin_pessimistic()?
+    } else {
+        client.begin_optimistic()?
+    }))
 }
 
-fn transaction_commit(transaction: &mut Transaction) -> Result<()> {
-    TOKIO_RUNTIME.block_on(transaction.inner.commit())?;
-    Ok(())
+fn failover_snapshot(client: &FailoverClient) -> Result<Box<Snapshot>> {
+    Ok(Box::new(client.snapshot()?))
 }
 
-fn transaction_rollback(transaction: &mut Transaction) -> Result<()> {
-    TOKIO_RUNTIME.block_on(transaction.inner.rollback())?;
+/// `target` is a `FailoverTarget` of the C++ side.
+fn failover_switch(client: &FailoverClient, target: u8) -> Result<()> {
+    let target = match target {
+        0 => sync::FailoverTarget::Primary,
+        1 => sync::FailoverTarget::Standby,
+        _ => return Err(anyhow!("unknown failover target {}", target)),
+    };
+    client.switch(target);
     Ok(())
 }
 
-fn transaction_prewrite_primary(
-    transaction: &mut Transaction,
-    primary_key: &CxxString,
-) -> Result<PrewriteResult> {
-    let start = Instant::now();
-    let primary_key = if primary_key.is_empty() {
-        None
-    } else {
-        Some(primary_key.as_bytes().to_owned().into())
+fn failover_status(client: &FailoverClient) -> FailoverStatus {
+    client.status().into()
+}
+
+fn snapshot_batch_get(snapshot: &mut Snapshot, keys: &CxxVector<CxxString>) -> Result<Vec<KvPair>> {
+    let _busy = snapshot.enter()?;
+    let kv_pairs = snapshot.batch_get(keys.iter().map(CxxString::as_bytes))?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
+}
+
+fn snapshot_batch_get_limited(
+    snapshot: &mut Snapshot,
+    keys: &CxxVector<CxxString>,
+    max_bytes: u64,
+) -> Result<BatchGetLimitedResult> {
+    let _busy = snapshot.enter()?;
+    let keys = keys.iter().map(CxxString::as_bytes).collect::<Vec<_>>();
+    Ok(snapshot.batch_get_limited(&keys, max_bytes)?.into())
+}
+
+fn snapshot_smart_get(
+    snapshot: &mut Snapshot,
+    keys: &CxxVector<CxxString>,
+) -> Result<Vec<OptionalValue>> {
+    let _busy = snapshot.enter()?;
+    let keys = keys.iter().map(CxxString::as_bytes).collect::<Vec<_>>();
+    let values = snapshot.smart_get(&keys)?;
+    Ok(values.into_iter().map(Into::into).collect())
+}
+
+/// `algo` is a `HashAlgorithm` of the C++ side.
+fn snapshot_batch_get_hashes(
+    snapshot: &mut Snapshot,
+    keys: &CxxVector<CxxString>,
+    algo: u8,
+) -> Result<Vec<OptionalHash>> {
+    let _busy = snapshot.enter()?;
+    let algorithm = match algo {
+        0 => checksum::HashAlgorithm::Crc64,
+        1 => checksum::HashAlgorithm::Sha256,
+        _ => return Err(anyhow!("unknown hash algorithm {}", algo)),
     };
-    match TOKIO_RUNTIME.block_on(transaction.inner.prewrite_primary(primary_key)) {
-        Ok((key, ts)) => Ok({
-            debug!("prewrite primary time {:?}", start.elapsed());
-            PrewriteResult {
-                key: key.into(),
-                version: ts.version(),
-            }
-        }),
-        Err(e) => {
-            debug!("prewrite primary time {:?}", start.elapsed());
-            Err(e.into())
-        }
-    }
+    let keys = keys.iter().map(CxxString::as_bytes).collect::<Vec<_>>();
+    let hashes = snapshot.batch_get_hashes(&keys, algorithm)?;
+    Ok(hashes.into_iter().map(Into::into).collect())
 }
 
-fn transaction_prewrite_secondary(
-    transaction: &mut Transaction,
-    primary_key: &CxxString,
-    start_ts: u64,
+fn snapshot_set_smart_get_options(
+    snapshot: &mut Snapshot,
+    density_percent: u32,
+    min_run_keys: u32,
 ) -> Result<()> {
-    let start = Instant::now();
-    TOKIO_RUNTIME.block_on(transaction.inner.prewrite_secondary(
-        primary_key.as_bytes().to_owned().into(),
-        tikv_client::Timestamp::from_version(start_ts),
-    ))?;
-    debug!("prewrite secondary time {:?}", start.elapsed());
-    Ok(())
+    let _busy = snapshot.enter()?;
+    snapshot.set_smart_get_options(sync::SmartGetOptions {
+        density_percent,
+        min_run_keys,
+    })
 }
 
-fn transaction_commit_primary(transaction: &mut Transaction) -> Result<u64> {
-    let start = Instant::now();
-    match TOKIO_RUNTIME.block_on(transaction.inner.commit_primary()) {
-        Ok(ts) => {
-            debug!("commit primary time {:?}", start.elapsed());
-            Ok(ts.version())
-        }
-        Err(e) => Err(e.into()),
-    }
+fn snapshot_scan(
+    snapshot: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<Vec<KvPair>> {
+    let _busy = snapshot.enter()?;
+    let kv_pairs = snapshot.scan(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
 }
 
-fn transaction_commit_secondary(transaction: &mut Transaction, commit_ts: u64) {
-    let start = Instant::now();
-    TOKIO_RUNTIME.block_on(
-        transaction
-            .inner
-            .commit_secondary(tikv_client::Timestamp::from_version(commit_ts)),
-    );
-    debug!("commit secondary time {:?}", start.elapsed());
+fn snapshot_scan_until(
+    snapshot: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+    deadline_ms: u64,
+) -> Result<ScanUntilResult> {
+    let _busy = snapshot.enter()?;
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let deadline = Instant::now() + Duration::from_millis(deadline_ms.saturating_sub(now_ms));
+    let result = snapshot.scan_until(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+        deadline,
+    )?;
+    Ok(ScanUntilResult {
+        pairs: result.kv_pairs.into_iter().map(Into::into).collect(),
+        completed: result.completed,
+        resume_key: result.resume_key,
+    })
 }
 
-fn to_bound_range(
+fn snapshot_scan_with_codec(
+    snapshot: &mut Snapshot,
     start: &CxxString,
     start_bound: Bound,
     end: &CxxString,
     end_bound: Bound,
-) -> tikv_client::BoundRange {
-    let start_bound = match start_bound {
-        Bound::Included => ops::Bound::Included(start.as_bytes().to_owned()),
-        Bound::Excluded => ops::Bound::Excluded(start.as_bytes().to_owned()),
-        Bound::Unbounded => ops::Bound::Unbounded,
-        _ => panic!("unexpected bound"),
-    };
-    let end_bound = match end_bound {
-        Bound::Included => ops::Bound::Included(end.as_bytes().to_owned()),
-        Bound::Excluded => ops::Bound::Excluded(end.as_bytes().to_owned()),
-        Bound::Unbounded => ops::Bound::Unbounded,
-        _ => panic!("unexpected bound"),
-    };
-    tikv_client::BoundRange::from((start_bound, end_bound))
+    limit: u64,
+    bounds_are_decoded: bool,
+    decode_keys: bool,
+) -> Result<Vec<KvPair>> {
+    let _busy = snapshot.enter()?;
+    let kv_pairs = snapshot.scan_with_codec(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+        sync::ScanCodec {
+            bounds_are_decoded,
+            decode_keys,
+        },
+    )?;
+    Ok(kv_pairs.into_iter().map(Into::into).collect())
 }
 
-fn snapshot_new(client: &TransactionClient) -> Result<Box<Snapshot>> {
-    let timestamp = TOKIO_RUNTIME.block_on(client.inner.current_timestamp())?;
-    Ok(Box::new(Snapshot {
-        inner: client
-            .inner
-            .snapshot(timestamp, TransactionOptions::new_optimistic()),
-    }))
+fn snapshot_scan_with_truncation(
+    snapshot: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<ScanResult> {
+    let _busy = snapshot.enter()?;
+    let result = snapshot.scan_with_truncation(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(result.into())
 }
 
-fn snapshot_new_with_timestamp(
-    client: &TransactionClient,
-    timestamp: u64,
-) -> Result<Box<Snapshot>> {
-    let timestamp = tikv_client::Timestamp::from_version(timestamp);
-    Ok(Box::new(Snapshot {
-        inner: client
-            .inner
-            .snapshot(timestamp, TransactionOptions::new_optimistic()),
-    }))
+fn snapshot_scan_skipping_oversized(
+    snapshot: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<SkippingScanResult> {
+    let _busy = snapshot.enter()?;
+    let result = snapshot.scan_skipping_oversized(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(SkippingScanResult {
+        pairs: result.kv_pairs.into_iter().map(Into::into).collect(),
+        skipped: result.skipped.into_iter().map(Into::into).collect(),
+    })
 }
 
-fn current_timestamp(client: &TransactionClient) -> Result<u64> {
-    let timestamp = TOKIO_RUNTIME.block_on(client.inner.current_timestamp())?;
-    Ok(timestamp.version())
+fn snapshot_scan_packed(
+    snapshot: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<PackedScanResult> {
+    let _busy = snapshot.enter()?;
+    let kv_pairs = snapshot.scan(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(packed::pack(kv_pairs).into())
 }
 
-fn snapshot_get(snapshot: &mut Snapshot, key: &CxxString) -> Result<OptionalValue> {
-    match TOKIO_RUNTIME.block_on(snapshot.inner.get(key.as_bytes().to_owned()))? {
-        Some(value) => Ok(OptionalValue {
-            is_none: false,
-            value,
-        }),
-        None => Ok(OptionalValue {
-            is_none: true,
-            value: Vec::new(),
-        }),
-    }
+#[allow(clippy::too_many_arguments)]
+fn snapshot_scan_pair(
+    snapshot: &mut Snapshot,
+    start_a: &CxxString,
+    start_a_bound: Bound,
+    end_a: &CxxString,
+    end_a_bound: Bound,
+    start_b: &CxxString,
+    start_b_bound: Bound,
+    end_b: &CxxString,
+    end_b_bound: Bound,
+    limit: u64,
+) -> Result<ScanPair> {
+    let _busy = snapshot.enter()?;
+    let (a, b) = snapshot.scan_pair(
+        (
+            to_bound(start_a, start_a_bound),
+            to_bound(end_a, end_a_bound),
+        ),
+        (
+            to_bound(start_b, start_b_bound),
+            to_bound(end_b, end_b_bound),
+        ),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(ScanPair {
+        a: a.into_iter().map(Into::into).collect(),
+        b: b.into_iter().map(Into::into).collect(),
+    })
 }
 
-fn snapshot_batch_get(snapshot: &mut Snapshot, keys: &CxxVector<CxxString>) -> Result<Vec<KvPair>> {
-    let keys = keys.iter().map(|key| key.as_bytes().to_owned());
-    let kv_pairs = TOKIO_RUNTIME
-        .block_on(snapshot.inner.batch_get(keys))?
-        .map(|tikv_client::KvPair(key, value)| KvPair {
-            key: key.into(),
-            value,
-        })
-        .collect();
-    Ok(kv_pairs)
+fn snapshot_scan_keys(
+    snapshot: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    limit: u64,
+) -> Result<Vec<Key>> {
+    let _busy = snapshot.enter()?;
+    let keys = snapshot.scan_keys(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(keys.into_iter().map(Into::into).collect())
 }
 
-fn snapshot_scan(
+fn snapshot_scan_key_sizes(
     snapshot: &mut Snapshot,
     start: &CxxString,
     start_bound: Bound,
     end: &CxxString,
     end_bound: Bound,
-    limit: u32,
-) -> Result<Vec<KvPair>> {
-    let range = to_bound_range(start, start_bound, end, end_bound);
-    let kv_pairs = TOKIO_RUNTIME
-        .block_on(snapshot.inner.scan(range, limit))?
-        .map(|tikv_client::KvPair(key, value)| KvPair {
+    limit: u64,
+) -> Result<Vec<KeySize>> {
+    let _busy = snapshot.enter()?;
+    let key_sizes = snapshot.scan_key_sizes(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        to_scan_limit(limit)?,
+    )?;
+    Ok(key_sizes
+        .into_iter()
+        .map(|(key, value_len)| KeySize {
             key: key.into(),
-            value,
+            value_len,
         })
-        .collect();
-    Ok(kv_pairs)
+        .collect())
 }
 
-fn snapshot_scan_keys(
+fn snapshot_checksum_range(
     snapshot: &mut Snapshot,
     start: &CxxString,
     start_bound: Bound,
     end: &CxxString,
     end_bound: Bound,
-    limit: u32,
-) -> Result<Vec<Key>> {
-    let range = to_bound_range(start, start_bound, end, end_bound);
-    let keys = TOKIO_RUNTIME
-        .block_on(snapshot.inner.scan_keys(range, limit))?
-        .map(|key| Key { key: key.into() })
-        .collect();
-    Ok(keys)
+) -> Result<ChecksumResult> {
+    let _busy = snapshot.enter()?;
+    let checksum::Checksum {
+        checksum,
+        total_kvs,
+        total_bytes,
+    } = snapshot.checksum_range(to_bound(start, start_bound), to_bound(end, end_bound))?;
+    Ok(ChecksumResult {
+        checksum,
+        total_kvs,
+        total_bytes,
+    })
+}
+
+fn snapshot_export_range(
+    snapshot: &mut Snapshot,
+    start: &CxxString,
+    start_bound: Bound,
+    end: &CxxString,
+    end_bound: Bound,
+    file_path: &CxxString,
+) -> Result<u64> {
+    let _busy = snapshot.enter()?;
+    snapshot.export_range(
+        to_bound(start, start_bound),
+        to_bound(end, end_bound),
+        file_path.to_str()?,
+    )
+}
+
+fn client_import_file(
+    client: &TransactionClient,
+    file_path: &CxxString,
+    keys_per_txn: u32,
+) -> Result<u64> {
+    client.import_file(file_path.to_str()?, keys_per_txn)
+}
+
+fn shutdown_runtime() {
+    sync::shutdown_runtime();
+}
+
+fn client_capabilities() -> Vec<u8> {
+    sync::capabilities()
+        .into_iter()
+        .map(|capability| capability as u8)
+        .collect()
+}
+
+fn format_key_hex(key: &CxxString) -> String {
+    sync::format_key(key.as_bytes())
+}
+
+fn parse_key_hex(text: &CxxString) -> Result<Vec<u8>> {
+    sync::parse_key(text.to_str()?)
+}
+
+fn version_from_unix_ms(ms: u64) -> u64 {
+    sync::version_from_unix_ms(ms)
+}
+
+fn unix_ms_of_version(version: u64) -> u64 {
+    sync::unix_ms_of_version(version)
+}
+
+fn hash_kv_pairs(keys: &CxxVector<CxxString>, values: &CxxVector<CxxString>) -> Result<Vec<u8>> {
+    if keys.len() != values.len() {
+        return Err(anyhow!(
+            "got {} keys but {} values",
+            keys.len(),
+            values.len()
+        ));
+    }
+    let kv_pairs = keys
+        .iter()
+        .map(CxxString::as_bytes)
+        .zip(values.iter().map(CxxString::as_bytes));
+    Ok(checksum::hash_kv_pairs(kv_pairs).to_vec())
+}
+
+fn encode_u64_asc(value: u64) -> Vec<u8> {
+    codec::encode_u64_asc(value).to_vec()
+}
+
+fn encode_u64_desc(value: u64) -> Vec<u8> {
+    codec::encode_u64_desc(value).to_vec()
+}
+
+fn encode_i64_asc(value: i64) -> Vec<u8> {
+    codec::encode_i64_asc(value).to_vec()
+}
+
+fn encode_i64_desc(value: i64) -> Vec<u8> {
+    codec::encode_i64_desc(value).to_vec()
+}
+
+fn decode_u64_asc(data: &CxxString) -> Result<u64> {
+    codec::decode_u64_asc(data.as_bytes())
+}
+
+fn decode_u64_desc(data: &CxxString) -> Result<u64> {
+    codec::decode_u64_desc(data.as_bytes())
+}
+
+fn decode_i64_asc(data: &CxxString) -> Result<i64> {
+    codec::decode_i64_asc(data.as_bytes())
+}
+
+fn decode_i64_desc(data: &CxxString) -> Result<i64> {
+    codec::decode_i64_desc(data.as_bytes())
+}
+
+fn encode_bytes(data: &CxxString) -> Vec<u8> {
+    codec::encode_bytes(data.as_bytes())
+}
+
+fn decode_bytes(data: &CxxString) -> Result<DecodedBytes> {
+    let (value, consumed) = codec::decode_bytes(data.as_bytes())?;
+    Ok(DecodedBytes {
+        value,
+        consumed: consumed as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_limits_map_onto_u32() {
+        assert_eq!(to_scan_limit(0).unwrap(), 0);
+        assert_eq!(to_scan_limit(1).unwrap(), 1);
+        assert_eq!(to_scan_limit(u32::MAX as u64).unwrap(), u32::MAX);
+        let error = to_scan_limit(u32::MAX as u64 + 1).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "scan limit {} is over the maximum of {} pairs per call, pass 0 to scan the whole range",
+                u32::MAX as u64 + 1,
+                u32::MAX
+            )
+        );
+        assert!(to_scan_limit(u64::MAX).is_err());
+    }
 }
@@ -0,0 +1,278 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::fs::OpenOptions;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, TimeZone};
+use slog::{o, Drain, OwnedKVList, Record};
+
+const DEFAULT_CHAN_SIZE: usize = 4096;
+
+/// What the logger does with a record it fails to write to the log file, e.g.
+/// on a full disk. Either way the failure is counted and never aborts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFailureMode {
+    Drop,
+    Stderr,
+}
+
+/// What the clones of a [`Logger`] share.
+#[derive(Default)]
+struct LogState {
+    fallback_to_stderr: AtomicBool,
+    failed_writes: AtomicU64,
+}
+
+/// Logs to `file`, handling its failures as set by
+/// [`Logger::set_failure_mode`] instead of panicking the drain thread like a
+/// fused drain would.
+struct FallbackDrain<D> {
+    file: D,
+    stderr: slog_term::FullFormat<slog_term::PlainSyncDecorator<io::Stderr>>,
+    state: Arc<LogState>,
+}
+
+impl<D: Drain> Drain for FallbackDrain<D> {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+        if self.file.log(record, values).is_err() {
+            self.state.failed_writes.fetch_add(1, Ordering::Relaxed);
+            if self.state.fallback_to_stderr.load(Ordering::Relaxed) {
+                let _ = self.stderr.log(record, values);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counts the records the async drain refuses, e.g. with its channel full
+/// behind a stalled disk, as failed writes, instead of dropping them
+/// unnoticed.
+struct CountingDrain<D> {
+    drain: D,
+    state: Arc<LogState>,
+}
+
+impl<D: Drain> Drain for CountingDrain<D> {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+        if self.drain.log(record, values).is_err() {
+            self.state.failed_writes.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// The path of the log file created at `now` under `log_dir`, joined with the
+/// separator of the platform: drive letters and UNC shares work on Windows,
+/// and a trailing separator in `log_dir` does not double. An empty `log_dir`
+/// is the current directory.
+pub fn log_file_path<Tz: TimeZone>(log_dir: &str, now: &DateTime<Tz>) -> PathBuf
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let file_name = now.format("tikv-client-%Y%m%d%H%M%S.log").to_string();
+    Path::new(log_dir).join(file_name)
+}
+
+/// The logger of a client, writing to a timestamped file under a directory,
+/// see [`log_file_path`], with what it does on failed writes and their
+/// count. Clones share the file, the mode and the count.
+#[derive(Clone)]
+pub struct Logger {
+    slog: slog::Logger,
+    state: Arc<LogState>,
+}
+
+impl Logger {
+    /// The logger handed to tikv-client.
+    pub fn slog(&self) -> &slog::Logger {
+        &self.slog
+    }
+
+    /// Sets what is done with the records that fail to be written from then
+    /// on; the default is [`LogFailureMode::Drop`].
+    pub fn set_failure_mode(&self, mode: LogFailureMode) {
+        self.state
+            .fallback_to_stderr
+            .store(mode == LogFailureMode::Stderr, Ordering::Relaxed);
+    }
+
+    /// How many records failed to be written to the log file so far, or
+    /// were dropped while the channel to the thread writing it was full.
+    pub fn failed_writes(&self) -> u64 {
+        self.state.failed_writes.load(Ordering::Relaxed)
+    }
+
+    /// Makes this logger the one behind the `log` crate macros, which the
+    /// wrapper itself uses. The `log` crate has one logger per process, set
+    /// once, so this fails when it has one already, of another client or of
+    /// the application.
+    pub fn set_log_crate_logger(&self) -> Result<()> {
+        log::set_boxed_logger(Box::new(LogCrateBridge(self.slog.clone())))
+            .map_err(|_| anyhow!("the log crate has a logger already"))?;
+        log::set_max_level(log::LevelFilter::Trace);
+        Ok(())
+    }
+}
+
+/// Hands the records of the `log` crate macros over to a slog logger.
+struct LogCrateBridge(slog::Logger);
+
+impl log::Log for LogCrateBridge {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let level = match record.level() {
+            log::Level::Error => slog::Level::Error,
+            log::Level::Warn => slog::Level::Warning,
+            log::Level::Info => slog::Level::Info,
+            log::Level::Debug => slog::Level::Debug,
+            log::Level::Trace => slog::Level::Trace,
+        };
+        let location = slog::RecordLocation {
+            file: record.file_static().unwrap_or("<unknown>"),
+            line: record.line().unwrap_or(0),
+            column: 0,
+            function: "",
+            module: record.module_path_static().unwrap_or("<unknown>"),
+        };
+        let record_static = slog::RecordStatic {
+            location: &location,
+            tag: record.target(),
+            level,
+        };
+        self.0.log(&Record::new(
+            &record_static,
+            record.args(),
+            slog::BorrowedKV(&()),
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Creates the logger of a client, writing to a timestamped file under
+/// `log_dir`, see [`log_file_path`].
+///
+/// Each call returns a logger of its own, so that clients of different
+/// clusters log to their own files and count their own failed writes.
+pub fn create_logger(log_dir: &str) -> Result<Logger> {
+    let log_path = log_file_path(log_dir, &chrono::Local::now());
+    // Appending is the one mode opening an existing file without truncating
+    // it that behaves the same on every platform.
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("failed to open the log file {}", log_path.display()))?;
+
+    let state = Arc::new(LogState::default());
+    let decorator = slog_term::PlainDecorator::new(file);
+    let drain = FallbackDrain {
+        file: slog_term::FullFormat::new(decorator)
+            .use_local_timestamp()
+            .build(),
+        stderr: slog_term::FullFormat::new(slog_term::PlainSyncDecorator::new(io::stderr()))
+            .use_local_timestamp()
+            .build(),
+        state: state.clone(),
+    };
+    let drain = CountingDrain {
+        drain: slog_async::Async::new(drain)
+            .chan_size(DEFAULT_CHAN_SIZE)
+            .overflow_strategy(slog_async::OverflowStrategy::Drop)
+            .build(),
+        state: state.clone(),
+    };
+    Ok(Logger {
+        slog: slog::Logger::root(drain, o!()),
+        state,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::MAIN_SEPARATOR;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    const FILE_NAME: &str = "tikv-client-20210304050607.log";
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2021, 3, 4).and_hms(5, 6, 7)
+    }
+
+    #[test]
+    fn an_empty_directory_is_the_current_one() {
+        assert_eq!(log_file_path("", &now()), PathBuf::from(FILE_NAME));
+    }
+
+    #[test]
+    fn log_files_go_right_under_the_directory() {
+        let expected = Path::new("logs").join(FILE_NAME);
+        assert_eq!(log_file_path("logs", &now()), expected);
+        let trailing = format!("logs{}", MAIN_SEPARATOR);
+        assert_eq!(log_file_path(&trailing, &now()), expected);
+        assert!(!log_file_path(&trailing, &now())
+            .to_string_lossy()
+            .contains(&format!("{0}{0}", MAIN_SEPARATOR)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_directories_are_joined_with_slashes() {
+        assert_eq!(
+            log_file_path("/var/log/tikv", &now()),
+            PathBuf::from(format!("/var/log/tikv/{}", FILE_NAME))
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_drives_and_shares_are_joined_with_backslashes() {
+        assert_eq!(
+            log_file_path(r"C:\logs", &now()),
+            PathBuf::from(format!(r"C:\logs\{}", FILE_NAME))
+        );
+        assert_eq!(
+            log_file_path(r"\\server\share\logs", &now()),
+            PathBuf::from(format!(r"\\server\share\logs\{}", FILE_NAME))
+        );
+    }
+
+    #[test]
+    fn loggers_fail_on_a_missing_directory() {
+        let dir = env::temp_dir()
+            .join(format!("tikv-client-missing-{}", std::process::id()))
+            .join("logs");
+        match create_logger(&dir.to_string_lossy()) {
+            Ok(_) => panic!("created a logger under the missing {}", dir.display()),
+            Err(e) => assert!(format!("{:#}", e).contains("failed to open the log file")),
+        }
+    }
+
+    #[test]
+    fn loggers_open_a_file_in_an_existing_directory() {
+        let dir = env::temp_dir().join(format!("tikv-client-logs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let logger = create_logger(&dir.to_string_lossy()).unwrap();
+        assert_eq!(logger.failed_writes(), 0);
+        assert!(std::fs::read_dir(&dir).unwrap().next().is_some());
+        drop(logger);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Key-value pairs packed into one contiguous buffer, so that a large scan
+//! crosses the FFI as two allocations instead of two per pair.
+
+use tikv_client::KvPair;
+
+/// Pairs packed back to back into `data`. For the `i`-th pair, `offsets`
+/// holds `[key_off, key_len, value_off, value_len]` at `4 * i`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackedKvPairs {
+    pub data: Vec<u8>,
+    pub offsets: Vec<u64>,
+}
+
+impl PackedKvPairs {
+    pub fn len(&self) -> usize {
+        self.offsets.len() / 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// The key and value of the `i`-th pair.
+    pub fn get(&self, i: usize) -> Option<(&[u8], &[u8])> {
+        let offsets = self.offsets.get(4 * i..4 * i + 4)?;
+        let slice = |off: u64, len: u64| &self.data[off as usize..(off + len) as usize];
+        Some((slice(offsets[0], offsets[1]), slice(offsets[2], offsets[3])))
+    }
+}
+
+pub fn pack(kv_pairs: Vec<KvPair>) -> PackedKvPairs {
+    let kv_pairs = kv_pairs
+        .into_iter()
+        .map(|KvPair(key, value)| (Vec::<u8>::from(key), value))
+        .collect::<Vec<_>>();
+    let size = kv_pairs
+        .iter()
+        .map(|(key, value)| key.len() + value.len())
+        .sum();
+    let mut packed = PackedKvPairs {
+        data: Vec::with_capacity(size),
+        offsets: Vec::with_capacity(4 * kv_pairs.len()),
+    };
+    for (key, value) in &kv_pairs {
+        for field in &[key, value] {
+            packed.offsets.push(packed.data.len() as u64);
+            packed.offsets.push(field.len() as u64);
+            packed.data.extend_from_slice(field);
+        }
+    }
+    packed
+}
@@ -0,0 +1,347 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! An append-only trail of the writes committed through a client, kept
+//! apart from the debug log for compliance.
+//!
+//! Every record is one line of JSON carrying `"v"`, the version of the
+//! format, currently [`AUDIT_FORMAT_VERSION`]; fields may be added within a
+//! version, never removed or changed. A commit writes one `"mutation"`
+//! record per put or delete, then one `"commit"` record:
+//!
+//! ```text
+//! {"v":1,"type":"mutation","client":"app","ts_ms":1700000000000,"commit_ts":445252480335904769,"op":"put","key_sha256":"9f86...","value_len":12}
+//! {"v":1,"type":"commit","client":"app","ts_ms":1700000000000,"commit_ts":445252480335904769,"mutations":1}
+//! ```
+//!
+//! Keys are hashed as stored, prefix included, and values are not recorded
+//! at all, only their length.
+
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+
+use super::to_hex;
+use crate::checksum::Sha256;
+
+/// The version written in the `"v"` field of every record.
+pub const AUDIT_FORMAT_VERSION: u32 = 1;
+
+/// The bytes buffered before they are written to the file, which they also
+/// are at the end of every commit.
+const BUFFER_BYTES: usize = 64 << 10;
+
+/// When the audit file is synced to disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditFsync {
+    /// Never: the records of a commit are written to the file before the
+    /// commit returns, and reach the disk when the OS flushes it.
+    Never,
+    /// After the records of every commit, before the commit returns; the
+    /// slowest, and the only one surviving a power loss.
+    EveryCommit,
+}
+
+/// Where and how a client writes its audit trail, see
+/// [`TransactionClient::set_audit_log`](super::TransactionClient::set_audit_log).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditOptions {
+    pub path: PathBuf,
+    /// Written in the `"client"` field of every record, to tell the writer.
+    pub client_tag: String,
+    /// The size past which the file is rotated to `<path>.1`, the older
+    /// ones shifting to `<path>.2` and on; 0 never rotates. The records of
+    /// a commit are never split across files.
+    pub max_file_bytes: u64,
+    /// The rotated files kept, the oldest being deleted; at least one when
+    /// rotating, so that no file is deleted before it was rotated.
+    pub max_rotated_files: u32,
+    pub fsync: AuditFsync,
+}
+
+/// A mutation as recorded in the audit trail.
+pub(crate) struct AuditMutation<'a> {
+    /// The key as stored.
+    pub key: &'a [u8],
+    /// The length of the value of a put, `None` for a delete.
+    pub value_len: Option<u64>,
+}
+
+struct AuditFile {
+    writer: BufWriter<File>,
+    /// The bytes in the file, buffered ones included.
+    bytes: u64,
+}
+
+/// The audit trail of a client, shared by the clients derived from it.
+pub(crate) struct AuditLog {
+    options: AuditOptions,
+    file: Mutex<AuditFile>,
+}
+
+impl AuditLog {
+    pub fn open(options: AuditOptions) -> Result<Self> {
+        if options.client_tag.is_empty() {
+            bail!("the audit log needs a client tag");
+        }
+        if options.max_file_bytes > 0 && options.max_rotated_files == 0 {
+            bail!("a rotated audit log must keep at least one rotated file");
+        }
+        let file = open(&options.path)?;
+        Ok(AuditLog {
+            options,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends the records of a commit at `commit_ts` and flushes them, and
+    /// syncs the file when so configured. Nothing is written for a commit
+    /// without mutations.
+    pub fn write_commit<'a>(
+        &self,
+        commit_ts: u64,
+        mutations: impl IntoIterator<Item = AuditMutation<'a>>,
+    ) -> Result<()> {
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        let head = format!("{{\"v\":{},\"type\":", AUDIT_FORMAT_VERSION);
+        let common = format!(
+            "\"client\":\"{}\",\"ts_ms\":{},\"commit_ts\":{}",
+            escape_json(&self.options.client_tag),
+            ts_ms,
+            commit_ts
+        );
+        let mut records = String::new();
+        let mut count = 0;
+        for mutation in mutations {
+            let mut digest = Sha256::new();
+            digest.write(mutation.key);
+            let (op, value_len) = match mutation.value_len {
+                Some(len) => ("put", len),
+                None => ("delete", 0),
+            };
+            let _ = writeln!(
+                records,
+                "{}\"mutation\",{},\"op\":\"{}\",\"key_sha256\":\"{}\",\"value_len\":{}}}",
+                head,
+                common,
+                op,
+                to_hex(&digest.finish(), 32),
+                value_len
+            );
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        let _ = writeln!(
+            records,
+            "{}\"commit\",{},\"mutations\":{}}}",
+            head, common, count
+        );
+
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let max_bytes = self.options.max_file_bytes;
+        if max_bytes > 0 && file.bytes > 0 && file.bytes + records.len() as u64 > max_bytes {
+            file.writer.flush()?;
+            rotate(&self.options.path, self.options.max_rotated_files)?;
+            *file = open(&self.options.path)?;
+        }
+        file.writer.write_all(records.as_bytes())?;
+        file.bytes += records.len() as u64;
+        file.writer.flush()?;
+        if self.options.fsync == AuditFsync::EveryCommit {
+            file.writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> Result<AuditFile> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open the audit log {}", path.display()))?;
+    let bytes = file.metadata()?.len();
+    Ok(AuditFile {
+        writer: BufWriter::with_capacity(BUFFER_BYTES, file),
+        bytes,
+    })
+}
+
+/// Shifts `<path>.n` to `<path>.n+1` down to `path` itself, keeping
+/// `max_rotated_files` of them.
+fn rotate(path: &Path, max_rotated_files: u32) -> Result<()> {
+    let rotated = |n: u32| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    let _ = fs::remove_file(rotated(max_rotated_files));
+    for n in (1..max_rotated_files).rev() {
+        let from = rotated(n);
+        if from.exists() {
+            fs::rename(&from, rotated(n + 1))?;
+        }
+    }
+    fs::rename(path, rotated(1))
+        .with_context(|| format!("failed to rotate the audit log {}", path.display()))
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    /// The SHA-256 of `test`.
+    const TEST_SHA256: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    /// A directory of its own for the audit files of `test`.
+    fn audit_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("audit_{}_{}", test, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn options(path: PathBuf, max_file_bytes: u64, max_rotated_files: u32) -> AuditOptions {
+        AuditOptions {
+            path,
+            client_tag: "app \"1\"".to_owned(),
+            max_file_bytes,
+            max_rotated_files,
+            fsync: AuditFsync::EveryCommit,
+        }
+    }
+
+    fn put(key: &[u8], len: u64) -> AuditMutation<'_> {
+        AuditMutation {
+            key,
+            value_len: Some(len),
+        }
+    }
+
+    /// The lines of `path`, the `ts_ms` field of each replaced with 0.
+    fn records(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let at = line.find("\"ts_ms\":").unwrap() + "\"ts_ms\":".len();
+                let end = at + line[at..].find(',').unwrap();
+                format!("{}0{}", &line[..at], &line[end..])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_commit_writes_its_mutations_then_itself() {
+        let dir = audit_dir("format");
+        let path = dir.join("audit.log");
+        let log = AuditLog::open(options(path.clone(), 0, 0)).unwrap();
+        let delete = AuditMutation {
+            key: b"test",
+            value_len: None,
+        };
+        log.write_commit(7, vec![put(b"test", 12), delete]).unwrap();
+        log.write_commit(8, Vec::new()).unwrap();
+        let common = "\"client\":\"app \\\"1\\\"\",\"ts_ms\":0";
+        assert_eq!(
+            records(&path),
+            [
+                format!(
+                    "{{\"v\":1,\"type\":\"mutation\",{},\"commit_ts\":7,\"op\":\"put\",\
+                     \"key_sha256\":\"{}\",\"value_len\":12}}",
+                    common, TEST_SHA256
+                ),
+                format!(
+                    "{{\"v\":1,\"type\":\"mutation\",{},\"commit_ts\":7,\"op\":\"delete\",\
+                     \"key_sha256\":\"{}\",\"value_len\":0}}",
+                    common, TEST_SHA256
+                ),
+                format!(
+                    "{{\"v\":1,\"type\":\"commit\",{},\"commit_ts\":7,\"mutations\":2}}",
+                    common
+                ),
+            ]
+        );
+
+        // Reopened, the log appends.
+        drop(log);
+        let log = AuditLog::open(options(path.clone(), 0, 0)).unwrap();
+        log.write_commit(9, vec![put(b"test", 1)]).unwrap();
+        assert_eq!(records(&path).len(), 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_keeps_commits_whole_and_drops_the_oldest_files() {
+        let dir = audit_dir("rotation");
+        let path = dir.join("audit.log");
+        let log = AuditLog::open(options(path.clone(), 500, 2)).unwrap();
+        for commit_ts in 1..=6 {
+            log.write_commit(commit_ts, vec![put(b"a", 1), put(b"b", 2)])
+                .unwrap();
+        }
+        let rotated = |n| dir.join(format!("audit.log.{}", n));
+        assert!(!rotated(3).exists());
+        // Each file holds whole commits, the newest in the file itself.
+        let mut commit_ts = Vec::new();
+        for file in [rotated(2), rotated(1), path.clone()].iter() {
+            let records = records(file);
+            assert!(fs::metadata(file).unwrap().len() <= 500);
+            assert!(records.last().unwrap().contains("\"type\":\"commit\""));
+            for record in records.iter().filter(|r| r.contains("\"type\":\"commit\"")) {
+                let at = record.find("\"commit_ts\":").unwrap() + "\"commit_ts\":".len();
+                let end = at + record[at..].find(',').unwrap();
+                commit_ts.push(record[at..end].parse::<u64>().unwrap());
+            }
+        }
+        assert_eq!(commit_ts.last(), Some(&6));
+        assert!(commit_ts.windows(2).all(|pair| pair[0] + 1 == pair[1]));
+        assert!(commit_ts[0] > 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_options_are_checked() {
+        let dir = audit_dir("options");
+        let path = dir.join("audit.log");
+        let mut untagged = options(path.clone(), 0, 0);
+        untagged.client_tag.clear();
+        assert!(AuditLog::open(untagged).is_err());
+        assert!(AuditLog::open(options(path.clone(), 100, 0)).is_err());
+        assert!(AuditLog::open(options(dir.join("missing").join("audit.log"), 0, 0)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(escape_json("plain"), "plain");
+        assert_eq!(escape_json("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(escape_json("\n\u{1}é"), "\\u000a\\u0001é");
+    }
+}
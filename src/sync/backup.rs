@@ -0,0 +1,199 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Logical export of a range to a file, and import of such a file.
+//!
+//! The file starts with a fixed-size header: an 8-byte magic, then the
+//! snapshot version, the number of records and the crc64 of everything after
+//! the header, each as a big-endian `u64`. Records follow in key order, each
+//! a big-endian `u32` key length, the key, a big-endian `u32` value length
+//! and the value. The magic is only written once the export is complete, so
+//! an interrupted export is never mistaken for a valid file.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use tikv_client::KvPair;
+
+use super::{Snapshot, TransactionClient, SCAN_PAGE_SIZE};
+use crate::checksum::Crc64;
+
+const MAGIC: &[u8; 8] = b"TIKVEXP1";
+const HEADER_LEN: usize = 32;
+
+struct Header {
+    version: u64,
+    count: u64,
+    checksum: u64,
+}
+
+impl Header {
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..8].copy_from_slice(MAGIC);
+        bytes[8..16].copy_from_slice(&self.version.to_be_bytes());
+        bytes[16..24].copy_from_slice(&self.count.to_be_bytes());
+        bytes[24..].copy_from_slice(&self.checksum.to_be_bytes());
+        bytes
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Header> {
+        let mut bytes = [0; HEADER_LEN];
+        reader
+            .read_exact(&mut bytes)
+            .context("the file is too short for an export header")?;
+        if &bytes[..8] != MAGIC {
+            bail!("the file is not a complete export (bad magic)");
+        }
+        let field = |i: usize| {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(&bytes[i..i + 8]);
+            u64::from_be_bytes(buf)
+        };
+        Ok(Header {
+            version: field(8),
+            count: field(16),
+            checksum: field(24),
+        })
+    }
+}
+
+fn write_record(writer: &mut impl Write, crc: &mut Crc64, key: &[u8], value: &[u8]) -> Result<()> {
+    for data in &[key, value] {
+        let len = u32::try_from(data.len()).context("record too large to export")?;
+        let len = len.to_be_bytes();
+        crc.write(&len);
+        crc.write(data);
+        writer.write_all(&len)?;
+        writer.write_all(data)?;
+    }
+    Ok(())
+}
+
+/// Reads the next record, or `None` at the end of the file.
+fn read_record(reader: &mut impl BufRead, crc: &mut Crc64) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut read_field = || -> Result<Vec<u8>> {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        // Not preallocated: a corrupt length must not be trusted.
+        let expected = u32::from_be_bytes(len) as u64;
+        let mut data = Vec::new();
+        reader.by_ref().take(expected).read_to_end(&mut data)?;
+        if data.len() as u64 != expected {
+            bail!("expected {} bytes, got {}", expected, data.len());
+        }
+        crc.write(&len);
+        crc.write(&data);
+        Ok(data)
+    };
+    let key = read_field().context("the file is truncated")?;
+    let value = read_field().context("the file is truncated")?;
+    Ok(Some((key, value)))
+}
+
+impl Snapshot {
+    /// Writes every pair of the range to the file at `path`, streaming a
+    /// paginated scan so that the range is never held in memory whole, and
+    /// returns the number of pairs written. Keys are written as seen through
+    /// this snapshot's prefix.
+    pub fn export_range(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        path: impl AsRef<Path>,
+    ) -> Result<u64> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("failed to create {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(&[0; HEADER_LEN])?;
+        let mut crc = Crc64::new();
+        let mut count = 0;
+        self.scan_each(start, end, SCAN_PAGE_SIZE, |KvPair(key, value)| {
+            let key: Vec<u8> = key.into();
+            write_record(&mut writer, &mut crc, &key, &value)?;
+            count += 1;
+            Ok(())
+        })?;
+        let header = Header {
+            version: self.version(),
+            count,
+            checksum: crc.sum64(),
+        };
+        let mut file = writer.into_inner()?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header.to_bytes())?;
+        file.sync_all()?;
+        Ok(count)
+    }
+}
+
+impl TransactionClient {
+    /// Writes the pairs of a file produced by
+    /// [`Snapshot::export_range`] into this client's keyspace, `keys_per_txn`
+    /// pairs per transaction, and returns the number of pairs written.
+    ///
+    /// The whole file is verified against its header before anything is
+    /// written, so a corrupt or truncated file fails without side effects.
+    /// Like other chunked operations the import is not atomic: transactions
+    /// committed before an error stay committed, and importing the same file
+    /// again is idempotent.
+    pub fn import_file(&self, path: impl AsRef<Path>, keys_per_txn: u32) -> Result<u64> {
+        if keys_per_txn == 0 {
+            bail!("keys_per_txn must be positive");
+        }
+        let path = path.as_ref();
+        let open = || -> Result<_> {
+            let file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+            let mut reader = BufReader::new(file);
+            let header = Header::read(&mut reader)?;
+            Ok((header, reader))
+        };
+
+        let (header, mut reader) = open()?;
+        let mut crc = Crc64::new();
+        let mut count = 0;
+        while read_record(&mut reader, &mut crc)?.is_some() {
+            count += 1;
+        }
+        if count != header.count || crc.sum64() != header.checksum {
+            bail!(
+                "checksum mismatch: the header of the export at version {} promises {} records, \
+                 the file holds {}",
+                header.version,
+                header.count,
+                count
+            );
+        }
+
+        let (_, mut reader) = open()?;
+        let mut crc = Crc64::new();
+        let mut imported = 0;
+        loop {
+            let mut chunk = Vec::new();
+            while chunk.len() < keys_per_txn as usize {
+                match read_record(&mut reader, &mut crc)? {
+                    Some(record) => chunk.push(record),
+                    None => break,
+                }
+            }
+            if chunk.is_empty() {
+                return Ok(imported);
+            }
+            self.retry_chunk(false, |txn| {
+                for (key, value) in &chunk {
+                    txn.put(key, value)?;
+                }
+                txn.commit()?;
+                Ok(())
+            })
+            .map_err(|e| e.context(format!("imported {} pairs before failing", imported)))?;
+            imported += chunk.len() as u64;
+        }
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! High-throughput writes of keys known not to exist yet, e.g. an initial
+//! import, committed in chunked transactions running in parallel.
+
+use std::mem;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::{anyhow, bail, Result};
+use tikv_client::KvPair;
+use tokio::sync::Semaphore;
+
+use super::namespace::Namespace;
+use super::{
+    as_ref_bound, from_tikv_error, resume_token, to_owned_bound, PartialOutcome, SizeLimits,
+    Snapshot, TransactionClient, SCAN_PAGE_SIZE, TOKIO_RUNTIME,
+};
+
+/// A chunk of a bulk write that failed to commit, with its pairs so that it
+/// can be written again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BulkWriteFailure {
+    pub kv_pairs: Vec<KvPair>,
+    pub error: String,
+}
+
+/// How far a bulk write has got, in keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BulkWriteProgress {
+    /// Pushed and not yet committed nor failed.
+    pub pending_keys: u64,
+    pub written_keys: u64,
+    pub failed_keys: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    pushed_keys: AtomicU64,
+    written_keys: AtomicU64,
+    failed_keys: AtomicU64,
+}
+
+/// A bulk write session, from [`TransactionClient::bulk_writer`].
+///
+/// Pushed pairs are cut into chunks of `keys_per_txn` keys, each written by
+/// an optimistic transaction of its own which only puts: nothing is read
+/// before writing, and keys already present are overwritten. Up to
+/// `concurrency` chunks commit at once, on the shared runtime; pushing
+/// blocks while they all are in flight. A failed chunk does not stop the
+/// others and is reported by [`finish`](Self::finish).
+///
+/// The pinned tikv-client has no 1PC, so every chunk goes through two-phase
+/// commit.
+pub struct BulkWriter {
+    client: Arc<tikv_client::TransactionClient>,
+    namespace: Namespace,
+    size_limits: SizeLimits,
+    keys_per_txn: usize,
+    concurrency: u32,
+    permits: Arc<Semaphore>,
+    chunk: Vec<(Vec<u8>, Vec<u8>)>,
+    counters: Arc<Counters>,
+    failures: Arc<Mutex<Vec<BulkWriteFailure>>>,
+}
+
+impl TransactionClient {
+    /// Starts a bulk write session of chunks of `keys_per_txn` keys, with up
+    /// to `concurrency` of them committing at once.
+    pub fn bulk_writer(&self, keys_per_txn: u32, concurrency: u32) -> Result<BulkWriter> {
+        if keys_per_txn == 0 || concurrency == 0 {
+            bail!("keys_per_txn and concurrency must be positive");
+        }
+        Ok(BulkWriter {
+            client: self.inner().clone(),
+            namespace: self.namespace().clone(),
+            size_limits: self.size_limits(),
+            keys_per_txn: keys_per_txn as usize,
+            concurrency,
+            permits: Arc::new(Semaphore::new(concurrency as usize)),
+            chunk: Vec::with_capacity(keys_per_txn as usize),
+            counters: Arc::default(),
+            failures: Arc::default(),
+        })
+    }
+}
+
+impl TransactionClient {
+    /// Copies the pairs of a range of `source`, which may read another
+    /// cluster, into this client, returning how many were copied. The range
+    /// is scanned page by page and written as by a [`BulkWriter`] of
+    /// `keys_per_txn` and `concurrency`, so only a few pages are held at
+    /// once.
+    ///
+    /// Like every bulk write this is not atomic and overwrites keys already
+    /// present: chunks committed before a failure stay committed, and
+    /// copying again is idempotent.
+    pub fn copy_range(
+        &self,
+        source: &mut Snapshot,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        keys_per_txn: u32,
+        concurrency: u32,
+    ) -> Result<u64> {
+        let mut writer = self.bulk_writer(keys_per_txn, concurrency)?;
+        let mut cursor = to_owned_bound(start);
+        let pushed = push_range(&mut writer, source, &mut cursor, end);
+        let failures = writer.finish();
+        let written_keys = writer.progress().written_keys;
+        pushed.map_err(|e| e.context(format!("copied {} pairs before failing", written_keys)))?;
+        if let Some(failure) = failures.first() {
+            bail!(
+                "copy failed: {} chunks did not commit, {} pairs were copied; the first error: {}",
+                failures.len(),
+                written_keys,
+                failure.error
+            );
+        }
+        Ok(written_keys)
+    }
+
+    /// Like [`copy_range`](Self::copy_range), returning how far it got
+    /// rather than failing: the pairs copied and failed, and on an error
+    /// where to resume, the first key of the earliest chunk that failed or
+    /// the page being copied when the copy stopped.
+    pub fn copy_range_partial(
+        &self,
+        source: &mut Snapshot,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        keys_per_txn: u32,
+        concurrency: u32,
+    ) -> PartialOutcome {
+        let mut writer = match self.bulk_writer(keys_per_txn, concurrency) {
+            Ok(writer) => writer,
+            Err(e) => return PartialOutcome::new(0, 0, Err(e), resume_token(start)),
+        };
+        let mut cursor = to_owned_bound(start);
+        let pushed = push_range(&mut writer, source, &mut cursor, end);
+        let failures = writer.finish();
+        let progress = writer.progress();
+        let first_failed = failures
+            .iter()
+            .filter_map(|failure| failure.kv_pairs.first())
+            .map(|KvPair(key, _)| Vec::<u8>::from(key.clone()))
+            .min();
+        let (result, resume) = match (pushed, first_failed) {
+            (Ok(()), None) => (Ok(()), Vec::new()),
+            (Ok(()), Some(key)) => (
+                Err(anyhow!(
+                    "{} chunks did not commit; the first error: {}",
+                    failures.len(),
+                    failures[0].error
+                )),
+                key,
+            ),
+            (Err(e), first_failed) => {
+                let stopped_at = resume_token(as_ref_bound(&cursor));
+                let resume = first_failed.map_or(stopped_at.clone(), |key| key.min(stopped_at));
+                (Err(e), resume)
+            }
+        };
+        PartialOutcome::new(progress.written_keys, progress.failed_keys, result, resume)
+    }
+}
+
+/// Pushes the pairs of a range of `source` to `writer`, one scanned page at
+/// a time, from `cursor` on, moving it past every page pushed.
+fn push_range(
+    writer: &mut BulkWriter,
+    source: &mut Snapshot,
+    cursor: &mut Bound<Vec<u8>>,
+    end: Bound<&[u8]>,
+) -> Result<()> {
+    loop {
+        let page = source.scan(as_ref_bound(cursor), end, SCAN_PAGE_SIZE)?;
+        writer.push(
+            page.iter()
+                .map(|KvPair(key, value)| (key.into(), value.as_slice())),
+        )?;
+        match page.last() {
+            Some(KvPair(key, _)) if page.len() == SCAN_PAGE_SIZE as usize => {
+                *cursor = Bound::Excluded(key.clone().into());
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+impl BulkWriter {
+    /// Queues `kv_pairs`, handing every full chunk to a transaction. The key
+    /// and size checks of the client apply: if any pair fails them, none of
+    /// `kv_pairs` is queued.
+    pub fn push<'a>(
+        &mut self,
+        kv_pairs: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    ) -> Result<()> {
+        let kv_pairs = kv_pairs.into_iter().collect::<Vec<_>>();
+        for &(key, value) in &kv_pairs {
+            let encoded = self.namespace.encode_key(key)?;
+            self.size_limits.check(key, encoded.len(), value.len())?;
+        }
+        self.counters
+            .pushed_keys
+            .fetch_add(kv_pairs.len() as u64, Ordering::Relaxed);
+        for (key, value) in kv_pairs {
+            self.chunk.push((key.to_owned(), value.to_owned()));
+            if self.chunk.len() == self.keys_per_txn {
+                self.submit();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn progress(&self) -> BulkWriteProgress {
+        let written_keys = self.counters.written_keys.load(Ordering::Relaxed);
+        let failed_keys = self.counters.failed_keys.load(Ordering::Relaxed);
+        BulkWriteProgress {
+            pending_keys: self.counters.pushed_keys.load(Ordering::Relaxed)
+                - written_keys
+                - failed_keys,
+            written_keys,
+            failed_keys,
+        }
+    }
+
+    /// Writes the last, partial chunk, waits for every chunk in flight and
+    /// returns the chunks that failed since the last call. The session can
+    /// go on afterwards.
+    pub fn finish(&mut self) -> Vec<BulkWriteFailure> {
+        if !self.chunk.is_empty() {
+            self.submit();
+        }
+        // Holding every permit means no chunk is in flight any more.
+        drop(TOKIO_RUNTIME.block_on(self.permits.acquire_many(self.concurrency)));
+        mem::take(&mut *self.failures.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Hands the current chunk to a transaction, once one of the
+    /// `concurrency` slots is free.
+    fn submit(&mut self) {
+        let chunk = mem::replace(&mut self.chunk, Vec::with_capacity(self.keys_per_txn));
+        let permit = TOKIO_RUNTIME
+            .block_on(self.permits.clone().acquire_owned())
+            .expect("the bulk write semaphore is never closed");
+        let client = self.client.clone();
+        let namespace = self.namespace.clone();
+        let counters = self.counters.clone();
+        let failures = self.failures.clone();
+        TOKIO_RUNTIME.spawn(async move {
+            let _permit = permit;
+            let keys = chunk.len() as u64;
+            match write_chunk(&client, &namespace, &chunk).await {
+                Ok(()) => {
+                    counters.written_keys.fetch_add(keys, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    counters.failed_keys.fetch_add(keys, Ordering::Relaxed);
+                    failures
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push(BulkWriteFailure {
+                            kv_pairs: chunk
+                                .into_iter()
+                                .map(|(key, value)| KvPair(key.into(), value))
+                                .collect(),
+                            error: format!("{:#}", from_tikv_error(e)),
+                        });
+                }
+            }
+        });
+    }
+}
+
+async fn write_chunk(
+    client: &tikv_client::TransactionClient,
+    namespace: &Namespace,
+    chunk: &[(Vec<u8>, Vec<u8>)],
+) -> tikv_client::Result<()> {
+    let mut txn = client.begin_optimistic().await?;
+    for (key, value) in chunk {
+        if let Err(e) = txn
+            .put(namespace.encode(key), namespace.encode_value(value))
+            .await
+        {
+            let _ = txn.rollback().await;
+            return Err(e);
+        }
+    }
+    if let Err(e) = txn.commit().await {
+        let _ = txn.rollback().await;
+        return Err(e);
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(debug_assertions)]
+use std::sync::{Mutex, PoisonError};
+#[cfg(debug_assertions)]
+use std::thread::{self, ThreadId};
+
+use anyhow::Result;
+#[cfg(debug_assertions)]
+use log::debug;
+
+use super::metrics::Metrics;
+use super::Error;
+
+/// Marks an object as being used by a call, so that a second call made on it
+/// concurrently fails with [`Error::Busy`].
+///
+/// Rust rules such sharing out, but the C++ side can hand one object to
+/// several threads. This does not make that sound; it turns the common race,
+/// e.g. a timeout path racing a commit, into a clean error instead of
+/// corrupted state. Handing an object from one thread to the next, one call
+/// at a time, is sound: the guarded types are `Send`, as asserted in the
+/// crate root.
+///
+/// Debug builds also log, at debug, every call made on another thread than
+/// the one before, to audit which objects move between threads.
+#[derive(Default)]
+pub(crate) struct BusyFlag {
+    busy: Arc<AtomicBool>,
+    #[cfg(debug_assertions)]
+    last_thread: Mutex<Option<ThreadId>>,
+}
+
+/// Clears its [`BusyFlag`] when dropped, the call it guards counting as in
+/// flight on the metrics of its client until then.
+pub(crate) struct BusyGuard {
+    busy: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+}
+
+impl BusyFlag {
+    pub fn enter(&self, what: &'static str, metrics: &Arc<Metrics>) -> Result<BusyGuard> {
+        if self.busy.swap(true, Ordering::Acquire) {
+            return Err(Error::Busy { what }.into());
+        }
+        #[cfg(debug_assertions)]
+        self.note_thread(what);
+        metrics.start_operation();
+        Ok(BusyGuard {
+            busy: self.busy.clone(),
+            metrics: metrics.clone(),
+        })
+    }
+
+    /// Logs a call made on another thread than the last one.
+    #[cfg(debug_assertions)]
+    fn note_thread(&self, what: &'static str) {
+        let current = thread::current();
+        let mut last = self
+            .last_thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match *last {
+            Some(id) if id != current.id() => debug!(
+                "{} moved from thread {:?} to thread {:?} ({})",
+                what,
+                id,
+                current.id(),
+                current.name().unwrap_or("unnamed")
+            ),
+            _ => {}
+        }
+        *last = Some(current.id());
+    }
+}
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        self.metrics.finish_operation();
+        self.busy.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn concurrent_calls_fail_with_busy() {
+        let flag = Arc::new(BusyFlag::default());
+        let metrics = Arc::new(Metrics::default());
+        // Both threads pass each barrier together: the first holds the flag
+        // from the first barrier to the second, and the second tries to enter
+        // between them, and again once the first let go.
+        let held = Arc::new(Barrier::new(2));
+        let released = Arc::new(Barrier::new(2));
+
+        let holder = {
+            let (flag, metrics) = (flag.clone(), metrics.clone());
+            let (held, released) = (held.clone(), released.clone());
+            thread::spawn(move || {
+                let guard = flag.enter("transaction", &metrics).unwrap();
+                held.wait();
+                held.wait();
+                drop(guard);
+                released.wait();
+            })
+        };
+        held.wait();
+        let e = flag.enter("transaction", &metrics).err().unwrap();
+        assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::Busy {
+                what: "transaction"
+            })
+        ));
+        assert_eq!(metrics.in_flight_operations(), 1);
+        held.wait();
+        released.wait();
+        holder.join().unwrap();
+
+        let guard = flag.enter("transaction", &metrics).unwrap();
+        assert_eq!(metrics.in_flight_operations(), 1);
+        drop(guard);
+        assert_eq!(metrics.in_flight_operations(), 0);
+    }
+}
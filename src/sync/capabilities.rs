@@ -0,0 +1,128 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! The functions that need a request the pinned tikv-client does not send,
+//! and which a patched fork of it may.
+//!
+//! Every such function is always compiled, so that the bridge and the C++
+//! header are the same in every build; without the capability it checks
+//! its arguments and fails with [`Error::Unsupported`]. Each capability has
+//! a cargo feature, `cap-<name>`, declaring that the linked tikv-client
+//! provides the request, and [`capabilities`] lists the ones enabled.
+//!
+//! This tree builds against the pinned tikv-client only, so a feature is a
+//! build error until the function it names calls the fork; enabling it is
+//! the place to do so.
+
+use super::Error;
+
+/// A request a patched tikv-client may provide, with the functions it makes
+/// real.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Capability {
+    /// Checking and committing a transaction by key and timestamps:
+    /// [`TransactionClient::finalize_commit`](super::TransactionClient::finalize_commit).
+    CheckTxnStatus = 0,
+    /// Splitting and scattering regions:
+    /// [`TransactionClient::presplit_for_keys`](super::TransactionClient::presplit_for_keys).
+    RegionSplit = 1,
+    /// Looking keys up in the region cache:
+    /// [`TransactionClient::region_span`](super::TransactionClient::region_span).
+    RegionLookup = 2,
+    /// [`TransactionClient::register_service_safepoint`](super::TransactionClient::register_service_safepoint).
+    ServiceSafepoint = 3,
+    /// The PD leader the client talks to:
+    /// [`TransactionClient::current_pd`](super::TransactionClient::current_pd)
+    /// and [`TransactionClient::pd_leader_changes`](super::TransactionClient::pd_leader_changes).
+    PdLeader = 4,
+    /// [`Transaction::for_update_ts`](super::Transaction::for_update_ts) and
+    /// [`Transaction::refresh_for_update_ts`](super::Transaction::refresh_for_update_ts).
+    ForUpdateTs = 5,
+    /// Releasing single pessimistic locks:
+    /// [`Transaction::pessimistic_rollback_keys`](super::Transaction::pessimistic_rollback_keys).
+    PessimisticRollback = 6,
+    /// [`PessimisticOptions::pipelined_locking`](super::PessimisticOptions::pipelined_locking).
+    PipelinedLocking = 7,
+    /// Request priorities other than [`Priority::Normal`](super::Priority::Normal).
+    RequestPriority = 8,
+    /// Connecting within an API v2 keyspace:
+    /// [`TransactionClient::new_with_keyspace`](super::TransactionClient::new_with_keyspace).
+    ApiV2 = 9,
+}
+
+const ALL: &[Capability] = &[
+    Capability::CheckTxnStatus,
+    Capability::RegionSplit,
+    Capability::RegionLookup,
+    Capability::ServiceSafepoint,
+    Capability::PdLeader,
+    Capability::ForUpdateTs,
+    Capability::PessimisticRollback,
+    Capability::PipelinedLocking,
+    Capability::RequestPriority,
+    Capability::ApiV2,
+];
+
+impl Capability {
+    /// The cargo feature declaring the capability.
+    pub fn feature(self) -> &'static str {
+        match self {
+            Capability::CheckTxnStatus => "cap-check-txn-status",
+            Capability::RegionSplit => "cap-region-split",
+            Capability::RegionLookup => "cap-region-lookup",
+            Capability::ServiceSafepoint => "cap-service-safepoint",
+            Capability::PdLeader => "cap-pd-leader",
+            Capability::ForUpdateTs => "cap-for-update-ts",
+            Capability::PessimisticRollback => "cap-pessimistic-rollback",
+            Capability::PipelinedLocking => "cap-pipelined-locking",
+            Capability::RequestPriority => "cap-request-priority",
+            Capability::ApiV2 => "cap-api-v2",
+        }
+    }
+
+    pub fn is_enabled(self) -> bool {
+        match self {
+            Capability::CheckTxnStatus => cfg!(feature = "cap-check-txn-status"),
+            Capability::RegionSplit => cfg!(feature = "cap-region-split"),
+            Capability::RegionLookup => cfg!(feature = "cap-region-lookup"),
+            Capability::ServiceSafepoint => cfg!(feature = "cap-service-safepoint"),
+            Capability::PdLeader => cfg!(feature = "cap-pd-leader"),
+            Capability::ForUpdateTs => cfg!(feature = "cap-for-update-ts"),
+            Capability::PessimisticRollback => cfg!(feature = "cap-pessimistic-rollback"),
+            Capability::PipelinedLocking => cfg!(feature = "cap-pipelined-locking"),
+            Capability::RequestPriority => cfg!(feature = "cap-request-priority"),
+            Capability::ApiV2 => cfg!(feature = "cap-api-v2"),
+        }
+    }
+}
+
+/// The capabilities of this build, in the order of their values.
+pub fn capabilities() -> Vec<Capability> {
+    ALL.iter()
+        .copied()
+        .filter(|capability| capability.is_enabled())
+        .collect()
+}
+
+/// The error of a function lacking `capability`, `what` saying what it was
+/// asked to do.
+pub(crate) fn unsupported(capability: Capability, what: String) -> anyhow::Error {
+    Error::Unsupported { capability, what }.into()
+}
+
+#[cfg(any(
+    feature = "cap-check-txn-status",
+    feature = "cap-region-split",
+    feature = "cap-region-lookup",
+    feature = "cap-service-safepoint",
+    feature = "cap-pd-leader",
+    feature = "cap-for-update-ts",
+    feature = "cap-pessimistic-rollback",
+    feature = "cap-pipelined-locking",
+    feature = "cap-request-priority",
+    feature = "cap-api-v2",
+))]
+compile_error!(
+    "a cap-* feature needs a patched tikv-client, called from the functions its capability \
+     lists in src/sync/capabilities.rs; none of them does in this tree"
+);
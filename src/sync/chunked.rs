@@ -0,0 +1,302 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Values over the value size limit, split across keys of their own.
+//!
+//! A chunked value of `key` is stored as chunks under `key\x00chunk_0`,
+//! `key\x00chunk_1` and on, each at most the value limit long and stored as
+//! is, uncompressed, and as a manifest at `key` itself: the 4-byte magic
+//! `\xffCHK`, then the number of chunks as a big-endian `u32`, the length of
+//! the value and its crc64 as big-endian `u64`s. Every write of a chunked
+//! value writes its key, so the manifest is what conflicts with other
+//! writers, as a plain value would.
+//!
+//! Only clients set to [`OversizedValues::Chunk`] open manifests and hide
+//! chunk keys from their scans; others read both as stored.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use anyhow::Result;
+use tikv_client::{Key, KvPair, Value};
+
+use super::{as_ref_bound, to_owned_bound, Error};
+use crate::checksum::Crc64;
+
+const MANIFEST_MAGIC: &[u8; 4] = b"\xffCHK";
+pub(crate) const MANIFEST_LEN: usize = 24;
+const CHUNK_SEPARATOR: &[u8] = b"\x00chunk_";
+
+/// What a client does with a value over the `max_value_bytes` of its
+/// [`SizeLimits`](super::SizeLimits).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OversizedValues {
+    /// The put fails right away with
+    /// [`Error::EntryTooLarge`](super::Error::EntryTooLarge).
+    Reject,
+    /// The value is split into chunks, see the [module](self) docs.
+    Chunk,
+}
+
+impl Default for OversizedValues {
+    fn default() -> Self {
+        OversizedValues::Reject
+    }
+}
+
+/// The record stored at the key of a chunked value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    pub chunks: u32,
+    len: u64,
+    crc: u64,
+}
+
+impl Manifest {
+    /// The manifest of `value` split into chunks of `chunk_bytes`.
+    pub fn of(value: &[u8], chunk_bytes: usize) -> Self {
+        Manifest {
+            chunks: ((value.len() + chunk_bytes - 1) / chunk_bytes) as u32,
+            len: value.len() as u64,
+            crc: crc64(value),
+        }
+    }
+
+    /// The manifest stored as `value`, if it is one.
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        if value.len() != MANIFEST_LEN || !value.starts_with(MANIFEST_MAGIC) {
+            return None;
+        }
+        let mut chunks = [0; 4];
+        chunks.copy_from_slice(&value[4..8]);
+        let mut len = [0; 8];
+        len.copy_from_slice(&value[8..16]);
+        let mut crc = [0; 8];
+        crc.copy_from_slice(&value[16..24]);
+        Some(Manifest {
+            chunks: u32::from_be_bytes(chunks),
+            len: u64::from_be_bytes(len),
+            crc: u64::from_be_bytes(crc),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(MANIFEST_LEN);
+        value.extend_from_slice(MANIFEST_MAGIC);
+        value.extend_from_slice(&self.chunks.to_be_bytes());
+        value.extend_from_slice(&self.len.to_be_bytes());
+        value.extend_from_slice(&self.crc.to_be_bytes());
+        value
+    }
+
+    /// Puts back together the value of `key` from the pairs `fetched` for
+    /// its `chunk_keys`, as sent to TiKV and in chunk order, failing with
+    /// [`Error::ChunkedValueCorrupt`] when one is missing or the value is
+    /// not the one the manifest describes.
+    pub fn assemble(
+        &self,
+        key: &[u8],
+        chunk_keys: &[Key],
+        fetched: impl IntoIterator<Item = KvPair>,
+    ) -> Result<Value> {
+        let mut fetched = fetched
+            .into_iter()
+            .map(|KvPair(key, value)| (key, value))
+            .collect::<BTreeMap<_, _>>();
+        let mut value = Vec::with_capacity(self.len as usize);
+        for chunk_key in chunk_keys {
+            match fetched.remove(chunk_key) {
+                Some(chunk) => value.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        if value.len() as u64 != self.len || crc64(&value) != self.crc {
+            return Err(Error::ChunkedValueCorrupt {
+                key: key.to_owned(),
+            }
+            .into());
+        }
+        Ok(value)
+    }
+}
+
+/// The key of chunk `n` of the chunked value of `key`.
+pub(crate) fn chunk_key(key: &[u8], n: u32) -> Vec<u8> {
+    let mut chunk_key = Vec::with_capacity(key.len() + CHUNK_SEPARATOR.len() + 10);
+    chunk_key.extend_from_slice(key);
+    chunk_key.extend_from_slice(CHUNK_SEPARATOR);
+    chunk_key.extend_from_slice(n.to_string().as_bytes());
+    chunk_key
+}
+
+/// Whether `key` has the form of a chunk key, `<key>\x00chunk_<n>`.
+pub(crate) fn is_chunk_key(key: &[u8]) -> bool {
+    let at = match key
+        .windows(CHUNK_SEPARATOR.len())
+        .rposition(|window| window == CHUNK_SEPARATOR)
+    {
+        Some(at) => at,
+        None => return false,
+    };
+    let n = &key[at + CHUNK_SEPARATOR.len()..];
+    !n.is_empty() && n.iter().all(u8::is_ascii_digit) && (n == b"0" || n[0] != b'0')
+}
+
+/// Scans up to a positive `limit` items from `start` through `fetch`, which
+/// scans up to a given number of items from a start bound, leaving out the
+/// items whose key, as told by `key_of`, is a chunk key: the scan goes on
+/// past them until `limit` are found or the range ends.
+pub(crate) fn scan_hiding_chunks<T>(
+    start: Bound<&[u8]>,
+    limit: u32,
+    key_of: fn(&T) -> &[u8],
+    mut fetch: impl FnMut(Bound<&[u8]>, u32) -> Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    let mut cursor = to_owned_bound(start);
+    let mut items = Vec::new();
+    loop {
+        let want = limit - items.len() as u32;
+        let page = fetch(as_ref_bound(&cursor), want)?;
+        let full = page.len() == want as usize;
+        if let Some(last) = page.last() {
+            cursor = Bound::Excluded(key_of(last).to_owned());
+        }
+        items.extend(page.into_iter().filter(|item| !is_chunk_key(key_of(item))));
+        if !full || items.len() == limit as usize {
+            return Ok(items);
+        }
+    }
+}
+
+pub(crate) fn pair_key(kv_pair: &KvPair) -> &[u8] {
+    (&kv_pair.0).into()
+}
+
+pub(crate) fn key_bytes(key: &Key) -> &[u8] {
+    key.into()
+}
+
+fn crc64(value: &[u8]) -> u64 {
+    let mut crc = Crc64::new();
+    crc.write(value);
+    crc.sum64()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::super::tests::{model, range, scan_model};
+    use super::*;
+
+    fn chunks_of(value: &[u8], chunk_bytes: usize) -> (Vec<Key>, Vec<KvPair>) {
+        let manifest = Manifest::of(value, chunk_bytes);
+        let chunk_keys = (0..manifest.chunks)
+            .map(|n| Key::from(chunk_key(b"k", n)))
+            .collect::<Vec<_>>();
+        let pairs = chunk_keys
+            .iter()
+            .zip(value.chunks(chunk_bytes))
+            .map(|(key, chunk)| KvPair(key.clone(), chunk.to_vec()))
+            .collect();
+        (chunk_keys, pairs)
+    }
+
+    fn is_corrupt(result: Result<Value>) -> bool {
+        let e = match result {
+            Ok(_) => return false,
+            Err(e) => e,
+        };
+        matches!(e.downcast_ref::<Error>(), Some(Error::ChunkedValueCorrupt { key }) if key == b"k")
+    }
+
+    #[test]
+    fn manifests_count_the_chunks_and_round_trip() {
+        assert_eq!(Manifest::of(b"", 4).chunks, 0);
+        assert_eq!(Manifest::of(b"abcd", 4).chunks, 1);
+        assert_eq!(Manifest::of(b"abcde", 4).chunks, 2);
+        let manifest = Manifest::of(b"abcdefghi", 4);
+        let encoded = manifest.encode();
+        assert_eq!(encoded.len(), MANIFEST_LEN);
+        assert!(encoded.starts_with(b"\xffCHK\0\0\0\x03"));
+        assert_eq!(Manifest::parse(&encoded), Some(manifest));
+    }
+
+    #[test]
+    fn only_manifests_parse_as_such() {
+        let encoded = Manifest::of(b"abcdefghi", 4).encode();
+        assert_eq!(Manifest::parse(&encoded[..MANIFEST_LEN - 1]), None);
+        assert_eq!(Manifest::parse(&[&encoded[..], b"x"].concat()), None);
+        let mut other_magic = encoded;
+        other_magic[0] = b'x';
+        assert_eq!(Manifest::parse(&other_magic), None);
+        assert_eq!(Manifest::parse(b""), None);
+    }
+
+    #[test]
+    fn assembling_checks_the_value_against_the_manifest() {
+        let value = b"abcdefghi";
+        let manifest = Manifest::of(value, 4);
+        let (chunk_keys, pairs) = chunks_of(value, 4);
+        // In whatever order the chunks were fetched.
+        let reversed = pairs.iter().rev().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            manifest.assemble(b"k", &chunk_keys, reversed).unwrap(),
+            value
+        );
+
+        let missing = pairs.iter().skip(1).cloned().collect::<Vec<_>>();
+        assert!(is_corrupt(manifest.assemble(b"k", &chunk_keys, missing)));
+        let mut altered = pairs.clone();
+        altered[1].1[0] ^= 1;
+        assert!(is_corrupt(manifest.assemble(b"k", &chunk_keys, altered)));
+        let mut longer = pairs;
+        longer[2].1.push(b'j');
+        assert!(is_corrupt(manifest.assemble(b"k", &chunk_keys, longer)));
+
+        let empty = Manifest::of(b"", 4);
+        assert_eq!(empty.assemble(b"k", &[], Vec::new()).unwrap(), b"");
+    }
+
+    #[test]
+    fn chunk_keys_have_a_canonical_number() {
+        assert_eq!(chunk_key(b"k", 0), b"k\x00chunk_0");
+        assert_eq!(chunk_key(b"k", 12), b"k\x00chunk_12");
+        assert!(is_chunk_key(&chunk_key(b"k", 0)));
+        assert!(is_chunk_key(&chunk_key(b"", u32::MAX)));
+        assert!(is_chunk_key(&chunk_key(&chunk_key(b"k", 1), 2)));
+        for key in [
+            &b"k"[..],
+            b"k\x00chunk_",
+            b"k\x00chunk_01",
+            b"k\x00chunk_1a",
+            b"k\x00chunk_0\x00x",
+            b"kchunk_0",
+        ]
+        .iter()
+        {
+            assert!(!is_chunk_key(key), "{:?}", key);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn scans_hide_chunk_keys(
+            model in model(),
+            chunks in prop::collection::vec((prop::collection::vec(0u8..3, 0..4), 0u32..12), 0..8),
+            (start, end) in range(),
+            limit in 1..6u32,
+        ) {
+            let mut stored = model.clone();
+            for (key, n) in chunks {
+                stored.insert(chunk_key(&key, n), Vec::new());
+            }
+            let kv_pairs = scan_hiding_chunks(as_ref_bound(&start), limit, pair_key, |cursor, want| {
+                assert!(want > 0);
+                Ok(scan_model(&stored, &to_owned_bound(cursor), &end, want))
+            })
+            .unwrap();
+            let expected = scan_model(&model, &start, &end, limit);
+            prop_assert_eq!(kv_pairs, expected);
+        }
+    }
+}
@@ -0,0 +1,1612 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::ops::Bound;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use futures::future;
+use log::warn;
+use tikv_client::{
+    request, Backoff, CheckLevel, Config, Key, Timestamp, TimestampExt, TransactionOptions, Value,
+};
+
+use super::audit::{AuditLog, AuditOptions};
+use super::capabilities::unsupported;
+use super::chunked::{chunk_key, Manifest};
+use super::metrics::{Metrics, MetricsSnapshot, StoreMetric};
+use super::mirror::{Mirror, MirrorOps};
+use super::namespace::Namespace;
+use super::pressure::{PressureCallback, PressureMonitor};
+use super::registry::{
+    EmptyCommits, SnapshotRegistry, SnapshotStats, StrictOptions, TxnRegistry, TxnReporter,
+};
+use super::snapshot::DEFAULT_READ_RETRY;
+use super::version::check_version;
+use super::{
+    as_ref_bound, block_on, check_priority, check_runtime_alive, format_key, from_tikv_error,
+    is_retryable, resume_token, to_owned_bound, Capability, Error, IsolationLevel, PartialOutcome,
+    Priority, SizeLimits, Snapshot, Transaction, TOKIO_RUNTIME,
+};
+use crate::compression::Compression;
+use crate::logger::{create_logger, LogFailureMode, Logger};
+
+/// How many times a single transaction of a chunked operation is retried on
+/// conflicts before giving up.
+const CHUNK_RETRIES: u32 = 16;
+
+/// Returns the version [`TransactionClient::snapshot_pinned`] reads at, 0
+/// when there is none yet.
+pub type ReadTsProvider = Box<dyn Fn() -> Result<u64> + Send + Sync>;
+
+/// Options of a pessimistic transaction.
+#[derive(Clone, Copy, Debug)]
+pub struct PessimisticOptions {
+    /// See [`TransactionClient::begin_pessimistic_with_isolation`].
+    pub isolation: IsolationLevel,
+    /// Whether `get_for_update` returns as soon as the lock request is sent
+    /// rather than once the lock is held, failures only showing up at
+    /// prewrite as retryable conflicts. This weakens what a successful
+    /// `get_for_update` guarantees, and is not supported by the pinned
+    /// tikv-client.
+    pub pipelined_locking: bool,
+}
+
+impl Default for PessimisticOptions {
+    fn default() -> Self {
+        PessimisticOptions {
+            isolation: IsolationLevel::SnapshotIsolation,
+            pipelined_locking: false,
+        }
+    }
+}
+
+/// How a transaction backs off on meeting a lock: `max_attempts` retries,
+/// waiting from `base_delay_ms` doubling up to `max_delay_ms`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackoffOptions {
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub max_attempts: u32,
+}
+
+/// What happens when a transaction is dropped while still active, neither
+/// committed nor rolled back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropCheck {
+    /// Logs a warning; the default, since a panic raised while C++ unwinds
+    /// through the bridge aborts the process.
+    Warn,
+    Panic,
+    Ignore,
+}
+
+impl Default for DropCheck {
+    fn default() -> Self {
+        DropCheck::Warn
+    }
+}
+
+impl DropCheck {
+    fn check_level(self) -> CheckLevel {
+        match self {
+            DropCheck::Warn => CheckLevel::Warn,
+            DropCheck::Panic => CheckLevel::Panic,
+            DropCheck::Ignore => CheckLevel::None,
+        }
+    }
+}
+
+/// Options of the transactions begun by the `begin_*` methods of a client,
+/// see [`TransactionClient::set_default_txn_options`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TxnOptions {
+    /// `None` keeps the lock backoff of tikv-client.
+    pub lock_backoff: Option<BackoffOptions>,
+    pub drop_check: DropCheck,
+    /// Whether reads give up on the first lock they meet instead of backing
+    /// off until it goes away; it overrides `lock_backoff`.
+    pub fail_fast_on_locks: bool,
+}
+
+/// How the client retries fetching a timestamp from PD, e.g. while its
+/// leader fails over: up to `max_attempts` attempts within `budget`, the
+/// waits between them doubling and adding up to the budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimestampRetry {
+    pub max_attempts: u32,
+    pub budget: Duration,
+}
+
+impl Default for TimestampRetry {
+    fn default() -> Self {
+        TimestampRetry {
+            max_attempts: 3,
+            budget: Duration::from_millis(500),
+        }
+    }
+}
+
+impl TimestampRetry {
+    /// The wait after the first failed attempt.
+    fn first_delay(self) -> Duration {
+        let waits = (1u32 << self.max_attempts.saturating_sub(1).min(31)) - 1;
+        self.budget / waits.max(1)
+    }
+}
+
+/// What [`TransactionClient::finalize_commit`] found.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FinalizeResult {
+    /// Whether the primary was committed before the call, which then sent
+    /// no commit.
+    pub already_committed: bool,
+}
+
+/// How far a PD timestamp is from the local clock, see
+/// [`TransactionClient::check_clock_skew`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockSkewReport {
+    /// The physical part of the timestamp, in milliseconds since the epoch.
+    pub pd_physical_ms: i64,
+    pub local_ms: i64,
+    /// `pd_physical_ms - local_ms`, positive when PD runs ahead.
+    pub skew_ms: i64,
+    pub within_bound: bool,
+}
+
+/// A connection to a TiKV cluster, from which transactions and snapshots are
+/// started.
+pub struct TransactionClient {
+    inner: Arc<tikv_client::TransactionClient>,
+    logger: Logger,
+    pd_endpoints: Arc<[String]>,
+    namespace: Namespace,
+    size_limits: SizeLimits,
+    default_txn_options: RwLock<TxnOptions>,
+    timestamp_retry: TimestampRetry,
+    snapshot_read_retry: BackoffOptions,
+    strict_versions: Option<u32>,
+    metrics: Arc<Metrics>,
+    pressure_monitor: Option<PressureMonitor>,
+    mirror: Option<Arc<Mirror>>,
+    audit_log: Option<Arc<AuditLog>>,
+    read_ts_provider: Option<Arc<ReadTsProvider>>,
+    txn_registry: Arc<TxnRegistry>,
+    strict: Option<StrictOptions>,
+    txn_reporter: Option<TxnReporter>,
+    snapshot_registry: Arc<SnapshotRegistry>,
+    max_live_snapshots: Option<u32>,
+}
+
+impl TransactionClient {
+    /// Connects to the cluster behind `pd_endpoints` with the default config
+    /// and the given grpc timeout, logging to a file under `log_dir`.
+    pub fn new(pd_endpoints: Vec<String>, log_dir: &str, timeout: Duration) -> Result<Self> {
+        Self::new_with_config(
+            pd_endpoints,
+            log_dir,
+            Config::default().with_timeout(timeout),
+        )
+    }
+
+    /// Connects to the cluster behind `pd_endpoints` with an explicit config,
+    /// e.g. one carrying TLS paths.
+    pub fn new_with_config(
+        pd_endpoints: Vec<String>,
+        log_dir: &str,
+        config: Config,
+    ) -> Result<Self> {
+        let logger = create_logger(log_dir)?;
+        // The first client to connect also takes the `log` macros of the
+        // wrapper; the later ones find a logger set.
+        if logger.set_log_crate_logger().is_ok() {
+            log::debug!("the log crate logs to the file of this client");
+        }
+        Ok(TransactionClient {
+            inner: Arc::new(block_on(tikv_client::TransactionClient::new_with_config(
+                pd_endpoints.clone(),
+                config,
+                Some(logger.slog().clone()),
+            ))?),
+            logger,
+            pd_endpoints: pd_endpoints.into(),
+            namespace: Namespace::default(),
+            size_limits: SizeLimits::default(),
+            default_txn_options: RwLock::default(),
+            timestamp_retry: TimestampRetry::default(),
+            snapshot_read_retry: DEFAULT_READ_RETRY,
+            strict_versions: None,
+            metrics: Arc::default(),
+            pressure_monitor: None,
+            mirror: None,
+            audit_log: None,
+            read_ts_provider: None,
+            txn_registry: Arc::default(),
+            strict: None,
+            txn_reporter: None,
+            snapshot_registry: Arc::default(),
+            max_live_snapshots: None,
+        })
+    }
+
+    /// Connects like [`new_with_config`](Self::new_with_config) to a cluster
+    /// with API v2 enabled, within its keyspace named `keyspace`, which
+    /// tikv-client then encodes every key and range of the client into.
+    ///
+    /// The keyspace is a setting of the connection, so it cannot be chosen
+    /// for a client derived from another. The pinned tikv-client speaks API
+    /// v1 only, so without [`Capability::ApiV2`] this fails with
+    /// [`Error::Unsupported`] before connecting.
+    pub fn new_with_keyspace(
+        pd_endpoints: Vec<String>,
+        _log_dir: &str,
+        _config: Config,
+        keyspace: &str,
+    ) -> Result<Self> {
+        if keyspace.is_empty() {
+            bail!("the keyspace name must not be empty");
+        }
+        Err(unsupported(
+            Capability::ApiV2,
+            format!(
+                "connecting to {} within the API v2 keyspace {:?}",
+                pd_endpoints.join(","),
+                keyspace
+            ),
+        ))
+    }
+
+    /// Returns a handle on the same connection for which every key of every
+    /// operation is prefixed with `prefix` on the way in and stripped of it on
+    /// the way out. Scans never leave the prefix, even when unbounded.
+    ///
+    /// Prefixes nest: calling this on a prefixed client appends to its prefix.
+    pub fn with_prefix(&self, prefix: &[u8]) -> TransactionClient {
+        TransactionClient {
+            inner: self.inner.clone(),
+            logger: self.logger.clone(),
+            pd_endpoints: self.pd_endpoints.clone(),
+            namespace: self.namespace.join(prefix),
+            size_limits: self.size_limits,
+            default_txn_options: RwLock::new(self.default_txn_options()),
+            timestamp_retry: self.timestamp_retry,
+            snapshot_read_retry: self.snapshot_read_retry,
+            strict_versions: self.strict_versions,
+            metrics: self.metrics.clone(),
+            pressure_monitor: None,
+            mirror: None,
+            audit_log: self.audit_log.clone(),
+            read_ts_provider: self.read_ts_provider.clone(),
+            txn_registry: self.txn_registry.clone(),
+            strict: self.strict,
+            txn_reporter: None,
+            snapshot_registry: self.snapshot_registry.clone(),
+            max_live_snapshots: self.max_live_snapshots,
+        }
+    }
+
+    /// Sets the priority of every request of this client by default; only
+    /// [`Priority::Normal`] is supported by the pinned tikv-client.
+    pub fn set_default_priority(&mut self, priority: Priority) -> Result<()> {
+        check_priority(priority)
+    }
+
+    /// Sets the limits checked by the writes of the transactions begun from
+    /// then on; they default to TiKV's own. With
+    /// [`OversizedValues::Chunk`](super::OversizedValues::Chunk), those
+    /// transactions and the snapshots created from then on also read
+    /// chunked values back whole and hide chunk keys from their scans; a
+    /// client reading them otherwise sees the manifests and chunks as
+    /// stored.
+    pub fn set_size_limits(&mut self, limits: SizeLimits) {
+        self.size_limits = limits;
+    }
+
+    pub fn size_limits(&self) -> SizeLimits {
+        self.size_limits
+    }
+
+    /// Sets the options of the transactions begun from then on by
+    /// [`begin_optimistic`](Self::begin_optimistic) and the
+    /// `begin_pessimistic*` methods, the drop check also applying to
+    /// [`begin_optimistic_with_retry`](Self::begin_optimistic_with_retry);
+    /// safe to call while other threads begin transactions. Clients derived
+    /// with [`with_prefix`](Self::with_prefix) start with a copy of the
+    /// options.
+    pub fn set_default_txn_options(&self, options: TxnOptions) {
+        *self
+            .default_txn_options
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = options;
+    }
+
+    pub fn default_txn_options(&self) -> TxnOptions {
+        *self
+            .default_txn_options
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sets how [`current_timestamp`](Self::current_timestamp), and the
+    /// `begin_*` and `snapshot*` methods fetching a timestamp, retry when PD
+    /// fails; once out of attempts or budget they fail with
+    /// [`Error::TimestampUnavailable`]. Defaults to 3 attempts within
+    /// 500ms.
+    pub fn set_timestamp_retry(&mut self, retry: TimestampRetry) -> Result<()> {
+        if retry.max_attempts == 0 {
+            bail!("max_attempts must be positive");
+        }
+        self.timestamp_retry = retry;
+        Ok(())
+    }
+
+    pub fn timestamp_retry(&self) -> TimestampRetry {
+        self.timestamp_retry
+    }
+
+    /// Sets how the reads of the snapshots created from then on are sent
+    /// again when they fail on a region error, such as not-leader or
+    /// epoch-not-match while regions are rebalanced: up to `max_attempts`
+    /// more times, waiting from `base_delay_ms` doubling up to
+    /// `max_delay_ms`; a `max_attempts` of 0 disables it. Other errors, and
+    /// the last region error once out of retries, still fail the read.
+    /// Defaults to the region backoff of tikv-client, 10 retries from 2ms up
+    /// to 500ms.
+    ///
+    /// The retries are counted in
+    /// [`MetricsSnapshot::snapshot_read_retries`]. The snapshots
+    /// transactions read through under read-committed keep the default.
+    pub fn set_snapshot_read_retry(&mut self, retry: BackoffOptions) {
+        self.snapshot_read_retry = retry;
+    }
+
+    pub fn snapshot_read_retry(&self) -> BackoffOptions {
+        self.snapshot_read_retry
+    }
+
+    /// Lets the point operations of this client, and of the transactions and
+    /// snapshots started from then on, take the empty key, which they reject
+    /// with [`Error::EmptyKey`] by default: it is most often an uninitialized
+    /// key, and once written it sorts before every other one. Empty scan
+    /// bounds are always valid.
+    pub fn set_allow_empty_keys(&mut self, allow: bool) {
+        self.namespace.set_allow_empty_keys(allow);
+    }
+
+    /// Makes the transactions and bulk writers started from then on store
+    /// the values they write in the envelope of [`crate::compression`],
+    /// compressed when they have at least `min_value_bytes` and it saves
+    /// space. While compression is set, their reads open the values carrying
+    /// the envelope, so enveloped and plain values can be mixed;
+    /// [`Snapshot::get_raw`] and [`Snapshot::scan_raw`] read the stored
+    /// bytes. With [`Compression::None`], reads return every value as
+    /// stored, envelopes included, so a client reading compressed values
+    /// must set compression too.
+    pub fn set_compression(&mut self, compression: Compression, min_value_bytes: usize) {
+        self.namespace.set_compression(compression, min_value_bytes);
+    }
+
+    /// Makes the point operations of this client, and of the transactions
+    /// and snapshots started from then on, fail with [`Error::ReservedKey`]
+    /// on keys under [`RESERVED_PREFIX`](super::RESERVED_PREFIX), and their
+    /// scans skip the range of that prefix, so that user data and the
+    /// wrapper's own bookkeeping keys cannot collide.
+    pub fn set_guard_reserved(&mut self, guard: bool) {
+        self.namespace.set_guard_reserved(guard);
+    }
+
+    /// The prefix added to every key, empty for the whole keyspace.
+    pub fn prefix(&self) -> &[u8] {
+        self.namespace.prefix()
+    }
+
+    /// The PD endpoints the client was created with.
+    pub fn pd_endpoints(&self) -> &[String] {
+        &self.pd_endpoints
+    }
+
+    pub(crate) fn inner(&self) -> &Arc<tikv_client::TransactionClient> {
+        &self.inner
+    }
+
+    pub(crate) fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    pub fn begin_optimistic(&self) -> Result<Transaction> {
+        let (inner, start_version) = self.begin_with_default_options(false)?;
+        Ok(self.transaction(inner, start_version, false))
+    }
+
+    /// Begins a tikv-client transaction with the default transaction options
+    /// and returns it with its start version.
+    fn begin_with_default_options(
+        &self,
+        pessimistic: bool,
+    ) -> Result<(tikv_client::Transaction, u64)> {
+        let options = self.default_options(pessimistic);
+        let timestamp = self.fetch_timestamp()?;
+        let start_version = timestamp.version();
+        Ok((
+            self.inner.new_transaction_with_options(timestamp, options),
+            start_version,
+        ))
+    }
+
+    /// The tikv-client options of a transaction begun with the default
+    /// transaction options.
+    fn default_options(&self, pessimistic: bool) -> TransactionOptions {
+        let defaults = self.default_txn_options();
+        let (mut options, mut retry_options) = if pessimistic {
+            (
+                TransactionOptions::new_pessimistic(),
+                request::RetryOptions::default_pessimistic(),
+            )
+        } else {
+            (
+                TransactionOptions::new_optimistic(),
+                request::RetryOptions::default_optimistic(),
+            )
+        };
+        if defaults.fail_fast_on_locks {
+            retry_options.lock_backoff = Backoff::no_backoff();
+            options = options.retry_options(retry_options);
+        } else if let Some(backoff) = defaults.lock_backoff {
+            retry_options.lock_backoff = Backoff::no_jitter_backoff(
+                backoff.base_delay_ms,
+                backoff.max_delay_ms,
+                backoff.max_attempts,
+            );
+            options = options.retry_options(retry_options);
+        }
+        options.drop_check(defaults.drop_check.check_level())
+    }
+
+    /// Begins an optimistic transaction whose lock backoff gives up after
+    /// `retry` attempts.
+    pub fn begin_optimistic_with_retry(&self, retry: u32) -> Result<Transaction> {
+        let options = TransactionOptions::new_optimistic();
+        let mut retry_options = request::RetryOptions::default_optimistic();
+        retry_options.lock_backoff = Backoff::no_jitter_backoff(2, 500, retry);
+        let options = options
+            .retry_options(retry_options)
+            .drop_check(self.default_txn_options().drop_check.check_level());
+        let timestamp = self.fetch_timestamp()?;
+        let start_version = timestamp.version();
+        Ok(self.transaction(
+            self.inner.new_transaction_with_options(timestamp, options),
+            start_version,
+            false,
+        ))
+    }
+
+    /// Begins a pessimistic transaction with its own lock backoff, whatever
+    /// the default transaction options, e.g. a more aggressive ceiling than
+    /// the optimistic workload of the same client.
+    pub fn begin_pessimistic_with_backoff(&self, backoff: BackoffOptions) -> Result<Transaction> {
+        let options = TransactionOptions::new_pessimistic();
+        let mut retry_options = request::RetryOptions::default_pessimistic();
+        retry_options.lock_backoff = Backoff::no_jitter_backoff(
+            backoff.base_delay_ms,
+            backoff.max_delay_ms,
+            backoff.max_attempts,
+        );
+        let options = options
+            .retry_options(retry_options)
+            .drop_check(self.default_txn_options().drop_check.check_level());
+        let timestamp = self.fetch_timestamp()?;
+        let start_version = timestamp.version();
+        Ok(self.transaction(
+            self.inner.new_transaction_with_options(timestamp, options),
+            start_version,
+            true,
+        ))
+    }
+
+    /// Begins a transaction that only reads: it is marked read-only in
+    /// tikv-client, which then sets up nothing for a commit, its mutations
+    /// fail with [`Error::ReadOnly`](super::Error::ReadOnly), and its commit
+    /// sends nothing and returns 0.
+    pub fn begin_read_only(&self) -> Result<Transaction> {
+        let options = TransactionOptions::new_optimistic()
+            .read_only()
+            .drop_check(self.default_txn_options().drop_check.check_level());
+        let timestamp = self.fetch_timestamp()?;
+        let start_version = timestamp.version();
+        let inner = self.inner.new_transaction_with_options(timestamp, options);
+        Ok(self
+            .transaction(inner, start_version, false)
+            .into_read_only())
+    }
+
+    pub fn begin_pessimistic(&self) -> Result<Transaction> {
+        self.begin_pessimistic_with_isolation(IsolationLevel::SnapshotIsolation)
+    }
+
+    /// Begins a pessimistic transaction whose plain reads have the given
+    /// isolation level; locking reads always see the latest data.
+    ///
+    /// tikv-client has no read-committed mode, so the wrapper emulates it:
+    /// reads of keys the transaction has not written go to a snapshot at a
+    /// fresh timestamp, which costs a PD round trip per read.
+    pub fn begin_pessimistic_with_isolation(
+        &self,
+        isolation: IsolationLevel,
+    ) -> Result<Transaction> {
+        self.begin_pessimistic_with_options(PessimisticOptions {
+            isolation,
+            ..PessimisticOptions::default()
+        })
+    }
+
+    pub fn begin_pessimistic_with_options(
+        &self,
+        options: PessimisticOptions,
+    ) -> Result<Transaction> {
+        if options.pipelined_locking {
+            return Err(unsupported(
+                Capability::PipelinedLocking,
+                "pipelined pessimistic locking".to_owned(),
+            ));
+        }
+        let (inner, start_version) = self.begin_with_default_options(true)?;
+        Ok(self.registered(
+            Transaction::new(
+                inner,
+                self.inner.clone(),
+                true,
+                options.isolation,
+                self.namespace.clone(),
+                self.size_limits,
+                self.metrics.clone(),
+            )
+            .with_start_version(start_version)
+            .with_audit_log(self.audit_log.clone())
+            .with_chunk_options(self.default_options(false)),
+        ))
+    }
+
+    /// Reads `key`, hands its current value to `modify` and writes back what
+    /// it returns, in an optimistic transaction that is retried from scratch
+    /// on retryable conflicts, up to `max_retries` times.
+    ///
+    /// `modify` is called again with the freshly read value on every retry;
+    /// returning `None` aborts. Returns the value that was committed.
+    pub fn read_modify_write(
+        &self,
+        key: &[u8],
+        max_retries: u32,
+        mut modify: impl FnMut(Option<&[u8]>) -> Result<Option<Vec<u8>>>,
+    ) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            let mut txn = self.begin_optimistic()?;
+            let result = txn
+                .get(key)
+                .and_then(|current| match modify(current.as_deref())? {
+                    Some(value) => {
+                        txn.put(key, &value)?;
+                        txn.commit()?;
+                        Ok(Some(value))
+                    }
+                    None => Ok(None),
+                });
+            match result {
+                Ok(Some(value)) => return Ok(value),
+                Ok(None) => {
+                    txn.rollback()?;
+                    bail!(
+                        "read-modify-write of {} aborted by the callback",
+                        format_key(key)
+                    );
+                }
+                Err(e) if attempt < max_retries && is_retryable(&e) => {
+                    let _ = txn.rollback();
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let _ = txn.rollback();
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Runs `f` in a fresh optimistic transaction and commits it, both
+    /// retried from scratch on retryable conflicts, for up to `max_attempts`
+    /// attempts in all. Returns the commit version.
+    ///
+    /// Retries wait `base_delay` at first, doubled after every attempt up to
+    /// `max_delay`. `f` returning `false` rolls back and aborts without
+    /// retrying; the transaction it is given only lives for one attempt.
+    pub fn run_optimistic(
+        &self,
+        max_attempts: u32,
+        base_delay: Duration,
+        max_delay: Duration,
+        mut f: impl FnMut(&mut Transaction) -> Result<bool>,
+    ) -> Result<u64> {
+        if max_attempts == 0 {
+            bail!("max_attempts must be positive");
+        }
+        let mut attempt = 1;
+        let mut delay = base_delay;
+        loop {
+            let mut txn = self.begin_optimistic()?;
+            let result = f(&mut txn).and_then(|proceed| {
+                if proceed {
+                    txn.commit().map(Some)
+                } else {
+                    Ok(None)
+                }
+            });
+            match result {
+                Ok(Some(commit_ts)) => return Ok(commit_ts),
+                Ok(None) => {
+                    txn.rollback()?;
+                    bail!("transaction aborted by the callback");
+                }
+                Err(e) if attempt < max_attempts && is_retryable(&e) => {
+                    let _ = txn.rollback();
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(max_delay);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let _ = txn.rollback();
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Deletes every key in the range, `keys_per_txn` keys per transaction, and
+    /// returns how many keys were deleted.
+    ///
+    /// Each chunk scans the next keys of what is left of the range, deletes
+    /// them and commits; a chunk hitting a retryable conflict is rolled back
+    /// and retried on its own. The range as a whole is not atomic: chunks
+    /// committed before an error stay deleted, so calling this again with the
+    /// same range resumes where it stopped. Keys written behind the progress
+    /// of a running call are not deleted by it.
+    pub fn delete_range_chunked(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        keys_per_txn: u32,
+        pessimistic: bool,
+    ) -> Result<u64> {
+        let mut cursor = to_owned_bound(start);
+        let mut deleted = 0;
+        self.delete_range_from(&mut cursor, end, keys_per_txn, pessimistic, &mut deleted)
+            .map_err(|e| e.context(format!("deleted {} keys before failing", deleted)))?;
+        Ok(deleted)
+    }
+
+    /// Like [`delete_range_chunked`](Self::delete_range_chunked), returning
+    /// how far it got rather than failing: the keys deleted, and on an error
+    /// where to resume.
+    pub fn delete_range_partial(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        keys_per_txn: u32,
+        pessimistic: bool,
+    ) -> PartialOutcome {
+        let mut cursor = to_owned_bound(start);
+        let mut deleted = 0;
+        let result =
+            self.delete_range_from(&mut cursor, end, keys_per_txn, pessimistic, &mut deleted);
+        PartialOutcome::new(deleted, 0, result, resume_token(as_ref_bound(&cursor)))
+    }
+
+    /// Deletes the range from `cursor` on, moving it past every chunk
+    /// committed and counting their keys in `deleted`.
+    fn delete_range_from(
+        &self,
+        cursor: &mut Bound<Vec<u8>>,
+        end: Bound<&[u8]>,
+        keys_per_txn: u32,
+        pessimistic: bool,
+        deleted: &mut u64,
+    ) -> Result<()> {
+        if keys_per_txn == 0 {
+            bail!("keys_per_txn must be positive");
+        }
+        loop {
+            let chunk = self.retry_chunk(pessimistic, |txn| {
+                delete_chunk(txn, as_ref_bound(cursor), end, keys_per_txn)
+            })?;
+            match chunk {
+                Some((last_key, count)) => {
+                    *deleted += count;
+                    *cursor = Bound::Excluded(last_key);
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Splits regions at the given sample keys ahead of a bulk load, and
+    /// optionally scatters the new regions, returning how many splits were
+    /// created.
+    ///
+    /// The pinned tikv-client exposes no split or scatter request, so after
+    /// computing the split points this always fails; the signature is in
+    /// place for when it does.
+    pub fn presplit_for_keys<'a>(
+        &self,
+        sample_keys: impl IntoIterator<Item = &'a [u8]>,
+        scatter: bool,
+    ) -> Result<u32> {
+        let mut split_keys = sample_keys
+            .into_iter()
+            .filter(|key| !key.is_empty())
+            .map(|key| self.namespace.encode(key))
+            .collect::<Vec<_>>();
+        split_keys.sort();
+        split_keys.dedup();
+        Err(unsupported(
+            Capability::RegionSplit,
+            format!(
+                "splitting regions (at {} keys{})",
+                split_keys.len(),
+                if scatter { ", with scatter" } else { "" }
+            ),
+        ))
+    }
+
+    /// How many regions the keys buffered by `transaction` span, looked up
+    /// in the region cache without sending any write, so possibly off when
+    /// the cache is stale.
+    ///
+    /// The pinned tikv-client keeps its region cache and PD client private,
+    /// so with any key buffered this always fails; the signature is in
+    /// place for when it exposes a region lookup.
+    pub fn region_span(&self, transaction: &Transaction) -> Result<u64> {
+        let keys = transaction.buffered_keys();
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        Err(unsupported(
+            Capability::RegionLookup,
+            format!("looking up the regions of {} buffered keys", keys.len()),
+        ))
+    }
+
+    /// Runs `f`, which is expected to commit, in a fresh transaction that is
+    /// rolled back and retried from scratch on retryable conflicts, up to
+    /// [`CHUNK_RETRIES`] times.
+    pub(crate) fn retry_chunk<T>(
+        &self,
+        pessimistic: bool,
+        mut f: impl FnMut(&mut Transaction) -> Result<T>,
+    ) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let mut txn = if pessimistic {
+                self.begin_pessimistic()?
+            } else {
+                self.begin_optimistic()?
+            };
+            match f(&mut txn) {
+                Ok(result) => return Ok(result),
+                Err(e) if attempt < CHUNK_RETRIES && is_retryable(&e) => {
+                    let _ = txn.rollback();
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let _ = txn.rollback();
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Creates a snapshot at the current timestamp.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let timestamp = self.fetch_timestamp()?;
+        self.registered_snapshot(self.snapshot_at_timestamp(timestamp))
+    }
+
+    /// Sets where [`snapshot_pinned`](Self::snapshot_pinned) takes its
+    /// version from, e.g. the commit timestamp of the last block the
+    /// application saw complete; `None` removes it. Clients derived with
+    /// [`with_prefix`](Self::with_prefix) from then on share it.
+    pub fn set_read_ts_provider(&mut self, provider: Option<ReadTsProvider>) {
+        self.read_ts_provider = provider.map(Arc::new);
+    }
+
+    /// Creates a snapshot at the version of the read timestamp provider
+    /// instead of a fresh one from PD, so that it never sees writes past
+    /// what the application considers committed.
+    ///
+    /// The provider is called once, here, on the calling thread; the reads
+    /// of the snapshot never call it. When it returns 0 the snapshot is
+    /// taken at a fresh timestamp, with a warning. Fails when the provider
+    /// fails or none is set.
+    pub fn snapshot_pinned(&self) -> Result<Snapshot> {
+        let provider = match &self.read_ts_provider {
+            Some(provider) => provider,
+            None => bail!("a pinned snapshot needs a read timestamp provider"),
+        };
+        match provider().context("the read timestamp provider failed")? {
+            0 => {
+                warn!("the read timestamp provider returned 0, reading at a fresh timestamp");
+                self.snapshot()
+            }
+            version => self.snapshot_at(version),
+        }
+    }
+
+    /// Creates a snapshot at a fresh timestamp whose reads do not wait on
+    /// locks: one meeting a lock fails at once with
+    /// [`Error::KeyLocked`](super::Error::KeyLocked), e.g. for a probe
+    /// reporting writes in progress.
+    ///
+    /// tikv-client has no switch to skip lock resolution, so this is its
+    /// lock backoff set to none: a lock met is still checked once against
+    /// the status of its transaction, and only a live one fails the read.
+    pub fn snapshot_fail_fast(&self) -> Result<Snapshot> {
+        let timestamp = self.fetch_timestamp()?;
+        let mut retry_options = request::RetryOptions::default_optimistic();
+        retry_options.lock_backoff = Backoff::no_backoff();
+        let options = TransactionOptions::new_optimistic().retry_options(retry_options);
+        let snapshot = Snapshot::new(
+            self.inner.snapshot(timestamp.clone(), options),
+            self.inner.clone(),
+            timestamp.version(),
+            self.namespace.clone(),
+            self.metrics.clone(),
+        )
+        .with_read_retry(self.snapshot_read_retry)
+        .with_mirror(self.mirror.clone())
+        .with_chunked_values(self.size_limits.chunk_bytes().is_some())
+        .failing_fast();
+        self.registered_snapshot(snapshot)
+    }
+
+    /// Creates a snapshot at the given version.
+    pub fn snapshot_at(&self, version: u64) -> Result<Snapshot> {
+        self.check_version("snapshot version", version)?;
+        self.registered_snapshot(self.snapshot_at_timestamp(Timestamp::from_version(version)))
+    }
+
+    /// Adds a snapshot just created to the registry, failing when as many
+    /// as [`set_max_live_snapshots`](Self::set_max_live_snapshots) allows
+    /// are live already.
+    fn registered_snapshot(&self, snapshot: Snapshot) -> Result<Snapshot> {
+        let record = self.snapshot_registry.register(self.max_live_snapshots)?;
+        Ok(snapshot.with_record(record))
+    }
+
+    /// Limits the snapshots of this connection live at once, `None` leaving
+    /// them unlimited, the default: creating one more with
+    /// [`snapshot`](Self::snapshot), [`snapshot_at`](Self::snapshot_at),
+    /// [`snapshot_pinned`](Self::snapshot_pinned) or
+    /// [`snapshot_fail_fast`](Self::snapshot_fail_fast) fails with
+    /// [`Error::TooManySnapshots`](super::Error::TooManySnapshots), giving the
+    /// ages of the oldest live ones. A leaked snapshot holds back the
+    /// resolved timestamp of the whole cluster, so this catches leaks early.
+    ///
+    /// Snapshots are counted from their creation until they are dropped,
+    /// across this client and the ones derived from it with
+    /// [`with_prefix`](Self::with_prefix), which take a copy of the limit;
+    /// the snapshots of transactions are not counted.
+    pub fn set_max_live_snapshots(&mut self, max_live: Option<u32>) -> Result<()> {
+        if max_live == Some(0) {
+            bail!("the limit of live snapshots must be positive");
+        }
+        self.max_live_snapshots = max_live;
+        Ok(())
+    }
+
+    /// The live and created snapshots counted by
+    /// [`set_max_live_snapshots`](Self::set_max_live_snapshots), whether a
+    /// limit is set or not.
+    pub fn snapshot_stats(&self) -> SnapshotStats {
+        self.snapshot_registry.stats()
+    }
+
+    /// Gets `key` at each of `versions`, reading them concurrently, and
+    /// returns the results in the same order. A failed read only fails its
+    /// own entry.
+    ///
+    /// The pinned tikv-client cannot query the GC safepoint, so versions
+    /// below it are not rejected up front; their entry fails if TiKV rejects
+    /// the read, otherwise it holds whatever GC left behind. Chunked values
+    /// are reassembled at the version of their entry when the client chunks
+    /// values.
+    pub fn multi_version_get(&self, key: &[u8], versions: &[u64]) -> Vec<Result<Option<Value>>> {
+        let encoded = match self.namespace.encode_key(key) {
+            Ok(encoded) => encoded,
+            // Errors do not clone, so every entry gets the error of encoding
+            // the key anew.
+            Err(_) => {
+                return versions
+                    .iter()
+                    .map(|_| self.namespace.encode_key(key).map(|_| None))
+                    .collect()
+            }
+        };
+        if check_runtime_alive().is_err() {
+            return versions
+                .iter()
+                .map(|_| Err(Error::RuntimeShutDown.into()))
+                .collect();
+        }
+        let namespace = &self.namespace;
+        let chunked = self.size_limits.chunk_bytes().is_some();
+        let reads = versions.iter().map(|&version| {
+            let checked = self.check_version("version", version);
+            let mut snapshot = self.inner.snapshot(
+                Timestamp::from_version(version),
+                TransactionOptions::new_optimistic(),
+            );
+            let encoded = encoded.clone();
+            async move {
+                checked?;
+                let value = match snapshot.get(encoded).await.map_err(from_tikv_error)? {
+                    Some(value) => namespace.decode_value(value),
+                    None => return Ok(None),
+                };
+                let manifest = match Manifest::parse(&value) {
+                    Some(manifest) if chunked => manifest,
+                    _ => return Ok(Some(value)),
+                };
+                let chunk_keys = (0..manifest.chunks)
+                    .map(|n| Key::from(namespace.encode(&chunk_key(key, n))))
+                    .collect::<Vec<_>>();
+                let fetched = snapshot
+                    .batch_get(chunk_keys.clone())
+                    .await
+                    .map_err(from_tikv_error)?;
+                manifest.assemble(key, &chunk_keys, fetched).map(Some)
+            }
+        });
+        TOKIO_RUNTIME.block_on(future::join_all(reads))
+    }
+
+    pub(crate) fn snapshot_at_timestamp(&self, timestamp: Timestamp) -> Snapshot {
+        let version = timestamp.version();
+        Snapshot::new(
+            self.inner
+                .snapshot(timestamp, TransactionOptions::new_optimistic()),
+            self.inner.clone(),
+            version,
+            self.namespace.clone(),
+            self.metrics.clone(),
+        )
+        .with_read_retry(self.snapshot_read_retry)
+        .with_mirror(self.mirror.clone())
+        .with_chunked_values(self.size_limits.chunk_bytes().is_some())
+    }
+
+    fn transaction(
+        &self,
+        inner: tikv_client::Transaction,
+        start_version: u64,
+        pessimistic: bool,
+    ) -> Transaction {
+        self.registered(
+            Transaction::new(
+                inner,
+                self.inner.clone(),
+                pessimistic,
+                IsolationLevel::SnapshotIsolation,
+                self.namespace.clone(),
+                self.size_limits,
+                self.metrics.clone(),
+            )
+            .with_start_version(start_version)
+            .with_audit_log(self.audit_log.clone())
+            .with_chunk_options(self.default_options(false)),
+        )
+    }
+
+    /// Adds a transaction just begun to the registry, warning first when
+    /// strict mode finds too many active.
+    fn registered(&self, txn: Transaction) -> Transaction {
+        let empty_commits = match &self.strict {
+            Some(strict) => {
+                self.txn_registry
+                    .check_active(strict.max_active_transactions);
+                strict.empty_commits
+            }
+            None => EmptyCommits::Allow,
+        };
+        txn.registered_in(&self.txn_registry, empty_commits)
+    }
+
+    /// Turns on the checks of strict mode for the transactions begun from
+    /// then on, or off with `None`, to catch transactions leaked or
+    /// committed for nothing: beginning one while more than
+    /// `max_active_transactions` are active logs them at warn, with their
+    /// tags and ages, committing one that buffered nothing warns or fails
+    /// with [`Error::EmptyCommit`](super::Error::EmptyCommit), and a thread
+    /// of its own logs every transaction still active at `report_age`,
+    /// once, with the summary of its operation log when it keeps one.
+    ///
+    /// The active transactions are those begun through this client or the
+    /// ones derived from it with [`with_prefix`](Self::with_prefix), which
+    /// take a copy of the options, and not committed, rolled back or
+    /// dropped yet. The report thread belongs to this handle.
+    pub fn set_strict_mode(&mut self, options: Option<StrictOptions>) -> Result<()> {
+        self.txn_reporter = None;
+        if let Some(report_age) = options.and_then(|options| options.report_age) {
+            self.txn_reporter = Some(TxnReporter::start(self.txn_registry.clone(), report_age)?);
+        }
+        self.strict = options;
+        Ok(())
+    }
+
+    pub fn strict_mode(&self) -> Option<StrictOptions> {
+        self.strict
+    }
+
+    /// How many transactions begun through this client or the ones derived
+    /// from it are active, see [`set_strict_mode`](Self::set_strict_mode).
+    pub fn active_transaction_count(&self) -> usize {
+        self.txn_registry.active().len()
+    }
+
+    /// What the transactions and snapshots of this connection saw of each
+    /// TiKV store, shared by the clients derived with
+    /// [`with_prefix`](Self::with_prefix).
+    pub fn store_metrics(&self) -> Vec<StoreMetric> {
+        self.metrics.store_metrics()
+    }
+
+    /// The read and write totals of this connection, shared like
+    /// [`store_metrics`](Self::store_metrics).
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot(self.logger.failed_writes())
+    }
+
+    /// Sets what the logger of this connection does with the records it
+    /// fails to write to its file from then on; the default is
+    /// [`LogFailureMode::Drop`]. Failed writes never abort and are counted
+    /// in [`MetricsSnapshot::failed_log_writes`].
+    pub fn set_log_failure_mode(&self, mode: LogFailureMode) {
+        self.logger.set_failure_mode(mode);
+    }
+
+    /// Zeroes the read and write totals, e.g. at the start of a billing
+    /// period.
+    pub fn reset_metrics(&self) {
+        self.metrics.reset();
+    }
+
+    /// Makes the connection log a warning when more than `per_minute`
+    /// not-leader, epoch-not-match and server-is-busy errors are counted
+    /// within a minute, at most once a minute; 0, the default, disables it.
+    pub fn set_region_error_warn_threshold(&self, per_minute: u64) {
+        self.metrics.set_region_error_warn_threshold(per_minute);
+    }
+
+    /// Calls `callback` with [`PressureLevel::High`](super::PressureLevel::High)
+    /// once the operations in flight on this connection reach
+    /// `high_watermark`, and with
+    /// [`PressureLevel::Normal`](super::PressureLevel::Normal) once they fall
+    /// back to `low_watermark`, so that a scheduler can shed load before
+    /// requests time out; replaces the callback set before, and `None`
+    /// removes it.
+    ///
+    /// The callback runs on a thread of its own, never on the runtime, at
+    /// most once every 500ms, the level being checked every 50ms. The
+    /// operations counted are those of
+    /// [`MetricsSnapshot::in_flight_operations`]. The callback belongs to
+    /// this handle: clients derived with [`with_prefix`](Self::with_prefix)
+    /// have none, though their operations count.
+    pub fn set_pressure_callback(
+        &mut self,
+        callback: Option<PressureCallback>,
+        high_watermark: u64,
+        low_watermark: u64,
+    ) -> Result<()> {
+        self.pressure_monitor = None;
+        if let Some(callback) = callback {
+            self.pressure_monitor = Some(PressureMonitor::start(
+                self.metrics.clone(),
+                callback,
+                high_watermark,
+                low_watermark,
+            )?);
+        }
+        Ok(())
+    }
+
+    /// Replays `sample_percent` out of every hundred `ops` reads of the
+    /// snapshots created from then on against the cluster of `mirror`, e.g.
+    /// a staging one being validated, counting in
+    /// [`MetricsSnapshot::mirror_matches`] and the counters after it whether
+    /// both returned the same; replaces the mirror set before.
+    ///
+    /// The replay runs on the runtime after the read returned, so it
+    /// neither delays nor fails it, and is dropped when 64 are already in
+    /// flight. It reads at a fresh timestamp of the mirror cluster, with
+    /// the keys under the prefix of `mirror`, so writes in between show as
+    /// mismatches. The first key of a mismatch and the crc64 of both values
+    /// are logged at warn, at most once every 10s. The reads of transactions
+    /// are not mirrored, nor are writes. The mirror belongs to this handle:
+    /// clients derived with [`with_prefix`](Self::with_prefix) have none.
+    pub fn set_mirror(
+        &mut self,
+        mirror: &TransactionClient,
+        sample_percent: u32,
+        ops: MirrorOps,
+    ) -> Result<()> {
+        if sample_percent > 100 {
+            bail!("the sample rate {}% is over 100%", sample_percent);
+        }
+        self.mirror = Some(Arc::new(Mirror::new(
+            mirror.inner.clone(),
+            mirror.namespace.clone(),
+            sample_percent,
+            ops,
+            self.metrics.clone(),
+        )));
+        Ok(())
+    }
+
+    /// Stops replaying the reads of the snapshots created from then on;
+    /// those of the snapshots already created still are.
+    pub fn clear_mirror(&mut self) {
+        self.mirror = None;
+    }
+
+    /// Makes the commits of the transactions begun from then on append their
+    /// puts and deletes to the audit trail of `options`, see
+    /// [`AuditOptions`](super::AuditOptions) for the format, and return only
+    /// once the records are written, and synced if so configured; `None`
+    /// stops it. A commit whose records fail to be written fails with
+    /// [`Error::AuditFailed`], though it did commit.
+    ///
+    /// Records are buffered while a commit is formatted and written in one
+    /// go at its end, so the cost is one write per commit, plus one sync
+    /// with [`AuditFsync::EveryCommit`](super::AuditFsync::EveryCommit).
+    /// Commits of the same client are written one at a time. Clients derived
+    /// with [`with_prefix`](Self::with_prefix) from then on share the
+    /// trail; bulk writes and the write probe of the self-check are not
+    /// recorded.
+    pub fn set_audit_log(&mut self, options: Option<AuditOptions>) -> Result<()> {
+        self.audit_log = match options {
+            Some(options) => Some(Arc::new(AuditLog::open(options)?)),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Fetches a fresh timestamp from PD and returns its version.
+    pub fn current_timestamp(&self) -> Result<u64> {
+        let timestamp = self.fetch_timestamp()?;
+        Ok(timestamp.version())
+    }
+
+    /// Makes the calls of this client taking a version, such as
+    /// [`snapshot_at`](Self::snapshot_at) and [`gc`](Self::gc), reject one
+    /// whose physical part is more than `within_years` from now, e.g. Unix
+    /// milliseconds passed as is instead of through
+    /// [`version_from_unix_ms`](super::version_from_unix_ms). `None`, the
+    /// default, checks nothing.
+    pub fn set_strict_versions(&mut self, within_years: Option<u32>) {
+        self.strict_versions = within_years;
+    }
+
+    fn check_version(&self, what: &str, version: u64) -> Result<()> {
+        match self.strict_versions {
+            Some(within_years) => check_version(what, version, within_years),
+            None => Ok(()),
+        }
+    }
+
+    /// Fetches a timestamp from PD, retrying as set by
+    /// [`set_timestamp_retry`](Self::set_timestamp_retry); the error is the
+    /// last attempt's.
+    fn fetch_timestamp(&self) -> Result<Timestamp> {
+        let retry = self.timestamp_retry;
+        let start = Instant::now();
+        let mut delay = retry.first_delay();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let e = match block_on(self.inner.current_timestamp()) {
+                Ok(timestamp) => return Ok(timestamp),
+                Err(e) => e,
+            };
+            let remaining = retry.budget.saturating_sub(start.elapsed());
+            if attempts >= retry.max_attempts || remaining.is_zero() {
+                return Err(e.context(Error::TimestampUnavailable { attempts }));
+            }
+            thread::sleep(delay.min(remaining));
+            delay = delay.saturating_mul(2);
+        }
+    }
+
+    /// The PD endpoint the client is connected to, the PD leader.
+    ///
+    /// The pinned tikv-client keeps its PD connection private and reports no
+    /// leader change, and the crate has no PD protocol client of its own to
+    /// poll the membership with, so this always fails.
+    pub fn current_pd(&self) -> Result<String> {
+        Err(unsupported(
+            Capability::PdLeader,
+            "reading the current PD endpoint".to_owned(),
+        ))
+    }
+
+    /// How many PD leader changes the client has seen; always fails, see
+    /// [`current_pd`](Self::current_pd).
+    pub fn pd_leader_changes(&self) -> Result<u64> {
+        Err(unsupported(
+            Capability::PdLeader,
+            "observing PD leader changes".to_owned(),
+        ))
+    }
+
+    /// Fetches a timestamp from PD and compares its physical part with the
+    /// local clock, which is read halfway through the request so that the
+    /// round trip does not count as skew.
+    pub fn check_clock_skew(&self, max_skew: Duration) -> Result<ClockSkewReport> {
+        let before = local_ms()?;
+        let timestamp = block_on(self.inner.current_timestamp())?;
+        let after = local_ms()?;
+        let local_ms = before + (after - before) / 2;
+        let skew_ms = timestamp.physical - local_ms;
+        Ok(ClockSkewReport {
+            pd_physical_ms: timestamp.physical,
+            local_ms,
+            skew_ms,
+            within_bound: skew_ms.unsigned_abs() as u128 <= max_skew.as_millis(),
+        })
+    }
+
+    /// Finishes the commit of the transaction started at `start_ts` with the
+    /// primary key `primary_key`, by committing the primary at `commit_ts`,
+    /// or confirms it was already committed; meant to be replayed by an
+    /// external coordinator after a crash, any number of times.
+    ///
+    /// This needs the transaction status check and the commit request for a
+    /// given key and timestamps, which the pinned tikv-client only sends
+    /// from within its own transactions, where its commit also picks the
+    /// commit timestamp itself; so after checking its arguments this always
+    /// fails.
+    pub fn finalize_commit(
+        &self,
+        primary_key: &[u8],
+        start_ts: u64,
+        commit_ts: u64,
+    ) -> Result<FinalizeResult> {
+        self.namespace.encode_key(primary_key)?;
+        self.check_version("start_ts", start_ts)?;
+        self.check_version("commit_ts", commit_ts)?;
+        if commit_ts <= start_ts {
+            bail!(
+                "commit_ts {} must be greater than start_ts {}",
+                commit_ts,
+                start_ts
+            );
+        }
+        Err(unsupported(
+            Capability::CheckTxnStatus,
+            format!(
+                "finalizing the commit of {} at {}",
+                format_key(primary_key),
+                commit_ts
+            ),
+        ))
+    }
+
+    /// Runs GC with the given safepoint version.
+    pub fn gc(&self, safepoint: u64) -> Result<bool> {
+        self.check_version("GC safepoint", safepoint)?;
+        let safepoint = Timestamp::from_version(safepoint);
+        Ok(block_on(self.inner.gc(safepoint))?)
+    }
+}
+
+fn local_ms() -> Result<i64> {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    Ok(elapsed.as_millis() as i64)
+}
+
+/// Deletes and commits up to `limit` keys from the start of the range.
+/// Returns the last key deleted and how many were, or `None` once the range
+/// is empty.
+fn delete_chunk(
+    txn: &mut Transaction,
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+    limit: u32,
+) -> Result<Option<(Vec<u8>, u64)>> {
+    let keys: Vec<Vec<u8>> = txn
+        .scan_keys(start, end, limit)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    let last_key = match keys.last() {
+        Some(key) => key.clone(),
+        None => {
+            txn.rollback()?;
+            return Ok(None);
+        }
+    };
+    for key in &keys {
+        txn.delete(key)?;
+    }
+    txn.commit()?;
+    Ok(Some((last_key, keys.len() as u64)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::super::tests::cluster_client;
+    use super::*;
+
+    /// What tikv-client logs when a transaction is dropped while active.
+    const DROP_WARNING: &str = "Dropping an active transaction";
+
+    /// Drops an active transaction begun with `drop_check` by a client
+    /// logging to a directory of its own, and returns whether the drop
+    /// panicked and what the client logged.
+    fn drop_active_transaction(drop_check: DropCheck) -> (bool, String) {
+        let endpoints = env::var("TIKV_PD_ENDPOINTS").expect("TIKV_PD_ENDPOINTS is not set");
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let log_dir = env::temp_dir().join(format!("drop_check_{:?}_{}", drop_check, nanos));
+        fs::create_dir_all(&log_dir).unwrap();
+        let client = TransactionClient::new(
+            endpoints.split(',').map(str::to_owned).collect(),
+            &log_dir.to_string_lossy(),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+        .with_prefix(format!("test/drop_check/{}/", nanos).as_bytes());
+        client.set_default_txn_options(TxnOptions {
+            drop_check,
+            ..TxnOptions::default()
+        });
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.put(b"k", b"v").unwrap();
+        let panicked = panic::catch_unwind(AssertUnwindSafe(move || drop(txn))).is_err();
+        // The last handle on the logger flushes it.
+        drop(client);
+        let mut log = String::new();
+        for entry in fs::read_dir(&log_dir).unwrap() {
+            log.push_str(&fs::read_to_string(entry.unwrap().path()).unwrap());
+        }
+        fs::remove_dir_all(&log_dir).unwrap();
+        (panicked, log)
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn dropping_an_active_transaction_does_as_its_drop_check_says() {
+        let (panicked, log) = drop_active_transaction(DropCheck::Warn);
+        assert!(!panicked);
+        assert!(log.contains(DROP_WARNING), "{}", log);
+        assert!(log.contains("WARN"), "{}", log);
+
+        let (panicked, log) = drop_active_transaction(DropCheck::Ignore);
+        assert!(!panicked);
+        assert!(!log.contains(DROP_WARNING), "{}", log);
+
+        let (panicked, log) = drop_active_transaction(DropCheck::Panic);
+        assert!(panicked);
+        assert!(!log.contains(DROP_WARNING), "{}", log);
+    }
+
+    /// How long `contend` took `txn` to give up on a lock another
+    /// transaction holds, checking that it did give up; `txn` is then
+    /// rolled back.
+    fn lock_wait(mut txn: Transaction, contend: fn(&mut Transaction) -> Result<()>) -> Duration {
+        let start = Instant::now();
+        assert!(contend(&mut txn).is_err());
+        let waited = start.elapsed();
+        txn.rollback().unwrap();
+        waited
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn pessimistic_lock_backoff_gives_up_after_its_attempts() {
+        let client = cluster_client("pessimistic_backoff");
+        let mut holder = client.begin_pessimistic().unwrap();
+        holder.get_for_update(b"k").unwrap();
+
+        let lock = |txn: &mut Transaction| txn.get_for_update(b"k").map(drop);
+        let backoff = |max_attempts| BackoffOptions {
+            base_delay_ms: 100,
+            max_delay_ms: 100,
+            max_attempts,
+        };
+        let begin = |max_attempts| client.begin_pessimistic_with_backoff(backoff(max_attempts));
+        let at_once = lock_wait(begin(0).unwrap(), lock);
+        let after_three = lock_wait(begin(3).unwrap(), lock);
+        assert!(at_once < Duration::from_millis(100), "{:?}", at_once);
+        assert!(
+            after_three >= Duration::from_millis(300),
+            "{:?}",
+            after_three
+        );
+        assert!(after_three < Duration::from_secs(2), "{:?}", after_three);
+        holder.rollback().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn optimistic_lock_backoff_gives_up_after_its_attempts() {
+        let client = cluster_client("optimistic_backoff");
+        let mut holder = client.begin_pessimistic().unwrap();
+        holder.get_for_update(b"k").unwrap();
+
+        // The prewrite of the commit meets the lock of the holder.
+        let commit = |txn: &mut Transaction| {
+            txn.put(b"k", b"v")?;
+            txn.commit().map(drop)
+        };
+        let at_once = lock_wait(client.begin_optimistic_with_retry(0).unwrap(), commit);
+        // Backing off 2 ms doubling, 254 ms in all.
+        let after_seven = lock_wait(client.begin_optimistic_with_retry(7).unwrap(), commit);
+        assert!(at_once < Duration::from_millis(100), "{:?}", at_once);
+        assert!(
+            after_seven >= Duration::from_millis(250),
+            "{:?}",
+            after_seven
+        );
+        assert!(after_seven < Duration::from_secs(2), "{:?}", after_seven);
+        holder.rollback().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn strict_mode_rejects_empty_commits_and_counts_active_transactions() {
+        let mut client = cluster_client("strict_mode");
+        client
+            .set_strict_mode(Some(StrictOptions {
+                empty_commits: EmptyCommits::Reject,
+                ..StrictOptions::default()
+            }))
+            .unwrap();
+        let mut empty = client.begin_optimistic().unwrap();
+        let e = empty.commit().unwrap_err();
+        assert!(
+            matches!(e.downcast_ref::<Error>(), Some(Error::EmptyCommit)),
+            "{:?}",
+            e
+        );
+        // Still active, the transaction can buffer something and commit.
+        assert_eq!(client.active_transaction_count(), 1);
+        empty.put(b"k", b"v").unwrap();
+        empty.commit().unwrap();
+        assert!(empty.commit().is_err());
+        // Read-only transactions are not checked.
+        let mut read_only = client.begin_read_only().unwrap();
+        assert_eq!(read_only.commit().unwrap(), 0);
+
+        let derived = client.with_prefix(b"derived/");
+        let first = client.begin_optimistic().unwrap();
+        let mut second = derived.begin_pessimistic().unwrap();
+        assert_eq!(client.active_transaction_count(), 2);
+        assert_eq!(derived.active_transaction_count(), 2);
+        second.rollback().unwrap();
+        drop(first);
+        assert_eq!(client.active_transaction_count(), 0);
+
+        client.set_strict_mode(None).unwrap();
+        let mut empty = client.begin_optimistic().unwrap();
+        assert_eq!(empty.commit().unwrap(), 0);
+    }
+
+    /// Commits `keys` with values of their own in one transaction.
+    fn write_keys(client: &TransactionClient, keys: &[&[u8]]) {
+        let mut txn = client.begin_optimistic().unwrap();
+        for key in keys {
+            txn.put(key, key).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn range_deletes_go_chunk_by_chunk_and_resume_from_any_point() {
+        let client = cluster_client("delete_range_chunked");
+        let in_range: [&[u8]; 7] = [b"k0", b"k1", b"k2", b"k3", b"k4", b"k5", b"k6"];
+        write_keys(&client, &in_range);
+        write_keys(&client, &[b"j", b"l"]);
+
+        let range = (Bound::Included(&b"k"[..]), Bound::Excluded(&b"l"[..]));
+        assert_eq!(
+            client
+                .delete_range_chunked(range.0, range.1, 3, false)
+                .unwrap(),
+            7
+        );
+        let mut snapshot = client.snapshot().unwrap();
+        assert!(snapshot.scan_keys(range.0, range.1, 10).unwrap().is_empty());
+        assert!(snapshot.get(b"j").unwrap().is_some());
+        assert!(snapshot.get(b"l").unwrap().is_some());
+
+        // Running again over what is left deletes only the keys written
+        // since, whichever way the chunks lock.
+        assert_eq!(
+            client
+                .delete_range_chunked(range.0, range.1, 3, true)
+                .unwrap(),
+            0
+        );
+        write_keys(&client, &in_range[4..]);
+        let outcome = client.delete_range_partial(Bound::Excluded(&b"k4"[..]), range.1, 1, true);
+        assert_eq!(outcome.succeeded_items, 2);
+        assert_eq!(outcome.first_error, None);
+        assert!(outcome.resume_token.is_empty());
+        assert_eq!(
+            client
+                .delete_range_chunked(range.0, range.1, 1, false)
+                .unwrap(),
+            1
+        );
+
+        let outcome = client.delete_range_partial(Bound::Excluded(&b"k1"[..]), range.1, 0, false);
+        assert_eq!(outcome.succeeded_items, 0);
+        assert!(outcome.first_error.is_some());
+        assert_eq!(outcome.resume_token, b"k1\0");
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn a_conflicting_chunk_is_retried_on_its_own() {
+        let client = cluster_client("delete_chunk_conflict");
+        write_keys(&client, &[b"k0", b"k1"]);
+
+        let mut attempts = 0;
+        let chunk = client
+            .retry_chunk(false, |txn| {
+                attempts += 1;
+                if attempts == 1 {
+                    // Committed after the chunk began, so its commit conflicts.
+                    write_keys(&client, &[b"k1"]);
+                }
+                delete_chunk(
+                    txn,
+                    Bound::Included(&b"k"[..]),
+                    Bound::Excluded(&b"l"[..]),
+                    10,
+                )
+            })
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(chunk, Some((b"k1".to_vec(), 2)));
+        let mut snapshot = client.snapshot().unwrap();
+        assert!(snapshot.get(b"k1").unwrap().is_none());
+
+        // An empty range ends the loop without a commit.
+        let chunk = client
+            .retry_chunk(false, |txn| {
+                delete_chunk(
+                    txn,
+                    Bound::Included(&b"k"[..]),
+                    Bound::Excluded(&b"l"[..]),
+                    10,
+                )
+            })
+            .unwrap();
+        assert_eq!(chunk, None);
+    }
+
+    #[test]
+    fn transactions_warn_on_drop_by_default() {
+        assert_eq!(TxnOptions::default().drop_check, DropCheck::Warn);
+        assert!(matches!(DropCheck::Warn.check_level(), CheckLevel::Warn));
+        assert!(matches!(DropCheck::Panic.check_level(), CheckLevel::Panic));
+        assert!(matches!(DropCheck::Ignore.check_level(), CheckLevel::None));
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! The contended keys a transaction met, gathered from the errors of its
+//! reads, locks and commit, see [`Transaction::conflict_report`].
+//!
+//! The proto types carrying the details are not re-exported by tikv-client,
+//! so like [`is_retryable`](super::is_retryable) this goes by the debug
+//! rendering of the errors: every `WriteConflict`, `LockInfo` and `Deadlock`
+//! it holds, however deeply nested, gives one entry. Errors that only name
+//! a conflict without its fields give none.
+//!
+//! [`Transaction::conflict_report`]: super::Transaction::conflict_report
+
+use anyhow::Error;
+
+use super::namespace::Namespace;
+
+/// The entries a transaction keeps at most, the first ones met.
+const MAX_ENTRIES: usize = 1024;
+
+/// What stood in the way of a transaction on a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ConflictKind {
+    /// Another transaction committed the key after this one started.
+    WriteConflict = 0,
+    /// Another transaction held a lock on the key.
+    Locked = 1,
+    /// Waiting for the lock on the key closed a cycle of waiting
+    /// transactions.
+    Deadlock = 2,
+}
+
+/// A contended key, with the start version of the transaction it was
+/// contended with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConflictEntry {
+    /// The key without the prefix of the client; a key outside it, e.g.
+    /// the primary lock of another client, is given as stored.
+    pub key: Vec<u8>,
+    pub kind: ConflictKind,
+    /// The start version of the other transaction, 0 when not reported.
+    pub other_start_ts: u64,
+}
+
+/// The contended keys of a transaction, each kind of conflict on a key
+/// listed once, with the latest version met.
+#[derive(Default)]
+pub(crate) struct ConflictLog {
+    entries: Vec<ConflictEntry>,
+}
+
+impl ConflictLog {
+    /// Adds the conflicts `e` tells of, its keys decoded with `namespace`.
+    pub fn record(&mut self, e: &Error, namespace: &Namespace) {
+        let message = format!("{:?}", e);
+        for (kind, marker, key_field, ts_field) in &[
+            (
+                ConflictKind::WriteConflict,
+                "WriteConflict {",
+                "key",
+                "conflict_ts",
+            ),
+            (ConflictKind::Locked, "LockInfo {", "key", "lock_version"),
+            (ConflictKind::Deadlock, "Deadlock {", "lock_key", "lock_ts"),
+        ] {
+            for body in struct_bodies(&message, marker) {
+                let key = match field(body, key_field).and_then(parse_bytes) {
+                    Some(key) => key,
+                    None => continue,
+                };
+                let other_start_ts = field(body, ts_field)
+                    .and_then(|ts| ts.parse().ok())
+                    .unwrap_or(0);
+                let prefix = namespace.prefix();
+                let key = if key.starts_with(prefix) {
+                    key[prefix.len()..].to_owned()
+                } else {
+                    key
+                };
+                self.add(ConflictEntry {
+                    key,
+                    kind: *kind,
+                    other_start_ts,
+                });
+            }
+        }
+    }
+
+    pub fn entries(&self) -> Vec<ConflictEntry> {
+        self.entries.clone()
+    }
+
+    fn add(&mut self, entry: ConflictEntry) {
+        let known = self
+            .entries
+            .iter()
+            .position(|known| known.key == entry.key && known.kind == entry.kind);
+        match known {
+            Some(at) => {
+                let known = &mut self.entries[at];
+                known.other_start_ts = known.other_start_ts.max(entry.other_start_ts);
+            }
+            None if self.entries.len() < MAX_ENTRIES => self.entries.push(entry),
+            None => {}
+        }
+    }
+}
+
+/// The contents of every `marker ... }` of `message`, `marker` ending with
+/// the opening brace.
+fn struct_bodies<'a>(message: &'a str, marker: &str) -> Vec<&'a str> {
+    let mut bodies = Vec::new();
+    let mut rest = message;
+    while let Some(at) = rest.find(marker) {
+        let body = &rest[at + marker.len()..];
+        let mut depth = 0;
+        let end = body
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '{' | '[' | '(' => depth += 1,
+                    '}' | ']' | ')' if depth == 0 => return true,
+                    '}' | ']' | ')' => depth -= 1,
+                    _ => {}
+                }
+                false
+            })
+            .map_or(body.len(), |(end, _)| end);
+        bodies.push(&body[..end]);
+        rest = &body[end..];
+    }
+    bodies
+}
+
+/// The value of the field `name` of a struct `body`, not of the structs
+/// nested in it.
+fn field<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let mut depth = 0;
+    let mut start = 0;
+    for (at, c) in body.char_indices() {
+        match c {
+            '{' | '[' | '(' => depth += 1,
+            '}' | ']' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                if let Some(value) = field_value(&body[start..at], name) {
+                    return Some(value);
+                }
+                start = at + 1;
+            }
+            _ => {}
+        }
+    }
+    field_value(&body[start..], name)
+}
+
+fn field_value<'a>(field: &'a str, name: &str) -> Option<&'a str> {
+    let field = field.trim();
+    if field.starts_with(name) && field[name.len()..].starts_with(": ") {
+        Some(field[name.len() + 2..].trim())
+    } else {
+        None
+    }
+}
+
+/// Parses bytes rendered as a list of integers, `[1, 2, 3]`.
+fn parse_bytes(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim();
+    if !value.starts_with('[') || !value.ends_with(']') {
+        return None;
+    }
+    let inner = value[1..value.len() - 1].trim();
+    if inner.is_empty() {
+        return Some(Vec::new());
+    }
+    inner
+        .split(',')
+        .map(|byte| byte.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    /// The conflicts a log of the namespace `p/` records off `rendered`.
+    fn recorded(rendered: &[&str]) -> Vec<ConflictEntry> {
+        let namespace = Namespace::default().join(b"p/");
+        let mut log = ConflictLog::default();
+        for rendered in rendered {
+            log.record(&anyhow!("{}", rendered), &namespace);
+        }
+        log.entries()
+    }
+
+    fn entry(key: &[u8], kind: ConflictKind, other_start_ts: u64) -> ConflictEntry {
+        ConflictEntry {
+            key: key.to_owned(),
+            kind,
+            other_start_ts,
+        }
+    }
+
+    #[test]
+    fn every_kind_of_conflict_is_found_however_nested() {
+        let entries = recorded(&[
+            "MultipleKeyErrors([KeyError(KeyError { locked: None, retryable: \"\", \
+             conflict: Some(WriteConflict { start_ts: 5, conflict_ts: 7, key: [112, 47, 97], \
+             primary: [112, 47, 122], conflict_commit_ts: 8 }), deadlock: None }), \
+             KeyError(KeyError { locked: Some(LockInfo { primary_lock: [112, 47, 122], \
+             lock_version: 9, key: [112, 47, 98], lock_ttl: 3000, txn_size: 1 }) })])",
+            "KeyError { deadlock: Some(Deadlock { lock_ts: 11, lock_key: [112, 47, 99], \
+             deadlock_key_hash: 3, wait_chain: [WaitForEntry { txn: 1, key: [112, 47, 100] }] }) }",
+        ]);
+        assert_eq!(
+            entries,
+            [
+                entry(b"a", ConflictKind::WriteConflict, 7),
+                entry(b"b", ConflictKind::Locked, 9),
+                entry(b"c", ConflictKind::Deadlock, 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_outside_the_prefix_stay_as_stored() {
+        let entries = recorded(&["LockInfo { lock_version: 3, key: [113, 47, 97] }"]);
+        assert_eq!(entries, [entry(b"q/a", ConflictKind::Locked, 3)]);
+    }
+
+    #[test]
+    fn each_conflict_on_a_key_is_listed_once_with_its_latest_version() {
+        let entries = recorded(&[
+            "LockInfo { lock_version: 3, key: [112, 47, 97] }",
+            "LockInfo { lock_version: 5, key: [112, 47, 97] }",
+            "LockInfo { lock_version: 4, key: [112, 47, 97] }",
+            "WriteConflict { conflict_ts: 2, key: [112, 47, 97] }",
+        ]);
+        assert_eq!(
+            entries,
+            [
+                entry(b"a", ConflictKind::Locked, 5),
+                entry(b"a", ConflictKind::WriteConflict, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicts_without_a_key_give_no_entry() {
+        let entries = recorded(&[
+            "WriteConflict",
+            "WriteConflict { conflict_ts: 2 }",
+            "LockInfo { lock_version: 3, key: <redacted> }",
+            "Deadlock { lock_ts: 1, wait_chain: [WaitForEntry { key: [97] }] }",
+            "the store is down",
+        ]);
+        assert!(entries.is_empty(), "{:?}", entries);
+
+        let entries = recorded(&["LockInfo { key: [112, 47, 97], lock_version: none }"]);
+        assert_eq!(entries, [entry(b"a", ConflictKind::Locked, 0)]);
+    }
+
+    #[test]
+    fn the_first_entries_are_kept() {
+        let rendered = (0..=MAX_ENTRIES)
+            .map(|i| {
+                format!(
+                    "LockInfo {{ lock_version: 1, key: [{}, {}] }}",
+                    i / 256,
+                    i % 256
+                )
+            })
+            .collect::<Vec<_>>();
+        let entries = recorded(&rendered.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].key, [0, 0]);
+        assert_eq!(entries[MAX_ENTRIES - 1].key, [3, 255]);
+    }
+
+    #[test]
+    fn bytes_parse_from_lists_of_integers() {
+        assert_eq!(parse_bytes("[]"), Some(Vec::new()));
+        assert_eq!(parse_bytes(" [1, 2,255] "), Some(vec![1, 2, 255]));
+        assert_eq!(parse_bytes("[256]"), None);
+        assert_eq!(parse_bytes("[1, 2"), None);
+        assert_eq!(parse_bytes("\"ab\""), None);
+    }
+
+    #[test]
+    fn fields_are_read_off_the_outer_struct_only() {
+        let body = " inner: Inner { key: [1] }, list: [(key, 2)], key: [3] ";
+        assert_eq!(field(body, "key"), Some("[3]"));
+        assert_eq!(field(body, "inner"), Some("Inner { key: [1] }"));
+        assert_eq!(field(body, "ke"), None);
+        assert_eq!(
+            struct_bodies("A { x: 1 } B A { y: { 2 } }", "A {"),
+            [" x: 1 ", " y: { 2 } "]
+        );
+        assert_eq!(struct_bodies("A { x: 1", "A {"), [" x: 1"]);
+    }
+}
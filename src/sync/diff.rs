@@ -0,0 +1,275 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! What changed in a range between two versions, worked out client-side by
+//! merging scans of a snapshot at each version.
+//!
+//! Only the two end states are compared: a key changed and changed back in
+//! between is reported as untouched, and a key rewritten with the same
+//! value is not reported.
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::iter::Peekable;
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+use tikv_client::{Key, KvPair};
+
+use super::{as_ref_bound, to_owned_bound, Snapshot, TransactionClient, SCAN_PAGE_SIZE};
+
+/// How a key differs between the two versions of a
+/// [`TransactionClient::range_diff`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffEntry {
+    pub key: Key,
+    pub change: Change,
+    /// The length of the value at the newer version, 0 when removed.
+    pub new_value_len: u64,
+}
+
+impl TransactionClient {
+    /// Lists the keys of the range whose value differs between versions
+    /// `old` and `new`, in key order, up to `limit` of them, or all of them
+    /// when `limit` is 0; resume after the last key returned.
+    ///
+    /// Both versions are scanned a page at a time and merged as they go, so
+    /// neither side is held in full, but every pair of both sides is read:
+    /// the cost is that of two scans of the range. Versions below the GC
+    /// safepoint give whatever GC left behind.
+    pub fn range_diff(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        old: u64,
+        new: u64,
+        limit: u32,
+    ) -> Result<Vec<DiffEntry>> {
+        if old > new {
+            bail!("the old version {} is after the new one {}", old, new);
+        }
+        let old = PagedScan::new(self.snapshot_at(old)?, start, end);
+        let new = PagedScan::new(self.snapshot_at(new)?, start, end);
+        merge_diff(old, new, limit)
+    }
+}
+
+/// The diff of `old` and `new`, the pairs of one range at two versions in
+/// key order, up to `limit` entries or all of them when `limit` is 0. Each
+/// side is read only as far as the entries returned need.
+fn merge_diff(
+    old: impl Iterator<Item = Result<KvPair>>,
+    new: impl Iterator<Item = Result<KvPair>>,
+    limit: u32,
+) -> Result<Vec<DiffEntry>> {
+    let mut old = old.peekable();
+    let mut new = new.peekable();
+    let mut diff = Vec::new();
+    while limit == 0 || diff.len() < limit as usize {
+        let order = match (peek_key(&mut old)?, peek_key(&mut new)?) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(old_key), Some(new_key)) => old_key.cmp(new_key),
+        };
+        let entry = match order {
+            Ordering::Less => removed(pop(&mut old)),
+            Ordering::Greater => added(pop(&mut new)),
+            Ordering::Equal => {
+                let KvPair(_, old_value) = pop(&mut old);
+                let KvPair(key, new_value) = pop(&mut new);
+                if old_value == new_value {
+                    continue;
+                }
+                DiffEntry {
+                    key,
+                    change: Change::Modified,
+                    new_value_len: new_value.len() as u64,
+                }
+            }
+        };
+        diff.push(entry);
+    }
+    Ok(diff)
+}
+
+/// The key of the next pair of `pairs`, or the error reading it.
+fn peek_key<I: Iterator<Item = Result<KvPair>>>(pairs: &mut Peekable<I>) -> Result<Option<&Key>> {
+    if let Some(Err(_)) = pairs.peek() {
+        return Err(pairs.next().expect("a pair was peeked").unwrap_err());
+    }
+    Ok(pairs
+        .peek()
+        .map(|pair| &pair.as_ref().expect("not an error").0))
+}
+
+/// Takes the pair [`peek_key`] returned the key of.
+fn pop(pairs: &mut impl Iterator<Item = Result<KvPair>>) -> KvPair {
+    pairs
+        .next()
+        .and_then(Result::ok)
+        .expect("pop is only called after peek_key found a pair")
+}
+
+fn added(KvPair(key, value): KvPair) -> DiffEntry {
+    DiffEntry {
+        key,
+        change: Change::Added,
+        new_value_len: value.len() as u64,
+    }
+}
+
+fn removed(KvPair(key, _): KvPair) -> DiffEntry {
+    DiffEntry {
+        key,
+        change: Change::Removed,
+        new_value_len: 0,
+    }
+}
+
+/// A scan of a range read one page ahead of what is consumed.
+struct PagedScan {
+    snapshot: Snapshot,
+    cursor: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    page: VecDeque<KvPair>,
+    done: bool,
+}
+
+impl PagedScan {
+    fn new(snapshot: Snapshot, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Self {
+        PagedScan {
+            snapshot,
+            cursor: to_owned_bound(start),
+            end: to_owned_bound(end),
+            page: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    fn read_page(&mut self) -> Result<()> {
+        let page = self.snapshot.scan(
+            as_ref_bound(&self.cursor),
+            as_ref_bound(&self.end),
+            SCAN_PAGE_SIZE,
+        )?;
+        self.done = page.len() < SCAN_PAGE_SIZE as usize;
+        if let Some(KvPair(key, _)) = page.last() {
+            self.cursor = Bound::Excluded(key.clone().into());
+        }
+        self.page.extend(page);
+        Ok(())
+    }
+}
+
+/// The pairs of the range, reading the next page when needed; a failed read
+/// ends the scan after its error.
+impl Iterator for PagedScan {
+    type Item = Result<KvPair>;
+
+    fn next(&mut self) -> Option<Result<KvPair>> {
+        if self.page.is_empty() && !self.done {
+            if let Err(error) = self.read_page() {
+                self.done = true;
+                return Some(Err(error));
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<Result<KvPair>> {
+        pairs
+            .iter()
+            .map(|(key, value)| {
+                Ok(KvPair(
+                    key.as_bytes().to_vec().into(),
+                    value.as_bytes().to_vec(),
+                ))
+            })
+            .collect()
+    }
+
+    fn entry(key: &str, change: Change, new_value_len: u64) -> DiffEntry {
+        DiffEntry {
+            key: key.as_bytes().to_vec().into(),
+            change,
+            new_value_len,
+        }
+    }
+
+    #[test]
+    fn merge_reports_added_removed_and_modified_keys() {
+        let old = pairs(&[("a", "1"), ("b", "2"), ("c", "3"), ("e", "5")]);
+        let new = pairs(&[("b", "2"), ("c", "33"), ("d", "4444"), ("e", "5")]);
+        let diff = merge_diff(old.into_iter(), new.into_iter(), 0).unwrap();
+        assert_eq!(
+            diff,
+            [
+                entry("a", Change::Removed, 0),
+                entry("c", Change::Modified, 2),
+                entry("d", Change::Added, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_of_untouched_or_empty_sides() {
+        let same = || pairs(&[("a", "1"), ("b", "")]).into_iter();
+        assert!(merge_diff(same(), same(), 0).unwrap().is_empty());
+        let empty = || pairs(&[]).into_iter();
+        assert!(merge_diff(empty(), empty(), 0).unwrap().is_empty());
+
+        let diff = merge_diff(
+            pairs(&[]).into_iter(),
+            pairs(&[("a", "1"), ("b", "")]).into_iter(),
+            0,
+        );
+        assert_eq!(
+            diff.unwrap(),
+            [entry("a", Change::Added, 1), entry("b", Change::Added, 0)]
+        );
+        let diff = merge_diff(pairs(&[("a", "1")]).into_iter(), pairs(&[]).into_iter(), 0);
+        assert_eq!(diff.unwrap(), [entry("a", Change::Removed, 0)]);
+    }
+
+    #[test]
+    fn merge_stops_reading_at_the_limit() {
+        let read = Cell::new(0);
+        let old = pairs(&[("a", "1"), ("b", "1"), ("c", "1"), ("d", "1")]);
+        let old = old.into_iter().inspect(|_| read.set(read.get() + 1));
+        let new = pairs(&[("a", "1"), ("b", "2"), ("c", "2"), ("d", "2")]);
+        let diff = merge_diff(old, new.into_iter(), 1).unwrap();
+        assert_eq!(diff, [entry("b", Change::Modified, 1)]);
+        assert_eq!(read.get(), 2);
+    }
+
+    #[test]
+    fn merge_fails_on_an_error_of_either_side() {
+        let mut failing = pairs(&[("a", "1")]);
+        failing.push(Err(anyhow!("the scan failed")));
+        failing.extend(pairs(&[("c", "1")]));
+        let other = || pairs(&[("a", "1"), ("b", "1"), ("c", "1")]).into_iter();
+
+        let error = merge_diff(failing.into_iter(), other(), 0).unwrap_err();
+        assert_eq!(error.to_string(), "the scan failed");
+        let mut failing = pairs(&[]);
+        failing.push(Err(anyhow!("the scan failed")));
+        let error = merge_diff(other(), failing.into_iter(), 0).unwrap_err();
+        assert_eq!(error.to_string(), "the scan failed");
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::fmt;
+use std::time::Duration;
+
+use super::{format_key, Capability, FailoverTarget, TransactionState, RESERVED_PREFIX};
+
+/// Errors raised by the wrapper itself rather than by tikv-client. They are
+/// returned inside [`anyhow::Error`] and can be told apart with
+/// `downcast_ref::<Error>()`; their messages start with a stable phrase for
+/// callers that only see the text, such as the C++ side.
+#[derive(Debug)]
+pub enum Error {
+    /// The current value of `key` did not match the one a conditional write
+    /// expected.
+    PreconditionFailed { key: Vec<u8> },
+    /// A mutation would have grown the buffer of a transaction to `size`
+    /// bytes, over its memory limit.
+    TransactionTooLarge { size: u64, limit: u64 },
+    /// An operation was attempted on a transaction that is no longer active.
+    TransactionFinished { state: TransactionState },
+    /// A call was made on a transaction or snapshot, named by `what`, while
+    /// another thread was using it.
+    Busy { what: &'static str },
+    /// A point operation was given the empty key, which the client does not
+    /// allow.
+    EmptyKey,
+    /// A user operation was given a key under
+    /// [`RESERVED_PREFIX`](super::RESERVED_PREFIX), which the client guards.
+    ReservedKey { key: Vec<u8> },
+    /// A fail-fast read met a lock on `key`, e.g. of a write in progress,
+    /// instead of waiting for it.
+    KeyLocked { key: Vec<u8> },
+    /// A mutation was attempted on a transaction begun read-only.
+    ReadOnly,
+    /// Chunk number `chunk` of a chunked commit, starting at `first_key`,
+    /// failed; the chunks before it committed, the last one at
+    /// `last_commit_ts`.
+    ChunkFailed {
+        chunk: u32,
+        first_key: Vec<u8>,
+        last_commit_ts: u64,
+    },
+    /// PD gave no timestamp within the `attempts` made by the client's
+    /// [`TimestampRetry`](super::TimestampRetry).
+    TimestampUnavailable { attempts: u32 },
+    /// The `what` ("key" or "value") of a mutation of `key` is `size` bytes,
+    /// over the [`SizeLimits`](super::SizeLimits) of the client.
+    EntryTooLarge {
+        what: &'static str,
+        key: Vec<u8>,
+        size: u64,
+        limit: u64,
+    },
+    /// A call was made after [`shutdown_runtime`](super::shutdown_runtime).
+    RuntimeShutDown,
+    /// The transaction committed at `commit_ts`, but its records could not
+    /// be written to the audit log of the client.
+    AuditFailed { commit_ts: u64 },
+    /// No route of a [`RoutedClient`](super::RoutedClient) owns `key`.
+    NoRoute { key: Vec<u8> },
+    /// An operation of a [`RoutedTransaction`](super::RoutedTransaction)
+    /// needs the client of `prefix`, while the transaction is bound to the
+    /// one of `bound_prefix`, or, for a scan spanning routes, would start
+    /// on it.
+    CrossRoute {
+        bound_prefix: Vec<u8>,
+        prefix: Vec<u8>,
+    },
+    /// The chunks of the chunked value of `key` are missing or do not add
+    /// up to the value its manifest describes.
+    ChunkedValueCorrupt { key: Vec<u8> },
+    /// A transaction that buffered nothing was committed under the
+    /// [`EmptyCommits::Reject`](super::EmptyCommits::Reject) of strict mode.
+    EmptyCommit,
+    /// The deadline set with
+    /// [`Transaction::set_deadline`](super::Transaction::set_deadline) passed
+    /// before or during an operation.
+    DeadlineExceeded,
+    /// A function needs `capability`, which the linked tikv-client lacks;
+    /// `what` says what it was asked to do.
+    Unsupported {
+        capability: Capability,
+        what: String,
+    },
+    /// A call other than the two-phase commit functions was made on a
+    /// transaction whose commit they drive, see
+    /// [`TransactionState::ExternalTwoPhase`].
+    ExternalTwoPhase,
+    /// A two-phase commit function was called on a transaction that
+    /// [`Transaction::commit`](super::Transaction::commit) or
+    /// [`Transaction::commit_chunked`](super::Transaction::commit_chunked)
+    /// started committing.
+    CommitStarted,
+    /// A snapshot was created while `live` of its client were, as many as
+    /// the `limit` set with
+    /// [`TransactionClient::set_max_live_snapshots`](super::TransactionClient::set_max_live_snapshots);
+    /// `oldest_ages` are the ages of the oldest ones.
+    TooManySnapshots {
+        live: u64,
+        limit: u32,
+        oldest_ages: Vec<Duration>,
+    },
+    /// The [`FailoverClient`](super::FailoverClient) a transaction was begun
+    /// through switched away from its `target` since; it can only be rolled
+    /// back.
+    FailedOver { target: FailoverTarget },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::PreconditionFailed { key } => write!(
+                f,
+                "precondition failed: the value of {} is not the expected one",
+                format_key(key)
+            ),
+            Error::TransactionTooLarge { size, limit } => write!(
+                f,
+                "transaction too large: the mutation would buffer {} bytes, the limit is {}",
+                size, limit
+            ),
+            Error::TransactionFinished { state } => {
+                write!(f, "transaction finished (state={:?})", state)
+            }
+            Error::Busy { what } => write!(
+                f,
+                "{} is busy: it is being used by another call, from another thread",
+                what
+            ),
+            Error::EmptyKey => write!(
+                f,
+                "empty key: point operations reject the empty key unless the client allows it"
+            ),
+            Error::ReservedKey { key } => write!(
+                f,
+                "reserved key: {} is under the prefix {} reserved for the wrapper",
+                format_key(key),
+                format_key(RESERVED_PREFIX)
+            ),
+            Error::KeyLocked { key } => write!(
+                f,
+                "key locked: {} is locked by another transaction, the read did not wait",
+                format_key(key)
+            ),
+            Error::ReadOnly => write!(
+                f,
+                "transaction is read-only: it was begun read-only and cannot buffer mutations"
+            ),
+            Error::ChunkFailed {
+                chunk,
+                first_key,
+                last_commit_ts,
+            } => write!(
+                f,
+                "chunk failed: chunk {} of the chunked commit, from key {}, did not commit; \
+                 the {} chunks before it did, the last one at {}",
+                chunk,
+                format_key(first_key),
+                chunk,
+                last_commit_ts
+            ),
+            Error::TimestampUnavailable { attempts } => write!(
+                f,
+                "timestamp unavailable: PD gave no timestamp after {} attempts",
+                attempts
+            ),
+            Error::EntryTooLarge {
+                what,
+                key,
+                size,
+                limit,
+            } => write!(
+                f,
+                "entry too large: the {} of key {} is {} bytes, the limit is {}",
+                what,
+                format_key(key),
+                size,
+                limit
+            ),
+            Error::RuntimeShutDown => write!(
+                f,
+                "runtime shut down: the process is tearing down and sends no more requests"
+            ),
+            Error::AuditFailed { commit_ts } => write!(
+                f,
+                "audit log write failed: the transaction committed at {}, \
+                 but its audit records could not be written",
+                commit_ts
+            ),
+            Error::NoRoute { key } => write!(
+                f,
+                "no route: no client of the routed client owns {}",
+                format_key(key)
+            ),
+            Error::CrossRoute {
+                bound_prefix,
+                prefix,
+            } => write!(
+                f,
+                "cross-route transaction: the operation needs the client of prefix {}, \
+                 the transaction is on the one of prefix {}; transactions cannot span routes",
+                format_key(prefix),
+                format_key(bound_prefix)
+            ),
+            Error::ChunkedValueCorrupt { key } => write!(
+                f,
+                "chunked value corrupt: the chunks of {} do not match its manifest",
+                format_key(key)
+            ),
+            Error::EmptyCommit => write!(
+                f,
+                "empty commit: the transaction buffered nothing, which strict mode rejects"
+            ),
+            Error::DeadlineExceeded => write!(
+                f,
+                "transaction deadline exceeded: the operation was not started or was cut short"
+            ),
+            Error::Unsupported { capability, what } => write!(
+                f,
+                "unsupported: {} is not supported by this tikv-client version, it needs {}",
+                what,
+                capability.feature()
+            ),
+            Error::ExternalTwoPhase => write!(
+                f,
+                "transaction is under external 2PC control: only the two-phase commit functions \
+                 may drive it"
+            ),
+            Error::CommitStarted => write!(
+                f,
+                "transaction is being committed by commit(): the two-phase commit functions \
+                 cannot drive it"
+            ),
+            Error::TooManySnapshots {
+                live,
+                limit,
+                oldest_ages,
+            } => {
+                let ages = oldest_ages
+                    .iter()
+                    .map(|age| format!("{:.1}s", age.as_secs_f64()))
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "too many snapshots: {} are live, the client allows {}; the oldest were \
+                     created {} ago",
+                    live,
+                    limit,
+                    ages.join(", ")
+                )
+            }
+            Error::FailedOver { target } => write!(
+                f,
+                "failed over: the transaction was begun on the {:?} cluster, which the client \
+                 switched away from",
+                target
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,281 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Per-key expiry of transactional data, emulated on the client.
+//!
+//! A value written with an expiry is stored in an envelope: a 4-byte magic,
+//! the expiry as a big-endian `u64`, then the value. Every such write also
+//! puts an entry `INDEX_PREFIX ++ expiry ++ key` into an index under a
+//! reserved prefix, so that expired keys are found by scanning the index in
+//! expiry order rather than the data.
+//!
+//! An expiry of 0 is none: such a value never expires and gets no index
+//! entry.
+//!
+//! Index entries are never updated in place: overwriting a key, with or
+//! without a new expiry, or deleting it leaves its old entry behind, and
+//! purging drops such stale entries after checking the current envelope of
+//! the key. Expiry times are opaque `u64`s compared with the `now` given to
+//! each call, e.g. Unix seconds or TSO versions; one clock must be used
+//! throughout.
+//!
+//! The index lives in the keyspace of the client, under keys starting with
+//! the [`RESERVED_PREFIX`](super::RESERVED_PREFIX) followed by `expiry\x00`.
+
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+use tikv_client::Value;
+
+use super::{as_ref_bound, format_key, Transaction, TransactionClient};
+
+const ENVELOPE_MAGIC: &[u8; 4] = b"\xffTTL";
+const ENVELOPE_HEADER_LEN: usize = 12;
+/// Under [`RESERVED_PREFIX`](super::RESERVED_PREFIX).
+const INDEX_PREFIX: &[u8] = b"\x00__tikv_cpp__expiry\x00";
+/// The first key after every index entry.
+const INDEX_END: &[u8] = b"\x00__tikv_cpp__expiry\x01";
+
+fn to_envelope(expire_at: u64, value: &[u8]) -> Vec<u8> {
+    let mut envelope = Vec::with_capacity(ENVELOPE_HEADER_LEN + value.len());
+    envelope.extend_from_slice(ENVELOPE_MAGIC);
+    envelope.extend_from_slice(&expire_at.to_be_bytes());
+    envelope.extend_from_slice(value);
+    envelope
+}
+
+/// Splits an envelope into its expiry and value.
+fn open_envelope(envelope: &[u8]) -> Option<(u64, &[u8])> {
+    if envelope.len() < ENVELOPE_HEADER_LEN || &envelope[..4] != ENVELOPE_MAGIC {
+        return None;
+    }
+    let mut expire_at = [0; 8];
+    expire_at.copy_from_slice(&envelope[4..ENVELOPE_HEADER_LEN]);
+    Some((
+        u64::from_be_bytes(expire_at),
+        &envelope[ENVELOPE_HEADER_LEN..],
+    ))
+}
+
+/// Whether a value expiring at `expire_at` has expired at `now`.
+fn is_expired(expire_at: u64, now: u64) -> bool {
+    expire_at != 0 && expire_at <= now
+}
+
+fn to_index_key(expire_at: u64, key: &[u8]) -> Vec<u8> {
+    let mut index_key = Vec::with_capacity(INDEX_PREFIX.len() + 8 + key.len());
+    index_key.extend_from_slice(INDEX_PREFIX);
+    index_key.extend_from_slice(&expire_at.to_be_bytes());
+    index_key.extend_from_slice(key);
+    index_key
+}
+
+/// Splits an index key into the expiry and the key it points to.
+fn parse_index_key(index_key: &[u8]) -> Option<(u64, &[u8])> {
+    if index_key.len() < INDEX_PREFIX.len() + 8 || !index_key.starts_with(INDEX_PREFIX) {
+        return None;
+    }
+    let rest = &index_key[INDEX_PREFIX.len()..];
+    let mut expire_at = [0; 8];
+    expire_at.copy_from_slice(&rest[..8]);
+    Some((u64::from_be_bytes(expire_at), &rest[8..]))
+}
+
+impl Transaction {
+    /// Writes `value` under `key`, to expire at `expire_at`: from then on
+    /// [`get_with_expiry`](Self::get_with_expiry) no longer returns it, and
+    /// [`TransactionClient::purge_expired`] deletes it. An `expire_at` of 0
+    /// never expires.
+    pub fn put_with_expiry(&mut self, key: &[u8], value: &[u8], expire_at: u64) -> Result<()> {
+        if key.starts_with(INDEX_PREFIX) {
+            bail!("keys under the expiry index prefix are reserved");
+        }
+        self.put(key, &to_envelope(expire_at, value))?;
+        if expire_at == 0 {
+            return Ok(());
+        }
+        self.with_reserved_keys(|txn| txn.put(&to_index_key(expire_at, key), &[0]))
+    }
+
+    /// Gets the value of a key written with
+    /// [`put_with_expiry`](Self::put_with_expiry), or `None` once it has
+    /// expired, that is when its expiry is at most `now` and not 0.
+    ///
+    /// Fails on a value that was not written with an expiry.
+    pub fn get_with_expiry(&mut self, key: &[u8], now: u64) -> Result<Option<Value>> {
+        let envelope = match self.get(key)? {
+            Some(envelope) => envelope,
+            None => return Ok(None),
+        };
+        match open_envelope(&envelope) {
+            Some((expire_at, _)) if is_expired(expire_at, now) => Ok(None),
+            Some((_, value)) => Ok(Some(value.to_vec())),
+            None => bail!(
+                "the value of {} was not written with an expiry",
+                format_key(key)
+            ),
+        }
+    }
+}
+
+impl TransactionClient {
+    /// Deletes the keys starting with `prefix` that have expired at `now`,
+    /// checking `keys_per_txn` index entries per transaction, and returns how
+    /// many keys were deleted.
+    ///
+    /// The index is ordered by expiry, not by key, so this scans the expired
+    /// entries of every prefix and skips those outside `prefix`. The entries
+    /// it checks are dropped, but a key is only deleted if it still holds the
+    /// expiry of its entry: keys overwritten or deleted since are left alone.
+    /// Like other chunked operations this is not atomic; calling it again
+    /// resumes where it stopped.
+    pub fn purge_expired(&self, prefix: &[u8], now: u64, keys_per_txn: u32) -> Result<u64> {
+        if keys_per_txn == 0 {
+            bail!("keys_per_txn must be positive");
+        }
+        let end = match now.checked_add(1) {
+            Some(next) => to_index_key(next, &[]),
+            None => INDEX_END.to_vec(),
+        };
+        let mut cursor = Bound::Included(INDEX_PREFIX.to_vec());
+        let mut purged = 0;
+        loop {
+            let chunk = self
+                .retry_chunk(false, |txn| {
+                    purge_chunk(txn, as_ref_bound(&cursor), &end, prefix, keys_per_txn)
+                })
+                .map_err(|e| e.context(format!("purged {} keys before failing", purged)))?;
+            match chunk {
+                Some((last_index_key, count)) => {
+                    purged += count;
+                    cursor = Bound::Excluded(last_index_key);
+                }
+                None => return Ok(purged),
+            }
+        }
+    }
+}
+
+/// Checks and commits up to `limit` index entries from the start of the
+/// range. Returns the last index key scanned and how many keys were deleted,
+/// or `None` once the range is empty.
+fn purge_chunk(
+    txn: &mut Transaction,
+    start: Bound<&[u8]>,
+    end: &[u8],
+    prefix: &[u8],
+    limit: u32,
+) -> Result<Option<(Vec<u8>, u64)>> {
+    txn.with_reserved_keys(|txn| purge_index_chunk(txn, start, end, prefix, limit))
+}
+
+fn purge_index_chunk(
+    txn: &mut Transaction,
+    start: Bound<&[u8]>,
+    end: &[u8],
+    prefix: &[u8],
+    limit: u32,
+) -> Result<Option<(Vec<u8>, u64)>> {
+    let index_keys: Vec<Vec<u8>> = txn
+        .scan_keys(start, Bound::Excluded(end), limit)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    let last_index_key = match index_keys.last() {
+        Some(index_key) => index_key.clone(),
+        None => {
+            txn.rollback()?;
+            return Ok(None);
+        }
+    };
+    let mut purged = 0;
+    for index_key in &index_keys {
+        let (expire_at, key) = match parse_index_key(index_key) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if !key.starts_with(prefix) {
+            continue;
+        }
+        txn.delete(index_key)?;
+        let current = txn.get(key)?;
+        if let Some((current_expiry, _)) = current.as_deref().and_then(open_envelope) {
+            if current_expiry == expire_at {
+                txn.delete(key)?;
+                purged += 1;
+            }
+        }
+    }
+    txn.commit()?;
+    Ok(Some((last_index_key, purged)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::cluster_client;
+    use super::*;
+
+    #[test]
+    fn values_expire_at_their_deadline_unless_it_is_0() {
+        assert!(!is_expired(10, 9));
+        assert!(is_expired(10, 10));
+        assert!(is_expired(10, 11));
+        assert!(is_expired(u64::MAX, u64::MAX));
+        assert!(!is_expired(0, 0));
+        assert!(!is_expired(0, u64::MAX));
+    }
+
+    #[test]
+    fn envelopes_and_index_keys_parse_back() {
+        for &expire_at in [0, 1, 10, u64::MAX].iter() {
+            let envelope = to_envelope(expire_at, b"value");
+            assert_eq!(open_envelope(&envelope), Some((expire_at, &b"value"[..])));
+            let index_key = to_index_key(expire_at, b"key");
+            assert_eq!(parse_index_key(&index_key), Some((expire_at, &b"key"[..])));
+            assert!(index_key.as_slice() < INDEX_END);
+        }
+        assert_eq!(open_envelope(&to_envelope(7, b"")), Some((7, &b""[..])));
+        assert_eq!(open_envelope(b"value"), None);
+        assert_eq!(
+            open_envelope(&to_envelope(7, b"")[..ENVELOPE_HEADER_LEN - 1]),
+            None
+        );
+        assert_eq!(parse_index_key(INDEX_PREFIX), None);
+        // Entries sort by expiry first, whatever their keys.
+        assert!(to_index_key(1, b"z") < to_index_key(2, b"a"));
+        assert!(to_index_key(255, b"") < to_index_key(256, b""));
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn keys_expire_and_purge_at_their_deadline() {
+        let client = cluster_client("expiry");
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.put_with_expiry(b"session/1", b"a", 10).unwrap();
+        txn.put_with_expiry(b"session/2", b"b", 0).unwrap();
+        txn.commit().unwrap();
+
+        let mut txn = client.begin_optimistic().unwrap();
+        assert_eq!(
+            txn.get_with_expiry(b"session/1", 9).unwrap(),
+            Some(b"a".to_vec())
+        );
+        assert_eq!(txn.get_with_expiry(b"session/1", 10).unwrap(), None);
+        assert_eq!(txn.get_with_expiry(b"session/1", 11).unwrap(), None);
+        assert_eq!(
+            txn.get_with_expiry(b"session/2", u64::MAX).unwrap(),
+            Some(b"b".to_vec())
+        );
+        txn.rollback().unwrap();
+
+        assert_eq!(client.purge_expired(b"session/", 9, 16).unwrap(), 0);
+        assert_eq!(client.purge_expired(b"session/", 10, 16).unwrap(), 1);
+        assert_eq!(client.purge_expired(b"session/", u64::MAX, 16).unwrap(), 0);
+        let mut txn = client.begin_optimistic().unwrap();
+        assert_eq!(txn.get(b"session/1").unwrap(), None);
+        assert_eq!(
+            txn.get_with_expiry(b"session/2", u64::MAX).unwrap(),
+            Some(b"b".to_vec())
+        );
+        txn.rollback().unwrap();
+    }
+}
@@ -0,0 +1,535 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A pair of clients, of an active cluster and of its warm standby, with
+//! new transactions and snapshots switched over to the standby once the
+//! active one fails too often.
+//!
+//! The transactions and snapshots begun through the pair are those of the
+//! client of the current target, watched: their operations count towards
+//! the health of the primary while it is the target, and once the pair
+//! switched away from the cluster a transaction was begun on, every
+//! operation of it but a rollback fails with [`Error::FailedOver`].
+//! Snapshots keep reading the cluster they were taken on. Keeping the data
+//! of the clusters consistent is up to the caller.
+//!
+//! Only errors of the cluster count as failures, and an operation running
+//! past the deadline of its transaction: the other errors of the wrapper
+//! itself, e.g. a failed precondition, and conflicts and locks, which a
+//! retry may get past, count as neither failures nor successes.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use anyhow::{bail, Result};
+use log::warn;
+
+use super::{is_retryable, Error, Snapshot, Transaction, TransactionClient};
+
+/// A cluster of a [`FailoverClient`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FailoverTarget {
+    Primary = 0,
+    Standby = 1,
+}
+
+/// When a [`FailoverClient`] switches from the primary to the standby.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FailoverPolicy {
+    /// The failed operations in a row that trip the switch; 0 never trips
+    /// on them.
+    pub max_consecutive_failures: u32,
+    /// The share of failed operations, in percent, among the last `window`
+    /// counted, that trips the switch once `window` were counted; 0 never
+    /// trips on it.
+    pub max_error_percent: u32,
+    pub window: u32,
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        FailoverPolicy {
+            max_consecutive_failures: 5,
+            max_error_percent: 50,
+            window: 100,
+        }
+    }
+}
+
+impl FailoverPolicy {
+    fn check(&self) -> Result<()> {
+        if self.max_error_percent > 100 {
+            bail!(
+                "the error rate threshold must be within 0% and 100%, not {}%",
+                self.max_error_percent
+            );
+        }
+        if self.max_error_percent != 0 && self.window == 0 {
+            bail!("an error rate threshold needs a positive window");
+        }
+        Ok(())
+    }
+}
+
+/// Asked, with the failures in a row and the error rate in percent that
+/// tripped it, whether to switch to the standby; the switch is made on
+/// `true`, while `false` or an error, which is logged, keeps the primary
+/// and starts counting afresh. Called on the thread whose operation tripped
+/// the switch, once at a time.
+pub type FailoverConfirmation = Box<dyn Fn(u32, u32) -> Result<bool> + Send + Sync>;
+
+/// Where a [`FailoverClient`] stands, see [`FailoverClient::status`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailoverStatus {
+    pub target: FailoverTarget,
+    /// The switches made, automatic or manual.
+    pub switches: u64,
+    /// The failures in a row of the primary while it is the target.
+    pub consecutive_failures: u32,
+    /// The operations counted in the current window, and the failed ones.
+    pub window_operations: u32,
+    pub window_failures: u32,
+    /// Why the last switch was made, `None` before any.
+    pub last_reason: Option<String>,
+}
+
+struct Health {
+    target: FailoverTarget,
+    switches: u64,
+    consecutive_failures: u32,
+    /// Whether each of the last operations counted failed, oldest first.
+    window: VecDeque<bool>,
+    window_failures: u32,
+    last_reason: Option<String>,
+    /// Whether the confirmation is being asked, so that it is asked once.
+    confirming: bool,
+}
+
+impl Health {
+    fn reset(&mut self) {
+        self.consecutive_failures = 0;
+        self.window.clear();
+        self.window_failures = 0;
+    }
+}
+
+/// The clients are a parameter only for the tests.
+struct Pair<C = TransactionClient> {
+    primary: C,
+    standby: C,
+    policy: FailoverPolicy,
+    confirmation: Option<FailoverConfirmation>,
+    health: Mutex<Health>,
+    /// Bumped by every switch, so that a watch tells a stale target at a
+    /// glance.
+    epoch: AtomicU64,
+}
+
+impl<C> Pair<C> {
+    fn new(
+        primary: C,
+        standby: C,
+        policy: FailoverPolicy,
+        confirmation: Option<FailoverConfirmation>,
+    ) -> Self {
+        Pair {
+            primary,
+            standby,
+            policy,
+            confirmation,
+            health: Mutex::new(Health {
+                target: FailoverTarget::Primary,
+                switches: 0,
+                consecutive_failures: 0,
+                window: VecDeque::new(),
+                window_failures: 0,
+                last_reason: None,
+                confirming: false,
+            }),
+            epoch: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Health> {
+        self.health.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn client(&self, target: FailoverTarget) -> &C {
+        match target {
+            FailoverTarget::Primary => &self.primary,
+            FailoverTarget::Standby => &self.standby,
+        }
+    }
+
+    /// Counts an operation of the primary made at `epoch`, switching to the
+    /// standby when it trips the policy and the switch is confirmed.
+    fn record(&self, epoch: u64, failed: bool) {
+        let mut health = self.lock();
+        if health.target != FailoverTarget::Primary || self.epoch.load(Ordering::Acquire) != epoch {
+            return;
+        }
+        health.window.push_back(failed);
+        health.window_failures += failed as u32;
+        if health.window.len() > self.policy.window as usize {
+            let oldest = health.window.pop_front().unwrap_or(false);
+            health.window_failures -= oldest as u32;
+        }
+        health.consecutive_failures = if failed {
+            health.consecutive_failures + 1
+        } else {
+            0
+        };
+        let error_percent = match health.window.len() {
+            0 => 0,
+            len => health.window_failures * 100 / len as u32,
+        };
+        let policy = &self.policy;
+        let tripped = (policy.max_consecutive_failures != 0
+            && health.consecutive_failures >= policy.max_consecutive_failures)
+            || (policy.max_error_percent != 0
+                && health.window.len() >= policy.window as usize
+                && error_percent >= policy.max_error_percent);
+        if !tripped || health.confirming {
+            return;
+        }
+        let consecutive_failures = health.consecutive_failures;
+        health.confirming = true;
+        drop(health);
+        let confirmed = match &self.confirmation {
+            Some(confirmation) => {
+                confirmation(consecutive_failures, error_percent).unwrap_or_else(|e| {
+                    warn!(
+                        "the failover confirmation failed, keeping the primary: {:#}",
+                        e
+                    );
+                    false
+                })
+            }
+            None => true,
+        };
+        let mut health = self.lock();
+        health.confirming = false;
+        if !confirmed || self.epoch.load(Ordering::Acquire) != epoch {
+            health.reset();
+            return;
+        }
+        let reason = format!(
+            "{} failures in a row, {}% of the last {} operations failed",
+            consecutive_failures,
+            error_percent,
+            health.window.len()
+        );
+        self.switch(&mut health, FailoverTarget::Standby, reason);
+    }
+
+    fn switch(&self, health: &mut Health, target: FailoverTarget, reason: String) {
+        if health.target == target {
+            return;
+        }
+        warn!(
+            "switching from the {:?} to the {:?} cluster: {}",
+            health.target, target, reason
+        );
+        health.target = target;
+        health.switches += 1;
+        health.reset();
+        health.last_reason = Some(reason);
+        self.epoch.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// What a transaction or snapshot begun through a [`FailoverClient`] keeps
+/// of it: the pair, and the target and epoch it was begun at.
+pub(crate) struct FailoverWatch {
+    pair: Arc<Pair>,
+    target: FailoverTarget,
+    epoch: u64,
+}
+
+impl FailoverWatch {
+    /// Fails with [`Error::FailedOver`] once the pair switched since.
+    pub fn check(&self) -> Result<()> {
+        if self.pair.epoch.load(Ordering::Acquire) != self.epoch {
+            return Err(Error::FailedOver {
+                target: self.target,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Counts the outcome of an operation towards the health of the primary.
+    pub fn record<T>(&self, result: &Result<T>) {
+        if self.target != FailoverTarget::Primary {
+            return;
+        }
+        match result {
+            Ok(_) => self.pair.record(self.epoch, false),
+            Err(e) if is_cluster_failure(e) => self.pair.record(self.epoch, true),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Whether `e` tells of a cluster failing or too slow, rather than of the
+/// wrapper, a conflict or a lock.
+fn is_cluster_failure(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<Error>() {
+        Some(Error::DeadlineExceeded) => true,
+        Some(_) => false,
+        None => !is_retryable(e),
+    }
+}
+
+/// Two clients, of a primary cluster and of its standby, see the
+/// [module documentation](self).
+pub struct FailoverClient {
+    pair: Arc<Pair>,
+}
+
+impl FailoverClient {
+    /// Creates a pair targeting the primary, switching to the standby when
+    /// `policy` trips and `confirmation`, if any, agrees.
+    pub fn new(
+        primary: TransactionClient,
+        standby: TransactionClient,
+        policy: FailoverPolicy,
+        confirmation: Option<FailoverConfirmation>,
+    ) -> Result<Self> {
+        policy.check()?;
+        Ok(FailoverClient {
+            pair: Arc::new(Pair::new(primary, standby, policy, confirmation)),
+        })
+    }
+
+    /// A watch of the current target, with its client.
+    fn watch(&self) -> (FailoverWatch, &TransactionClient) {
+        let health = self.pair.lock();
+        let watch = FailoverWatch {
+            pair: self.pair.clone(),
+            target: health.target,
+            epoch: self.pair.epoch.load(Ordering::Acquire),
+        };
+        (watch, self.pair.client(health.target))
+    }
+
+    pub fn begin_optimistic(&self) -> Result<Transaction> {
+        let (watch, client) = self.watch();
+        let result = client.begin_optimistic();
+        watch.record(&result);
+        Ok(result?.with_failover(watch))
+    }
+
+    pub fn begin_pessimistic(&self) -> Result<Transaction> {
+        let (watch, client) = self.watch();
+        let result = client.begin_pessimistic();
+        watch.record(&result);
+        Ok(result?.with_failover(watch))
+    }
+
+    /// Creates a snapshot of the current target, which keeps reading it
+    /// after a switch.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let (watch, client) = self.watch();
+        let result = client.snapshot();
+        watch.record(&result);
+        Ok(result?.with_failover(watch))
+    }
+
+    /// Switches to `target` at once, without asking the confirmation; the
+    /// transactions begun on the other cluster fail from then on. Does
+    /// nothing when `target` is the current one.
+    pub fn switch(&self, target: FailoverTarget) {
+        let mut health = self.pair.lock();
+        self.pair
+            .switch(&mut health, target, "switched manually".to_owned());
+    }
+
+    pub fn status(&self) -> FailoverStatus {
+        let health = self.pair.lock();
+        FailoverStatus {
+            target: health.target,
+            switches: health.switches,
+            consecutive_failures: health.consecutive_failures,
+            window_operations: health.window.len() as u32,
+            window_failures: health.window_failures,
+            last_reason: health.last_reason.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn policy(
+        max_consecutive_failures: u32,
+        max_error_percent: u32,
+        window: u32,
+    ) -> FailoverPolicy {
+        FailoverPolicy {
+            max_consecutive_failures,
+            max_error_percent,
+            window,
+        }
+    }
+
+    fn pair(policy: FailoverPolicy) -> Pair<()> {
+        Pair::new((), (), policy, None)
+    }
+
+    /// Records the outcomes of `failures`, in order, at the current epoch.
+    fn record(pair: &Pair<()>, failures: &[bool]) {
+        for &failed in failures {
+            pair.record(pair.epoch.load(Ordering::Acquire), failed);
+        }
+    }
+
+    fn target(pair: &Pair<()>) -> FailoverTarget {
+        pair.lock().target
+    }
+
+    #[test]
+    fn policies_need_a_percentage_and_a_window_for_it() {
+        assert!(FailoverPolicy::default().check().is_ok());
+        assert!(policy(0, 0, 0).check().is_ok());
+        assert!(policy(1, 100, 1).check().is_ok());
+        assert!(policy(0, 101, 10).check().is_err());
+        assert!(policy(0, 50, 0).check().is_err());
+    }
+
+    #[test]
+    fn failures_in_a_row_trip_the_switch() {
+        let pair = pair(policy(3, 0, 0));
+        record(&pair, &[true, true, false, true, true]);
+        assert_eq!(target(&pair), FailoverTarget::Primary);
+        assert_eq!(pair.lock().consecutive_failures, 2);
+
+        record(&pair, &[true]);
+        let health = pair.lock();
+        assert_eq!(health.target, FailoverTarget::Standby);
+        assert_eq!(health.switches, 1);
+        assert_eq!(health.consecutive_failures, 0);
+        assert_eq!(
+            health.last_reason.as_deref(),
+            Some("3 failures in a row, 0% of the last 0 operations failed")
+        );
+        assert_eq!(pair.epoch.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn the_error_rate_trips_once_the_window_is_full() {
+        let pair = pair(policy(0, 50, 4));
+        // 2 failures out of 3 operations, short of the window.
+        record(&pair, &[true, false, true]);
+        assert_eq!(target(&pair), FailoverTarget::Primary);
+        record(&pair, &[false]);
+        assert_eq!(target(&pair), FailoverTarget::Standby);
+        assert_eq!(
+            pair.lock().last_reason.as_deref(),
+            Some("0 failures in a row, 50% of the last 4 operations failed")
+        );
+    }
+
+    #[test]
+    fn the_window_forgets_the_oldest_operations() {
+        let pair = pair(policy(0, 50, 4));
+        record(&pair, &[true, false, false, false, false]);
+        let health = pair.lock();
+        assert_eq!(health.target, FailoverTarget::Primary);
+        assert_eq!(health.window.len(), 4);
+        assert_eq!(health.window_failures, 0);
+        drop(health);
+        // The window now ends with 1 failure out of 4, then 2.
+        record(&pair, &[true]);
+        assert_eq!(target(&pair), FailoverTarget::Primary);
+        record(&pair, &[true]);
+        assert_eq!(target(&pair), FailoverTarget::Standby);
+    }
+
+    #[test]
+    fn zero_thresholds_never_trip() {
+        let pair = pair(policy(0, 0, 10));
+        record(&pair, &[true; 100]);
+        assert_eq!(target(&pair), FailoverTarget::Primary);
+        assert_eq!(pair.lock().consecutive_failures, 100);
+    }
+
+    #[test]
+    fn only_the_primary_at_the_current_epoch_is_counted() {
+        let pair = pair(policy(2, 0, 0));
+        pair.record(1, true);
+        pair.record(1, true);
+        assert_eq!(target(&pair), FailoverTarget::Primary);
+        assert_eq!(pair.lock().consecutive_failures, 0);
+
+        record(&pair, &[true, true]);
+        assert_eq!(target(&pair), FailoverTarget::Standby);
+        record(&pair, &[true, true]);
+        let health = pair.lock();
+        assert_eq!(health.switches, 1);
+        assert_eq!(health.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn switching_back_counts_afresh() {
+        let pair = pair(policy(2, 0, 0));
+        record(&pair, &[true, true]);
+        assert_eq!(target(&pair), FailoverTarget::Standby);
+
+        let mut health = pair.lock();
+        pair.switch(&mut health, FailoverTarget::Primary, "back".to_owned());
+        assert_eq!(health.target, FailoverTarget::Primary);
+        assert_eq!(health.switches, 2);
+        assert_eq!(health.last_reason.as_deref(), Some("back"));
+        // Switching to the current target changes nothing.
+        pair.switch(&mut health, FailoverTarget::Primary, "again".to_owned());
+        assert_eq!(health.switches, 2);
+        assert_eq!(health.last_reason.as_deref(), Some("back"));
+        drop(health);
+        assert_eq!(pair.epoch.load(Ordering::Acquire), 2);
+
+        // Operations of the primary begun before the first switch are stale.
+        pair.record(0, true);
+        pair.record(0, true);
+        assert_eq!(target(&pair), FailoverTarget::Primary);
+        record(&pair, &[true]);
+        assert_eq!(target(&pair), FailoverTarget::Primary);
+        record(&pair, &[true]);
+        assert_eq!(target(&pair), FailoverTarget::Standby);
+        assert_eq!(pair.lock().switches, 3);
+    }
+
+    #[test]
+    fn a_refused_or_failed_confirmation_keeps_the_primary() {
+        let asked = Arc::new(AtomicU32::new(0));
+        let confirmation = |answer: fn() -> Result<bool>| -> FailoverConfirmation {
+            let asked = asked.clone();
+            Box::new(move |failures, percent| {
+                assert_eq!((failures, percent), (2, 0));
+                asked.fetch_add(1, Ordering::Relaxed);
+                answer()
+            })
+        };
+        let refusals: [fn() -> Result<bool>; 2] = [|| Ok(false), || Err(anyhow!("no answer"))];
+        for &answer in refusals.iter() {
+            let pair = Pair::new((), (), policy(2, 0, 0), Some(confirmation(answer)));
+            record(&pair, &[true, true]);
+            let health = pair.lock();
+            assert_eq!(health.target, FailoverTarget::Primary);
+            assert_eq!(health.consecutive_failures, 0);
+            assert!(health.window.is_empty());
+        }
+        assert_eq!(asked.load(Ordering::Relaxed), 2);
+
+        let pair = Pair::new((), (), policy(2, 0, 0), Some(confirmation(|| Ok(true))));
+        record(&pair, &[true, true]);
+        assert_eq!(target(&pair), FailoverTarget::Standby);
+        assert_eq!(asked.load(Ordering::Relaxed), 3);
+    }
+}
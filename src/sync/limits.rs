@@ -0,0 +1,79 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use anyhow::Result;
+
+use super::{Error, OversizedValues};
+
+/// TiKV's default `storage.max-key-size`.
+const DEFAULT_MAX_KEY_BYTES: u64 = 8 * 1024;
+
+/// TiKV's default `raftstore.raft-entry-max-size`, which no single value can
+/// exceed.
+const DEFAULT_MAX_VALUE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Caps on the size of the keys and values written through a client, checked
+/// before a mutation is buffered so that an oversized one fails right away
+/// with [`Error::EntryTooLarge`] instead of at commit, or, for a value, is
+/// chunked when so set. `None` disables a check, for clusters whose limits
+/// were raised.
+///
+/// Keys are measured as sent to TiKV, with the prefix of the client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub max_key_bytes: Option<u64>,
+    pub max_value_bytes: Option<u64>,
+    /// What a put of a value over `max_value_bytes` does.
+    pub oversized_values: OversizedValues,
+}
+
+impl SizeLimits {
+    /// Limits checking nothing.
+    pub const UNLIMITED: SizeLimits = SizeLimits {
+        max_key_bytes: None,
+        max_value_bytes: None,
+        oversized_values: OversizedValues::Reject,
+    };
+
+    /// The size of the chunks values over the value limit are split into,
+    /// when they are.
+    pub(crate) fn chunk_bytes(&self) -> Option<usize> {
+        match (self.oversized_values, self.max_value_bytes) {
+            (OversizedValues::Chunk, Some(limit)) => Some(limit.max(1) as usize),
+            _ => None,
+        }
+    }
+
+    /// Checks a mutation of `key`, `encoded_len` bytes long once prefixed,
+    /// writing `value_len` bytes.
+    pub(crate) fn check(&self, key: &[u8], encoded_len: usize, value_len: usize) -> Result<()> {
+        let checks = [
+            ("key", encoded_len, self.max_key_bytes),
+            ("value", value_len, self.max_value_bytes),
+        ];
+        for &(what, size, limit) in &checks {
+            match limit {
+                Some(limit) if size as u64 > limit => {
+                    return Err(Error::EntryTooLarge {
+                        what,
+                        key: key.to_owned(),
+                        size: size as u64,
+                        limit,
+                    }
+                    .into())
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        SizeLimits {
+            max_key_bytes: Some(DEFAULT_MAX_KEY_BYTES),
+            max_value_bytes: Some(DEFAULT_MAX_VALUE_BYTES),
+            oversized_values: OversizedValues::Reject,
+        }
+    }
+}
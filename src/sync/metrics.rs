@@ -0,0 +1,357 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Counters of a client, shared by the transactions and snapshots started
+//! from it.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::warn;
+use tikv_client::{Key, KvPair};
+
+/// What errors carrying a store id are recognized by, in their debug
+/// rendering; the proto types are not re-exported by tikv-client. It also
+/// matches `request_store_id: `.
+const STORE_ID_MARKER: &str = "store_id: ";
+
+/// Markers of the region errors counted by [`MetricsSnapshot`], in the debug
+/// rendering of an error: the variant name or the field of the proto.
+const NOT_LEADER_MARKERS: &[&str] = &["NotLeader", "not_leader: Some"];
+const EPOCH_NOT_MATCH_MARKERS: &[&str] = &["EpochNotMatch", "epoch_not_match: Some"];
+const SERVER_IS_BUSY_MARKERS: &[&str] = &["ServerIsBusy", "server_is_busy: Some"];
+
+/// The window over which region errors are compared with the warning
+/// threshold, see [`Metrics::set_region_error_warn_threshold`].
+const REGION_ERROR_WINDOW: Duration = Duration::from_secs(60);
+
+/// What the client saw of one TiKV store, from
+/// [`TransactionClient::store_metrics`](super::TransactionClient::store_metrics).
+///
+/// The pinned tikv-client does not tell which store served a request, only
+/// some errors name one, so only `errors` is counted: `requests` and `p99`
+/// are always zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StoreMetric {
+    pub store_id: u64,
+    pub requests: u64,
+    /// Errors naming the store, by the first store id they mention.
+    pub errors: u64,
+    pub p99: Duration,
+}
+
+/// The running totals of a client, from
+/// [`TransactionClient::metrics`](super::TransactionClient::metrics).
+///
+/// Reads count the keys and the key and value bytes returned, by snapshots
+/// and by transactions, including read-committed reads made through an
+/// internal snapshot. Writes count the puts and deletes of committed
+/// transactions, with their key and value bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub snapshot_read_keys: u64,
+    pub snapshot_read_bytes: u64,
+    pub transaction_read_keys: u64,
+    pub transaction_read_bytes: u64,
+    pub written_keys: u64,
+    pub written_bytes: u64,
+    /// Region errors by kind, e.g. during a rolling restart of TiKV. The
+    /// pinned tikv-client retries them internally without telling, so only
+    /// those that made an operation fail are counted.
+    pub not_leader_errors: u64,
+    pub epoch_not_match_errors: u64,
+    pub server_is_busy_errors: u64,
+    /// Snapshot reads sent again after a region error, see
+    /// [`TransactionClient::set_snapshot_read_retry`](super::TransactionClient::set_snapshot_read_retry);
+    /// the errors they absorbed are not counted above.
+    pub snapshot_read_retries: u64,
+    /// The calls of transactions and snapshots made through the C++ bridge
+    /// that are running, a gauge which resetting leaves alone.
+    pub in_flight_operations: u64,
+    /// The sampled reads replayed against the mirror, see
+    /// [`TransactionClient::set_mirror`](super::TransactionClient::set_mirror):
+    /// those whose result was the same, those whose result differed, those
+    /// the mirror failed to read, and those not replayed because too many
+    /// were already.
+    pub mirror_matches: u64,
+    pub mirror_mismatches: u64,
+    pub mirror_errors: u64,
+    pub mirror_dropped: u64,
+    /// The keys [`Snapshot::smart_get`](super::Snapshot::smart_get) served
+    /// by scans and by batch gets, and the pairs its scans read that were
+    /// not asked for, to tune its density threshold.
+    pub smart_get_scanned_keys: u64,
+    pub smart_get_batched_keys: u64,
+    pub smart_get_extra_pairs: u64,
+    /// The puts dropped for writing back the value already there, see
+    /// [`Transaction::set_skip_noop_writes`](super::Transaction::set_skip_noop_writes).
+    pub skipped_noop_writes: u64,
+    /// The prewrites sent to TiKV, by commits and by the two-phase calls;
+    /// the commit of a transaction that buffered nothing sends none.
+    pub prewrites: u64,
+    /// Records the logger of the connection failed to write or dropped with
+    /// its channel full, never reset.
+    pub failed_log_writes: u64,
+}
+
+/// Whose reads a read is counted as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ReadSource {
+    Snapshot,
+    Transaction,
+}
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    store_errors: Mutex<BTreeMap<u64, u64>>,
+    snapshot_read_keys: AtomicU64,
+    snapshot_read_bytes: AtomicU64,
+    transaction_read_keys: AtomicU64,
+    transaction_read_bytes: AtomicU64,
+    written_keys: AtomicU64,
+    written_bytes: AtomicU64,
+    not_leader_errors: AtomicU64,
+    epoch_not_match_errors: AtomicU64,
+    server_is_busy_errors: AtomicU64,
+    snapshot_read_retries: AtomicU64,
+    in_flight_operations: AtomicU64,
+    mirror_matches: AtomicU64,
+    mirror_mismatches: AtomicU64,
+    mirror_errors: AtomicU64,
+    mirror_dropped: AtomicU64,
+    smart_get_scanned_keys: AtomicU64,
+    smart_get_batched_keys: AtomicU64,
+    smart_get_extra_pairs: AtomicU64,
+    skipped_noop_writes: AtomicU64,
+    prewrites: AtomicU64,
+    region_error_warn_threshold: AtomicU64,
+    /// The start of the current window and the region errors seen in it.
+    region_error_window: Mutex<Option<(Instant, u64)>>,
+}
+
+impl Metrics {
+    pub fn record_read(&self, source: ReadSource, keys: u64, bytes: u64) {
+        let (read_keys, read_bytes) = match source {
+            ReadSource::Snapshot => (&self.snapshot_read_keys, &self.snapshot_read_bytes),
+            ReadSource::Transaction => (&self.transaction_read_keys, &self.transaction_read_bytes),
+        };
+        read_keys.fetch_add(keys, Ordering::Relaxed);
+        read_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_pairs(&self, source: ReadSource, kv_pairs: &[KvPair]) {
+        let bytes = kv_pairs
+            .iter()
+            .map(|KvPair(key, value)| key_len(key) + value.len() as u64)
+            .sum();
+        self.record_read(source, kv_pairs.len() as u64, bytes);
+    }
+
+    pub fn record_keys(&self, source: ReadSource, keys: &[Key]) {
+        let bytes = keys.iter().map(key_len).sum();
+        self.record_read(source, keys.len() as u64, bytes);
+    }
+
+    pub fn record_write(&self, keys: u64, bytes: u64) {
+        self.written_keys.fetch_add(keys, Ordering::Relaxed);
+        self.written_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_snapshot_read_retry(&self) {
+        self.snapshot_read_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_mirror_match(&self) {
+        self.mirror_matches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_mirror_mismatch(&self) {
+        self.mirror_mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_mirror_error(&self) {
+        self.mirror_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_mirror_dropped(&self) {
+        self.mirror_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_smart_get_scan(&self, keys: u64, extra_pairs: u64) {
+        self.smart_get_scanned_keys
+            .fetch_add(keys, Ordering::Relaxed);
+        self.smart_get_extra_pairs
+            .fetch_add(extra_pairs, Ordering::Relaxed);
+    }
+
+    pub fn record_smart_get_batch(&self, keys: u64) {
+        self.smart_get_batched_keys
+            .fetch_add(keys, Ordering::Relaxed);
+    }
+
+    pub fn record_skipped_noop_write(&self) {
+        self.skipped_noop_writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_prewrite(&self) {
+        self.prewrites.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn start_operation(&self) {
+        self.in_flight_operations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finish_operation(&self) {
+        self.in_flight_operations.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn in_flight_operations(&self) -> u64 {
+        self.in_flight_operations.load(Ordering::Relaxed)
+    }
+
+    /// The counters, with the failed writes of the logger of the connection.
+    pub fn snapshot(&self, failed_log_writes: u64) -> MetricsSnapshot {
+        MetricsSnapshot {
+            snapshot_read_keys: self.snapshot_read_keys.load(Ordering::Relaxed),
+            snapshot_read_bytes: self.snapshot_read_bytes.load(Ordering::Relaxed),
+            transaction_read_keys: self.transaction_read_keys.load(Ordering::Relaxed),
+            transaction_read_bytes: self.transaction_read_bytes.load(Ordering::Relaxed),
+            written_keys: self.written_keys.load(Ordering::Relaxed),
+            written_bytes: self.written_bytes.load(Ordering::Relaxed),
+            not_leader_errors: self.not_leader_errors.load(Ordering::Relaxed),
+            epoch_not_match_errors: self.epoch_not_match_errors.load(Ordering::Relaxed),
+            server_is_busy_errors: self.server_is_busy_errors.load(Ordering::Relaxed),
+            snapshot_read_retries: self.snapshot_read_retries.load(Ordering::Relaxed),
+            in_flight_operations: self.in_flight_operations(),
+            mirror_matches: self.mirror_matches.load(Ordering::Relaxed),
+            mirror_mismatches: self.mirror_mismatches.load(Ordering::Relaxed),
+            mirror_errors: self.mirror_errors.load(Ordering::Relaxed),
+            mirror_dropped: self.mirror_dropped.load(Ordering::Relaxed),
+            smart_get_scanned_keys: self.smart_get_scanned_keys.load(Ordering::Relaxed),
+            smart_get_batched_keys: self.smart_get_batched_keys.load(Ordering::Relaxed),
+            smart_get_extra_pairs: self.smart_get_extra_pairs.load(Ordering::Relaxed),
+            skipped_noop_writes: self.skipped_noop_writes.load(Ordering::Relaxed),
+            prewrites: self.prewrites.load(Ordering::Relaxed),
+            failed_log_writes,
+        }
+    }
+
+    /// Zeroes the read, write, region error, retry, mirror, smart get,
+    /// skipped write and prewrite counters. Each counter is reset on its own,
+    /// so a concurrent operation may be counted in some and not others.
+    pub fn reset(&self) {
+        for counter in &[
+            &self.snapshot_read_keys,
+            &self.snapshot_read_bytes,
+            &self.transaction_read_keys,
+            &self.transaction_read_bytes,
+            &self.written_keys,
+            &self.written_bytes,
+            &self.not_leader_errors,
+            &self.epoch_not_match_errors,
+            &self.server_is_busy_errors,
+            &self.snapshot_read_retries,
+            &self.mirror_matches,
+            &self.mirror_mismatches,
+            &self.mirror_errors,
+            &self.mirror_dropped,
+            &self.smart_get_scanned_keys,
+            &self.smart_get_batched_keys,
+            &self.smart_get_extra_pairs,
+            &self.skipped_noop_writes,
+            &self.prewrites,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the error of `result`, if any, and passes it through.
+    pub fn record<T>(&self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result {
+            self.record_error(e);
+        }
+        result
+    }
+
+    pub fn record_error(&self, e: &anyhow::Error) {
+        let message = format!("{:?}", e);
+        for (markers, counter) in &[
+            (NOT_LEADER_MARKERS, &self.not_leader_errors),
+            (EPOCH_NOT_MATCH_MARKERS, &self.epoch_not_match_errors),
+            (SERVER_IS_BUSY_MARKERS, &self.server_is_busy_errors),
+        ] {
+            if markers.iter().any(|marker| message.contains(marker)) {
+                counter.fetch_add(1, Ordering::Relaxed);
+                self.record_region_error();
+            }
+        }
+        if let Some(store_id) = store_id_of(&message) {
+            let mut store_errors = self
+                .store_errors
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            *store_errors.entry(store_id).or_default() += 1;
+        }
+    }
+
+    /// Makes the client log a warning when more than `per_minute` region
+    /// errors are counted within a minute, at most once a minute; 0, the
+    /// default, disables it.
+    pub fn set_region_error_warn_threshold(&self, per_minute: u64) {
+        self.region_error_warn_threshold
+            .store(per_minute, Ordering::Relaxed);
+    }
+
+    fn record_region_error(&self) {
+        let threshold = self.region_error_warn_threshold.load(Ordering::Relaxed);
+        if threshold == 0 {
+            return;
+        }
+        let mut window = self
+            .region_error_window
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let (start, count) = match *window {
+            Some((start, count)) if now - start < REGION_ERROR_WINDOW => (start, count + 1),
+            _ => (now, 1),
+        };
+        *window = Some((start, count));
+        if count == threshold + 1 {
+            warn!(
+                "{} region errors (not leader, epoch not match, server is busy) within {:?}, \
+                 over the threshold of {}",
+                count, REGION_ERROR_WINDOW, threshold
+            );
+        }
+    }
+
+    /// The stores seen so far, by id.
+    pub fn store_metrics(&self) -> Vec<StoreMetric> {
+        self.store_errors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(&store_id, &errors)| StoreMetric {
+                store_id,
+                errors,
+                ..StoreMetric::default()
+            })
+            .collect()
+    }
+}
+
+fn key_len(key: &Key) -> u64 {
+    <&[u8]>::from(key).len() as u64
+}
+
+fn store_id_of(message: &str) -> Option<u64> {
+    let start = message.find(STORE_ID_MARKER)? + STORE_ID_MARKER.len();
+    let digits = message[start..]
+        .bytes()
+        .take_while(u8::is_ascii_digit)
+        .count();
+    message[start..start + digits].parse().ok()
+}
@@ -0,0 +1,274 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Replaying a sample of the snapshot reads of a client against another
+//! cluster, e.g. a staging one being validated, and comparing the results.
+//!
+//! The replay is spawned on the runtime once the read returned, so it
+//! neither delays nor fails it; a sampled read only pays for copying its
+//! result. The mirror reads at a fresh timestamp of its own cluster, so a
+//! key written on either cluster in between shows as a mismatch: watch the
+//! rate of mismatches rather than single ones. Writes are not mirrored.
+
+use std::cmp::Ordering as KeyOrdering;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::warn;
+use tikv_client::{KvPair, TransactionOptions, Value};
+
+use super::metrics::Metrics;
+use super::namespace::Namespace;
+use super::{
+    as_ref_bound, check_runtime_alive, format_key, from_tikv_error, sort_by_key, to_owned_bound,
+    TOKIO_RUNTIME,
+};
+use crate::checksum::Crc64;
+
+/// The mirror reads in flight past which sampled reads are dropped rather
+/// than queued, so that a slow mirror cannot pile up work on the runtime.
+const MAX_IN_FLIGHT: u64 = 64;
+
+/// The least time between two warnings about mismatches; those found in
+/// between are counted in the next one.
+const WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Which reads a mirror replays, see
+/// [`TransactionClient::set_mirror`](super::TransactionClient::set_mirror).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MirrorOps {
+    pub get: bool,
+    pub batch_get: bool,
+    pub scan: bool,
+}
+
+/// The mirror of a client, shared by the snapshots created from it.
+pub(crate) struct Mirror {
+    client: Arc<tikv_client::TransactionClient>,
+    namespace: Namespace,
+    sample_percent: u64,
+    ops: MirrorOps,
+    metrics: Arc<Metrics>,
+    sampled: AtomicU64,
+    in_flight: AtomicU64,
+    /// When the last warning was logged and the mismatches found since.
+    warnings: Mutex<(Option<Instant>, u64)>,
+}
+
+/// A read to replay, with the result the primary cluster gave.
+enum Read {
+    Get(Vec<u8>, Option<Value>),
+    BatchGet(Vec<Vec<u8>>, Vec<KvPair>),
+    Scan(Bound<Vec<u8>>, Bound<Vec<u8>>, u32, Vec<KvPair>),
+}
+
+/// The first key the two clusters disagree on, with the crc64 of the value
+/// each read, `None` when it read none.
+struct Mismatch {
+    key: Vec<u8>,
+    primary: Option<u64>,
+    mirror: Option<u64>,
+}
+
+impl Mirror {
+    /// A mirror reading through `client` with the keys encoded as
+    /// `namespace`, counting its outcomes in the `metrics` of the primary
+    /// client.
+    pub fn new(
+        client: Arc<tikv_client::TransactionClient>,
+        namespace: Namespace,
+        sample_percent: u32,
+        ops: MirrorOps,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Mirror {
+            client,
+            namespace,
+            sample_percent: sample_percent.min(100) as u64,
+            ops,
+            metrics,
+            sampled: AtomicU64::default(),
+            in_flight: AtomicU64::default(),
+            warnings: Mutex::default(),
+        }
+    }
+
+    pub fn get(self: &Arc<Self>, key: &[u8], value: &Option<Value>) {
+        if self.ops.get && self.sample() {
+            self.spawn(Read::Get(key.to_owned(), value.clone()));
+        }
+    }
+
+    /// Replays a batch get, `kv_pairs` being sorted by key.
+    pub fn batch_get(self: &Arc<Self>, keys: &[&[u8]], kv_pairs: &[KvPair]) {
+        if self.ops.batch_get && self.sample() {
+            let keys = keys.iter().map(|key| key.to_vec()).collect();
+            self.spawn(Read::BatchGet(keys, kv_pairs.to_owned()));
+        }
+    }
+
+    pub fn scan(
+        self: &Arc<Self>,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+        kv_pairs: &[KvPair],
+    ) {
+        if self.ops.scan && self.sample() {
+            self.spawn(Read::Scan(
+                to_owned_bound(start),
+                to_owned_bound(end),
+                limit,
+                kv_pairs.to_owned(),
+            ));
+        }
+    }
+
+    /// Takes `sample_percent` reads out of every hundred, evenly spread.
+    fn sample(&self) -> bool {
+        let n = self.sampled.fetch_add(1, Ordering::Relaxed) % 100;
+        n * self.sample_percent / 100 != (n + 1) * self.sample_percent / 100
+    }
+
+    fn spawn(self: &Arc<Self>, read: Read) {
+        if check_runtime_alive().is_err() {
+            self.metrics.record_mirror_dropped();
+            return;
+        }
+        if self.in_flight.fetch_add(1, Ordering::Relaxed) >= MAX_IN_FLIGHT {
+            self.in_flight.fetch_sub(1, Ordering::Relaxed);
+            self.metrics.record_mirror_dropped();
+            return;
+        }
+        let mirror = self.clone();
+        TOKIO_RUNTIME.spawn(async move {
+            match mirror.replay(read).await {
+                Ok(None) => mirror.metrics.record_mirror_match(),
+                Ok(Some(mismatch)) => {
+                    mirror.metrics.record_mirror_mismatch();
+                    mirror.warn(mismatch);
+                }
+                Err(_) => mirror.metrics.record_mirror_error(),
+            }
+            mirror.in_flight.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+
+    async fn replay(&self, read: Read) -> Result<Option<Mismatch>> {
+        let timestamp = self
+            .client
+            .current_timestamp()
+            .await
+            .map_err(from_tikv_error)?;
+        let mut snapshot = self
+            .client
+            .snapshot(timestamp, TransactionOptions::new_optimistic());
+        let namespace = &self.namespace;
+        Ok(match read {
+            Read::Get(key, primary) => {
+                let value = snapshot
+                    .get(namespace.encode_key(&key)?)
+                    .await
+                    .map_err(from_tikv_error)?
+                    .map(|value| namespace.decode_value(value));
+                if value == primary {
+                    None
+                } else {
+                    Some(Mismatch {
+                        key,
+                        primary: primary.as_deref().map(crc64),
+                        mirror: value.as_deref().map(crc64),
+                    })
+                }
+            }
+            Read::BatchGet(keys, primary) => {
+                let keys = keys
+                    .iter()
+                    .map(|key| namespace.encode_key(key))
+                    .collect::<Result<Vec<_>>>()?;
+                let mut kv_pairs = snapshot
+                    .batch_get(keys)
+                    .await
+                    .map_err(from_tikv_error)?
+                    .map(|kv_pair| namespace.decode_pair(kv_pair))
+                    .collect::<Vec<_>>();
+                sort_by_key(&mut kv_pairs);
+                first_mismatch(&primary, &kv_pairs)
+            }
+            Read::Scan(start, end, limit, primary) => {
+                let range = namespace.encode_range(as_ref_bound(&start), as_ref_bound(&end));
+                let kv_pairs = snapshot
+                    .scan(range, limit)
+                    .await
+                    .map_err(from_tikv_error)?
+                    .map(|kv_pair| namespace.decode_pair(kv_pair))
+                    .collect::<Vec<_>>();
+                first_mismatch(&primary, &kv_pairs)
+            }
+        })
+    }
+
+    /// Logs `mismatch` unless a warning was logged within
+    /// [`WARN_INTERVAL`], in which case it is only counted.
+    fn warn(&self, mismatch: Mismatch) {
+        let mut warnings = self.warnings.lock().unwrap_or_else(PoisonError::into_inner);
+        let (last, suppressed) = &mut *warnings;
+        if last.map_or(false, |at| at.elapsed() < WARN_INTERVAL) {
+            *suppressed += 1;
+            return;
+        }
+        warn!(
+            "mirrored read mismatch on {}: crc64 {} on the primary, {} on the mirror; \
+             {} more mismatches since the last warning",
+            format_key(&mismatch.key),
+            format_crc(mismatch.primary),
+            format_crc(mismatch.mirror),
+            suppressed
+        );
+        *last = Some(Instant::now());
+        *suppressed = 0;
+    }
+}
+
+/// The first key of two sorted lists of pairs missing from one or with
+/// another value in each.
+fn first_mismatch(primary: &[KvPair], mirror: &[KvPair]) -> Option<Mismatch> {
+    let (mut p, mut m) = (0, 0);
+    loop {
+        let (key, primary_value, mirror_value) = match (primary.get(p), mirror.get(m)) {
+            (None, None) => return None,
+            (Some(KvPair(key, value)), None) => (key, Some(value), None),
+            (None, Some(KvPair(key, value))) => (key, None, Some(value)),
+            (Some(a), Some(b)) => match a.0.cmp(&b.0) {
+                KeyOrdering::Less => (&a.0, Some(&a.1), None),
+                KeyOrdering::Greater => (&b.0, None, Some(&b.1)),
+                KeyOrdering::Equal if a.1 == b.1 => {
+                    p += 1;
+                    m += 1;
+                    continue;
+                }
+                KeyOrdering::Equal => (&a.0, Some(&a.1), Some(&b.1)),
+            },
+        };
+        return Some(Mismatch {
+            key: key.clone().into(),
+            primary: primary_value.map(|value| crc64(value)),
+            mirror: mirror_value.map(|value| crc64(value)),
+        });
+    }
+}
+
+fn crc64(value: &[u8]) -> u64 {
+    let mut crc = Crc64::new();
+    crc.write(value);
+    crc.sum64()
+}
+
+fn format_crc(crc: Option<u64>) -> String {
+    match crc {
+        Some(crc) => format!("{:016x}", crc),
+        None => "absent".to_owned(),
+    }
+}
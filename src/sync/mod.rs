@@ -0,0 +1,1143 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Blocking Rust API over tikv-client.
+//!
+//! Every call is driven to completion on a shared multi-threaded tokio
+//! runtime, so the types here can be used from plain threads. The cxx bridge
+//! in the crate root is a thin adapter over this module; Rust tooling can use
+//! it directly with `&[u8]` keys and `std::ops::Bound` ranges.
+//!
+//! Handles may be dropped at any time, including by C++ static destructors
+//! while the process exits, so their `Drop` never blocks, never waits on the
+//! runtime and at most flips a flag or releases local state; once
+//! [`shutdown_runtime`] was called, nothing uses the runtime any more and
+//! blocking calls fail with [`Error::RuntimeShutDown`].
+
+mod audit;
+mod backup;
+mod bulk;
+mod busy;
+mod capabilities;
+mod chunked;
+mod client;
+mod conflicts;
+mod diff;
+mod error;
+mod expiry;
+mod failover;
+mod limits;
+mod metrics;
+mod mirror;
+mod mvcc;
+mod namespace;
+mod op_log;
+mod prefetch;
+mod pressure;
+mod registry;
+mod routed;
+mod safepoint;
+mod self_check;
+mod smart_get;
+mod snapshot;
+mod transaction;
+mod version;
+mod write_set;
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{anyhow, bail, Context, Result};
+use once_cell::sync::Lazy;
+use tokio::runtime::Runtime;
+
+use self::capabilities::unsupported;
+use crate::codec;
+
+pub use self::audit::{AuditFsync, AuditOptions, AUDIT_FORMAT_VERSION};
+pub use self::bulk::{BulkWriteFailure, BulkWriteProgress, BulkWriter};
+pub use self::capabilities::{capabilities, Capability};
+pub use self::chunked::OversizedValues;
+pub use self::client::{
+    BackoffOptions, ClockSkewReport, DropCheck, FinalizeResult, PessimisticOptions, ReadTsProvider,
+    TimestampRetry, TransactionClient, TxnOptions,
+};
+pub use self::conflicts::{ConflictEntry, ConflictKind};
+pub use self::diff::{Change, DiffEntry};
+pub use self::error::Error;
+pub use self::failover::{
+    FailoverClient, FailoverConfirmation, FailoverPolicy, FailoverStatus, FailoverTarget,
+};
+pub use self::limits::SizeLimits;
+pub use self::metrics::{MetricsSnapshot, StoreMetric};
+pub use self::mirror::MirrorOps;
+pub use self::mvcc::{MvccVersion, WriteType};
+pub use self::op_log::{OpKind, OpLogEntry};
+pub use self::prefetch::PrefetchStats;
+pub use self::pressure::{PressureCallback, PressureLevel};
+pub use self::registry::{EmptyCommits, SnapshotStats, StrictOptions};
+pub use self::routed::{RoutedClient, RoutedSnapshot, RoutedTransaction};
+pub use self::safepoint::SafepointGuard;
+pub use self::self_check::CheckItem;
+pub use self::smart_get::SmartGetOptions;
+pub use self::snapshot::{ScanUntilResult, SkippingScanResult, Snapshot};
+pub use self::transaction::{
+    ChunkedCommitResult, CommitInfo, CommitProtocol, CommitStats, IsolationLevel, PrimarySelection,
+    SecondaryPrewriteResult, TracedValue, Transaction, TransactionState, ValueSource,
+};
+pub use self::version::{unix_ms_of_version, version_from_unix_ms, LOGICAL_BITS};
+pub use tikv_client::{BoundRange, Config, Key, KvPair, Value};
+
+static TOKIO_RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create TOKIO_RUNTIME")
+});
+
+/// Cleared by [`shutdown_runtime`].
+static RUNTIME_ALIVE: AtomicBool = AtomicBool::new(true);
+
+/// Marks the shared runtime as gone, for process teardown: from then on
+/// every blocking call fails at once with [`Error::RuntimeShutDown`] and
+/// nothing is spawned on the runtime any more, instead of waiting on worker
+/// threads the exit may already be tearing down. Meant to be called once
+/// the last operation has returned, e.g. at the end of `main` or from an
+/// `atexit` handler; it cannot be undone.
+///
+/// The runtime itself is a static and is never dropped; the tasks already
+/// spawned, such as the chunks of a bulk write, keep running until the
+/// process is gone.
+pub fn shutdown_runtime() {
+    RUNTIME_ALIVE.store(false, Ordering::Release);
+}
+
+/// Fails with [`Error::RuntimeShutDown`] once [`shutdown_runtime`] was
+/// called.
+pub(crate) fn check_runtime_alive() -> Result<()> {
+    if !RUNTIME_ALIVE.load(Ordering::Acquire) {
+        return Err(Error::RuntimeShutDown.into());
+    }
+    Ok(())
+}
+
+/// The prefix of the keys the wrapper writes for its own bookkeeping, such as
+/// the expiry index, within the keyspace of a client. Clients can be made to
+/// reject user keys under it with
+/// [`TransactionClient::set_guard_reserved`].
+pub const RESERVED_PREFIX: &[u8] = b"\x00__tikv_cpp__";
+/// The first key after every key starting with [`RESERVED_PREFIX`].
+pub(crate) const RESERVED_END: &[u8] = b"\x00__tikv_cpp_`";
+
+/// How many pairs paginated scans fetch per request.
+pub(crate) const SCAN_PAGE_SIZE: u32 = 1024;
+
+/// Markers of TiKV rejecting the key encoding of a request, which is what a
+/// cluster with API v2 enabled does when no keyspace was selected.
+const KEY_MODE_ERRORS: &[&str] = &["InvalidKeyMode", "invalid key mode", "ApiVersionNotMatched"];
+
+/// Runs a tikv-client request to completion on the shared runtime.
+pub(crate) fn block_on<T>(future: impl Future<Output = tikv_client::Result<T>>) -> Result<T> {
+    check_runtime_alive()?;
+    TOKIO_RUNTIME.block_on(future).map_err(from_tikv_error)
+}
+
+/// Runs `f` on a blocking thread of the shared runtime and waits for it, to
+/// keep CPU-bound work, e.g. hashing large values, off the calling thread.
+pub(crate) fn block_on_worker<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T> {
+    check_runtime_alive()?;
+    TOKIO_RUNTIME
+        .block_on(TOKIO_RUNTIME.spawn_blocking(f))
+        .map_err(|e| anyhow!("a task of the runtime failed: {}", e))
+}
+
+/// Like [`block_on`], failing with [`Error::DeadlineExceeded`] once
+/// `deadline` passed instead of starting `future`, and dropping it if it is
+/// still running then.
+pub(crate) fn block_until<T>(
+    deadline: Option<tokio::time::Instant>,
+    future: impl Future<Output = tikv_client::Result<T>>,
+) -> Result<T> {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return block_on(future),
+    };
+    check_runtime_alive()?;
+    if tokio::time::Instant::now() >= deadline {
+        return Err(Error::DeadlineExceeded.into());
+    }
+    match TOKIO_RUNTIME.block_on(tokio::time::timeout_at(deadline, future)) {
+        Ok(result) => result.map_err(from_tikv_error),
+        Err(_) => Err(Error::DeadlineExceeded.into()),
+    }
+}
+
+/// The priority TiKV gives to a request over others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    Normal,
+    Low,
+    High,
+}
+
+/// Fails unless `priority` is the one every request already gets: the pinned
+/// tikv-client gives no way to set the priority of the request context.
+pub(crate) fn check_priority(priority: Priority) -> Result<()> {
+    if priority != Priority::Normal {
+        return Err(unsupported(
+            Capability::RequestPriority,
+            format!("request priority {:?}", priority),
+        ));
+    }
+    Ok(())
+}
+
+/// Markers of errors after which retrying the whole transaction, with a
+/// fresh start timestamp, can succeed.
+const RETRYABLE_ERRORS: &[&str] = &[
+    "WriteConflict",
+    "conflict: Some",
+    "Deadlock",
+    "deadlock: Some",
+    "KeyIsLocked",
+    "locked: Some",
+    "TxnLockNotFound",
+];
+
+/// Markers of a read that stopped at a lock it could not resolve.
+const LOCKED_ERRORS: &[&str] = &["KeyIsLocked", "locked: Some", "ResolveLockError"];
+
+/// Whether `e` is a read that met a lock and gave up on it.
+pub(crate) fn is_locked(e: &anyhow::Error) -> bool {
+    let message = format!("{:?}", e);
+    LOCKED_ERRORS.iter().any(|marker| message.contains(marker))
+}
+
+/// Markers of the region errors after which the same read, sent again once
+/// tikv-client has reloaded the region, can succeed: the region moved,
+/// split or merged, or its leader changed.
+const REGION_ERRORS: &[&str] = &[
+    "NotLeader",
+    "not_leader: Some",
+    "EpochNotMatch",
+    "epoch_not_match: Some",
+    "RegionNotFound",
+    "region_not_found: Some",
+    "StaleCommand",
+    "stale_command: Some",
+];
+
+/// Whether `e` is a region error a retry of the request may get past.
+pub(crate) fn is_region_error(e: &anyhow::Error) -> bool {
+    let message = format!("{:?}", e);
+    REGION_ERRORS.iter().any(|marker| message.contains(marker))
+}
+
+/// Markers of gRPC refusing a message over its size limit, which is what
+/// reading a huge value fails with.
+const OVERSIZED_ERRORS: &[&str] = &[
+    "RESOURCE_EXHAUSTED",
+    "ResourceExhausted",
+    "larger than max",
+    "message length too large",
+];
+
+/// Whether `e` is a request or response over the gRPC message size limit.
+pub(crate) fn is_oversized(e: &anyhow::Error) -> bool {
+    let message = format!("{:?}", e);
+    OVERSIZED_ERRORS
+        .iter()
+        .any(|marker| message.contains(marker))
+}
+
+/// Whether `e` is a transaction conflict which a retry may get past.
+///
+/// The proto types carrying the details are not re-exported by tikv-client,
+/// so this goes by the debug rendering of the error.
+pub fn is_retryable(e: &anyhow::Error) -> bool {
+    let message = format!("{:?}", e);
+    RETRYABLE_ERRORS
+        .iter()
+        .any(|marker| message.contains(marker))
+}
+
+fn from_tikv_error(e: tikv_client::Error) -> anyhow::Error {
+    let message = format!("{:?}", e);
+    if KEY_MODE_ERRORS
+        .iter()
+        .any(|marker| message.contains(marker))
+    {
+        anyhow!(
+            "the cluster rejected the key encoding, it probably has API v2 enabled \
+             and the client must connect within a keyspace: {}",
+            e
+        )
+    } else {
+        e.into()
+    }
+}
+
+/// Renders `bytes` in hex, cut after `max_bytes` bytes with a trailing `...`.
+pub(crate) fn to_hex(bytes: &[u8], max_bytes: usize) -> String {
+    let mut hex = bytes
+        .iter()
+        .take(max_bytes)
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    if bytes.len() > max_bytes {
+        hex.push_str("...");
+    }
+    hex
+}
+
+/// How many bytes of a key [`format_key`] shows.
+pub const FORMAT_KEY_BYTES: usize = 32;
+
+/// Formats a key the way every error and log of the crate does: `0x`
+/// followed by its bytes in lowercase hex, cut after [`FORMAT_KEY_BYTES`]
+/// bytes with a trailing `...`.
+pub fn format_key(key: &[u8]) -> String {
+    format!("0x{}", to_hex(key, FORMAT_KEY_BYTES))
+}
+
+/// Parses a key printed by [`format_key`], in either case; a key that was cut
+/// cannot be recovered and fails.
+pub fn parse_key(text: &str) -> Result<Vec<u8>> {
+    let hex = match text.strip_prefix("0x") {
+        Some(hex) => hex,
+        None => bail!("a formatted key starts with 0x, got {:?}", text),
+    };
+    if hex.ends_with("...") {
+        bail!("the key was cut when formatted and cannot be parsed back");
+    }
+    if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        bail!("invalid hex digits in {:?}", text);
+    }
+    if hex.len() % 2 != 0 {
+        bail!("odd number of hex digits in {:?}", text);
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("checked hex digits"))
+        .collect())
+}
+
+/// Sorts `kv_pairs` by key: the order batch_get returns pairs in, whatever
+/// the order tikv-client fetched them in.
+pub(crate) fn sort_by_key(kv_pairs: &mut [KvPair]) {
+    kv_pairs.sort_by(|a, b| a.0.cmp(&b.0));
+}
+
+/// Copies the start of `value` into `buf` and returns the length of `value`.
+pub(crate) fn copy_truncated(value: &[u8], buf: &mut [u8]) -> usize {
+    let len = value.len().min(buf.len());
+    buf[..len].copy_from_slice(&value[..len]);
+    value.len()
+}
+
+/// Scans a whole range by calling `scan_page` on what is left of it, one page
+/// of [`SCAN_PAGE_SIZE`] at a time, until a page comes back short; `key_of`
+/// gives the key of an item. This is what a scan `limit` of 0 does.
+pub(crate) fn scan_pages<T>(
+    start: Bound<&[u8]>,
+    mut scan_page: impl FnMut(Bound<&[u8]>, u32) -> Result<Vec<T>>,
+    key_of: impl Fn(&T) -> Vec<u8>,
+) -> Result<Vec<T>> {
+    let mut cursor = to_owned_bound(start);
+    let mut items = Vec::new();
+    loop {
+        let page = scan_page(as_ref_bound(&cursor), SCAN_PAGE_SIZE)?;
+        let full = page.len() == SCAN_PAGE_SIZE as usize;
+        if let (true, Some(item)) = (full, page.last()) {
+            cursor = Bound::Excluded(key_of(item));
+        }
+        items.extend(page);
+        if !full {
+            return Ok(items);
+        }
+    }
+}
+
+/// The result of a scan that tells whether it stopped at its limit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanResult {
+    pub kv_pairs: Vec<KvPair>,
+    /// Whether the range holds more pairs after the last one returned.
+    pub truncated: bool,
+    /// The key of the last pair returned, to resume from, excluded, when
+    /// `truncated`; `None` when no pair was returned.
+    pub last_key: Option<Key>,
+}
+
+impl ScanResult {
+    pub(crate) fn new(kv_pairs: Vec<KvPair>, truncated: bool) -> Self {
+        let last_key = kv_pairs.last().map(|KvPair(key, _)| key.clone());
+        ScanResult {
+            kv_pairs,
+            truncated,
+            last_key,
+        }
+    }
+}
+
+/// Runs `scan` from `start` for one pair more than `limit` to find out
+/// whether the range goes on past it.
+pub(crate) fn scan_with_truncation(
+    start: Bound<&[u8]>,
+    limit: u32,
+    scan: impl FnMut(Bound<&[u8]>, u32) -> Result<Vec<KvPair>>,
+) -> Result<ScanResult> {
+    truncating_scan(start, limit, u32::MAX, scan)
+}
+
+/// [`scan_with_truncation`] with requests of at most `max_limit` pairs: a
+/// scan of `max_limit` pairs cannot ask for one more, so when it comes back
+/// full a second request of one pair after the last tells whether the range
+/// goes on.
+fn truncating_scan(
+    start: Bound<&[u8]>,
+    limit: u32,
+    max_limit: u32,
+    mut scan: impl FnMut(Bound<&[u8]>, u32) -> Result<Vec<KvPair>>,
+) -> Result<ScanResult> {
+    if limit == 0 {
+        return Ok(ScanResult::new(scan(start, 0)?, false));
+    }
+    if limit < max_limit {
+        let mut kv_pairs = scan(start, limit + 1)?;
+        let truncated = kv_pairs.len() > limit as usize;
+        kv_pairs.truncate(limit as usize);
+        return Ok(ScanResult::new(kv_pairs, truncated));
+    }
+    let kv_pairs = scan(start, max_limit)?;
+    let truncated = match kv_pairs.last() {
+        Some(KvPair(last, _)) if kv_pairs.len() == max_limit as usize => {
+            let last: Vec<u8> = last.clone().into();
+            !scan(Bound::Excluded(&last), 1)?.is_empty()
+        }
+        _ => false,
+    };
+    Ok(ScanResult::new(kv_pairs, truncated))
+}
+
+/// How a scan translates between the keys stored, encoded with the
+/// memcomparable bytes encoding of [`codec`](crate::codec), and the keys its
+/// caller works with; the default scans the keys as stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScanCodec {
+    /// The bounds are given decoded and encoded before scanning, see
+    /// [`codec::encode_bound`].
+    pub bounds_are_decoded: bool,
+    /// The keys returned are decoded, see [`codec::decode_key`]; a key that
+    /// does not decode fails the scan.
+    pub decode_keys: bool,
+}
+
+/// Runs `scan` over `start` and `end` as `codec` tells. The encoding keeps
+/// the order of the keys, so the pairs come back ascending whether their
+/// keys are decoded or not.
+pub(crate) fn scan_with_codec(
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+    codec: ScanCodec,
+    scan: impl FnOnce(Bound<&[u8]>, Bound<&[u8]>) -> Result<Vec<KvPair>>,
+) -> Result<Vec<KvPair>> {
+    let kv_pairs = if codec.bounds_are_decoded {
+        let (start, end) = (codec::encode_bound(start), codec::encode_bound(end));
+        scan(as_ref_bound(&start), as_ref_bound(&end))?
+    } else {
+        scan(start, end)?
+    };
+    if !codec.decode_keys {
+        return Ok(kv_pairs);
+    }
+    kv_pairs
+        .into_iter()
+        .map(|KvPair(key, value)| {
+            let key = Vec::<u8>::from(key);
+            let decoded = codec::decode_key(&key)
+                .with_context(|| format!("cannot decode the key {}", format_key(&key)))?;
+            Ok(KvPair(decoded.into(), value))
+        })
+        .collect()
+}
+
+/// The result of a batch get whose pairs are capped in bytes, see
+/// [`Snapshot::batch_get_limited`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BatchGetLimitedResult {
+    /// The pairs of the processed keys that exist, in the order of the keys.
+    pub kv_pairs: Vec<KvPair>,
+    /// Whether every key was processed.
+    pub completed: bool,
+    /// The index of the first key not processed, where to resume; the
+    /// number of keys when completed.
+    pub next_index: u64,
+}
+
+/// Takes the pairs `found` for `keys` in the order of the keys until their
+/// keys and values add up to more than `max_bytes`, the pair going over
+/// being left for the next call unless it is the first one, so that every
+/// call makes progress. A key given twice is taken once.
+pub(crate) fn limit_batch_get(
+    keys: &[&[u8]],
+    max_bytes: u64,
+    found: Vec<KvPair>,
+) -> BatchGetLimitedResult {
+    let mut found = found
+        .into_iter()
+        .map(|KvPair(key, value)| (Vec::<u8>::from(key), value))
+        .collect::<BTreeMap<_, _>>();
+    let mut result = BatchGetLimitedResult::default();
+    let mut bytes = 0;
+    for &key in keys {
+        if let Some(value) = found.get(key) {
+            let size = (key.len() + value.len()) as u64;
+            if !result.kv_pairs.is_empty() && bytes + size > max_bytes {
+                return result;
+            }
+            bytes += size;
+            let value = found.remove(key).unwrap_or_default();
+            result.kv_pairs.push(KvPair(key.to_owned().into(), value));
+        }
+        result.next_index += 1;
+    }
+    result.completed = true;
+    result
+}
+
+/// How far an operation working in chunks got, returned whether it
+/// completed or not.
+///
+/// The operations returning it are idempotent and leave the chunks done
+/// before an error in place, so a failed one resumes by running it again
+/// over the same range from `resume_token`, included; what was done twice
+/// is harmless.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartialOutcome {
+    /// The items, e.g. keys, done.
+    pub succeeded_items: u64,
+    /// The items known to have failed; an error before items were read
+    /// counts none.
+    pub failed_items: u64,
+    /// The first error, `None` when the operation completed.
+    pub first_error: Option<String>,
+    /// The first key not known to be done, empty when completed; after an
+    /// error, the empty key resumes from the start of the keyspace.
+    pub resume_token: Vec<u8>,
+}
+
+impl PartialOutcome {
+    pub(crate) fn new(
+        succeeded_items: u64,
+        failed_items: u64,
+        result: Result<()>,
+        resume_token: Vec<u8>,
+    ) -> Self {
+        match result {
+            Ok(()) => PartialOutcome {
+                succeeded_items,
+                failed_items,
+                first_error: None,
+                resume_token: Vec::new(),
+            },
+            Err(e) => PartialOutcome {
+                succeeded_items,
+                failed_items,
+                first_error: Some(format!("{:#}", e)),
+                resume_token,
+            },
+        }
+    }
+}
+
+/// The smallest key within `start`, as a [`PartialOutcome::resume_token`].
+pub(crate) fn resume_token(start: Bound<&[u8]>) -> Vec<u8> {
+    match start {
+        Bound::Included(key) => key.to_owned(),
+        Bound::Excluded(key) => {
+            let mut key = key.to_owned();
+            key.push(0);
+            key
+        }
+        Bound::Unbounded => Vec::new(),
+    }
+}
+
+/// Builds the range taken by the scan functions from a pair of bounds.
+pub fn to_bound_range(start: Bound<&[u8]>, end: Bound<&[u8]>) -> BoundRange {
+    BoundRange::from((to_owned_bound(start), to_owned_bound(end)))
+}
+
+/// Whether no key can fall between `start` and `end`.
+///
+/// Scans over such ranges must not be sent as-is: tikv-client reads an empty
+/// end key as "no upper bound", so `..Excluded("")` would return every key
+/// instead of none.
+pub fn is_empty_range(start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+    match (start, end) {
+        (_, Bound::Excluded(end)) if end.is_empty() => true,
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end)) => start >= end,
+        (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+        // The smallest key after `start` is `start` followed by a zero byte.
+        (Bound::Excluded(start), Bound::Excluded(end)) => {
+            end <= start
+                || (end.len() == start.len() + 1 && end.starts_with(start) && end[start.len()] == 0)
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn to_owned_bound(bound: Bound<&[u8]>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.to_owned()),
+        Bound::Excluded(key) => Bound::Excluded(key.to_owned()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+pub(crate) fn as_ref_bound(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.as_slice()),
+        Bound::Excluded(key) => Bound::Excluded(key.as_slice()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::ops::Bound;
+    use std::process::Command;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// A client of the cluster behind the comma-separated PD endpoints in
+    /// `TIKV_PD_ENDPOINTS`, for the ignored tests needing one, confined to a
+    /// prefix of its own so that runs do not see each other's keys.
+    pub(crate) fn cluster_client(test: &str) -> TransactionClient {
+        let endpoints = env::var("TIKV_PD_ENDPOINTS").expect("TIKV_PD_ENDPOINTS is not set");
+        let client = TransactionClient::new(
+            endpoints.split(',').map(str::to_owned).collect(),
+            &env::temp_dir().to_string_lossy(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        client.with_prefix(format!("test/{}/{}/", test, nanos).as_bytes())
+    }
+
+    /// A deadline `ms` milliseconds from now, for
+    /// [`Transaction::set_deadline`].
+    pub(crate) fn deadline_in(ms: u64) -> Option<u64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Some(now.as_millis() as u64 + ms)
+    }
+
+    /// Whether this is the child process running the test `name` on its
+    /// own, for the tests of [`shutdown_runtime`], which cannot be undone
+    /// for the other tests. The parent runs the child and checks it passed.
+    fn in_child_process(name: &str) -> bool {
+        if env::var_os("TIKV_TEST_CHILD").is_some() {
+            return true;
+        }
+        let status = Command::new(env::current_exe().unwrap())
+            .args(&[name, "--include-ignored", "--test-threads=1"])
+            .env("TIKV_TEST_CHILD", name)
+            .status()
+            .unwrap();
+        assert!(status.success(), "the child process of {} failed", name);
+        false
+    }
+
+    fn is_shut_down<T>(result: Result<T>) -> bool {
+        matches!(
+            result.map(drop).unwrap_err().downcast_ref::<Error>(),
+            Some(Error::RuntimeShutDown)
+        )
+    }
+
+    pub(crate) type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+    /// Keys of a few bytes out of three, so that bounds often fall on keys
+    /// or right next to them.
+    pub(crate) fn key() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(0u8..3, 0..4)
+    }
+
+    fn bound() -> impl Strategy<Value = Bound<Vec<u8>>> {
+        prop_oneof![
+            key().prop_map(Bound::Included),
+            key().prop_map(Bound::Excluded),
+            Just(Bound::Unbounded),
+        ]
+    }
+
+    fn bound_of(key: Vec<u8>, kind: u8) -> Bound<Vec<u8>> {
+        match kind {
+            0 => Bound::Included(key),
+            1 => Bound::Excluded(key),
+            _ => Bound::Unbounded,
+        }
+    }
+
+    /// Bounds of any kind and order, half of them on one key or on a key
+    /// and the key right after it, where ranges become empty.
+    pub(crate) fn range() -> impl Strategy<Value = (Bound<Vec<u8>>, Bound<Vec<u8>>)> {
+        prop_oneof![
+            (bound(), bound()),
+            (key(), 0u8..3, 0u8..3, any::<bool>()).prop_map(|(key, start, end, next)| {
+                let mut end_key = key.clone();
+                if next {
+                    end_key.push(0);
+                }
+                (bound_of(key, start), bound_of(end_key, end))
+            }),
+        ]
+    }
+
+    pub(crate) fn model() -> impl Strategy<Value = Model> {
+        prop::collection::btree_map(key(), prop::collection::vec(any::<u8>(), 0..8), 0..32)
+    }
+
+    /// A model of `len` keys, the even numbers from 0 in big-endian, for
+    /// ranges spanning several pages.
+    fn numbered(len: u32) -> Model {
+        (0..len)
+            .map(|i| ((i * 2).to_be_bytes().to_vec(), i.to_be_bytes().to_vec()))
+            .collect()
+    }
+
+    /// A key among those of [`numbered`] when even, between two when odd.
+    fn numbered_key() -> impl Strategy<Value = Vec<u8>> {
+        prop_oneof![0..64u32, 0..4 * SCAN_PAGE_SIZE + 8].prop_map(|n| n.to_be_bytes().to_vec())
+    }
+
+    fn numbered_bound() -> impl Strategy<Value = Bound<Vec<u8>>> {
+        prop_oneof![
+            numbered_key().prop_map(Bound::Included),
+            numbered_key().prop_map(Bound::Excluded),
+            Just(Bound::Unbounded),
+        ]
+    }
+
+    fn numbered_len() -> impl Strategy<Value = u32> {
+        prop_oneof![
+            Just(0u32),
+            Just(1u32),
+            Just(SCAN_PAGE_SIZE - 1),
+            Just(SCAN_PAGE_SIZE),
+            Just(SCAN_PAGE_SIZE + 1),
+            Just(2 * SCAN_PAGE_SIZE),
+            0..40u32,
+        ]
+    }
+
+    fn limit() -> impl Strategy<Value = u32> {
+        prop_oneof![Just(0u32), Just(1u32), Just(SCAN_PAGE_SIZE), 0..40u32]
+    }
+
+    fn within(key: &[u8], start: &Bound<Vec<u8>>, end: &Bound<Vec<u8>>) -> bool {
+        let after_start = match start {
+            Bound::Included(start) => key >= start.as_slice(),
+            Bound::Excluded(start) => key > start.as_slice(),
+            Bound::Unbounded => true,
+        };
+        let before_end = match end {
+            Bound::Included(end) => key <= end.as_slice(),
+            Bound::Excluded(end) => key < end.as_slice(),
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    /// What TiKV returns for a scan of up to `limit` pairs, 0 for all.
+    pub(crate) fn scan_model(
+        model: &Model,
+        start: &Bound<Vec<u8>>,
+        end: &Bound<Vec<u8>>,
+        limit: u32,
+    ) -> Vec<KvPair> {
+        let limit = if limit == 0 {
+            usize::MAX
+        } else {
+            limit as usize
+        };
+        model
+            .iter()
+            .filter(|(key, _)| within(key, start, end))
+            .take(limit)
+            .map(|(key, value)| KvPair(key.clone().into(), value.clone()))
+            .collect()
+    }
+
+    fn pair_key(kv_pair: &KvPair) -> Vec<u8> {
+        kv_pair.0.clone().into()
+    }
+
+    fn pair_size(kv_pair: &KvPair) -> u64 {
+        (pair_key(kv_pair).len() + kv_pair.1.len()) as u64
+    }
+
+    proptest! {
+        #[test]
+        fn pairs_sort_alike_whatever_order_they_come_in(
+            model in model(),
+            order in prop::collection::vec(any::<u32>(), 0..8),
+        ) {
+            let sorted = scan_model(&model, &Bound::Unbounded, &Bound::Unbounded, 0);
+            // Permutes the pairs by sorting them on random ranks.
+            let mut ranked = sorted
+                .iter()
+                .cloned()
+                .zip(order.into_iter().chain(0..))
+                .collect::<Vec<_>>();
+            ranked.sort_by_key(|(_, rank)| *rank);
+            let mut kv_pairs = ranked.into_iter().map(|(kv_pair, _)| kv_pair).collect::<Vec<_>>();
+            sort_by_key(&mut kv_pairs);
+            prop_assert_eq!(kv_pairs, sorted);
+        }
+
+        #[test]
+        fn empty_ranges_hold_no_key(model in model(), (start, end) in range()) {
+            let empty = is_empty_range(as_ref_bound(&start), as_ref_bound(&end));
+            // A range holds a key unless empty: the smallest one within its
+            // start.
+            let smallest = resume_token(as_ref_bound(&start));
+            prop_assert_eq!(empty, !within(&smallest, &Bound::Unbounded, &end));
+            if empty {
+                prop_assert!(model.keys().all(|key| !within(key, &start, &end)));
+            }
+        }
+
+        #[test]
+        fn scan_pages_reads_the_whole_range(
+            len in numbered_len(),
+            start in numbered_bound(),
+            end in numbered_bound(),
+        ) {
+            let model = numbered(len);
+            let mut pages = 0;
+            let kv_pairs = scan_pages(
+                as_ref_bound(&start),
+                |cursor, limit| {
+                    assert_eq!(limit, SCAN_PAGE_SIZE);
+                    pages += 1;
+                    Ok(scan_model(&model, &to_owned_bound(cursor), &end, limit))
+                },
+                pair_key,
+            )
+            .unwrap();
+            let expected = scan_model(&model, &start, &end, 0);
+            prop_assert_eq!(pages, expected.len() / SCAN_PAGE_SIZE as usize + 1);
+            prop_assert_eq!(kv_pairs, expected);
+        }
+
+        #[test]
+        fn truncation_looks_one_pair_ahead(
+            model in model(),
+            (start, end) in range(),
+            limit in limit(),
+        ) {
+            let all = scan_model(&model, &start, &end, 0);
+            let result = scan_with_truncation(as_ref_bound(&start), limit, |cursor, n| {
+                Ok(scan_model(&model, &to_owned_bound(cursor), &end, n))
+            })
+            .unwrap();
+            let returned = if limit == 0 {
+                all.len()
+            } else {
+                all.len().min(limit as usize)
+            };
+            prop_assert_eq!(&result.kv_pairs[..], &all[..returned]);
+            prop_assert_eq!(result.truncated, limit != 0 && all.len() > limit as usize);
+            prop_assert_eq!(
+                result.last_key,
+                all[..returned].last().map(|kv_pair| kv_pair.0.clone())
+            );
+        }
+
+        #[test]
+        fn truncation_at_the_maximum_request_looks_past_the_last_pair(
+            model in model(),
+            (start, end) in range(),
+            max_limit in 1..8u32,
+        ) {
+            let all = scan_model(&model, &start, &end, 0);
+            let result = truncating_scan(as_ref_bound(&start), max_limit, max_limit, |cursor, n| {
+                assert!(n <= max_limit, "asked for {} pairs in one request", n);
+                Ok(scan_model(&model, &to_owned_bound(cursor), &end, n))
+            })
+            .unwrap();
+            let returned = all.len().min(max_limit as usize);
+            prop_assert_eq!(&result.kv_pairs[..], &all[..returned]);
+            prop_assert_eq!(result.truncated, all.len() > max_limit as usize);
+        }
+
+        #[test]
+        fn batch_get_limit_keeps_key_order(
+            model in model(),
+            keys in prop::collection::vec(key(), 0..16),
+            max_bytes in 0..64u64,
+        ) {
+            let keys = keys.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            // A batch get returns each pair found once, in no given order.
+            let found = keys
+                .iter()
+                .filter_map(|&key| model.get_key_value(key))
+                .collect::<BTreeMap<_, _>>()
+                .into_iter()
+                .rev()
+                .map(|(key, value)| KvPair(key.clone().into(), value.clone()))
+                .collect();
+            let result = limit_batch_get(&keys, max_bytes, found);
+            let next_index = result.next_index as usize;
+            prop_assert!(next_index <= keys.len());
+            prop_assert_eq!(result.completed, next_index == keys.len());
+            // Every call makes progress.
+            prop_assert!(keys.is_empty() || next_index > 0);
+
+            let mut expected = Vec::new();
+            for &key in &keys[..next_index] {
+                if let Some(value) = model.get(key) {
+                    if !expected.iter().any(|kv_pair: &KvPair| pair_key(kv_pair) == key) {
+                        expected.push(KvPair(key.to_owned().into(), value.clone()));
+                    }
+                }
+            }
+            prop_assert_eq!(&result.kv_pairs, &expected);
+            let bytes = expected.iter().map(pair_size).sum::<u64>();
+            prop_assert!(expected.len() <= 1 || bytes <= max_bytes);
+            if !result.completed {
+                // The key left is one whose pair goes over the budget.
+                let key = keys[next_index];
+                let value = model.get(key);
+                prop_assert!(value.is_some());
+                prop_assert!(!expected.iter().any(|kv_pair| pair_key(kv_pair) == key));
+                let size = (key.len() + value.map_or(0, Vec::len)) as u64;
+                prop_assert!(bytes + size > max_bytes);
+            }
+        }
+
+        #[test]
+        fn resumed_batch_get_limits_return_every_pair_once_in_key_order(
+            model in model(),
+            keys in prop::collection::btree_set(key(), 0..16),
+            max_bytes in 0..64u64,
+        ) {
+            // Distinct keys, out of sorted order.
+            let keys = keys.iter().rev().map(Vec::as_slice).collect::<Vec<_>>();
+            let mut kv_pairs = Vec::new();
+            let mut next_index = 0;
+            loop {
+                let rest = &keys[next_index..];
+                let found = rest
+                    .iter()
+                    .filter_map(|&key| model.get_key_value(key))
+                    .map(|(key, value)| KvPair(key.clone().into(), value.clone()))
+                    .collect();
+                let result = limit_batch_get(rest, max_bytes, found);
+                let bytes = result.kv_pairs.iter().map(pair_size).sum::<u64>();
+                prop_assert!(result.kv_pairs.len() <= 1 || bytes <= max_bytes);
+                kv_pairs.extend(result.kv_pairs);
+                next_index += result.next_index as usize;
+                if result.completed {
+                    break;
+                }
+            }
+            prop_assert_eq!(next_index, keys.len());
+            let expected = keys
+                .iter()
+                .filter_map(|&key| model.get(key).map(|value| KvPair(key.to_owned().into(), value.clone())))
+                .collect::<Vec<_>>();
+            prop_assert_eq!(kv_pairs, expected);
+        }
+
+        #[test]
+        fn decoded_scans_read_the_encoded_keys_in_the_decoded_range(
+            model in model(),
+            (start, end) in range(),
+        ) {
+            let encoded = model
+                .iter()
+                .map(|(key, value)| (codec::encode_bytes(key), value.clone()))
+                .collect::<Model>();
+            let scan = |start: Bound<&[u8]>, end: Bound<&[u8]>| {
+                Ok(scan_model(&encoded, &to_owned_bound(start), &to_owned_bound(end), 0))
+            };
+            let decoded = ScanCodec {
+                bounds_are_decoded: true,
+                decode_keys: true,
+            };
+            let kv_pairs = scan_with_codec(as_ref_bound(&start), as_ref_bound(&end), decoded, scan).unwrap();
+            prop_assert_eq!(&kv_pairs, &scan_model(&model, &start, &end, 0));
+
+            let encoded_keys = ScanCodec {
+                bounds_are_decoded: true,
+                decode_keys: false,
+            };
+            let kv_pairs = scan_with_codec(as_ref_bound(&start), as_ref_bound(&end), encoded_keys, scan).unwrap();
+            let keys = kv_pairs.iter().map(pair_key).collect::<Vec<_>>();
+            let expected = scan_model(&model, &start, &end, 0)
+                .iter()
+                .map(|kv_pair| codec::encode_bytes(&pair_key(kv_pair)))
+                .collect::<Vec<_>>();
+            prop_assert_eq!(keys, expected);
+        }
+
+        #[test]
+        fn formatted_keys_parse_back(
+            key in prop::collection::vec(any::<u8>(), 0..=FORMAT_KEY_BYTES),
+        ) {
+            let text = format_key(&key);
+            prop_assert_eq!(parse_key(&text).unwrap(), key.clone());
+            let upper = format!("0x{}", text[2..].to_uppercase());
+            prop_assert_eq!(parse_key(&upper).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn nothing_runs_on_the_runtime_once_shut_down() {
+        if !in_child_process("nothing_runs_on_the_runtime_once_shut_down") {
+            return;
+        }
+        assert_eq!(block_on(async { Ok(1) }).unwrap(), 1);
+        shutdown_runtime();
+        assert!(is_shut_down(check_runtime_alive()));
+        assert!(is_shut_down(block_on(async { Ok(()) })));
+        assert!(is_shut_down(block_until(None, async { Ok(()) })));
+        let later = tokio::time::Instant::now() + Duration::from_secs(60);
+        assert!(is_shut_down(block_until(Some(later), async { Ok(()) })));
+        assert!(is_shut_down(block_on_worker(|| ())));
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn handles_outlive_their_client() {
+        let client = cluster_client("outlive_client");
+        let mut setup = client.begin_optimistic().unwrap();
+        setup.put(b"k", b"v").unwrap();
+        setup.commit().unwrap();
+
+        let mut snapshot = client.snapshot().unwrap();
+        let mut txn = client.begin_optimistic().unwrap();
+        let mut unfinished = client.begin_pessimistic().unwrap();
+        unfinished.put(b"locked", b"v").unwrap();
+        drop(client);
+
+        assert_eq!(snapshot.get(b"k").unwrap(), Some(b"v".to_vec()));
+        txn.put(b"k", b"w").unwrap();
+        txn.commit().unwrap();
+        let start = Instant::now();
+        drop(unfinished);
+        drop(snapshot);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn handles_drop_at_once_after_the_runtime_shut_down() {
+        if !in_child_process("handles_drop_at_once_after_the_runtime_shut_down") {
+            return;
+        }
+        let client = cluster_client("shutdown_runtime");
+        let mut snapshot = client.snapshot().unwrap();
+        let mut locked = client.begin_pessimistic().unwrap();
+        locked.put(b"k", b"v").unwrap();
+        let mut buffered = client.begin_optimistic().unwrap();
+        buffered.put(b"k", b"w").unwrap();
+
+        shutdown_runtime();
+        let start = Instant::now();
+        assert!(is_shut_down(snapshot.get(b"k")));
+        assert!(is_shut_down(buffered.get(b"k")));
+        assert!(is_shut_down(locked.commit()));
+        assert!(is_shut_down(client.snapshot()));
+        drop(buffered);
+        drop(locked);
+        drop(snapshot);
+        drop(client);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn a_full_scan_at_the_maximum_request_is_not_truncated_at_the_range_end() {
+        let model = numbered(4);
+        let mut requests = Vec::new();
+        let mut scan = |cursor: Bound<&[u8]>, n| {
+            requests.push(n);
+            Ok(scan_model(
+                &model,
+                &to_owned_bound(cursor),
+                &Bound::Unbounded,
+                n,
+            ))
+        };
+        let result = truncating_scan(Bound::Unbounded, 4, 4, &mut scan).unwrap();
+        assert_eq!(result.kv_pairs.len(), 4);
+        assert!(!result.truncated);
+        let result = truncating_scan(Bound::Unbounded, 3, 3, &mut scan).unwrap();
+        assert_eq!(result.kv_pairs.len(), 3);
+        assert!(result.truncated);
+        let result = truncating_scan(Bound::Unbounded, 3, 4, &mut scan).unwrap();
+        assert_eq!(result.kv_pairs.len(), 3);
+        assert!(result.truncated);
+        assert_eq!(requests, [4, 1, 3, 1, 4]);
+    }
+
+    #[test]
+    fn scans_as_stored_by_default_and_fail_on_keys_that_do_not_decode() {
+        let stored = || Ok(vec![KvPair(b"raw".to_vec().into(), b"v".to_vec())]);
+        let kv_pairs = scan_with_codec(
+            Bound::Included(&b"a"[..]),
+            Bound::Excluded(&b"b"[..]),
+            ScanCodec::default(),
+            |start, end| {
+                assert_eq!(start, Bound::Included(&b"a"[..]));
+                assert_eq!(end, Bound::Excluded(&b"b"[..]));
+                stored()
+            },
+        )
+        .unwrap();
+        assert_eq!(kv_pairs, stored().unwrap());
+
+        let decode_keys = ScanCodec {
+            bounds_are_decoded: false,
+            decode_keys: true,
+        };
+        let e = scan_with_codec(Bound::Unbounded, Bound::Unbounded, decode_keys, |_, _| {
+            stored()
+        })
+        .unwrap_err();
+        assert!(e.to_string().contains("cannot decode the key"), "{:?}", e);
+    }
+
+    #[test]
+    fn format_key_cuts_after_the_limit() {
+        let key = (0..=FORMAT_KEY_BYTES as u8).collect::<Vec<_>>();
+        let at_limit = format_key(&key[..FORMAT_KEY_BYTES]);
+        assert_eq!(at_limit.len(), 2 + 2 * FORMAT_KEY_BYTES);
+        assert_eq!(parse_key(&at_limit).unwrap(), &key[..FORMAT_KEY_BYTES]);
+
+        let over_limit = format_key(&key);
+        assert_eq!(over_limit, format!("{}...", at_limit));
+        assert!(parse_key(&over_limit).is_err());
+
+        assert_eq!(format_key(b""), "0x");
+        assert_eq!(parse_key("0x").unwrap(), b"");
+        assert!(parse_key("00").is_err());
+        assert!(parse_key("0x0").is_err());
+        assert!(parse_key("0xzz").is_err());
+    }
+}
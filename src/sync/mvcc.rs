@@ -0,0 +1,117 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Version history of a single key, for diagnostics.
+//!
+//! tikv-client exposes neither the MVCC debug request nor commit timestamps
+//! on reads, so the history is reconstructed from plain snapshot reads: the
+//! value visible at a version only changes at the commit timestamps of the
+//! key, which are found by bisecting between a version reading the current
+//! value and an earlier one reading something else. This only ever reads, so
+//! it takes no locks and holds back no GC, but it has blind spots:
+//!
+//! - consecutive versions with equal values are reported as one;
+//! - a version overwritten and then restored to the same value between two
+//!   probes is missed, and so are short-lived versions when the key is
+//!   currently deleted, because earlier values are found by probing at
+//!   exponentially growing distances;
+//! - history older than the GC safepoint is whatever GC left behind.
+
+use anyhow::Result;
+use tikv_client::{Timestamp, TimestampExt, Value};
+
+use super::TransactionClient;
+
+/// What a version of a key wrote.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteType {
+    Put,
+    Delete,
+}
+
+/// A committed version of a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MvccVersion {
+    pub commit_ts: u64,
+    /// The value written, `None` for a delete.
+    pub value: Option<Value>,
+}
+
+impl MvccVersion {
+    pub fn write_type(&self) -> WriteType {
+        match self.value {
+            Some(_) => WriteType::Put,
+            None => WriteType::Delete,
+        }
+    }
+}
+
+impl TransactionClient {
+    /// Returns up to `max_versions` versions of `key`, newest first.
+    ///
+    /// This is slow, about a hundred reads per version; see the module
+    /// documentation for what it cannot see.
+    pub fn get_mvcc_versions(&self, key: &[u8], max_versions: u32) -> Result<Vec<MvccVersion>> {
+        let mut versions = Vec::new();
+        let mut upper = self.current_timestamp()?;
+        let mut value = self.value_at(key, upper)?;
+        while versions.len() < max_versions as usize {
+            let lower = match self.find_other_value(key, upper, &value)? {
+                Some(lower) => lower,
+                None => break,
+            };
+            let commit_ts = self.find_change(key, lower, upper, &value)?;
+            let previous = self.value_at(key, commit_ts - 1)?;
+            versions.push(MvccVersion {
+                commit_ts,
+                value: std::mem::replace(&mut value, previous),
+            });
+            upper = commit_ts - 1;
+        }
+        Ok(versions)
+    }
+
+    fn value_at(&self, key: &[u8], version: u64) -> Result<Option<Value>> {
+        self.snapshot_at_timestamp(Timestamp::from_version(version))
+            .get(key)
+    }
+
+    /// Probes versions at exponentially growing distances below `upper` for
+    /// one that does not read `value`.
+    fn find_other_value(
+        &self,
+        key: &[u8],
+        upper: u64,
+        value: &Option<Value>,
+    ) -> Result<Option<u64>> {
+        for shift in 0..64 {
+            let version = upper.saturating_sub(1 << shift);
+            if self.value_at(key, version)? != *value {
+                return Ok(Some(version));
+            }
+            if version == 0 {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Bisects `(lower, upper]` for a version reading `value` right after one
+    /// that does not, given that `lower` does not and `upper` does.
+    fn find_change(
+        &self,
+        key: &[u8],
+        mut lower: u64,
+        mut upper: u64,
+        value: &Option<Value>,
+    ) -> Result<u64> {
+        while upper - lower > 1 {
+            let middle = lower + (upper - lower) / 2;
+            if self.value_at(key, middle)? == *value {
+                upper = middle;
+            } else {
+                lower = middle;
+            }
+        }
+        Ok(upper)
+    }
+}
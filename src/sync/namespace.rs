@@ -0,0 +1,425 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tikv_client::{BoundRange, Key, KvPair, Value};
+
+use super::{is_empty_range, to_bound_range, Error, RESERVED_END, RESERVED_PREFIX};
+use crate::compression::{compress_value, decompress_value, Compression};
+
+/// A key prefix transparently added to every key on the way in and stripped
+/// from every key on the way out. The empty prefix is the whole keyspace.
+///
+/// It also holds the policy on the keys of point operations: the empty key
+/// is rejected unless allowed, while empty scan bounds stay valid. When
+/// guarding the reserved prefix, keys under [`RESERVED_PREFIX`] are rejected
+/// too, and scans skip its range. The values written are compressed as
+/// configured, and the values read are decompressed while compression is
+/// configured, whatever the compression of each value.
+#[derive(Clone, Default)]
+pub(crate) struct Namespace {
+    prefix: Arc<[u8]>,
+    allow_empty_keys: bool,
+    guard_reserved: bool,
+    compression: Compression,
+    compress_above: usize,
+}
+
+impl Namespace {
+    /// Returns the namespace of `prefix` nested inside this one.
+    pub fn join(&self, prefix: &[u8]) -> Namespace {
+        let mut joined = self.prefix.to_vec();
+        joined.extend_from_slice(prefix);
+        Namespace {
+            prefix: joined.into(),
+            allow_empty_keys: self.allow_empty_keys,
+            guard_reserved: self.guard_reserved,
+            compression: self.compression,
+            compress_above: self.compress_above,
+        }
+    }
+
+    pub fn set_allow_empty_keys(&mut self, allow: bool) {
+        self.allow_empty_keys = allow;
+    }
+
+    pub fn set_compression(&mut self, compression: Compression, min_value_bytes: usize) {
+        self.compression = compression;
+        self.compress_above = min_value_bytes;
+    }
+
+    pub fn guards_reserved(&self) -> bool {
+        self.guard_reserved
+    }
+
+    pub fn set_guard_reserved(&mut self, guard: bool) {
+        self.guard_reserved = guard;
+    }
+
+    /// Whether the range must be scanned around the reserved range, see
+    /// [`scan_outside_reserved`].
+    pub fn overlaps_reserved(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+        if !self.guard_reserved {
+            return false;
+        }
+        let start = match start {
+            Bound::Included(key) | Bound::Excluded(key) if key < RESERVED_PREFIX => {
+                Bound::Included(RESERVED_PREFIX)
+            }
+            Bound::Unbounded => Bound::Included(RESERVED_PREFIX),
+            start => start,
+        };
+        let end = match end {
+            Bound::Included(key) if key >= RESERVED_END => Bound::Excluded(RESERVED_END),
+            Bound::Excluded(key) if key > RESERVED_END => Bound::Excluded(RESERVED_END),
+            Bound::Unbounded => Bound::Excluded(RESERVED_END),
+            end => end,
+        };
+        !is_empty_range(start, end)
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    pub fn encode(&self, key: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(self.prefix.len() + key.len());
+        encoded.extend_from_slice(&self.prefix);
+        encoded.extend_from_slice(key);
+        encoded
+    }
+
+    /// Encodes the key of a point operation, failing with
+    /// [`Error::EmptyKey`] on the empty key unless it is allowed.
+    pub fn encode_key(&self, key: &[u8]) -> Result<Vec<u8>> {
+        if key.is_empty() && !self.allow_empty_keys {
+            return Err(Error::EmptyKey.into());
+        }
+        if self.guard_reserved && key.starts_with(RESERVED_PREFIX) {
+            return Err(Error::ReservedKey {
+                key: key.to_owned(),
+            }
+            .into());
+        }
+        Ok(self.encode(key))
+    }
+
+    /// The form `value` is stored in, compressed or not.
+    pub fn encode_value(&self, value: &[u8]) -> Vec<u8> {
+        compress_value(self.compression, self.compress_above, value)
+    }
+
+    /// The value `value` is stored as, opened when compression is set; a
+    /// client without leaves every value as stored.
+    pub fn decode_value(&self, value: Value) -> Value {
+        if self.compression == Compression::None {
+            return value;
+        }
+        decompress_value(value)
+    }
+
+    /// Strips the prefix from a key read back from TiKV.
+    pub fn decode(&self, key: Key) -> Key {
+        if self.prefix.is_empty() {
+            return key;
+        }
+        let mut key: Vec<u8> = key.into();
+        debug_assert!(key.starts_with(&self.prefix));
+        key.drain(..self.prefix.len());
+        key.into()
+    }
+
+    pub fn decode_pair(&self, KvPair(key, value): KvPair) -> KvPair {
+        KvPair(self.decode(key), self.decode_value(value))
+    }
+
+    /// Maps a range of this namespace onto the raw keyspace. Unbounded ends
+    /// are clamped to the prefix, so a scan can never escape the namespace.
+    pub fn encode_range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> BoundRange {
+        if self.prefix.is_empty() {
+            return to_bound_range(start, end);
+        }
+        let start = match start {
+            Bound::Included(key) => Bound::Included(self.encode(key)),
+            Bound::Excluded(key) => Bound::Excluded(self.encode(key)),
+            Bound::Unbounded => Bound::Included(self.prefix.to_vec()),
+        };
+        let end = match end {
+            Bound::Included(key) => Bound::Included(self.encode(key)),
+            Bound::Excluded(key) => Bound::Excluded(self.encode(key)),
+            Bound::Unbounded => match prefix_end(&self.prefix) {
+                Some(end) => Bound::Excluded(end),
+                None => Bound::Unbounded,
+            },
+        };
+        BoundRange::from((start, end))
+    }
+}
+
+/// Scans `start..end` as two scans, of what comes before the reserved range
+/// and of what comes after it, the second one for what is left of `limit`,
+/// which must be positive.
+pub(crate) fn scan_outside_reserved<T>(
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+    limit: u32,
+    mut scan: impl FnMut(Bound<&[u8]>, Bound<&[u8]>, u32) -> Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    let before_end = match end {
+        Bound::Included(key) if key < RESERVED_PREFIX => end,
+        Bound::Excluded(key) if key <= RESERVED_PREFIX => end,
+        _ => Bound::Excluded(RESERVED_PREFIX),
+    };
+    let after_start = match start {
+        Bound::Included(key) | Bound::Excluded(key) if key >= RESERVED_END => start,
+        _ => Bound::Included(RESERVED_END),
+    };
+    debug_assert!(limit > 0, "a scan of 0 pairs is a scan of the whole range");
+    let mut items = Vec::new();
+    for &(start, end) in &[(start, before_end), (after_start, end)] {
+        let left = limit.saturating_sub(items.len() as u32);
+        if left > 0 && !is_empty_range(start, end) {
+            items.extend(scan(start, end, left)?);
+        }
+    }
+    Ok(items)
+}
+
+/// The smallest key greater than every key starting with `prefix`, or `None`
+/// when there is none (the prefix is empty or all `0xFF`).
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xFF {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::to_owned_bound;
+    use super::*;
+
+    #[test]
+    fn prefix_end_bounds_exactly_the_prefixed_keys() {
+        assert_eq!(prefix_end(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_end(b"a\xff\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_end(b"\x00"), Some(b"\x01".to_vec()));
+
+        // The range from the prefix to its end holds every key starting with
+        // the prefix, up to the largest ones, and no other: the key right at
+        // the end is the first past them.
+        let prefix = &b"ab\xff"[..];
+        let end = prefix_end(prefix).unwrap();
+        assert_eq!(end, b"ac");
+        let within = |key: &[u8]| key >= prefix && key < &end[..];
+        for key in [&b"ab\xff"[..], b"ab\xff\x00", b"ab\xff\xff\xff\xff"].iter() {
+            assert!(key.starts_with(prefix) && within(key));
+        }
+        for key in [&b"ac"[..], b"ac\x00", b"ab\xfe\xff"].iter() {
+            assert!(!key.starts_with(prefix) && !within(key));
+        }
+    }
+
+    #[test]
+    fn all_0xff_prefixes_have_no_end() {
+        assert_eq!(prefix_end(b""), None);
+        assert_eq!(prefix_end(b"\xff"), None);
+        assert_eq!(prefix_end(b"\xff\xff\xff"), None);
+    }
+
+    fn allowing_empty_keys(allow: bool) -> Namespace {
+        let mut namespace = Namespace::default().join(b"p/");
+        namespace.set_allow_empty_keys(allow);
+        namespace
+    }
+
+    #[test]
+    fn the_empty_point_key_needs_allowing() {
+        for namespace in [Namespace::default(), allowing_empty_keys(false)].iter() {
+            let error = namespace.encode_key(b"").unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<Error>(),
+                Some(Error::EmptyKey)
+            ));
+            assert_eq!(
+                namespace.encode_key(b"k").unwrap(),
+                [namespace.prefix(), b"k"].concat()
+            );
+        }
+        assert_eq!(allowing_empty_keys(true).encode_key(b"").unwrap(), b"p/");
+        assert_eq!(
+            allowing_empty_keys(true)
+                .join(b"q/")
+                .encode_key(b"")
+                .unwrap(),
+            b"p/q/"
+        );
+    }
+
+    #[test]
+    fn empty_scan_bounds_stay_valid_either_way() {
+        for &allow in [false, true].iter() {
+            let namespace = allowing_empty_keys(allow);
+            let empty = &b""[..];
+            assert_eq!(
+                namespace.encode_range(Bound::Included(empty), Bound::Unbounded),
+                BoundRange::from((
+                    Bound::Included(b"p/".to_vec()),
+                    Bound::Excluded(b"p0".to_vec())
+                ))
+            );
+            assert_eq!(
+                namespace.encode_range(Bound::Excluded(empty), Bound::Excluded(empty)),
+                BoundRange::from((
+                    Bound::Excluded(b"p/".to_vec()),
+                    Bound::Excluded(b"p/".to_vec())
+                ))
+            );
+            assert!(is_empty_range(
+                Bound::Included(empty),
+                Bound::Excluded(empty)
+            ));
+
+            let mut whole = Namespace::default();
+            whole.set_allow_empty_keys(allow);
+            assert_eq!(
+                whole.encode_range(Bound::Included(empty), Bound::Included(empty)),
+                BoundRange::from((Bound::Included(Vec::new()), Bound::Included(Vec::new())))
+            );
+        }
+    }
+
+    fn guarding() -> Namespace {
+        let mut namespace = Namespace::default();
+        namespace.set_guard_reserved(true);
+        namespace
+    }
+
+    fn reserved(key: &[u8]) -> Vec<u8> {
+        [RESERVED_PREFIX, key].concat()
+    }
+
+    #[test]
+    fn guarded_point_keys_reject_the_reserved_prefix() {
+        let error = guarding().encode_key(&reserved(b"meta")).unwrap_err();
+        match error.downcast_ref::<Error>() {
+            Some(Error::ReservedKey { key }) => assert_eq!(key, &reserved(b"meta")),
+            _ => panic!("not a reserved key error: {}", error),
+        }
+        assert!(guarding().encode_key(RESERVED_PREFIX).is_err());
+
+        // Keys around the prefix, and the prefix short of its last byte, are
+        // the user's.
+        let short = &RESERVED_PREFIX[..RESERVED_PREFIX.len() - 1];
+        for key in [short, RESERVED_END, &b"\x00"[..], b"a"].iter() {
+            assert_eq!(guarding().encode_key(key).unwrap(), key.to_vec());
+        }
+        assert_eq!(
+            Namespace::default().encode_key(&reserved(b"meta")).unwrap(),
+            reserved(b"meta")
+        );
+    }
+
+    #[test]
+    fn only_ranges_reaching_into_the_reserved_one_overlap_it() {
+        let inside = reserved(b"meta");
+        let overlapping = [
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Included(&b""[..]), Bound::Excluded(&b"a"[..])),
+            (
+                Bound::Excluded(&b"\x00"[..]),
+                Bound::Included(RESERVED_PREFIX),
+            ),
+            (Bound::Included(&inside[..]), Bound::Included(&inside[..])),
+            (Bound::Excluded(&inside[..]), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Included(RESERVED_END)),
+        ];
+        for &(start, end) in overlapping.iter() {
+            assert!(
+                guarding().overlaps_reserved(start, end),
+                "{:?}",
+                (start, end)
+            );
+            assert!(!Namespace::default().overlaps_reserved(start, end));
+        }
+        let apart = [
+            (Bound::Unbounded, Bound::Excluded(RESERVED_PREFIX)),
+            (Bound::Included(RESERVED_END), Bound::Unbounded),
+            (Bound::Included(&b"a"[..]), Bound::Unbounded),
+            (Bound::Included(&inside[..]), Bound::Excluded(&inside[..])),
+        ];
+        for &(start, end) in apart.iter() {
+            assert!(
+                !guarding().overlaps_reserved(start, end),
+                "{:?}",
+                (start, end)
+            );
+        }
+    }
+
+    /// Runs [`scan_outside_reserved`] over `start..end` with scans returning
+    /// `found` items each at most, and returns the scans made.
+    fn scans_outside_reserved(
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+        found: u32,
+    ) -> (usize, Vec<(Bound<Vec<u8>>, Bound<Vec<u8>>, u32)>) {
+        let mut scans = Vec::new();
+        let items = scan_outside_reserved(start, end, limit, |start, end, limit| {
+            scans.push((to_owned_bound(start), to_owned_bound(end), limit));
+            Ok(vec![(); found.min(limit) as usize])
+        })
+        .unwrap();
+        (items.len(), scans)
+    }
+
+    #[test]
+    fn the_scan_after_the_reserved_range_gets_what_is_left_of_the_limit() {
+        let before = (Bound::Unbounded, Bound::Excluded(RESERVED_PREFIX.to_vec()));
+        let after = (Bound::Included(RESERVED_END.to_vec()), Bound::Unbounded);
+
+        let (items, scans) = scans_outside_reserved(Bound::Unbounded, Bound::Unbounded, 10, 3);
+        assert_eq!(items, 6);
+        assert_eq!(
+            scans,
+            [
+                (before.0.clone(), before.1.clone(), 10),
+                (after.0.clone(), after.1.clone(), 7)
+            ]
+        );
+
+        // A first scan filling the limit leaves none for the second.
+        let (items, scans) = scans_outside_reserved(Bound::Unbounded, Bound::Unbounded, 10, 10);
+        assert_eq!(items, 10);
+        assert_eq!(scans, [(before.0, before.1, 10)]);
+
+        // A range starting inside the reserved one is only scanned after it,
+        // for the whole limit.
+        let inside = reserved(b"meta");
+        let (items, scans) =
+            scans_outside_reserved(Bound::Included(&inside), Bound::Unbounded, 10, 3);
+        assert_eq!(items, 3);
+        assert_eq!(scans, [(after.0, after.1, 10)]);
+    }
+
+    #[test]
+    fn only_clients_set_to_compress_open_envelopes() {
+        let value = vec![b'x'; 1000];
+        let mut compressing = Namespace::default();
+        compressing.set_compression(Compression::Lz4, 0);
+        let stored = compressing.encode_value(&value);
+        assert_ne!(stored, value);
+        assert_eq!(compressing.decode_value(stored.clone()), value);
+
+        let plain = Namespace::default();
+        assert_eq!(plain.encode_value(&value), value);
+        assert_eq!(plain.decode_value(stored.clone()), stored);
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Opt-in record of what a transaction did, for debugging failed commits.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::format_key;
+
+/// How many of the latest entries the summary attached to a failed commit
+/// lists.
+const SUMMARY_ENTRIES: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpKind {
+    Get,
+    GetForUpdate,
+    BatchGet,
+    Scan,
+    ScanKeys,
+    Put,
+    Delete,
+    AssertUnchanged,
+    Commit,
+    Rollback,
+}
+
+impl OpKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            OpKind::Get => "get",
+            OpKind::GetForUpdate => "get_for_update",
+            OpKind::BatchGet => "batch_get",
+            OpKind::Scan => "scan",
+            OpKind::ScanKeys => "scan_keys",
+            OpKind::Put => "put",
+            OpKind::Delete => "delete",
+            OpKind::AssertUnchanged => "assert_unchanged",
+            OpKind::Commit => "commit",
+            OpKind::Rollback => "rollback",
+        }
+    }
+}
+
+/// An operation recorded by [`Transaction::set_op_log`](super::Transaction::set_op_log).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpLogEntry {
+    pub kind: OpKind,
+    /// The key, or the start of the range of a scan; empty for batch gets,
+    /// commits and rollbacks.
+    pub key: Vec<u8>,
+    /// The length of the value read or written, or the number of pairs
+    /// returned by a batch get or a scan.
+    pub value_len: u64,
+    /// When the operation returned, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// The error the operation failed with, `None` when it succeeded.
+    pub error: Option<String>,
+}
+
+/// A ring buffer of the latest operations of a transaction.
+pub(crate) struct OpLog {
+    entries: VecDeque<OpLogEntry>,
+    max_entries: usize,
+    dropped: u64,
+}
+
+impl OpLog {
+    pub fn new(max_entries: usize) -> Self {
+        OpLog {
+            entries: VecDeque::with_capacity(max_entries),
+            max_entries,
+            dropped: 0,
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        kind: OpKind,
+        key: &[u8],
+        value_len: u64,
+        error: Option<&anyhow::Error>,
+    ) {
+        if self.max_entries == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.entries.len() == self.max_entries {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        self.entries.push_back(OpLogEntry {
+            kind,
+            key: key.to_owned(),
+            value_len,
+            timestamp_ms,
+            error: error.map(|e| format!("{:#}", e)),
+        });
+    }
+
+    pub fn entries(&self) -> Vec<OpLogEntry> {
+        self.entries.iter().cloned().collect()
+    }
+
+    /// One line listing the latest few operations, oldest first.
+    pub fn summary(&self) -> String {
+        let skipped = self.entries.len().saturating_sub(SUMMARY_ENTRIES);
+        let latest = self
+            .entries
+            .iter()
+            .skip(skipped)
+            .map(|entry| {
+                let outcome = match &entry.error {
+                    Some(error) => format!("failed: {}", error),
+                    None => "ok".to_owned(),
+                };
+                format!(
+                    "{} {} ({}) {}",
+                    entry.kind.name(),
+                    format_key(&entry.key),
+                    entry.value_len,
+                    outcome
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "operation log: {} operations, the latest {}: {}",
+            self.entries.len() as u64 + self.dropped,
+            latest.len(),
+            latest.join("; ")
+        )
+    }
+}
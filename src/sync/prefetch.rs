@@ -0,0 +1,148 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Background fetching of the keys a snapshot is about to read.
+//!
+//! The values are fetched by another tikv-client snapshot at the same
+//! version, so whatever the cache holds is what the snapshot itself would
+//! read. They are kept as stored, before decompression, and absent keys are
+//! cached as such.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use tikv_client::{Key, KvPair, Timestamp, TimestampExt, TransactionOptions, Value};
+
+use super::{check_runtime_alive, TOKIO_RUNTIME};
+
+/// The bytes of keys and values a snapshot caches for its prefetches by
+/// default.
+pub(crate) const DEFAULT_PREFETCH_CACHE_BYTES: u64 = 64 << 20;
+
+/// How the prefetch cache of a snapshot did, see
+/// [`Snapshot::prefetch`](super::Snapshot::prefetch).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrefetchStats {
+    /// Point reads served from the cache.
+    pub hits: u64,
+    /// Point reads of keys not in the cache, including keys whose prefetch
+    /// was still in flight or failed.
+    pub misses: u64,
+    /// Keys fetched but not cached because the cache was full.
+    pub dropped: u64,
+}
+
+struct Cache {
+    values: BTreeMap<Key, Option<Value>>,
+    bytes: u64,
+    max_bytes: u64,
+    stats: PrefetchStats,
+}
+
+/// The values prefetched for a snapshot, filled by tasks on
+/// [`TOKIO_RUNTIME`].
+pub(crate) struct PrefetchCache {
+    cache: Arc<Mutex<Cache>>,
+    /// Whether anything was prefetched, so that the reads of snapshots not
+    /// using it neither lock nor count.
+    used: bool,
+}
+
+impl Default for PrefetchCache {
+    fn default() -> Self {
+        PrefetchCache {
+            cache: Arc::new(Mutex::new(Cache {
+                values: BTreeMap::new(),
+                bytes: 0,
+                max_bytes: DEFAULT_PREFETCH_CACHE_BYTES,
+                stats: PrefetchStats::default(),
+            })),
+            used: false,
+        }
+    }
+}
+
+impl PrefetchCache {
+    /// Caps the cached bytes; the values already cached are kept even if
+    /// over a new cap.
+    pub fn set_max_bytes(&self, max_bytes: u64) {
+        self.lock().max_bytes = max_bytes;
+    }
+
+    pub fn stats(&self) -> PrefetchStats {
+        self.lock().stats
+    }
+
+    /// Starts fetching the encoded `keys` not cached yet at `version` in the
+    /// background. A failed fetch caches nothing, and none is started once
+    /// the runtime is shut down.
+    pub fn spawn(
+        &mut self,
+        client: Arc<tikv_client::TransactionClient>,
+        version: u64,
+        keys: Vec<Key>,
+    ) {
+        self.used = true;
+        let keys = {
+            let cache = self.lock();
+            keys.into_iter()
+                .filter(|key| !cache.values.contains_key(key))
+                .collect::<Vec<_>>()
+        };
+        if keys.is_empty() || check_runtime_alive().is_err() {
+            return;
+        }
+        let cache = self.cache.clone();
+        TOKIO_RUNTIME.spawn(async move {
+            let mut snapshot = client.snapshot(
+                Timestamp::from_version(version),
+                TransactionOptions::new_optimistic(),
+            );
+            let mut found = match snapshot.batch_get(keys.clone()).await {
+                Ok(kv_pairs) => kv_pairs
+                    .map(|KvPair(key, value)| (key, value))
+                    .collect::<BTreeMap<_, _>>(),
+                Err(_) => return,
+            };
+            let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+            for key in keys {
+                let value = found.remove(&key);
+                cache.insert(key, value);
+            }
+        });
+    }
+
+    /// The cached stored value of the encoded `key`: `Some(None)` when it is
+    /// known to be absent, `None` when it is not cached.
+    pub fn get(&self, key: &Key) -> Option<Option<Value>> {
+        if !self.used {
+            return None;
+        }
+        let mut cache = self.lock();
+        let value = cache.values.get(key).cloned();
+        if value.is_some() {
+            cache.stats.hits += 1;
+        } else {
+            cache.stats.misses += 1;
+        }
+        value
+    }
+
+    fn lock(&self) -> MutexGuard<Cache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Cache {
+    fn insert(&mut self, key: Key, value: Option<Value>) {
+        if self.values.contains_key(&key) {
+            return;
+        }
+        let size = <&[u8]>::from(&key).len() as u64 + value.as_ref().map_or(0, |v| v.len() as u64);
+        if self.bytes + size > self.max_bytes {
+            self.stats.dropped += 1;
+            return;
+        }
+        self.values.insert(key, value);
+        self.bytes += size;
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Telling the caller when a client gets loaded, so that it can shed load
+//! before its requests start timing out.
+//!
+//! The load is the number of operations in flight, see
+//! [`MetricsSnapshot::in_flight_operations`](super::MetricsSnapshot::in_flight_operations):
+//! neither the pinned tikv-client nor the stable tokio API report how busy
+//! the runtime or PD are.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::warn;
+
+use super::metrics::Metrics;
+
+/// How often the operations in flight are compared with the watermarks.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The least time between two calls of a pressure callback; a level
+/// reached within it is reported once it is over, if still current.
+const MIN_CALLBACK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The level reported to a pressure callback, see
+/// [`TransactionClient::set_pressure_callback`](super::TransactionClient::set_pressure_callback).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PressureLevel {
+    /// The operations in flight fell back to the low watermark.
+    Normal,
+    /// The operations in flight reached the high watermark.
+    High,
+}
+
+/// Called with every change of [`PressureLevel`]; an error is logged.
+pub type PressureCallback = Box<dyn Fn(PressureLevel) -> Result<()> + Send>;
+
+/// A thread of its own calling a pressure callback, stopped when dropped.
+pub(crate) struct PressureMonitor {
+    stop: Arc<AtomicBool>,
+}
+
+impl PressureMonitor {
+    /// Starts watching the operations in flight on `metrics`: the level
+    /// turns high once they reach `high_watermark` and back to normal once
+    /// they fall to `low_watermark`.
+    pub fn start(
+        metrics: Arc<Metrics>,
+        callback: PressureCallback,
+        high_watermark: u64,
+        low_watermark: u64,
+    ) -> Result<Self> {
+        if low_watermark >= high_watermark {
+            bail!(
+                "the low watermark {} must be below the high watermark {}",
+                low_watermark,
+                high_watermark
+            );
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        thread::Builder::new()
+            .name("tikv-pressure".to_owned())
+            .spawn(move || {
+                let mut level = PressureLevel::Normal;
+                let mut reported = PressureLevel::Normal;
+                let mut last_call: Option<Instant> = None;
+                while !stopped.load(Ordering::Relaxed) {
+                    let in_flight = metrics.in_flight_operations();
+                    level = match level {
+                        PressureLevel::Normal if in_flight >= high_watermark => PressureLevel::High,
+                        PressureLevel::High if in_flight <= low_watermark => PressureLevel::Normal,
+                        level => level,
+                    };
+                    let due = last_call.map_or(true, |at| at.elapsed() >= MIN_CALLBACK_INTERVAL);
+                    if level != reported && due {
+                        reported = level;
+                        last_call = Some(Instant::now());
+                        if let Err(e) = callback(level) {
+                            warn!("the pressure callback failed on {:?}: {:?}", level, e);
+                        }
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })?;
+        Ok(PressureMonitor { stop })
+    }
+}
+
+impl Drop for PressureMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,465 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! The transactions begun through a client and not finished yet, and the
+//! strict mode checks built on them; the live snapshots of a client, and
+//! their limit.
+//!
+//! The registries hold weak references only, so a transaction leaves its
+//! registry when it is committed, rolled back or dropped, and a snapshot
+//! when it is dropped, from whatever thread; a failed transaction stays
+//! until it is rolled back. A transaction or snapshot leaked without being
+//! dropped, e.g. by C++ code holding it past an early return, stays, which
+//! is what strict mode and the snapshot limit report.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::warn;
+
+use super::op_log::OpLog;
+use super::Error;
+
+/// How often the reporter of strict mode looks for old transactions.
+const REPORT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The active transactions or live snapshots listed in a message at most.
+const MAX_LISTED: usize = 16;
+
+/// What committing a transaction that buffered nothing does in strict
+/// mode; read-only transactions are never checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmptyCommits {
+    Allow,
+    /// The commit goes ahead and a warning is logged.
+    Warn,
+    /// The commit fails with [`Error::EmptyCommit`](super::Error::EmptyCommit),
+    /// leaving the transaction active.
+    Reject,
+}
+
+/// The checks of strict mode, see
+/// [`TransactionClient::set_strict_mode`](super::TransactionClient::set_strict_mode).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StrictOptions {
+    /// The active transactions past which beginning another logs a warning
+    /// listing them; 0 never warns.
+    pub max_active_transactions: u32,
+    pub empty_commits: EmptyCommits,
+    /// The age past which an active transaction is logged, once, with the
+    /// summary of its operation log; `None` reports none.
+    pub report_age: Option<Duration>,
+}
+
+impl Default for StrictOptions {
+    fn default() -> Self {
+        StrictOptions {
+            max_active_transactions: 64,
+            empty_commits: EmptyCommits::Warn,
+            report_age: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// What the registry knows of a transaction, shared with it.
+pub(crate) struct TxnRecord {
+    started: Instant,
+    start_version: u64,
+    pessimistic: bool,
+    tag: Mutex<Option<String>>,
+    finished: AtomicBool,
+    reported: AtomicBool,
+    /// The operation log of the transaction, here so that the reporter can
+    /// summarize it.
+    op_log: Mutex<Option<OpLog>>,
+}
+
+impl TxnRecord {
+    pub fn new(start_version: u64, pessimistic: bool) -> Self {
+        TxnRecord {
+            started: Instant::now(),
+            start_version,
+            pessimistic,
+            tag: Mutex::default(),
+            finished: AtomicBool::new(false),
+            reported: AtomicBool::new(false),
+            op_log: Mutex::default(),
+        }
+    }
+
+    pub fn set_tag(&self, tag: Option<String>) {
+        *self.tag.lock().unwrap_or_else(PoisonError::into_inner) = tag;
+    }
+
+    /// Takes the transaction out of the registry, once committed or rolled
+    /// back.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    pub fn op_log(&self) -> MutexGuard<Option<OpLog>> {
+        self.op_log.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The tag, kind, start version and age of the transaction, for logs.
+    pub fn describe(&self) -> String {
+        let tag = self.tag.lock().unwrap_or_else(PoisonError::into_inner);
+        format!(
+            "{} ({} at version {}, begun {:.1}s ago)",
+            tag.as_deref().unwrap_or("untagged"),
+            if self.pessimistic {
+                "pessimistic"
+            } else {
+                "optimistic"
+            },
+            self.start_version,
+            self.started.elapsed().as_secs_f64()
+        )
+    }
+
+    fn is_active(&self) -> bool {
+        !self.finished.load(Ordering::Relaxed)
+    }
+}
+
+/// The transactions of a client, shared by the clients derived from it.
+#[derive(Default)]
+pub(crate) struct TxnRegistry {
+    records: Mutex<Vec<Weak<TxnRecord>>>,
+}
+
+impl TxnRegistry {
+    pub fn register(&self, record: TxnRecord) -> Arc<TxnRecord> {
+        let record = Arc::new(record);
+        let mut records = self.lock();
+        records.retain(|record| record.upgrade().map_or(false, |record| record.is_active()));
+        records.push(Arc::downgrade(&record));
+        record
+    }
+
+    /// The active transactions, oldest first.
+    pub fn active(&self) -> Vec<Arc<TxnRecord>> {
+        let mut records = self.lock();
+        records.retain(|record| record.upgrade().map_or(false, |record| record.is_active()));
+        records.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Warns when more than `max_active` transactions are active as another
+    /// begins.
+    pub fn check_active(&self, max_active: u32) {
+        if let Some(warning) = self.active_warning(max_active) {
+            warn!("{}", warning);
+        }
+    }
+
+    /// The warning of [`check_active`](Self::check_active), if any.
+    fn active_warning(&self, max_active: u32) -> Option<String> {
+        if max_active == 0 {
+            return None;
+        }
+        let active = self.active();
+        if active.len() <= max_active as usize {
+            return None;
+        }
+        let listed = active
+            .iter()
+            .take(MAX_LISTED)
+            .map(|record| record.describe())
+            .collect::<Vec<_>>();
+        Some(format!(
+            "beginning a transaction while {} are active, strict mode allows {}; the oldest: {}",
+            active.len(),
+            max_active,
+            listed.join("; ")
+        ))
+    }
+
+    /// The reports of the active transactions at least `report_age` old not
+    /// reported yet, which they then are.
+    fn due_reports(&self, report_age: Duration) -> Vec<String> {
+        let mut reports = Vec::new();
+        for record in self.active() {
+            if record.started.elapsed() < report_age
+                || record.reported.swap(true, Ordering::Relaxed)
+            {
+                continue;
+            }
+            let summary = match &*record.op_log() {
+                Some(op_log) => op_log.summary(),
+                None => "no operation log".to_owned(),
+            };
+            reports.push(format!(
+                "transaction {} is still active after {:?}; {}",
+                record.describe(),
+                report_age,
+                summary
+            ));
+        }
+        reports
+    }
+
+    fn lock(&self) -> MutexGuard<Vec<Weak<TxnRecord>>> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What a snapshot registry knows of a snapshot, held by the snapshot.
+pub(crate) struct SnapshotRecord {
+    created: Instant,
+}
+
+/// The live snapshots of a client, shared by the clients derived from it.
+#[derive(Default)]
+pub(crate) struct SnapshotRegistry {
+    records: Mutex<Vec<Weak<SnapshotRecord>>>,
+    created_total: AtomicU64,
+}
+
+/// The snapshots of a client, see
+/// [`TransactionClient::snapshot_stats`](super::TransactionClient::snapshot_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotStats {
+    /// The snapshots not dropped yet.
+    pub live: u64,
+    /// The age of the oldest live snapshot, zero when there is none.
+    pub oldest_age: Duration,
+    /// The snapshots created since the client connected.
+    pub created_total: u64,
+}
+
+impl SnapshotRegistry {
+    /// Registers a snapshot just created, failing with
+    /// [`Error::TooManySnapshots`] when `max_live` are live already.
+    pub fn register(&self, max_live: Option<u32>) -> Result<Arc<SnapshotRecord>> {
+        let mut records = self.lock();
+        records.retain(|record| record.strong_count() > 0);
+        if let Some(limit) = max_live {
+            if records.len() >= limit as usize {
+                let oldest_ages = records
+                    .iter()
+                    .filter_map(Weak::upgrade)
+                    .take(MAX_LISTED)
+                    .map(|record| record.created.elapsed())
+                    .collect();
+                return Err(Error::TooManySnapshots {
+                    live: records.len() as u64,
+                    limit,
+                    oldest_ages,
+                }
+                .into());
+            }
+        }
+        let record = Arc::new(SnapshotRecord {
+            created: Instant::now(),
+        });
+        records.push(Arc::downgrade(&record));
+        self.created_total.fetch_add(1, Ordering::Relaxed);
+        Ok(record)
+    }
+
+    pub fn stats(&self) -> SnapshotStats {
+        let mut records = self.lock();
+        records.retain(|record| record.strong_count() > 0);
+        SnapshotStats {
+            live: records.len() as u64,
+            oldest_age: records
+                .iter()
+                .find_map(Weak::upgrade)
+                .map_or(Duration::default(), |record| record.created.elapsed()),
+            created_total: self.created_total.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Vec<Weak<SnapshotRecord>>> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A thread of its own logging the transactions of a registry that grow
+/// older than an age, stopped when dropped.
+pub(crate) struct TxnReporter {
+    stop: Arc<AtomicBool>,
+}
+
+impl TxnReporter {
+    pub fn start(registry: Arc<TxnRegistry>, report_age: Duration) -> Result<Self> {
+        if report_age == Duration::from_secs(0) {
+            bail!("the report age of strict mode must be positive");
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        thread::Builder::new()
+            .name("tikv-strict".to_owned())
+            .spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    for report in registry.due_reports(report_age) {
+                        warn!("{}", report);
+                    }
+                    thread::sleep(REPORT_POLL_INTERVAL);
+                }
+            })?;
+        Ok(TxnReporter { stop })
+    }
+}
+
+impl Drop for TxnReporter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_snapshot_limit_counts_live_snapshots_only() {
+        let registry = SnapshotRegistry::default();
+        let first = registry.register(Some(2)).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        let second = registry.register(Some(2)).unwrap();
+        // The registry holds no strong reference.
+        assert_eq!(Arc::strong_count(&first), 1);
+
+        let e = registry.register(Some(2)).map(drop).unwrap_err();
+        match e.downcast_ref::<Error>() {
+            Some(Error::TooManySnapshots {
+                live,
+                limit,
+                oldest_ages,
+            }) => {
+                assert_eq!((*live, *limit), (2, 2));
+                assert_eq!(oldest_ages.len(), 2);
+                assert!(oldest_ages[0] > oldest_ages[1], "{:?}", oldest_ages);
+            }
+            _ => panic!("{:?}", e),
+        }
+        assert!(
+            e.to_string().starts_with("too many snapshots: 2 are live"),
+            "{}",
+            e
+        );
+        // Unlimited, or limited anew, registering goes on.
+        let third = registry.register(None).unwrap();
+
+        drop(first);
+        let age = second.created.elapsed();
+        let stats = registry.stats();
+        assert_eq!((stats.live, stats.created_total), (2, 3));
+        assert!(stats.oldest_age >= age);
+        drop(second);
+        drop(third);
+        assert!(registry.register(Some(1)).is_ok());
+        assert_eq!(registry.stats().created_total, 4);
+    }
+
+    #[test]
+    fn snapshots_dropped_on_other_threads_leave_the_registry() {
+        let registry = Arc::new(SnapshotRegistry::default());
+        let threads = (0..8)
+            .map(|_| {
+                let registry = registry.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        drop(registry.register(Some(12)).unwrap());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let records = (0..4)
+            .map(|_| registry.register(None).unwrap())
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let stats = registry.stats();
+        assert_eq!((stats.live, stats.created_total), (4, 804));
+        let handles = records
+            .into_iter()
+            .map(|record| thread::spawn(move || drop(record)))
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(registry.stats().live, 0);
+        assert_eq!(registry.stats().oldest_age, Duration::default());
+    }
+
+    #[test]
+    fn beginning_past_the_active_limit_lists_the_oldest() {
+        let registry = TxnRegistry::default();
+        let records = (1..=3)
+            .map(|start_version| registry.register(TxnRecord::new(start_version, false)))
+            .collect::<Vec<_>>();
+        records[0].set_tag(Some("first".to_owned()));
+        assert_eq!(registry.active_warning(3), None);
+        assert_eq!(registry.active_warning(0), None);
+        let warning = registry.active_warning(2).unwrap();
+        assert!(
+            warning.starts_with(
+                "beginning a transaction while 3 are active, strict mode allows 2; \
+                 the oldest: first (optimistic at version 1, begun "
+            ),
+            "{}",
+            warning
+        );
+        assert!(
+            warning.contains("; untagged (optimistic at version 3"),
+            "{}",
+            warning
+        );
+
+        let many = (0..MAX_LISTED)
+            .map(|_| registry.register(TxnRecord::new(9, true)))
+            .collect::<Vec<_>>();
+        let warning = registry.active_warning(1).unwrap();
+        assert_eq!(warning.matches(" ago)").count(), MAX_LISTED);
+        drop(many);
+        records[2].finish();
+        assert_eq!(registry.active_warning(2), None);
+    }
+
+    #[test]
+    fn old_transactions_are_reported_once() {
+        let registry = TxnRegistry::default();
+        let old = registry.register(TxnRecord::new(1, true));
+        thread::sleep(Duration::from_millis(20));
+        let young = registry.register(TxnRecord::new(2, false));
+        let reports = registry.due_reports(Duration::from_millis(10));
+        assert_eq!(reports.len(), 1);
+        assert!(
+            reports[0].starts_with("transaction untagged (pessimistic at version 1, begun "),
+            "{}",
+            reports[0]
+        );
+        assert!(reports[0].ends_with("; no operation log"), "{}", reports[0]);
+        assert!(registry.due_reports(Duration::from_millis(10)).is_empty());
+        // The young one is reported in turn once old enough.
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(registry.due_reports(Duration::from_millis(10)).len(), 1);
+        drop((old, young));
+    }
+
+    #[test]
+    fn finished_and_dropped_transactions_leave_the_registry() {
+        let registry = TxnRegistry::default();
+        let committed = registry.register(TxnRecord::new(1, false));
+        let dropped = registry.register(TxnRecord::new(2, true));
+        let active = registry.register(TxnRecord::new(3, false));
+        committed.finish();
+        drop(dropped);
+        let listed = registry.active();
+        assert_eq!(listed.len(), 1);
+        assert!(Arc::ptr_eq(&listed[0], &active));
+        active.set_tag(Some("import".to_owned()));
+        assert!(
+            active
+                .describe()
+                .starts_with("import (optimistic at version 3, begun "),
+            "{}",
+            active.describe()
+        );
+    }
+}
@@ -0,0 +1,548 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! One facade over several clients, each owning the keys under a prefix of
+//! its own, e.g. to split data across clusters.
+//!
+//! Keys are handed to the client owning them as they are, prefix included.
+//! A transaction is bound to the client of the first key it touches and
+//! rejects the keys of the others with [`Error::CrossRoute`]: atomicity
+//! across clusters is out of scope. A snapshot holds one snapshot per
+//! client, each at a timestamp of its own cluster, so reads of different
+//! routes are not of one point in time.
+
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use tikv_client::{KvPair, Value};
+
+use super::busy::{BusyFlag, BusyGuard};
+use super::metrics::Metrics;
+use super::namespace::prefix_end;
+use super::{
+    as_ref_bound, format_key, is_empty_range, to_owned_bound, Error, Snapshot, Transaction,
+    TransactionClient,
+};
+
+struct Route {
+    prefix: Vec<u8>,
+    client: TransactionClient,
+}
+
+struct Routes {
+    /// Sorted by prefix. No prefix starts another, so the ranges they own
+    /// are disjoint and in the same order.
+    routes: Vec<Route>,
+    split_scans: bool,
+    /// Only counts the calls of routed handles in flight, for their busy
+    /// guards; the clients behind them do not see those calls.
+    metrics: Arc<Metrics>,
+}
+
+/// A range clamped to the keys of one route.
+type Part = (usize, Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+impl Routes {
+    fn prefixes(&self) -> impl Iterator<Item = &[u8]> {
+        self.routes.iter().map(|route| route.prefix.as_slice())
+    }
+
+    fn route_of(&self, key: &[u8]) -> Result<usize> {
+        route_of(self.prefixes(), key).ok_or_else(|| {
+            Error::NoRoute {
+                key: key.to_owned(),
+            }
+            .into()
+        })
+    }
+
+    /// The parts of the range owned by each route, in key order; keys owned
+    /// by no route are left out.
+    fn split(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Vec<Part> {
+        split(self.prefixes(), start, end)
+    }
+
+    fn cross_route(&self, bound: usize, wanted: usize) -> anyhow::Error {
+        Error::CrossRoute {
+            bound_prefix: self.routes[bound].prefix.clone(),
+            prefix: self.routes[wanted].prefix.clone(),
+        }
+        .into()
+    }
+}
+
+/// Delegates every operation to the [`TransactionClient`] owning the prefix
+/// of its keys, see the [module documentation](self).
+pub struct RoutedClient {
+    routes: Arc<Routes>,
+}
+
+impl RoutedClient {
+    /// Creates a facade without routes, which are added with
+    /// [`add_route`](Self::add_route). With `split_scans`, snapshot scans
+    /// spanning several prefixes are split into a scan per client and
+    /// merged; otherwise they fail.
+    pub fn new(split_scans: bool) -> Self {
+        RoutedClient {
+            routes: Arc::new(Routes {
+                routes: Vec::new(),
+                split_scans,
+                metrics: Arc::default(),
+            }),
+        }
+    }
+
+    /// Routes the keys under each prefix of `routes` to its client.
+    pub fn with_routes(
+        routes: Vec<(Vec<u8>, TransactionClient)>,
+        split_scans: bool,
+    ) -> Result<Self> {
+        let mut client = Self::new(split_scans);
+        for (prefix, route_client) in routes {
+            client.add_route(&prefix, route_client)?;
+        }
+        Ok(client)
+    }
+
+    /// Routes the keys under `prefix` to `client`. The prefix must neither
+    /// start nor be started by the prefix of another route, and routes can
+    /// only be added while no routed transaction or snapshot is open.
+    pub fn add_route(&mut self, prefix: &[u8], client: TransactionClient) -> Result<()> {
+        let routes = Arc::get_mut(&mut self.routes).ok_or_else(|| {
+            anyhow!("routes cannot be added while routed transactions or snapshots are open")
+        })?;
+        if let Some(route) = routes
+            .routes
+            .iter()
+            .find(|route| route.prefix.starts_with(prefix) || prefix.starts_with(&route.prefix))
+        {
+            bail!(
+                "the prefix {} overlaps the prefix {} of another route",
+                format_key(prefix),
+                format_key(&route.prefix)
+            );
+        }
+        let index = routes
+            .routes
+            .partition_point(|route| route.prefix.as_slice() < prefix);
+        routes.routes.insert(
+            index,
+            Route {
+                prefix: prefix.to_owned(),
+                client,
+            },
+        );
+        Ok(())
+    }
+
+    /// Begins a transaction, on the client of the first key it touches.
+    pub fn begin_optimistic(&self) -> RoutedTransaction {
+        RoutedTransaction::new(self.routes.clone(), false)
+    }
+
+    pub fn begin_pessimistic(&self) -> RoutedTransaction {
+        RoutedTransaction::new(self.routes.clone(), true)
+    }
+
+    /// Creates a snapshot of every client, each at a fresh timestamp of its
+    /// own cluster.
+    pub fn snapshot(&self) -> Result<RoutedSnapshot> {
+        let snapshots = self
+            .routes
+            .routes
+            .iter()
+            .map(|route| route.client.snapshot())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RoutedSnapshot {
+            routes: self.routes.clone(),
+            snapshots,
+            busy: BusyFlag::default(),
+        })
+    }
+}
+
+/// A transaction of a [`RoutedClient`], begun on the client of the first
+/// key it touches and bound to it from then on.
+pub struct RoutedTransaction {
+    routes: Arc<Routes>,
+    pessimistic: bool,
+    bound: Option<(usize, Transaction)>,
+    busy: BusyFlag,
+}
+
+impl RoutedTransaction {
+    fn new(routes: Arc<Routes>, pessimistic: bool) -> Self {
+        RoutedTransaction {
+            routes,
+            pessimistic,
+            bound: None,
+            busy: BusyFlag::default(),
+        }
+    }
+
+    /// Marks the transaction as in use until the guard is dropped. Taken by
+    /// the FFI entry points.
+    pub(crate) fn enter(&self) -> Result<BusyGuard> {
+        self.busy.enter("routed transaction", &self.routes.metrics)
+    }
+
+    /// The prefix of the route the transaction is bound to, if any yet.
+    pub fn bound_prefix(&self) -> Option<&[u8]> {
+        self.bound
+            .as_ref()
+            .map(|&(route, _)| self.routes.routes[route].prefix.as_slice())
+    }
+
+    /// The transaction of `route`, begun if the transaction is not bound
+    /// yet; fails with [`Error::CrossRoute`] if bound to another one.
+    fn transaction(&mut self, route: usize) -> Result<&mut Transaction> {
+        match &self.bound {
+            Some((bound, _)) if *bound != route => {
+                return Err(self.routes.cross_route(*bound, route))
+            }
+            Some(_) => {}
+            None => {
+                let client = &self.routes.routes[route].client;
+                let txn = if self.pessimistic {
+                    client.begin_pessimistic()?
+                } else {
+                    client.begin_optimistic()?
+                };
+                self.bound = Some((route, txn));
+            }
+        }
+        Ok(&mut self.bound.as_mut().expect("bound above").1)
+    }
+
+    fn transaction_of(&mut self, key: &[u8]) -> Result<&mut Transaction> {
+        let route = self.routes.route_of(key)?;
+        self.transaction(route)
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        self.transaction_of(key)?.get(key)
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.transaction_of(key)?.put(key, value)
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.transaction_of(key)?.delete(key)
+    }
+
+    /// Scans the range, which must be owned by a single route whatever the
+    /// `split_scans` of the client: reading another cluster would leave the
+    /// transaction.
+    pub fn scan(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        let mut parts = self.routes.split(start, end);
+        if parts.len() > 1 {
+            let bound = self.bound.as_ref().map_or(parts[0].0, |&(bound, _)| bound);
+            let wanted = parts
+                .iter()
+                .map(|&(route, _, _)| route)
+                .find(|&route| route != bound)
+                .expect("parts are of distinct routes");
+            return Err(self.routes.cross_route(bound, wanted));
+        }
+        match parts.pop() {
+            Some((route, start, end)) => {
+                self.transaction(route)?
+                    .scan(as_ref_bound(&start), as_ref_bound(&end), limit)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Commits the transaction of the bound route, or does nothing and
+    /// returns 0 when no key was touched.
+    pub fn commit(&mut self) -> Result<u64> {
+        match &mut self.bound {
+            Some((_, txn)) => txn.commit(),
+            None => Ok(0),
+        }
+    }
+
+    pub fn rollback(&mut self) -> Result<()> {
+        match &mut self.bound {
+            Some((_, txn)) => txn.rollback(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A snapshot of a [`RoutedClient`], reading each key from the snapshot of
+/// the client owning it.
+pub struct RoutedSnapshot {
+    routes: Arc<Routes>,
+    /// By route.
+    snapshots: Vec<Snapshot>,
+    busy: BusyFlag,
+}
+
+impl RoutedSnapshot {
+    /// Marks the snapshot as in use until the guard is dropped. Taken by the
+    /// FFI entry points.
+    pub(crate) fn enter(&self) -> Result<BusyGuard> {
+        self.busy.enter("routed snapshot", &self.routes.metrics)
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        let route = self.routes.route_of(key)?;
+        self.snapshots[route].get(key)
+    }
+
+    /// Gets the values of `keys` from the client of each, sorted by key;
+    /// keys that do not exist are left out.
+    pub fn batch_get<'a>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<Vec<KvPair>> {
+        let mut by_route = vec![Vec::new(); self.snapshots.len()];
+        for key in keys {
+            by_route[self.routes.route_of(key)?].push(key);
+        }
+        let mut kv_pairs = Vec::new();
+        for (snapshot, keys) in self.snapshots.iter_mut().zip(by_route) {
+            if !keys.is_empty() {
+                kv_pairs.extend(snapshot.batch_get(keys)?);
+            }
+        }
+        // The routes are in key order, and so is each result.
+        Ok(kv_pairs)
+    }
+
+    /// Scans up to `limit` pairs of the range, or all of them when `limit`
+    /// is 0. A range spanning several routes is scanned route by route and
+    /// merged if the client splits scans, and fails otherwise.
+    pub fn scan(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        let parts = self.routes.split(start, end);
+        if parts.len() > 1 && !self.routes.split_scans {
+            bail!(
+                "the scan spans the prefixes of {} routes; the routed client must split scans \
+                 to allow it",
+                parts.len()
+            );
+        }
+        let mut kv_pairs = Vec::new();
+        for (route, start, end) in parts {
+            let left = match limit {
+                0 => 0,
+                limit => match limit - kv_pairs.len() as u32 {
+                    0 => break,
+                    left => left,
+                },
+            };
+            kv_pairs.extend(self.snapshots[route].scan(
+                as_ref_bound(&start),
+                as_ref_bound(&end),
+                left,
+            )?);
+        }
+        Ok(kv_pairs)
+    }
+}
+
+/// The index of the prefix `key` starts with, if any.
+fn route_of<'a>(mut prefixes: impl Iterator<Item = &'a [u8]>, key: &[u8]) -> Option<usize> {
+    prefixes.position(|prefix| key.starts_with(prefix))
+}
+
+/// The parts of the range under each of `prefixes`, sorted and disjoint, by
+/// index of the prefix.
+fn split<'a>(
+    prefixes: impl Iterator<Item = &'a [u8]>,
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+) -> Vec<Part> {
+    let mut parts = Vec::new();
+    for (index, prefix) in prefixes.enumerate() {
+        let route_end = prefix_end(prefix);
+        let part_start = max_start(start, Bound::Included(prefix));
+        let part_end = min_end(
+            end,
+            route_end
+                .as_deref()
+                .map_or(Bound::Unbounded, Bound::Excluded),
+        );
+        if !is_empty_range(part_start, part_end) {
+            parts.push((index, to_owned_bound(part_start), to_owned_bound(part_end)));
+        }
+    }
+    parts
+}
+
+/// The greater of two start bounds.
+fn max_start<'a>(a: Bound<&'a [u8]>, b: Bound<&'a [u8]>) -> Bound<&'a [u8]> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(x), Bound::Included(y)) => Bound::Included(x.max(y)),
+        (Bound::Excluded(x), Bound::Included(y)) | (Bound::Included(y), Bound::Excluded(x)) => {
+            match x.cmp(y) {
+                Ordering::Less => Bound::Included(y),
+                Ordering::Equal | Ordering::Greater => Bound::Excluded(x),
+            }
+        }
+        (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(x.max(y)),
+    }
+}
+
+/// The lesser of two end bounds.
+fn min_end<'a>(a: Bound<&'a [u8]>, b: Bound<&'a [u8]>) -> Bound<&'a [u8]> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(x), Bound::Included(y)) => Bound::Included(x.min(y)),
+        (Bound::Excluded(x), Bound::Included(y)) | (Bound::Included(y), Bound::Excluded(x)) => {
+            match x.cmp(y) {
+                Ordering::Greater => Bound::Included(y),
+                Ordering::Equal | Ordering::Less => Bound::Excluded(x),
+            }
+        }
+        (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(x.min(y)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIXES: [&[u8]; 3] = [b"a/", b"b/", b"c\xff"];
+
+    fn owned(bound: Bound<&[u8]>) -> Bound<Vec<u8>> {
+        to_owned_bound(bound)
+    }
+
+    #[test]
+    fn keys_go_to_the_route_of_their_prefix() {
+        let route = |key: &[u8]| route_of(PREFIXES.iter().copied(), key);
+        assert_eq!(route(b"a/"), Some(0));
+        assert_eq!(route(b"a/key"), Some(0));
+        assert_eq!(route(b"b/\xff"), Some(1));
+        assert_eq!(route(b"c\xff\x00"), Some(2));
+        for key in [&b""[..], b"a", b"a0", b"b", b"c", b"c\xfe"].iter() {
+            assert_eq!(route(key), None, "{:?}", key);
+        }
+        assert_eq!(route_of(std::iter::empty(), b"a/"), None);
+    }
+
+    #[test]
+    fn ranges_split_into_the_parts_of_each_route() {
+        let split = |start, end| split(PREFIXES.iter().copied(), start, end);
+        assert_eq!(
+            split(Bound::Unbounded, Bound::Unbounded),
+            [
+                (
+                    0,
+                    owned(Bound::Included(b"a/")),
+                    owned(Bound::Excluded(b"a0"))
+                ),
+                (
+                    1,
+                    owned(Bound::Included(b"b/")),
+                    owned(Bound::Excluded(b"b0"))
+                ),
+                (
+                    2,
+                    owned(Bound::Included(b"c\xff")),
+                    owned(Bound::Excluded(b"d"))
+                ),
+            ]
+        );
+        assert_eq!(
+            split(Bound::Excluded(b"a/m"), Bound::Included(b"b/m")),
+            [
+                (
+                    0,
+                    owned(Bound::Excluded(b"a/m")),
+                    owned(Bound::Excluded(b"a0"))
+                ),
+                (
+                    1,
+                    owned(Bound::Included(b"b/")),
+                    owned(Bound::Included(b"b/m"))
+                ),
+            ]
+        );
+        assert_eq!(
+            split(Bound::Included(b"b/x"), Bound::Excluded(b"b/y")),
+            [(
+                1,
+                owned(Bound::Included(b"b/x")),
+                owned(Bound::Excluded(b"b/y"))
+            )]
+        );
+        // Keys between two prefixes, and empty ranges, belong to no route.
+        assert!(split(Bound::Included(b"a0"), Bound::Excluded(b"b/")).is_empty());
+        assert!(split(Bound::Included(b"b/y"), Bound::Excluded(b"b/x")).is_empty());
+    }
+
+    #[test]
+    fn a_route_of_0xff_bytes_runs_to_the_end() {
+        let prefixes: [&[u8]; 2] = [b"a", b"\xff"];
+        assert_eq!(
+            split(
+                prefixes.iter().copied(),
+                Bound::Included(b"b"),
+                Bound::Unbounded
+            ),
+            [(1, owned(Bound::Included(b"\xff")), Bound::Unbounded)]
+        );
+    }
+
+    #[test]
+    fn bounds_clamp_to_the_tighter_one() {
+        let (a, b) = (&b"a"[..], &b"b"[..]);
+        assert_eq!(
+            max_start(Bound::Unbounded, Bound::Excluded(a)),
+            Bound::Excluded(a)
+        );
+        assert_eq!(
+            max_start(Bound::Included(a), Bound::Included(b)),
+            Bound::Included(b)
+        );
+        assert_eq!(
+            max_start(Bound::Included(a), Bound::Excluded(a)),
+            Bound::Excluded(a)
+        );
+        assert_eq!(
+            max_start(Bound::Excluded(a), Bound::Included(b)),
+            Bound::Included(b)
+        );
+        assert_eq!(
+            max_start(Bound::Excluded(b), Bound::Excluded(a)),
+            Bound::Excluded(b)
+        );
+
+        assert_eq!(
+            min_end(Bound::Included(a), Bound::Unbounded),
+            Bound::Included(a)
+        );
+        assert_eq!(
+            min_end(Bound::Included(a), Bound::Included(b)),
+            Bound::Included(a)
+        );
+        assert_eq!(
+            min_end(Bound::Included(b), Bound::Excluded(b)),
+            Bound::Excluded(b)
+        );
+        assert_eq!(
+            min_end(Bound::Excluded(b), Bound::Included(a)),
+            Bound::Included(a)
+        );
+        assert_eq!(
+            min_end(Bound::Excluded(b), Bound::Excluded(a)),
+            Bound::Excluded(a)
+        );
+    }
+}
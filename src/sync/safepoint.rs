@@ -0,0 +1,77 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Service GC safepoints, which keep GC from collecting the versions a
+//! long-running reader such as an analytical snapshot still needs.
+//!
+//! PD keeps the GC safepoint below every registered service safepoint until
+//! its TTL expires, so a registration has to be renewed for as long as it is
+//! needed. The pinned tikv-client only exposes updating the GC safepoint
+//! itself, not the service safepoint request, so registering currently
+//! always fails; the API is in place for when it does.
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use super::capabilities::unsupported;
+use super::{Capability, TransactionClient};
+
+/// A service safepoint registration, renewed in the background until the
+/// guard is released or dropped.
+pub struct SafepointGuard {
+    service_id: String,
+    released: bool,
+}
+
+impl SafepointGuard {
+    pub fn service_id(&self) -> &str {
+        &self.service_id
+    }
+
+    /// Whether the last renewal succeeded; a guard that is not healthy may no
+    /// longer be holding GC back.
+    pub fn healthy(&self) -> bool {
+        !self.released
+    }
+
+    /// Unregisters the safepoint; releasing twice does nothing.
+    pub fn release(&mut self) -> Result<()> {
+        self.released = true;
+        Ok(())
+    }
+}
+
+impl Drop for SafepointGuard {
+    /// Stops the renewals without unregistering, which would block: the
+    /// registration then lapses at the end of its TTL.
+    fn drop(&mut self) {
+        self.released = true;
+    }
+}
+
+impl TransactionClient {
+    /// Registers `safepoint` for `service_id` with a TTL of `ttl`, renewed
+    /// on the shared runtime for as long as the returned guard lives.
+    ///
+    /// Needs [`Capability::ServiceSafepoint`]: this always fails.
+    pub fn register_service_safepoint(
+        &self,
+        service_id: &str,
+        safepoint: u64,
+        ttl: Duration,
+    ) -> Result<SafepointGuard> {
+        if service_id.is_empty() {
+            bail!("service_id must not be empty");
+        }
+        if ttl.as_secs() == 0 {
+            bail!("the ttl of a service safepoint must be at least a second");
+        }
+        Err(unsupported(
+            Capability::ServiceSafepoint,
+            format!(
+                "registering service safepoint {} for {:?}",
+                safepoint, service_id
+            ),
+        ))
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A sequence of connectivity checks telling which part of a deployment is
+//! at fault when requests fail with generic gRPC errors.
+
+use std::future::Future;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use tikv_client::{TimestampExt, TransactionOptions};
+
+use super::{check_runtime_alive, from_tikv_error, TransactionClient, TOKIO_RUNTIME};
+
+/// The key written and deleted again by the write check, under the
+/// [`RESERVED_PREFIX`](super::RESERVED_PREFIX), followed by the local time so
+/// that concurrent checks do not conflict.
+const PROBE_KEY_PREFIX: &[u8] = b"\x00__tikv_cpp__self_check\x00";
+
+/// The outcome of one check of [`TransactionClient::self_check`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckItem {
+    pub name: String,
+    pub ok: bool,
+    /// What was found, or the error the check failed with.
+    pub detail: String,
+}
+
+impl CheckItem {
+    fn new(name: String, result: Result<String>) -> Self {
+        match result {
+            Ok(detail) => CheckItem {
+                name,
+                ok: true,
+                detail,
+            },
+            Err(e) => CheckItem {
+                name,
+                ok: false,
+                detail: format!("{:#}", e),
+            },
+        }
+    }
+}
+
+impl TransactionClient {
+    /// Checks, each within `timeout`: that every PD endpoint resolves and
+    /// accepts TCP connections, that PD hands out a timestamp, and that a
+    /// TiKV store answers a read; when `write_probe` is set, also that a key
+    /// under a reserved prefix can be written and deleted again.
+    ///
+    /// Every check runs even once one failed. The pinned tikv-client cannot
+    /// list the stores nor tell their addresses, so stores are only reached
+    /// through the read of a key, wherever its region is.
+    pub fn self_check(&self, timeout: Duration, write_probe: bool) -> Vec<CheckItem> {
+        let mut items = Vec::new();
+        for endpoint in self.pd_endpoints() {
+            let addrs = resolve(endpoint);
+            let connect = match &addrs {
+                Ok(addrs) => connect(addrs, timeout),
+                Err(_) => Err(anyhow!("skipped, the endpoint did not resolve")),
+            };
+            let addrs = addrs.map(|addrs| {
+                addrs
+                    .iter()
+                    .map(SocketAddr::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            });
+            items.push(CheckItem::new(format!("resolve {}", endpoint), addrs));
+            items.push(CheckItem::new(format!("connect {}", endpoint), connect));
+        }
+        let inner = self.inner();
+        let probe_key = self.namespace().encode(&probe_key());
+        items.push(CheckItem::new(
+            "fetch a timestamp from PD".to_owned(),
+            with_timeout(timeout, async {
+                let timestamp = inner.current_timestamp().await?;
+                Ok(format!("version {}", timestamp.version()))
+            }),
+        ));
+        items.push(CheckItem::new(
+            "read from a TiKV store".to_owned(),
+            with_timeout(timeout, async {
+                let timestamp = inner.current_timestamp().await?;
+                let mut snapshot = inner.snapshot(timestamp, TransactionOptions::new_optimistic());
+                snapshot.get(probe_key.clone()).await?;
+                Ok("read succeeded".to_owned())
+            }),
+        ));
+        if write_probe {
+            items.push(CheckItem::new(
+                "write and delete a key".to_owned(),
+                with_timeout(timeout, async {
+                    let mut txn = inner.begin_optimistic().await?;
+                    txn.put(probe_key.clone(), b"self_check".to_vec()).await?;
+                    txn.commit().await?;
+                    let mut txn = inner.begin_optimistic().await?;
+                    txn.delete(probe_key.clone()).await?;
+                    txn.commit().await?;
+                    Ok("round trip succeeded".to_owned())
+                }),
+            ));
+        }
+        items
+    }
+}
+
+fn probe_key() -> Vec<u8> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let mut key = PROBE_KEY_PREFIX.to_vec();
+    key.extend_from_slice(&now.to_be_bytes());
+    key
+}
+
+/// Resolves a PD endpoint, given as `host:port` with an optional URL scheme.
+fn resolve(endpoint: &str) -> Result<Vec<SocketAddr>> {
+    let host_port = endpoint
+        .trim_start_matches("http://")
+        .trim_start_matches("https://")
+        .trim_end_matches('/');
+    let addrs = host_port.to_socket_addrs()?.collect::<Vec<_>>();
+    if addrs.is_empty() {
+        bail!("{} resolved to no address", host_port);
+    }
+    Ok(addrs)
+}
+
+/// Connects to the first of `addrs` that accepts a connection.
+fn connect(addrs: &[SocketAddr], timeout: Duration) -> Result<String> {
+    let mut errors = Vec::new();
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, timeout) {
+            Ok(_) => return Ok(format!("connected to {}", addr)),
+            Err(e) => errors.push(format!("{}: {}", addr, e)),
+        }
+    }
+    bail!("no address accepted a connection ({})", errors.join("; "))
+}
+
+fn with_timeout(
+    timeout: Duration,
+    check: impl Future<Output = tikv_client::Result<String>>,
+) -> Result<String> {
+    check_runtime_alive()?;
+    match TOKIO_RUNTIME.block_on(tokio::time::timeout(timeout, check)) {
+        Ok(result) => result.map_err(from_tikv_error),
+        Err(_) => bail!("timed out after {:?}", timeout),
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Point reads of many keys, served by scans where the keys are dense
+//! enough for one to be cheaper than a batch get.
+//!
+//! How many keys lie between two requested ones is not known before reading
+//! them, so density is judged from the keys themselves: keys of the same
+//! length differing only in their last 8 bytes are read as integers, e.g.
+//! big-endian ids under a common prefix, and a run of them is dense when
+//! the keys asked for fill at least the configured share of the integers
+//! it spans. Other keys, such as variable-length ones, always go to the
+//! batch get.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+use tikv_client::{KvPair, Value};
+
+use super::Snapshot;
+
+/// The trailing bytes of a key read as an integer to measure density.
+const SUFFIX_BYTES: usize = 8;
+
+/// How [`Snapshot::smart_get`] decides between scans and a batch get.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SmartGetOptions {
+    /// The least share, in percent, of the keys a run spans that must be
+    /// asked for to scan it.
+    pub density_percent: u32,
+    /// The fewest keys worth a scan of their own.
+    pub min_run_keys: u32,
+}
+
+impl Default for SmartGetOptions {
+    fn default() -> Self {
+        SmartGetOptions {
+            density_percent: 50,
+            min_run_keys: 4,
+        }
+    }
+}
+
+impl SmartGetOptions {
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.density_percent == 0 || self.density_percent > 100 {
+            bail!(
+                "the density threshold must be within 1% and 100%, not {}%",
+                self.density_percent
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// Gets the values of `keys`, in the same order, `None` for those that
+    /// do not exist: the dense runs of the sorted keys, as set by
+    /// [`set_smart_get_options`](Self::set_smart_get_options), are read by a
+    /// scan from their first key to their last, the rest by one batch get.
+    ///
+    /// A scan reads the pairs between the keys asked for too, up to as many
+    /// as the run spans; when the range holds more, e.g. keys of other
+    /// lengths, the keys of the run it did not reach go to the batch get.
+    /// Which way each key was served is counted in
+    /// [`MetricsSnapshot::smart_get_scanned_keys`](super::MetricsSnapshot::smart_get_scanned_keys)
+    /// and the counters after it.
+    pub fn smart_get(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Value>>> {
+        let mut sorted = keys.to_vec();
+        sorted.sort_unstable();
+        sorted.dedup();
+        let options = self.smart_get_options();
+        let mut found = BTreeMap::new();
+        let mut batched = Vec::new();
+        for run in dense_runs(&sorted, options.density_percent) {
+            if run.len() < options.min_run_keys as usize {
+                batched.extend_from_slice(run);
+                continue;
+            }
+            let (first, last) = (run[0], run[run.len() - 1]);
+            let limit = distance(first, last)
+                .unwrap_or(0)
+                .saturating_add(1)
+                .min(u32::MAX as u64);
+            let kv_pairs =
+                self.scan(Bound::Included(first), Bound::Included(last), limit as u32)?;
+            let reached = match kv_pairs.last() {
+                Some(KvPair(key, _)) if kv_pairs.len() == limit as usize => {
+                    Some(Vec::<u8>::from(key.clone()))
+                }
+                _ => None,
+            };
+            let mut scanned = 0;
+            let mut wasted = 0;
+            for KvPair(key, value) in kv_pairs {
+                let key = Vec::<u8>::from(key);
+                if run.binary_search(&key.as_slice()).is_ok() {
+                    found.insert(key, value);
+                } else {
+                    wasted += 1;
+                }
+            }
+            for &key in run {
+                match &reached {
+                    Some(reached) if key > reached.as_slice() => batched.push(key),
+                    _ => scanned += 1,
+                }
+            }
+            self.metrics().record_smart_get_scan(scanned, wasted);
+        }
+        if !batched.is_empty() {
+            self.metrics().record_smart_get_batch(batched.len() as u64);
+            for KvPair(key, value) in self.batch_get(batched.iter().copied())? {
+                found.insert(key.into(), value);
+            }
+        }
+        Ok(keys.iter().map(|&key| found.get(key).cloned()).collect())
+    }
+}
+
+/// Splits sorted, distinct `keys` into runs, each growing while its keys
+/// fill at least `density_percent` of the integers it spans.
+fn dense_runs<'a, 'k>(keys: &'a [&'k [u8]], density_percent: u32) -> Vec<&'a [&'k [u8]]> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for end in 1..=keys.len() {
+        let dense = end < keys.len()
+            && distance(keys[start], keys[end]).map_or(false, |span| {
+                (end - start + 1) as u128 * 100 >= density_percent as u128 * (span as u128 + 1)
+            });
+        if !dense {
+            runs.push(&keys[start..end]);
+            start = end;
+        }
+    }
+    runs
+}
+
+/// How far `b` is after `a` when both have the same length and differ only
+/// in their last [`SUFFIX_BYTES`] bytes, read as big-endian integers.
+fn distance(a: &[u8], b: &[u8]) -> Option<u64> {
+    if a.len() != b.len() {
+        return None;
+    }
+    let split = a.len().saturating_sub(SUFFIX_BYTES);
+    if a[..split] != b[..split] {
+        return None;
+    }
+    suffix_value(&b[split..]).checked_sub(suffix_value(&a[split..]))
+}
+
+fn suffix_value(suffix: &[u8]) -> u64 {
+    suffix
+        .iter()
+        .fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A key of prefix `p` and id `id` in big-endian.
+    fn id(id: u64) -> Vec<u8> {
+        let mut key = b"p".to_vec();
+        key.extend_from_slice(&id.to_be_bytes());
+        key
+    }
+
+    fn runs(keys: &[Vec<u8>], density_percent: u32) -> Vec<Vec<Vec<u8>>> {
+        let keys = keys.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        dense_runs(&keys, density_percent)
+            .into_iter()
+            .map(|run| run.iter().map(|key| key.to_vec()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn distance_needs_one_length_and_prefix() {
+        assert_eq!(distance(&id(3), &id(3)), Some(0));
+        assert_eq!(distance(&id(3), &id(10)), Some(7));
+        assert_eq!(distance(&id(0), &id(u64::MAX)), Some(u64::MAX));
+        assert_eq!(distance(&id(10), &id(3)), None);
+        assert_eq!(
+            distance(&id(3), &[b"q".as_ref(), &10u64.to_be_bytes()].concat()),
+            None
+        );
+        assert_eq!(distance(&id(3), &3u64.to_be_bytes()), None);
+        assert_eq!(distance(b"a", b"c"), Some(2));
+        assert_eq!(distance(b"", b""), Some(0));
+    }
+
+    #[test]
+    fn no_keys_make_no_runs() {
+        assert!(runs(&[], 50).is_empty());
+    }
+
+    #[test]
+    fn a_single_key_is_a_run_of_its_own() {
+        assert_eq!(runs(&[id(7)], 50), [vec![id(7)]]);
+        assert_eq!(runs(&[id(7)], 100), [vec![id(7)]]);
+    }
+
+    #[test]
+    fn adjacent_keys_make_one_run() {
+        let keys = (1..=5).map(id).collect::<Vec<_>>();
+        assert_eq!(runs(&keys, 100), [keys.clone()]);
+        assert_eq!(runs(&keys, 50), [keys]);
+    }
+
+    #[test]
+    fn a_gap_below_the_density_splits_the_run() {
+        let keys = [id(1), id(2), id(100), id(101)];
+        assert_eq!(
+            runs(&keys, 50),
+            [vec![id(1), id(2)], vec![id(100), id(101)]]
+        );
+        // Two keys of three integers fill 67% of them.
+        let keys = [id(1), id(3)];
+        assert_eq!(runs(&keys, 66), [keys.to_vec()]);
+        assert_eq!(runs(&keys, 67), [vec![id(1)], vec![id(3)]]);
+        // Keys of other lengths or prefixes are never in the same run.
+        let keys = [id(1), [b"p".as_ref(), &2u64.to_be_bytes(), b"x"].concat()];
+        assert_eq!(
+            runs(&keys, 1),
+            [vec![keys[0].clone()], vec![keys[1].clone()]]
+        );
+    }
+
+    #[test]
+    fn unsorted_keys_split_where_they_go_down() {
+        let keys = [id(3), id(1), id(2), id(4)];
+        assert_eq!(runs(&keys, 50), [vec![id(3)], vec![id(1), id(2), id(4)]]);
+    }
+}
@@ -0,0 +1,1094 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use tikv_client::{Key, KvPair, Value};
+
+use super::busy::{BusyFlag, BusyGuard};
+use super::chunked::{chunk_key, key_bytes, pair_key, scan_hiding_chunks, Manifest};
+use super::failover::FailoverWatch;
+use super::metrics::{Metrics, ReadSource};
+use super::mirror::Mirror;
+use super::namespace::{scan_outside_reserved, Namespace};
+use super::prefetch::{PrefetchCache, PrefetchStats};
+use super::registry::SnapshotRecord;
+use super::smart_get::SmartGetOptions;
+use super::{
+    as_ref_bound, block_on, block_on_worker, check_priority, copy_truncated, is_empty_range,
+    is_locked, is_oversized, is_region_error, limit_batch_get, resume_token, scan_pages,
+    scan_with_codec, scan_with_truncation, sort_by_key, to_owned_bound, BackoffOptions,
+    BatchGetLimitedResult, Error, Priority, ScanCodec, ScanResult, SCAN_PAGE_SIZE,
+};
+use crate::checksum::{Checksum, HashAlgorithm};
+
+/// How snapshot reads are retried on region errors by default: as the region
+/// backoff of the default retry options of tikv-client, 10 retries waiting
+/// from 2ms doubling up to 500ms.
+pub(crate) const DEFAULT_READ_RETRY: BackoffOptions = BackoffOptions {
+    base_delay_ms: 2,
+    max_delay_ms: 500,
+    max_attempts: 10,
+};
+
+/// The buffered writes of a transaction a forked snapshot reads through, by
+/// key: the value of a put, `None` for a delete. See
+/// [`Transaction::fork_snapshot`](super::Transaction::fork_snapshot).
+pub(crate) type Overlay = BTreeMap<Vec<u8>, Option<Value>>;
+
+/// The result of [`Snapshot::scan_skipping_oversized`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SkippingScanResult {
+    pub kv_pairs: Vec<KvPair>,
+    /// The keys whose value is too large to be read, in key order.
+    pub skipped: Vec<Key>,
+}
+
+/// The result of [`Snapshot::scan_until`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanUntilResult {
+    pub kv_pairs: Vec<KvPair>,
+    /// Whether the range was read to its end, neither `limit` nor the
+    /// deadline stopping the scan before.
+    pub completed: bool,
+    /// The first key not read, to scan again from, included, unless
+    /// `completed`; empty when completed.
+    pub resume_key: Vec<u8>,
+}
+
+/// A read-only view of the cluster at a fixed timestamp.
+pub struct Snapshot {
+    inner: tikv_client::Snapshot,
+    client: Arc<tikv_client::TransactionClient>,
+    version: u64,
+    namespace: Namespace,
+    metrics: Arc<Metrics>,
+    read_source: ReadSource,
+    fail_fast: bool,
+    prefetch: PrefetchCache,
+    read_retry: BackoffOptions,
+    mirror: Option<Arc<Mirror>>,
+    overlay: Overlay,
+    smart_get: SmartGetOptions,
+    chunked: bool,
+    busy: BusyFlag,
+    /// Keeps the snapshot in the registry of its client while it lives.
+    _record: Option<Arc<SnapshotRecord>>,
+    failover: Option<FailoverWatch>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(
+        inner: tikv_client::Snapshot,
+        client: Arc<tikv_client::TransactionClient>,
+        version: u64,
+        namespace: Namespace,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Snapshot {
+            inner,
+            client,
+            version,
+            namespace,
+            metrics,
+            read_source: ReadSource::Snapshot,
+            fail_fast: false,
+            prefetch: PrefetchCache::default(),
+            read_retry: DEFAULT_READ_RETRY,
+            mirror: None,
+            overlay: Overlay::new(),
+            smart_get: SmartGetOptions::default(),
+            chunked: false,
+            busy: BusyFlag::default(),
+            _record: None,
+            failover: None,
+        }
+    }
+
+    /// Counts the snapshot as live in the registry `record` is from.
+    pub(crate) fn with_record(mut self, record: Arc<SnapshotRecord>) -> Self {
+        self._record = Some(record);
+        self
+    }
+
+    /// Counts the reads of the snapshot towards the health of the cluster
+    /// of a [`FailoverClient`](super::FailoverClient); unlike a transaction,
+    /// the snapshot keeps reading it after a switch.
+    pub(crate) fn with_failover(mut self, failover: FailoverWatch) -> Self {
+        self.failover = Some(failover);
+        self
+    }
+
+    /// Makes the reads of this snapshot count as reads of a transaction, for
+    /// the snapshots transactions read through.
+    pub(crate) fn counting_as_transaction(mut self) -> Self {
+        self.read_source = ReadSource::Transaction;
+        self
+    }
+
+    /// Makes [`get`](Self::get) report the locks it meets as
+    /// [`Error::KeyLocked`], for the snapshots whose lock backoff is none.
+    pub(crate) fn failing_fast(mut self) -> Self {
+        self.fail_fast = true;
+        self
+    }
+
+    /// Sets how the reads of this snapshot are retried on region errors.
+    pub(crate) fn with_read_retry(mut self, retry: BackoffOptions) -> Self {
+        self.read_retry = retry;
+        self
+    }
+
+    /// Makes the reads of this snapshot replayed against `mirror`, as
+    /// sampled by it.
+    pub(crate) fn with_mirror(mut self, mirror: Option<Arc<Mirror>>) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    /// Lays `overlay` over what [`get`](Self::get),
+    /// [`batch_get`](Self::batch_get) and [`scan`](Self::scan) read.
+    pub(crate) fn with_overlay(mut self, overlay: Overlay) -> Self {
+        self.overlay = overlay;
+        self
+    }
+
+    /// Makes the reads of this snapshot reassemble chunked values from
+    /// their chunks and scans hide the chunk keys, for the clients chunking
+    /// oversized values.
+    pub(crate) fn with_chunked_values(mut self, chunked: bool) -> Self {
+        self.chunked = chunked;
+        self
+    }
+
+    /// Marks the snapshot as in use until the guard is dropped, failing with
+    /// [`Error::Busy`](super::Error::Busy) if it already is. Taken by the FFI
+    /// entry points.
+    pub(crate) fn enter(&self) -> Result<BusyGuard> {
+        self.busy.enter("snapshot", &self.metrics)
+    }
+
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Sets how [`smart_get`](Self::smart_get) tells dense runs of keys;
+    /// defaults to runs of at least 4 keys filling half of what they span.
+    pub fn set_smart_get_options(&mut self, options: SmartGetOptions) -> Result<()> {
+        options.check()?;
+        self.smart_get = options;
+        Ok(())
+    }
+
+    pub fn smart_get_options(&self) -> SmartGetOptions {
+        self.smart_get
+    }
+
+    /// The version this snapshot reads at.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Sets the priority of the requests of this snapshot; only
+    /// [`Priority::Normal`] is supported by the pinned tikv-client.
+    pub fn set_priority(&mut self, priority: Priority) -> Result<()> {
+        check_priority(priority)
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        if let Some(value) = self.overlay.get(key) {
+            return Ok(value.clone());
+        }
+        let encoded = self.namespace.encode_key(key)?;
+        let value = match self.prefetch.get(&encoded) {
+            Some(value) => Ok(value),
+            None => self.read(|inner| block_on(inner.get(encoded.clone()))),
+        };
+        let fail_fast = self.fail_fast;
+        let value = value.map_err(|e| locked_error(fail_fast, key, e))?;
+        let value = value.map(|value| self.namespace.decode_value(value));
+        if let Some(mirror) = &self.mirror {
+            mirror.get(key, &value);
+        }
+        let value = match value {
+            Some(value) => Some(self.open_chunked(key, value)?),
+            None => None,
+        };
+        if let Some(value) = &value {
+            self.metrics
+                .record_read(self.read_source, 1, (key.len() + value.len()) as u64);
+        }
+        Ok(value)
+    }
+
+    /// Starts fetching `keys` in the background, so that the
+    /// [`get`](Self::get) and [`batch_get`](Self::batch_get) of them that
+    /// follow are served without a round trip once the fetch is done; a
+    /// read before that, or after a failed fetch, goes to TiKV as usual.
+    /// The keys are read at the version of the snapshot, so the cache never
+    /// disagrees with it.
+    pub fn prefetch<'a>(&mut self, keys: impl IntoIterator<Item = &'a [u8]>) -> Result<()> {
+        let keys = keys
+            .into_iter()
+            .map(|key| self.namespace.encode_key(key))
+            .collect::<Result<Vec<_>>>()?;
+        self.prefetch.spawn(self.client.clone(), self.version, keys);
+        Ok(())
+    }
+
+    /// Caps the bytes of keys and values cached by
+    /// [`prefetch`](Self::prefetch), 64 MiB by default; fetched keys that do
+    /// not fit are dropped and read from TiKV when asked for.
+    pub fn set_prefetch_cache_bytes(&mut self, bytes: u64) {
+        self.prefetch.set_max_bytes(bytes);
+    }
+
+    pub fn prefetch_stats(&self) -> PrefetchStats {
+        self.prefetch.stats()
+    }
+
+    /// Like [`get`](Self::get), returning the value as stored, without
+    /// decompressing it, for repair tooling.
+    pub fn get_raw(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        let encoded = self.namespace.encode_key(key)?;
+        self.read(|inner| block_on(inner.get(encoded.clone())))
+    }
+
+    /// Like [`scan`](Self::scan) with a positive `limit`, returning the
+    /// values as stored, without decompressing them.
+    pub fn scan_raw(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        if limit == 0 {
+            bail!("limit must be positive");
+        }
+        if is_empty_range(start, end) {
+            return Ok(Vec::new());
+        }
+        let range = self.namespace.encode_range(start, end);
+        let kv_pairs = self.read(|inner| {
+            block_on(inner.scan(range.clone(), limit)).map(|kv_pairs| kv_pairs.collect::<Vec<_>>())
+        })?;
+        let namespace = &self.namespace;
+        Ok(kv_pairs
+            .into_iter()
+            .map(|KvPair(key, value)| KvPair(namespace.decode(key), value))
+            .collect())
+    }
+
+    /// Gets the length of the value of `key`.
+    ///
+    /// TiKV has no request returning only the length, so the value is still
+    /// fetched from the cluster; only its copy to the caller is saved.
+    pub fn get_value_length(&mut self, key: &[u8]) -> Result<Option<u64>> {
+        Ok(self.get(key)?.map(|value| value.len() as u64))
+    }
+
+    /// Copies as much of the value of `key` as fits into `buf` and returns
+    /// the full length of the value, which is larger than `buf` when it was
+    /// truncated.
+    pub fn get_into(&mut self, key: &[u8], buf: &mut [u8]) -> Result<Option<usize>> {
+        Ok(self.get(key)?.map(|value| copy_truncated(&value, buf)))
+    }
+
+    /// Gets the values of `keys`, sorted by key whatever the order of `keys`;
+    /// keys that do not exist are left out.
+    ///
+    /// tikv-client returns pairs in no particular order, which has changed
+    /// between its versions; sorting makes the result deterministic.
+    pub fn batch_get<'a>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<Vec<KvPair>> {
+        let (overlaid, user_keys): (Vec<_>, Vec<_>) = keys
+            .into_iter()
+            .partition(|key| self.overlay.contains_key(*key));
+        let keys = user_keys
+            .iter()
+            .map(|key| self.namespace.encode_key(key))
+            .collect::<Result<Vec<_>>>()?;
+        let mut fetched = Vec::new();
+        let mut keys_to_fetch = Vec::new();
+        for key in keys {
+            match self.prefetch.get(&key) {
+                Some(Some(value)) => fetched.push(KvPair(key, value)),
+                Some(None) => {}
+                None => keys_to_fetch.push(key),
+            }
+        }
+        if !keys_to_fetch.is_empty() {
+            fetched.extend(self.read(|inner| {
+                block_on(inner.batch_get(keys_to_fetch.clone()))
+                    .map(|kv_pairs| kv_pairs.collect::<Vec<_>>())
+            })?);
+        }
+        let namespace = &self.namespace;
+        let mut kv_pairs = fetched
+            .into_iter()
+            .map(|kv_pair| namespace.decode_pair(kv_pair))
+            .collect::<Vec<_>>();
+        sort_by_key(&mut kv_pairs);
+        if let Some(mirror) = &self.mirror {
+            mirror.batch_get(&user_keys, &kv_pairs);
+        }
+        let mut kv_pairs = self.open_chunked_pairs(kv_pairs)?;
+        self.metrics.record_pairs(self.read_source, &kv_pairs);
+        if !overlaid.is_empty() {
+            let overlay = &self.overlay;
+            kv_pairs.extend(overlaid.into_iter().filter_map(|key| {
+                overlay[key]
+                    .clone()
+                    .map(|value| KvPair(key.to_owned().into(), value))
+            }));
+            sort_by_key(&mut kv_pairs);
+        }
+        Ok(kv_pairs)
+    }
+
+    /// Like [`batch_get`](Self::batch_get), returning the pairs in the order
+    /// of `keys` and only as many as fit in `max_bytes` of keys and values,
+    /// at least one; [`BatchGetLimitedResult::next_index`] tells where to
+    /// resume. Every key is still fetched: the budget bounds what is
+    /// returned, not what is read.
+    pub fn batch_get_limited(
+        &mut self,
+        keys: &[&[u8]],
+        max_bytes: u64,
+    ) -> Result<BatchGetLimitedResult> {
+        let found = self.batch_get(keys.iter().copied())?;
+        Ok(limit_batch_get(keys, max_bytes, found))
+    }
+
+    /// Like [`batch_get`](Self::batch_get), returning the hash of each value
+    /// instead of the value, in the order of `keys`, `None` for the keys
+    /// that do not exist, for verifiers comparing what nodes read without
+    /// shipping the values. The values are hashed on a worker thread of the
+    /// runtime once the batch get returns them, and dropped.
+    pub fn batch_get_hashes(
+        &mut self,
+        keys: &[&[u8]],
+        algorithm: HashAlgorithm,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let kv_pairs = self.batch_get(keys.iter().copied())?;
+        let hashes = block_on_worker(move || {
+            kv_pairs
+                .into_iter()
+                .map(|KvPair(key, value)| (Vec::<u8>::from(key), algorithm.hash(&value)))
+                .collect::<BTreeMap<_, _>>()
+        })?;
+        Ok(keys.iter().map(|&key| hashes.get(key).cloned()).collect())
+    }
+
+    /// Scans up to `limit` pairs of the range, or all of them, page by page,
+    /// when `limit` is 0.
+    pub fn scan(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        if limit == 0 {
+            return scan_pages(
+                start,
+                |start, page_size| self.scan(start, end, page_size),
+                |kv_pair| kv_pair.0.clone().into(),
+            );
+        }
+        if !self.overlay.is_empty() {
+            let overlay = mem::take(&mut self.overlay);
+            let kv_pairs = scan_overlaid(&overlay, start, end, limit, |fetch| {
+                self.scan(start, end, fetch)
+            });
+            self.overlay = overlay;
+            return kv_pairs;
+        }
+        if self.namespace.overlaps_reserved(start, end) {
+            return scan_outside_reserved(start, end, limit, |start, end, limit| {
+                self.scan(start, end, limit)
+            });
+        }
+        if is_empty_range(start, end) {
+            return Ok(Vec::new());
+        }
+        let kv_pairs = if self.chunked {
+            let kv_pairs = scan_hiding_chunks(start, limit, pair_key, |start, limit| {
+                self.scan_page(start, end, limit)
+            })?;
+            self.open_chunked_pairs(kv_pairs)?
+        } else {
+            self.scan_page(start, end, limit)?
+        };
+        self.metrics.record_pairs(self.read_source, &kv_pairs);
+        Ok(kv_pairs)
+    }
+
+    /// Scans up to `limit` pairs of the range from TiKV, as they are
+    /// mirrored.
+    fn scan_page(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        if is_empty_range(start, end) {
+            return Ok(Vec::new());
+        }
+        let range = self.namespace.encode_range(start, end);
+        let kv_pairs = self.read(|inner| {
+            block_on(inner.scan(range.clone(), limit)).map(|kv_pairs| kv_pairs.collect::<Vec<_>>())
+        })?;
+        let namespace = &self.namespace;
+        let kv_pairs = kv_pairs
+            .into_iter()
+            .map(|kv_pair| namespace.decode_pair(kv_pair))
+            .collect::<Vec<_>>();
+        if let Some(mirror) = &self.mirror {
+            mirror.scan(start, end, limit, &kv_pairs);
+        }
+        Ok(kv_pairs)
+    }
+
+    /// `value` of `key` reassembled from its chunks when it is the manifest
+    /// of a chunked value and the snapshot reads chunked values.
+    fn open_chunked(&mut self, key: &[u8], value: Value) -> Result<Value> {
+        let manifest = match Manifest::parse(&value) {
+            Some(manifest) if self.chunked => manifest,
+            _ => return Ok(value),
+        };
+        let chunk_keys = (0..manifest.chunks)
+            .map(|n| Key::from(self.namespace.encode(&chunk_key(key, n))))
+            .collect::<Vec<_>>();
+        let fetched = self.read(|inner| {
+            block_on(inner.batch_get(chunk_keys.clone()))
+                .map(|kv_pairs| kv_pairs.collect::<Vec<_>>())
+        })?;
+        manifest.assemble(key, &chunk_keys, fetched)
+    }
+
+    fn open_chunked_pairs(&mut self, kv_pairs: Vec<KvPair>) -> Result<Vec<KvPair>> {
+        if !self.chunked {
+            return Ok(kv_pairs);
+        }
+        kv_pairs
+            .into_iter()
+            .map(|KvPair(key, value)| {
+                let value = self.open_chunked(key_bytes(&key), value)?;
+                Ok(KvPair(key, value))
+            })
+            .collect()
+    }
+
+    /// Like [`scan`](Self::scan), translating the bounds and the keys
+    /// returned from and to their memcomparable encoding as `codec` tells.
+    pub fn scan_with_codec(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+        codec: ScanCodec,
+    ) -> Result<Vec<KvPair>> {
+        scan_with_codec(start, end, codec, |start, end| self.scan(start, end, limit))
+    }
+
+    /// Like [`scan`](Self::scan), also telling whether pairs of the range
+    /// were left out because of `limit`.
+    pub fn scan_with_truncation(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<ScanResult> {
+        scan_with_truncation(start, limit, |start, limit| self.scan(start, end, limit))
+    }
+
+    /// Like [`scan`](Self::scan), reading page by page and stopping after
+    /// the first page that ends at or past `deadline`, so that a scan bound
+    /// to a time window returns what it read so far instead of failing,
+    /// with whether it read the whole range and where to resume if not.
+    pub fn scan_until(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+        deadline: Instant,
+    ) -> Result<ScanUntilResult> {
+        let mut cursor = to_owned_bound(start);
+        let mut kv_pairs = Vec::new();
+        loop {
+            let page_size = match limit {
+                0 => SCAN_PAGE_SIZE,
+                limit => (limit - kv_pairs.len() as u32).min(SCAN_PAGE_SIZE),
+            };
+            let page = self.scan(as_ref_bound(&cursor), end, page_size)?;
+            let full = page.len() == page_size as usize;
+            if let (true, Some(KvPair(key, _))) = (full, page.last()) {
+                cursor = Bound::Excluded(key.clone().into());
+            }
+            kv_pairs.extend(page);
+            let truncated = if !full {
+                false
+            } else if kv_pairs.len() == limit as usize {
+                !self.scan(as_ref_bound(&cursor), end, 1)?.is_empty()
+            } else if Instant::now() >= deadline {
+                true
+            } else {
+                continue;
+            };
+            let resume_key = match (truncated, kv_pairs.last()) {
+                (true, Some(KvPair(key, _))) => resume_token(Bound::Excluded(key.into())),
+                _ => Vec::new(),
+            };
+            return Ok(ScanUntilResult {
+                kv_pairs,
+                completed: !truncated,
+                resume_key,
+            });
+        }
+    }
+
+    /// Like [`scan`](Self::scan), without the values.
+    pub fn scan_keys(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<Key>> {
+        if limit == 0 {
+            return scan_pages(
+                start,
+                |start, page_size| self.scan_keys(start, end, page_size),
+                |key| key.clone().into(),
+            );
+        }
+        if self.namespace.overlaps_reserved(start, end) {
+            return scan_outside_reserved(start, end, limit, |start, end, limit| {
+                self.scan_keys(start, end, limit)
+            });
+        }
+        if is_empty_range(start, end) {
+            return Ok(Vec::new());
+        }
+        let keys = if self.chunked {
+            scan_hiding_chunks(start, limit, key_bytes, |start, limit| {
+                self.scan_keys_page(start, end, limit)
+            })?
+        } else {
+            self.scan_keys_page(start, end, limit)?
+        };
+        self.metrics.record_keys(self.read_source, &keys);
+        Ok(keys)
+    }
+
+    fn scan_keys_page(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<Key>> {
+        if is_empty_range(start, end) {
+            return Ok(Vec::new());
+        }
+        let range = self.namespace.encode_range(start, end);
+        let keys = self.read(|inner| {
+            block_on(inner.scan_keys(range.clone(), limit)).map(|keys| keys.collect::<Vec<_>>())
+        })?;
+        let namespace = &self.namespace;
+        Ok(keys.into_iter().map(|key| namespace.decode(key)).collect())
+    }
+
+    /// Like [`scan`](Self::scan), but a value too large for a gRPC message
+    /// does not fail the whole scan: its key is reported in `skipped`, for
+    /// repair tooling to locate it.
+    ///
+    /// The plain scan is tried first. When it fails for size reasons, the
+    /// keys of the range are scanned without their values and fetched back
+    /// in batches, bisecting every batch that fails down to the keys that
+    /// cannot be read. All of it reads at the version of the snapshot, so
+    /// the result is as consistent as a plain scan.
+    pub fn scan_skipping_oversized(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<SkippingScanResult> {
+        match self.scan(start, end, limit) {
+            Ok(kv_pairs) => {
+                return Ok(SkippingScanResult {
+                    kv_pairs,
+                    skipped: Vec::new(),
+                })
+            }
+            Err(e) if !is_oversized(&e) => return Err(e),
+            Err(_) => {}
+        }
+        let keys = self
+            .scan_keys(start, end, limit)?
+            .into_iter()
+            .map(Vec::from)
+            .collect::<Vec<_>>();
+        let mut result = SkippingScanResult::default();
+        for batch in keys.chunks(SCAN_PAGE_SIZE as usize) {
+            self.get_bisecting(batch, &mut result)?;
+        }
+        Ok(result)
+    }
+
+    /// Gets the values of the sorted `keys` into `result`, splitting the
+    /// batch in halves when it fails for size reasons.
+    fn get_bisecting(&mut self, keys: &[Vec<u8>], result: &mut SkippingScanResult) -> Result<()> {
+        match self.batch_get(keys.iter().map(Vec::as_slice)) {
+            Ok(kv_pairs) => result.kv_pairs.extend(kv_pairs),
+            Err(e) if !is_oversized(&e) => return Err(e),
+            Err(_) if keys.len() == 1 => result.skipped.push(keys[0].clone().into()),
+            Err(_) => {
+                let (low, high) = keys.split_at(keys.len() / 2);
+                self.get_bisecting(low, result)?;
+                self.get_bisecting(high, result)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scans the range like [`scan`](Self::scan), returning each key with the
+    /// length of its value.
+    ///
+    /// TiKV cannot return value lengths without the values, so this costs as
+    /// much network traffic as a full scan; the values are only dropped
+    /// before reaching the caller.
+    pub fn scan_key_sizes(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<(Key, u64)>> {
+        let kv_pairs = self.scan(start, end, limit)?;
+        Ok(kv_pairs
+            .into_iter()
+            .map(|KvPair(key, value)| (key, value.len() as u64))
+            .collect())
+    }
+
+    /// Scans two ranges, e.g. a table and its index, returning both results.
+    ///
+    /// Every read of a snapshot is at its version, so separate scans of one
+    /// snapshot are just as consistent with each other; this only saves a
+    /// call.
+    pub fn scan_pair(
+        &mut self,
+        range_a: (Bound<&[u8]>, Bound<&[u8]>),
+        range_b: (Bound<&[u8]>, Bound<&[u8]>),
+        limit: u32,
+    ) -> Result<(Vec<KvPair>, Vec<KvPair>)> {
+        let a = self.scan(range_a.0, range_a.1, limit)?;
+        let b = self.scan(range_b.0, range_b.1, limit)?;
+        Ok((a, b))
+    }
+
+    /// Calls `f` on every pair of the range in key order, scanning it
+    /// `page_size` pairs at a time so that it is never held in memory whole.
+    pub fn scan_each(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        page_size: u32,
+        mut f: impl FnMut(KvPair) -> Result<()>,
+    ) -> Result<()> {
+        if page_size == 0 {
+            bail!("page_size must be positive");
+        }
+        let mut cursor = to_owned_bound(start);
+        loop {
+            let page = self.scan(as_ref_bound(&cursor), end, page_size)?;
+            let last_key = match page.last() {
+                Some(kv_pair) if page.len() == page_size as usize => Some(kv_pair.0.clone()),
+                _ => None,
+            };
+            for kv_pair in page {
+                f(kv_pair)?;
+            }
+            match last_key {
+                Some(key) => cursor = Bound::Excluded(key.into()),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Sends a read through `request`, sending it again after a region error
+    /// while retries are left, counting each in
+    /// [`MetricsSnapshot::snapshot_read_retries`](super::MetricsSnapshot::snapshot_read_retries);
+    /// only the error ending the read is recorded, and only the outcome of
+    /// the read counts towards the failover watch.
+    ///
+    /// tikv-client has already retried the region error on its own by the
+    /// time it returns one, and dropped the cached region, so the read sent
+    /// again finds where the region went.
+    fn read<T>(
+        &mut self,
+        mut request: impl FnMut(&mut tikv_client::Snapshot) -> Result<T>,
+    ) -> Result<T> {
+        let inner = &mut self.inner;
+        let metrics = &self.metrics;
+        let result = retry_region_errors(
+            self.read_retry,
+            || request(inner),
+            || metrics.record_snapshot_read_retry(),
+        );
+        let result = self.metrics.record(result);
+        if let Some(failover) = &self.failover {
+            failover.record(&result);
+        }
+        result
+    }
+
+    /// Computes the checksum of the pairs in the range, over the keys as seen
+    /// through this snapshot's prefix.
+    ///
+    /// This scans the range and folds it on the client, so every pair is
+    /// streamed from TiKV, one page at a time; tikv-client does not expose the
+    /// coprocessor checksum request that would compute it server-side.
+    pub fn checksum_range(&mut self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<Checksum> {
+        let mut checksum = Checksum::default();
+        self.scan_each(start, end, SCAN_PAGE_SIZE, |KvPair(key, value)| {
+            let key: Vec<u8> = key.into();
+            checksum.update(&key, &value);
+            Ok(())
+        })?;
+        Ok(checksum)
+    }
+}
+
+/// Gives `e` of a read of `key` the [`Error::KeyLocked`] context when a
+/// fail-fast read met a lock.
+fn locked_error(fail_fast: bool, key: &[u8], e: anyhow::Error) -> anyhow::Error {
+    if fail_fast && is_locked(&e) {
+        e.context(Error::KeyLocked {
+            key: key.to_owned(),
+        })
+    } else {
+        e
+    }
+}
+
+/// Runs `request` again after a region error while `retry` has attempts
+/// left, backing off between them and calling `on_retry` before each.
+fn retry_region_errors<T>(
+    retry: BackoffOptions,
+    mut request: impl FnMut() -> Result<T>,
+    mut on_retry: impl FnMut(),
+) -> Result<T> {
+    let max_delay = Duration::from_millis(retry.max_delay_ms);
+    let mut delay = Duration::from_millis(retry.base_delay_ms).min(max_delay);
+    let mut retries = 0;
+    loop {
+        let e = match request() {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        if retries >= retry.max_attempts || !is_region_error(&e) {
+            return Err(e);
+        }
+        retries += 1;
+        on_retry();
+        thread::sleep(delay);
+        delay = delay.saturating_mul(2).min(max_delay);
+    }
+}
+
+/// Scans up to a positive `limit` pairs of the committed data of the range
+/// overlaid with `overlay`, `scan` reading the committed data for a number
+/// of pairs.
+///
+/// The committed scan fetches one extra pair per delete of the overlay in
+/// the range, so that when it comes back full at least `limit` pairs up to
+/// its last key remain; the overlay past that key is left for the next page.
+pub(crate) fn scan_overlaid(
+    overlay: &Overlay,
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+    limit: u32,
+    scan: impl FnOnce(u32) -> Result<Vec<KvPair>>,
+) -> Result<Vec<KvPair>> {
+    if is_empty_range(start, end) {
+        return Ok(Vec::new());
+    }
+    let written = overlay.range::<[u8], _>((start, end)).collect::<Vec<_>>();
+    let deletes = written.iter().filter(|(_, value)| value.is_none()).count() as u32;
+    let fetch = limit.saturating_add(deletes);
+    let committed = scan(fetch)?;
+    let last = match committed.last() {
+        Some(KvPair(key, _)) if committed.len() == fetch as usize => {
+            Some(Vec::<u8>::from(key.clone()))
+        }
+        _ => None,
+    };
+    let mut merged = committed
+        .into_iter()
+        .map(|KvPair(key, value)| (Vec::<u8>::from(key), value))
+        .collect::<BTreeMap<_, _>>();
+    for (key, value) in written {
+        if last.as_ref().map_or(false, |last| key > last) {
+            break;
+        }
+        match value {
+            Some(value) => merged.insert(key.clone(), value.clone()),
+            None => merged.remove(key),
+        };
+    }
+    Ok(merged
+        .into_iter()
+        .take(limit as usize)
+        .map(|(key, value)| KvPair(key.into(), value))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::super::tests::{cluster_client, key, model, range, scan_model};
+    use super::super::TxnOptions;
+    use super::*;
+
+    fn values(kv_pairs: Vec<KvPair>) -> Vec<Vec<u8>> {
+        kv_pairs.into_iter().map(|KvPair(_, value)| value).collect()
+    }
+
+    fn overlay() -> impl Strategy<Value = Overlay> {
+        let value = prop::option::of(prop::collection::vec(any::<u8>(), 0..4));
+        prop::collection::btree_map(key(), value, 0..8)
+    }
+
+    /// An error rendered like the not-leader region error of tikv-client.
+    fn not_leader() -> anyhow::Error {
+        anyhow::anyhow!("RegionError {{ not_leader: Some(NotLeader {{ region_id: 2 }}) }}")
+    }
+
+    /// Retries with no backoff, to keep the tests fast.
+    fn retry(max_attempts: u32) -> BackoffOptions {
+        BackoffOptions {
+            base_delay_ms: 0,
+            max_delay_ms: 0,
+            max_attempts,
+        }
+    }
+
+    fn is_key_locked(e: &anyhow::Error) -> bool {
+        matches!(e.downcast_ref::<Error>(), Some(Error::KeyLocked { key }) if key == b"k")
+    }
+
+    #[test]
+    fn only_fail_fast_reads_meeting_a_lock_fail_with_key_locked() {
+        let locked = || anyhow::anyhow!("KeyError {{ locked: Some(LockInfo {{ key: [107] }}) }}");
+        assert!(is_key_locked(&locked_error(true, b"k", locked())));
+        assert!(!is_key_locked(&locked_error(false, b"k", locked())));
+        let other = locked_error(true, b"k", anyhow::anyhow!("the store is down"));
+        assert!(!is_key_locked(&other));
+        assert_eq!(other.to_string(), "the store is down");
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn fail_fast_reads_fail_at_once_on_a_lock() {
+        let client = cluster_client("fail_fast");
+        let mut holder = client.begin_optimistic().unwrap();
+        holder.put(b"k", b"v").unwrap();
+        holder.prewrite_primary(None).unwrap();
+
+        let mut snapshot = client.snapshot_fail_fast().unwrap();
+        let start = Instant::now();
+        let e = snapshot.get(b"k").unwrap_err();
+        let waited = start.elapsed();
+        assert!(is_key_locked(&e), "{:?}", e);
+        assert!(waited < Duration::from_millis(50), "{:?}", waited);
+        // Other keys read as usual.
+        assert_eq!(snapshot.get(b"other").unwrap(), None);
+
+        client.set_default_txn_options(TxnOptions {
+            fail_fast_on_locks: true,
+            ..TxnOptions::default()
+        });
+        let mut txn = client.begin_optimistic().unwrap();
+        let start = Instant::now();
+        assert!(txn.get(b"k").is_err());
+        let waited = start.elapsed();
+        assert!(waited < Duration::from_millis(50), "{:?}", waited);
+        txn.rollback().unwrap();
+
+        holder.commit_primary().unwrap();
+        let mut snapshot = client.snapshot_fail_fast().unwrap();
+        assert_eq!(snapshot.get(b"k").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn one_injected_not_leader_is_absorbed() {
+        let mut failures = vec![not_leader()];
+        let mut retries = 0;
+        let result = retry_region_errors(
+            retry(DEFAULT_READ_RETRY.max_attempts),
+            || match failures.pop() {
+                Some(e) => Err(e),
+                None => Ok(b"v".to_vec()),
+            },
+            || retries += 1,
+        );
+        assert_eq!(result.unwrap(), b"v");
+        assert_eq!(retries, 1);
+    }
+
+    #[test]
+    fn other_errors_and_exhausted_retries_propagate() {
+        let mut requests = 0;
+        let result: Result<()> = retry_region_errors(
+            retry(3),
+            || {
+                requests += 1;
+                Err(anyhow::anyhow!("the store is down"))
+            },
+            || {},
+        );
+        assert_eq!(result.unwrap_err().to_string(), "the store is down");
+        assert_eq!(requests, 1);
+
+        for &max_attempts in [0, 3].iter() {
+            let (mut requests, mut retries) = (0, 0);
+            let result: Result<()> = retry_region_errors(
+                retry(max_attempts),
+                || {
+                    requests += 1;
+                    Err(not_leader())
+                },
+                || retries += 1,
+            );
+            assert!(is_region_error(&result.unwrap_err()));
+            assert_eq!((requests, retries), (max_attempts + 1, max_attempts));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn overlaid_scans_read_the_committed_data_with_the_overlay(
+            committed in model(),
+            overlay in overlay(),
+            (start, end) in range(),
+            limit in 1..12u32,
+        ) {
+            let mut overlaid = committed.clone();
+            for (key, value) in &overlay {
+                match value {
+                    Some(value) => overlaid.insert(key.clone(), value.clone()),
+                    None => overlaid.remove(key),
+                };
+            }
+            let kv_pairs = scan_overlaid(&overlay, as_ref_bound(&start), as_ref_bound(&end), limit, |fetch| {
+                Ok(scan_model(&committed, &start, &end, fetch))
+            })
+            .unwrap();
+            prop_assert_eq!(kv_pairs, scan_model(&overlaid, &start, &end, limit));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn forked_snapshots_overlay_buffered_writes_as_asked() {
+        let client = cluster_client("fork_snapshot");
+        let mut setup = client.begin_optimistic().unwrap();
+        setup.put(b"k/deleted", b"old").unwrap();
+        setup.put(b"k/kept", b"old").unwrap();
+        setup.put(b"k/put", b"old").unwrap();
+        setup.commit().unwrap();
+
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.put(b"k/put", b"new").unwrap();
+        txn.put(b"k/added", b"new").unwrap();
+        txn.delete(b"k/deleted").unwrap();
+        // Committed after the transaction started, never visible to it.
+        let mut later = client.begin_optimistic().unwrap();
+        later.put(b"k/kept", b"later").unwrap();
+        later.put(b"k/later", b"later").unwrap();
+        later.commit().unwrap();
+
+        let range = (Bound::Included(&b"k/"[..]), Bound::Excluded(&b"k0"[..]));
+        let keys = [
+            &b"k/added"[..],
+            b"k/deleted",
+            b"k/kept",
+            b"k/later",
+            b"k/put",
+        ];
+        let mut committed = txn.fork_snapshot(&client, false).unwrap();
+        let pairs = committed.scan(range.0, range.1, 0).unwrap();
+        assert_eq!(values(pairs), [b"old", b"old", b"old"]);
+        assert_eq!(committed.get(b"k/put").unwrap(), Some(b"old".to_vec()));
+        assert_eq!(committed.get(b"k/added").unwrap(), None);
+        assert_eq!(committed.get(b"k/later").unwrap(), None);
+        assert_eq!(committed.batch_get(keys.iter().copied()).unwrap().len(), 3);
+
+        let mut buffered = txn.fork_snapshot(&client, true).unwrap();
+        let pairs = buffered.scan(range.0, range.1, 0).unwrap();
+        let pairs = pairs
+            .into_iter()
+            .map(|KvPair(key, value)| (Vec::<u8>::from(key), value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            [
+                (b"k/added".to_vec(), b"new".to_vec()),
+                (b"k/kept".to_vec(), b"old".to_vec()),
+                (b"k/put".to_vec(), b"new".to_vec()),
+            ]
+        );
+        assert_eq!(buffered.get(b"k/put").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(buffered.get(b"k/deleted").unwrap(), None);
+        assert_eq!(buffered.get(b"k/later").unwrap(), None);
+        let pairs = buffered.batch_get(keys.iter().copied()).unwrap();
+        assert_eq!(values(pairs), [b"new", b"old", b"new"]);
+        // The fork is a copy: writes buffered after it do not show.
+        txn.put(b"k/kept", b"newer").unwrap();
+        assert_eq!(buffered.get(b"k/kept").unwrap(), Some(b"old".to_vec()));
+        txn.rollback().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn scan_pair_reads_both_ranges_at_the_snapshot_version() {
+        let client = cluster_client("scan_pair");
+        let write = |value: &[u8]| {
+            let mut txn = client.begin_optimistic().unwrap();
+            txn.put(b"row/1", value).unwrap();
+            txn.put(b"index/1", value).unwrap();
+            txn.commit().unwrap()
+        };
+        let rows = (Bound::Included(&b"row/"[..]), Bound::Excluded(&b"row0"[..]));
+        let index = (
+            Bound::Included(&b"index/"[..]),
+            Bound::Excluded(&b"index0"[..]),
+        );
+
+        let first_commit = write(b"old");
+        let mut snapshot = client.snapshot().unwrap();
+        assert!(snapshot.version() > first_commit);
+        // Committed after the snapshot was taken, both new values are newer
+        // than its version in both ranges.
+        assert!(write(b"new") > snapshot.version());
+
+        let (a, b) = snapshot.scan_pair(rows, index, 0).unwrap();
+        assert_eq!(values(a), vec![b"old".to_vec()]);
+        assert_eq!(values(b), vec![b"old".to_vec()]);
+
+        let (a, b) = client
+            .snapshot()
+            .unwrap()
+            .scan_pair(rows, index, 0)
+            .unwrap();
+        assert_eq!(values(a), vec![b"new".to_vec()]);
+        assert_eq!(values(b), vec![b"new".to_vec()]);
+    }
+}
@@ -0,0 +1,2564 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use log::{debug, warn};
+use tikv_client::{CheckLevel, Key, KvPair, Timestamp, TimestampExt, TransactionOptions, Value};
+use tokio::time::Instant;
+
+use super::audit::{AuditLog, AuditMutation};
+use super::busy::{BusyFlag, BusyGuard};
+use super::capabilities::unsupported;
+use super::chunked::{
+    chunk_key, is_chunk_key, key_bytes, pair_key, scan_hiding_chunks, Manifest, MANIFEST_LEN,
+};
+use super::conflicts::{ConflictEntry, ConflictLog};
+use super::failover::FailoverWatch;
+use super::metrics::{Metrics, ReadSource};
+use super::namespace::{scan_outside_reserved, Namespace};
+use super::op_log::{OpKind, OpLog, OpLogEntry};
+use super::registry::{EmptyCommits, TxnRecord, TxnRegistry};
+use super::snapshot::{scan_overlaid, Overlay};
+use super::write_set::{MutationKind, WriteSet};
+use super::{
+    block_on, block_until, check_priority, copy_truncated, is_empty_range, limit_batch_get,
+    scan_pages, scan_with_codec, scan_with_truncation, sort_by_key, BatchGetLimitedResult,
+    Capability, Error, Priority, ScanCodec, ScanResult, SizeLimits, Snapshot, TransactionClient,
+    WriteType,
+};
+
+/// How [`Transaction::prewrite_primary_with_strategy`] picks the primary key
+/// among the buffered mutations, so that every party of an externally
+/// driven commit can agree on it without communicating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrimarySelection<'a> {
+    /// The first key mutated by the transaction.
+    FirstWritten,
+    SmallestKey,
+    LargestKey,
+    /// The given key, which need not have been mutated.
+    Explicit(&'a [u8]),
+}
+
+/// The isolation level of the plain reads of a transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// Every read sees the data as of the start timestamp.
+    SnapshotIsolation,
+    /// Every read sees the latest committed data, overlaid with the
+    /// transaction's own buffered writes.
+    ReadCommitted,
+}
+
+/// Where a transaction is in its life. Once it has left `Active`, every
+/// operation fails with [`Error::TransactionFinished`], except a rollback
+/// after a failed commit and the two-phase commit functions in
+/// `ExternalTwoPhase`.
+///
+/// A transaction is committed either by [`Transaction::commit`] (or
+/// [`Transaction::commit_chunked`]) or by the two-phase commit functions,
+/// [`Transaction::prewrite_primary`], [`Transaction::prewrite_secondary`] and
+/// [`Transaction::commit_primary`], never both: once a commit has started
+/// those fail with [`Error::CommitStarted`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TransactionState {
+    Active = 0,
+    Committed = 1,
+    RolledBack = 2,
+    /// A commit failed; only a rollback is still allowed.
+    Failed = 3,
+    /// A prewrite function was called, so the two-phase commit functions
+    /// drive the commit; every other call, [`Transaction::commit`] and
+    /// [`Transaction::rollback`] included, fails with
+    /// [`Error::ExternalTwoPhase`]. A failed two-phase commit function moves
+    /// the transaction to `Failed`, from which it can be rolled back.
+    ExternalTwoPhase = 4,
+}
+
+/// How a transaction was committed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CommitProtocol {
+    TwoPhase = 0,
+    /// Not used by the pinned tikv-client, which has no async commit.
+    AsyncCommit = 1,
+    /// Not used by the pinned tikv-client, which has no 1PC.
+    OnePhase = 2,
+    /// Nothing was buffered, so the commit sent no request.
+    ReadOnly = 3,
+}
+
+/// How a successful commit went, see [`Transaction::commit_info`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommitInfo {
+    pub protocol: CommitProtocol,
+    /// The commit version, 0 for a read-only commit.
+    pub commit_ts: u64,
+}
+
+/// What [`Transaction::prewrite_secondary`] prewrote.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SecondaryPrewriteResult {
+    pub mutation_count: u64,
+    /// How many regions the mutations spanned, 0 when unknown: the pinned
+    /// tikv-client does not report it, so it always is for now.
+    pub region_count: u64,
+}
+
+/// What [`Transaction::commit_chunked`] committed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkedCommitResult {
+    pub chunks: u32,
+    /// The commit version of the last chunk, 0 when there was none.
+    pub last_commit_ts: u64,
+}
+
+/// Where the time of a commit went, recorded when commit stats are enabled
+/// with [`Transaction::set_commit_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommitStats {
+    pub prewrite: Duration,
+    /// Fetching a timestamp from PD. tikv-client fetches the commit
+    /// timestamp within the commit of the primary key and does not time it
+    /// apart, so this times a fetch of its own made right before, an
+    /// estimate of the one counted in `commit_primary`.
+    pub get_commit_ts: Duration,
+    /// The commit of the primary key, with the fetch of the commit timestamp.
+    pub commit_primary: Duration,
+    pub commit_secondary: Duration,
+    pub total: Duration,
+    pub mutation_count: u64,
+}
+
+/// Where [`Transaction::get_traced`] got its value from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValueSource {
+    /// A put or delete buffered by the transaction itself.
+    Buffer = 0,
+    /// A read sent to TiKV.
+    Network = 1,
+}
+
+/// A value read by [`Transaction::get_traced`], with where it came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TracedValue {
+    pub value: Option<Value>,
+    pub source: ValueSource,
+    /// The whole read as seen by the caller, buffer lookups included.
+    pub elapsed: Duration,
+    /// The region that served a network read; the pinned tikv-client does
+    /// not report it, so it always is `None` for now.
+    pub region_id: Option<u64>,
+}
+
+/// A transaction started by a [`TransactionClient`](super::TransactionClient).
+pub struct Transaction {
+    inner: tikv_client::Transaction,
+    client: Arc<tikv_client::TransactionClient>,
+    start_version: u64,
+    pessimistic: bool,
+    isolation: IsolationLevel,
+    namespace: Namespace,
+    size_limits: SizeLimits,
+    metrics: Arc<Metrics>,
+    write_set: WriteSet,
+    memory_limit: Option<u64>,
+    commit_stats: bool,
+    skip_noop_writes: bool,
+    last_commit_stats: Option<CommitStats>,
+    commit_info: Option<CommitInfo>,
+    read_only: bool,
+    state: TransactionState,
+    /// Whether [`commit`](Self::commit) or
+    /// [`commit_chunked`](Self::commit_chunked) started committing.
+    commit_started: bool,
+    /// Whether [`prewrite_primary`](Self::prewrite_primary) prewrote the
+    /// primary key, which [`commit_primary`](Self::commit_primary) needs.
+    primary_prewritten: bool,
+    busy: BusyFlag,
+    record: Arc<TxnRecord>,
+    empty_commits: EmptyCommits,
+    audit_log: Option<Arc<AuditLog>>,
+    conflicts: ConflictLog,
+    deadline: Option<Instant>,
+    failover: Option<FailoverWatch>,
+    /// The options of the transactions of the chunks of
+    /// [`commit_chunked`](Self::commit_chunked).
+    chunk_options: TransactionOptions,
+}
+
+impl Transaction {
+    pub(crate) fn new(
+        inner: tikv_client::Transaction,
+        client: Arc<tikv_client::TransactionClient>,
+        pessimistic: bool,
+        isolation: IsolationLevel,
+        namespace: Namespace,
+        size_limits: SizeLimits,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Transaction {
+            inner,
+            client,
+            start_version: 0,
+            pessimistic,
+            isolation,
+            namespace,
+            size_limits,
+            metrics,
+            write_set: WriteSet::default(),
+            memory_limit: None,
+            commit_stats: false,
+            skip_noop_writes: false,
+            last_commit_stats: None,
+            commit_info: None,
+            read_only: false,
+            state: TransactionState::Active,
+            commit_started: false,
+            primary_prewritten: false,
+            busy: BusyFlag::default(),
+            record: Arc::new(TxnRecord::new(0, pessimistic)),
+            empty_commits: EmptyCommits::Allow,
+            audit_log: None,
+            conflicts: ConflictLog::default(),
+            deadline: None,
+            failover: None,
+            chunk_options: TransactionOptions::new_optimistic().drop_check(CheckLevel::Warn),
+        }
+    }
+
+    /// Sets the version the transaction was begun at, as fetched by the
+    /// client.
+    pub(crate) fn with_start_version(mut self, start_version: u64) -> Self {
+        self.start_version = start_version;
+        self
+    }
+
+    /// Adds the transaction to `registry`, once its start version is set,
+    /// committing it with nothing buffered doing as `empty_commits` says.
+    pub(crate) fn registered_in(
+        mut self,
+        registry: &TxnRegistry,
+        empty_commits: EmptyCommits,
+    ) -> Self {
+        self.record = registry.register(TxnRecord::new(self.start_version, self.pessimistic));
+        self.empty_commits = empty_commits;
+        self
+    }
+
+    /// Makes the commits of the transaction write their mutations to
+    /// `audit_log` before they return.
+    pub(crate) fn with_audit_log(mut self, audit_log: Option<Arc<AuditLog>>) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Begins the transactions of the chunks of
+    /// [`commit_chunked`](Self::commit_chunked) with `options`, those of the
+    /// optimistic transactions of the client.
+    pub(crate) fn with_chunk_options(mut self, options: TransactionOptions) -> Self {
+        self.chunk_options = options;
+        self
+    }
+
+    /// Counts the operations of the transaction towards the health of the
+    /// cluster of a [`FailoverClient`](super::FailoverClient), and fails
+    /// them with [`Error::FailedOver`] once it switched away from it.
+    pub(crate) fn with_failover(mut self, failover: FailoverWatch) -> Self {
+        self.failover = Some(failover);
+        self
+    }
+
+    /// Makes every mutation of the transaction fail with
+    /// [`Error::ReadOnly`], for the transactions begun read-only, whose
+    /// commit and rollback then send nothing.
+    pub(crate) fn into_read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn state(&self) -> TransactionState {
+        self.state
+    }
+
+    /// The version the transaction reads at, fetched from PD when it began.
+    pub fn start_version(&self) -> u64 {
+        self.start_version
+    }
+
+    /// Creates a snapshot at the start version of the transaction, e.g. to
+    /// hand a consistent read-only view to another thread, from `client`,
+    /// which must share the connection of the transaction and whose
+    /// snapshot read retry it takes. It reads through the key prefix of the
+    /// transaction, and never sees what was committed after the start.
+    ///
+    /// With `include_buffered`, the puts and deletes buffered so far are
+    /// laid over it: [`Snapshot::get`], [`Snapshot::batch_get`] and
+    /// [`Snapshot::scan`], and the reads built on them, see them, while
+    /// [`Snapshot::scan_keys`] and the raw reads do not. They are copied at
+    /// the fork, one lookup in the buffer of tikv-client per buffered put,
+    /// so the writes the transaction makes afterwards are not seen. Locks
+    /// are not writes and are left out. Without it, the snapshot sees the
+    /// committed data only.
+    ///
+    /// The snapshot reads at the start version whatever the isolation
+    /// level, so under read-committed it is older than what the
+    /// transaction reads.
+    pub fn fork_snapshot(
+        &mut self,
+        client: &TransactionClient,
+        include_buffered: bool,
+    ) -> Result<Snapshot> {
+        self.check_active()?;
+        if !Arc::ptr_eq(&self.client, client.inner()) {
+            bail!("a transaction can only be forked from a client of its own connection");
+        }
+        let mut overlay = Overlay::new();
+        if include_buffered {
+            let written = self
+                .write_set
+                .written()
+                .filter(|(key, _, _)| !(self.chunking() && is_chunk_key(key)))
+                .map(|(key, kind, _)| (key.to_owned(), kind))
+                .collect::<Vec<_>>();
+            for (key, kind) in written {
+                let value = match kind {
+                    MutationKind::Put => self.read_buffered(&key)?,
+                    _ => None,
+                };
+                overlay.insert(key, value);
+            }
+        }
+        Ok(Snapshot::new(
+            self.client.snapshot(
+                Timestamp::from_version(self.start_version),
+                TransactionOptions::new_optimistic(),
+            ),
+            self.client.clone(),
+            self.start_version,
+            self.namespace.clone(),
+            self.metrics.clone(),
+        )
+        .with_read_retry(client.snapshot_read_retry())
+        .with_chunked_values(self.chunking())
+        .with_overlay(overlay))
+    }
+
+    /// The keys with a buffered mutation, locks included, as sent to TiKV.
+    pub(crate) fn buffered_keys(&self) -> Vec<Key> {
+        self.write_set
+            .range(Bound::Unbounded, Bound::Unbounded)
+            .map(|(key, _)| self.namespace.encode(key))
+            .collect()
+    }
+
+    /// Marks the transaction as in use until the guard is dropped, failing
+    /// with [`Error::Busy`] if it already is. Taken by the FFI entry points.
+    pub(crate) fn enter(&self) -> Result<BusyGuard> {
+        self.busy.enter("transaction", &self.metrics)
+    }
+
+    /// Starts recording the reads, writes, commit and rollback of this
+    /// transaction, keeping the latest `max_entries`; `None` stops and drops
+    /// the log. Entries are read back with [`op_log`](Self::op_log).
+    pub fn set_op_log(&mut self, max_entries: Option<usize>) {
+        *self.record.op_log() = max_entries.map(OpLog::new);
+    }
+
+    /// The recorded operations, oldest first; empty when not recording.
+    pub fn op_log(&self) -> Vec<OpLogEntry> {
+        self.record
+            .op_log()
+            .as_ref()
+            .map_or_else(Vec::new, OpLog::entries)
+    }
+
+    /// Names the transaction in the warnings of strict mode, e.g. after the
+    /// call site that began it; `None` removes the tag.
+    pub fn set_tag(&mut self, tag: Option<&str>) {
+        self.record.set_tag(tag.map(str::to_owned));
+    }
+
+    /// The contended keys met so far by the reads, locks and commit of the
+    /// transaction, in the order they were first met, so that after a
+    /// failed commit all of them can be locked up front on retry, say.
+    ///
+    /// Only what the errors reported is known: a commit failing on its
+    /// first conflict tells nothing of the keys it did not reach.
+    pub fn conflict_report(&self) -> Vec<ConflictEntry> {
+        self.conflicts.entries()
+    }
+
+    /// Makes every later operation fail with [`Error::DeadlineExceeded`]
+    /// once `deadline_unix_ms`, in Unix milliseconds, has passed, without
+    /// sending anything; one running then is cut short with the same error.
+    /// `None` clears the deadline.
+    ///
+    /// A commit cut short fails the transaction even if its primary got
+    /// committed, as a commit timing out would. [`rollback`](Self::rollback)
+    /// is bounded by nothing, so that its locks can still be released; drop
+    /// the transaction instead to leave them to expire. There is no
+    /// heartbeat task of the wrapper to stop: tikv-client keeps the TTL of
+    /// its locks on its own until the transaction ends.
+    pub fn set_deadline(&mut self, deadline_unix_ms: Option<u64>) {
+        self.deadline = deadline_unix_ms.map(|deadline_ms| {
+            let now_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64);
+            Instant::now() + Duration::from_millis(deadline_ms.saturating_sub(now_ms))
+        });
+    }
+
+    fn check_deadline(&self) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(Error::DeadlineExceeded.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the conflicts `result` failed with, if any, and passes it
+    /// through.
+    fn noting_conflicts<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result {
+            self.conflicts.record(e, &self.namespace);
+        }
+        result
+    }
+
+    /// Runs `f`, recording it in the operation log if there is one, its
+    /// error in the metrics of the client and the conflict report, and its
+    /// outcome in the failover watch. Only a rollback goes ahead past the
+    /// deadline or a failover.
+    fn logged<T>(
+        &mut self,
+        kind: OpKind,
+        key: &[u8],
+        len: impl FnOnce(&T) -> u64,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let result = if kind == OpKind::Rollback {
+            f(self)
+        } else {
+            self.check_deadline()
+                .and_then(|()| self.failover.as_ref().map_or(Ok(()), FailoverWatch::check))
+                .and_then(|()| f(self))
+        };
+        if let Some(failover) = &self.failover {
+            failover.record(&result);
+        }
+        if let Err(e) = &result {
+            self.metrics.record_error(e);
+            self.conflicts.record(e, &self.namespace);
+        }
+        if let Some(op_log) = &mut *self.record.op_log() {
+            op_log.push(
+                kind,
+                key,
+                result.as_ref().map_or(0, len),
+                result.as_ref().err(),
+            );
+        }
+        result
+    }
+
+    /// Runs `f` with the guard of the reserved prefix lifted, for the
+    /// wrapper's own bookkeeping keys.
+    pub(crate) fn with_reserved_keys<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let guard = self.namespace.guards_reserved();
+        self.namespace.set_guard_reserved(false);
+        let result = f(self);
+        self.namespace.set_guard_reserved(guard);
+        result
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly.into());
+        }
+        Ok(())
+    }
+
+    fn check_active(&self) -> Result<()> {
+        match self.state {
+            TransactionState::Active => Ok(()),
+            TransactionState::ExternalTwoPhase => Err(Error::ExternalTwoPhase.into()),
+            state => Err(Error::TransactionFinished { state }.into()),
+        }
+    }
+
+    /// Checks that the two-phase commit functions may drive the commit.
+    fn check_two_phase(&self) -> Result<()> {
+        if self.commit_started {
+            return Err(Error::CommitStarted.into());
+        }
+        match self.state {
+            TransactionState::Active | TransactionState::ExternalTwoPhase => Ok(()),
+            state => Err(Error::TransactionFinished { state }.into()),
+        }
+    }
+
+    /// Passes `result` of a two-phase commit function through, moving the
+    /// transaction to [`TransactionState::Failed`] if it is an error.
+    fn two_phase_step<T>(&mut self, result: Result<T>) -> Result<T> {
+        if result.is_err() {
+            self.state = TransactionState::Failed;
+        }
+        result
+    }
+
+    pub fn is_pessimistic(&self) -> bool {
+        self.pessimistic
+    }
+
+    /// Sets the priority of the requests of this transaction; only
+    /// [`Priority::Normal`] is supported by the pinned tikv-client.
+    pub fn set_priority(&mut self, priority: Priority) -> Result<()> {
+        check_priority(priority)
+    }
+
+    /// The timestamp the locks of this pessimistic transaction are taken at.
+    ///
+    /// The pinned tikv-client does not expose it: it fetches a fresh one from
+    /// PD for every lock request, so locks never lag behind a long-lived
+    /// transaction, but this always fails.
+    pub fn for_update_ts(&self) -> Result<u64> {
+        self.check_for_update_ts()?;
+        Err(unsupported(
+            Capability::ForUpdateTs,
+            "reading for_update_ts".to_owned(),
+        ))
+    }
+
+    /// Moves the timestamp of later lock requests to a fresh one from PD and
+    /// returns it; locks already held keep the timestamp they were taken at.
+    ///
+    /// Always fails, see [`for_update_ts`](Self::for_update_ts).
+    pub fn refresh_for_update_ts(&mut self) -> Result<u64> {
+        self.check_for_update_ts()?;
+        Err(unsupported(
+            Capability::ForUpdateTs,
+            "refreshing for_update_ts".to_owned(),
+        ))
+    }
+
+    fn check_for_update_ts(&self) -> Result<()> {
+        if !self.pessimistic {
+            bail!("an optimistic transaction has no for_update_ts");
+        }
+        Ok(())
+    }
+
+    pub fn isolation(&self) -> IsolationLevel {
+        self.isolation
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        self.logged(OpKind::Get, key, value_len, |txn| {
+            txn.check_active()?;
+            if txn.isolation == IsolationLevel::ReadCommitted && !txn.write_set.is_written(key) {
+                return txn.latest_snapshot()?.get(key);
+            }
+            let value = block_until(txn.deadline, txn.inner.get(txn.namespace.encode_key(key)?))?
+                .map(|value| txn.namespace.decode_value(value));
+            let value = txn.open_chunked(key, value)?;
+            txn.record_value_read(key, &value);
+            Ok(value)
+        })
+    }
+
+    /// Like [`get`](Self::get), also telling whether the value came from the
+    /// mutations the transaction buffered or from TiKV, for debugging reads
+    /// of its own writes.
+    ///
+    /// tikv-client does not say where it read from, so the source is told
+    /// by the keys the wrapper saw being put or deleted: a key only locked
+    /// counts as a network read, even when tikv-client answers it from the
+    /// value it fetched with the lock.
+    pub fn get_traced(&mut self, key: &[u8]) -> Result<TracedValue> {
+        let source = if self.write_set.is_written(key) {
+            ValueSource::Buffer
+        } else {
+            ValueSource::Network
+        };
+        let start = Instant::now();
+        let value = self.get(key)?;
+        Ok(TracedValue {
+            value,
+            source,
+            elapsed: start.elapsed(),
+            region_id: None,
+        })
+    }
+
+    /// Gets the value of `key`, locking it when the transaction is
+    /// pessimistic.
+    pub fn get_for_update(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        self.logged(OpKind::GetForUpdate, key, value_len, |txn| {
+            txn.check_active()?;
+            let encoded = txn.namespace.encode_key(key)?;
+            if txn.pessimistic {
+                txn.check_memory_limit(key, MutationKind::Lock, 0)?;
+            }
+            let value = block_until(txn.deadline, txn.inner.get_for_update(encoded))?
+                .map(|value| txn.namespace.decode_value(value));
+            let value = txn.open_chunked(key, value)?;
+            txn.record_value_read(key, &value);
+            if txn.pessimistic {
+                txn.write_set.record(key, MutationKind::Lock, 0);
+            }
+            Ok(value)
+        })
+    }
+
+    /// Gets the value of `key` and locks it if it exists, leaving an absent
+    /// key unlocked so that concurrent creators of it do not wait on each
+    /// other; the transaction must be pessimistic.
+    ///
+    /// TiKV locks keys whether they exist or not, and this tikv-client can
+    /// neither lock only an existing key nor release the lock of one key,
+    /// so the existence is checked first at the start timestamp of the
+    /// transaction, and only a key found is locked as by
+    /// [`get_for_update`](Self::get_for_update), whose value is the one
+    /// returned; both requests are one operation of the transaction. A key
+    /// created since the transaction started is therefore not locked and
+    /// `None` is returned, as if it were read at the start, until a put of
+    /// it locks it like any; a key deleted since stays locked and `None` is
+    /// returned, as read under the lock. A key the transaction wrote is
+    /// locked already and read as usual.
+    pub fn get_and_lock_if_present(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        self.logged(OpKind::GetForUpdate, key, value_len, |txn| {
+            txn.check_active()?;
+            if !txn.pessimistic {
+                bail!("get_and_lock_if_present needs a pessimistic transaction");
+            }
+            let encoded = txn.namespace.encode_key(key)?;
+            let written = txn.write_set.is_written(key);
+            txn.check_memory_limit(key, MutationKind::Lock, 0)?;
+            let inner = &mut txn.inner;
+            let value = block_until(txn.deadline, async {
+                if !written && inner.get(encoded.clone()).await?.is_none() {
+                    return Ok((false, None));
+                }
+                Ok((true, inner.get_for_update(encoded).await?))
+            });
+            let (locked, value) = value?;
+            let value = value.map(|value| txn.namespace.decode_value(value));
+            let value = txn.open_chunked(key, value)?;
+            txn.record_value_read(key, &value);
+            if locked {
+                txn.write_set.record(key, MutationKind::Lock, 0);
+            }
+            Ok(value)
+        })
+    }
+
+    /// Gets the length of the value of `key`.
+    ///
+    /// TiKV has no request returning only the length, so the value is still
+    /// fetched from the cluster; only its copy to the caller is saved.
+    pub fn get_value_length(&mut self, key: &[u8]) -> Result<Option<u64>> {
+        Ok(self.get(key)?.map(|value| value.len() as u64))
+    }
+
+    /// Copies as much of the value of `key` as fits into `buf` and returns
+    /// the full length of the value, which is larger than `buf` when it was
+    /// truncated.
+    pub fn get_into(&mut self, key: &[u8], buf: &mut [u8]) -> Result<Option<usize>> {
+        Ok(self.get(key)?.map(|value| copy_truncated(&value, buf)))
+    }
+
+    /// Gets the values of `keys`, sorted by key whatever the order of `keys`;
+    /// keys that do not exist are left out.
+    pub fn batch_get<'a>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<Vec<KvPair>> {
+        self.logged(
+            OpKind::BatchGet,
+            &[],
+            |kv_pairs| kv_pairs.len() as u64,
+            |txn| {
+                txn.check_active()?;
+                let keys = keys.into_iter().collect::<Vec<_>>();
+                let (mut kv_pairs, keys) = if txn.isolation == IsolationLevel::ReadCommitted {
+                    let write_set = &txn.write_set;
+                    let (own, latest): (Vec<_>, Vec<_>) =
+                        keys.into_iter().partition(|key| write_set.is_written(key));
+                    (txn.latest_snapshot()?.batch_get(latest)?, own)
+                } else {
+                    (Vec::new(), keys)
+                };
+                let keys = keys
+                    .into_iter()
+                    .map(|key| txn.namespace.encode_key(key))
+                    .collect::<Result<Vec<_>>>()?;
+                let namespace = &txn.namespace;
+                let fetched = block_until(txn.deadline, txn.inner.batch_get(keys))?
+                    .map(|kv_pair| namespace.decode_pair(kv_pair))
+                    .collect::<Vec<_>>();
+                let fetched = txn.open_chunked_pairs(fetched)?;
+                txn.metrics.record_pairs(ReadSource::Transaction, &fetched);
+                kv_pairs.extend(fetched);
+                sort_by_key(&mut kv_pairs);
+                Ok(kv_pairs)
+            },
+        )
+    }
+
+    /// Like [`batch_get`](Self::batch_get), returning the pairs in the order
+    /// of `keys` and only as many as fit in `max_bytes`, see
+    /// [`Snapshot::batch_get_limited`].
+    pub fn batch_get_limited(
+        &mut self,
+        keys: &[&[u8]],
+        max_bytes: u64,
+    ) -> Result<BatchGetLimitedResult> {
+        let found = self.batch_get(keys.iter().copied())?;
+        Ok(limit_batch_get(keys, max_bytes, found))
+    }
+
+    /// Scans up to `limit` pairs of the range, or all of them, page by page,
+    /// when `limit` is 0.
+    pub fn scan(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        if limit == 0 {
+            return scan_pages(
+                start,
+                |start, page_size| self.scan(start, end, page_size),
+                |kv_pair| kv_pair.0.clone().into(),
+            );
+        }
+        if self.namespace.overlaps_reserved(start, end) {
+            return scan_outside_reserved(start, end, limit, |start, end, limit| {
+                self.scan(start, end, limit)
+            });
+        }
+        self.logged(
+            OpKind::Scan,
+            bound_key(start),
+            |kv_pairs| kv_pairs.len() as u64,
+            |txn| {
+                txn.check_active()?;
+                if is_empty_range(start, end) {
+                    return Ok(Vec::new());
+                }
+                if txn.isolation == IsolationLevel::ReadCommitted {
+                    return txn.scan_latest(start, end, limit);
+                }
+                let kv_pairs = if txn.chunking() {
+                    let kv_pairs = scan_hiding_chunks(start, limit, pair_key, |start, limit| {
+                        txn.scan_page(start, end, limit)
+                    })?;
+                    txn.open_chunked_pairs(kv_pairs)?
+                } else {
+                    txn.scan_page(start, end, limit)?
+                };
+                txn.metrics.record_pairs(ReadSource::Transaction, &kv_pairs);
+                Ok(kv_pairs)
+            },
+        )
+    }
+
+    /// Like [`scan`](Self::scan), translating the bounds and the keys
+    /// returned from and to their memcomparable encoding as `codec` tells.
+    pub fn scan_with_codec(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+        codec: ScanCodec,
+    ) -> Result<Vec<KvPair>> {
+        scan_with_codec(start, end, codec, |start, end| self.scan(start, end, limit))
+    }
+
+    /// Like [`scan`](Self::scan), also telling whether pairs of the range
+    /// were left out because of `limit`.
+    pub fn scan_with_truncation(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<ScanResult> {
+        scan_with_truncation(start, limit, |start, limit| self.scan(start, end, limit))
+    }
+
+    /// Like [`scan`](Self::scan), without the values.
+    pub fn scan_keys(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<Key>> {
+        if limit == 0 {
+            return scan_pages(
+                start,
+                |start, page_size| self.scan_keys(start, end, page_size),
+                |key| key.clone().into(),
+            );
+        }
+        if self.namespace.overlaps_reserved(start, end) {
+            return scan_outside_reserved(start, end, limit, |start, end, limit| {
+                self.scan_keys(start, end, limit)
+            });
+        }
+        self.logged(
+            OpKind::ScanKeys,
+            bound_key(start),
+            |keys| keys.len() as u64,
+            |txn| {
+                txn.check_active()?;
+                if is_empty_range(start, end) {
+                    return Ok(Vec::new());
+                }
+                if txn.isolation == IsolationLevel::ReadCommitted {
+                    let kv_pairs = txn.scan_latest(start, end, limit)?;
+                    return Ok(kv_pairs.into_iter().map(|KvPair(key, _)| key).collect());
+                }
+                let keys = if txn.chunking() {
+                    scan_hiding_chunks(start, limit, key_bytes, |start, limit| {
+                        txn.scan_keys_page(start, end, limit)
+                    })?
+                } else {
+                    txn.scan_keys_page(start, end, limit)?
+                };
+                txn.metrics.record_keys(ReadSource::Transaction, &keys);
+                Ok(keys)
+            },
+        )
+    }
+
+    /// Scans up to `limit` pairs of the range through tikv-client.
+    fn scan_page(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        if is_empty_range(start, end) {
+            return Ok(Vec::new());
+        }
+        let namespace = &self.namespace;
+        let range = namespace.encode_range(start, end);
+        Ok(block_until(self.deadline, self.inner.scan(range, limit))?
+            .map(|kv_pair| namespace.decode_pair(kv_pair))
+            .collect())
+    }
+
+    fn scan_keys_page(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<Key>> {
+        if is_empty_range(start, end) {
+            return Ok(Vec::new());
+        }
+        let namespace = &self.namespace;
+        let range = namespace.encode_range(start, end);
+        Ok(
+            block_until(self.deadline, self.inner.scan_keys(range, limit))?
+                .map(|key| namespace.decode(key))
+                .collect(),
+        )
+    }
+
+    /// Whether values over the value limit are chunked, and so read back
+    /// from their chunks.
+    fn chunking(&self) -> bool {
+        self.size_limits.chunk_bytes().is_some()
+    }
+
+    /// `value`, as read for `key`, reassembled from its chunks when it is
+    /// the manifest of a chunked value and the transaction chunks values.
+    fn open_chunked(&mut self, key: &[u8], value: Option<Value>) -> Result<Option<Value>> {
+        let manifest = match value.as_deref().and_then(Manifest::parse) {
+            Some(manifest) if self.chunking() => manifest,
+            _ => return Ok(value),
+        };
+        let chunk_keys = (0..manifest.chunks)
+            .map(|n| Key::from(self.namespace.encode(&chunk_key(key, n))))
+            .collect::<Vec<_>>();
+        let fetched = block_until(self.deadline, self.inner.batch_get(chunk_keys.clone()))?;
+        manifest.assemble(key, &chunk_keys, fetched).map(Some)
+    }
+
+    fn open_chunked_pairs(&mut self, kv_pairs: Vec<KvPair>) -> Result<Vec<KvPair>> {
+        if !self.chunking() {
+            return Ok(kv_pairs);
+        }
+        kv_pairs
+            .into_iter()
+            .map(|KvPair(key, value)| {
+                let value = self.open_chunked(key_bytes(&key), Some(value))?;
+                Ok(KvPair(key, value.unwrap_or_default()))
+            })
+            .collect()
+    }
+
+    /// The value of `key` in the buffer of tikv-client, or as committed
+    /// when it has none, reassembled when chunked.
+    fn read_buffered(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        let value = block_until(self.deadline, self.inner.get(self.namespace.encode(key)))?
+            .map(|value| self.namespace.decode_value(value));
+        self.open_chunked(key, value)
+    }
+
+    fn record_value_read(&self, key: &[u8], value: &Option<Value>) {
+        if let Some(value) = value {
+            self.metrics
+                .record_read(ReadSource::Transaction, 1, (key.len() + value.len()) as u64);
+        }
+    }
+
+    /// A snapshot at a fresh timestamp, for reads under read-committed.
+    fn latest_snapshot(&self) -> Result<Snapshot> {
+        let timestamp = block_until(self.deadline, self.client.current_timestamp())?;
+        let version = timestamp.version();
+        Ok(Snapshot::new(
+            self.client
+                .snapshot(timestamp, TransactionOptions::new_optimistic()),
+            self.client.clone(),
+            version,
+            self.namespace.clone(),
+            self.metrics.clone(),
+        )
+        .with_chunked_values(self.chunking())
+        .counting_as_transaction())
+    }
+
+    /// Scans the latest committed data overlaid with the buffered writes,
+    /// as [`scan_overlaid`] does for forked snapshots.
+    fn scan_latest(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>> {
+        let written = self
+            .write_set
+            .range(start, end)
+            .filter(|&(key, kind)| {
+                kind != MutationKind::Lock && !(self.chunking() && is_chunk_key(key))
+            })
+            .map(|(key, kind)| (key.to_owned(), kind))
+            .collect::<Vec<_>>();
+        let mut overlay = Overlay::new();
+        for (key, kind) in written {
+            if kind == MutationKind::Delete {
+                overlay.insert(key, None);
+            } else if let Some(value) = self.read_buffered(&key)? {
+                overlay.insert(key, Some(value));
+            }
+        }
+        let mut snapshot = self.latest_snapshot()?;
+        scan_overlaid(&overlay, start, end, limit, |fetch| {
+            snapshot.scan(start, end, fetch)
+        })
+    }
+
+    /// Buffers a write of `value` into `key`, failing with
+    /// [`Error::EntryTooLarge`] if either is over the client's
+    /// [`SizeLimits`], unless the value is chunked.
+    ///
+    /// With [`OversizedValues::Chunk`](super::OversizedValues::Chunk), a
+    /// value over the limit is buffered as its chunks and its manifest, and
+    /// every put and delete first reads the key, from the buffer when it
+    /// has a mutation, to delete the chunks of the value it replaces. The
+    /// chunks count in the write set like any key, e.g. in
+    /// [`written_keys`](Self::written_keys) and the audit log.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.logged(
+            OpKind::Put,
+            key,
+            |_| value.len() as u64,
+            |txn| {
+                txn.check_active()?;
+                txn.check_writable()?;
+                let encoded = txn.namespace.encode_key(key)?;
+                txn.check_put_size(key, encoded.len(), value.len())?;
+                txn.check_memory_limit(key, MutationKind::Put, value.len())?;
+                if txn.skip_noop_writes && txn.is_noop_put(key, &encoded, value)? {
+                    txn.metrics.record_skipped_noop_write();
+                    return Ok(());
+                }
+                txn.write_value(key, encoded, value)
+            },
+        )
+    }
+
+    /// Buffers the put of `value`, checked already, into `key`, chunked
+    /// when the transaction chunks values this large.
+    fn write_value(&mut self, key: &[u8], encoded: Key, value: &[u8]) -> Result<()> {
+        match self.size_limits.chunk_bytes() {
+            Some(chunk_bytes) if value.len() > chunk_bytes => {
+                return self.put_chunked(key, encoded, value, chunk_bytes);
+            }
+            Some(_) => self.delete_chunks(key, 0)?,
+            None => {}
+        }
+        block_until(
+            self.deadline,
+            self.inner.put(encoded, self.namespace.encode_value(value)),
+        )?;
+        self.write_set.record(key, MutationKind::Put, value.len());
+        Ok(())
+    }
+
+    /// Makes [`put`](Self::put) read the key first and drop the put when the
+    /// value is the one it already has, buffered or stored, counting it in
+    /// [`MetricsSnapshot::skipped_noop_writes`](super::MetricsSnapshot::skipped_noop_writes).
+    ///
+    /// Every put then pays for a read, answered from the buffer for a key
+    /// already mutated and from TiKV otherwise, which is only a win when
+    /// most of those values are hot there and many puts are no-ops. A
+    /// skipped key stays in the conflict check as if written: an optimistic
+    /// transaction buffers a lock on it, as
+    /// [`assert_unchanged`](Self::assert_unchanged) does, and a pessimistic
+    /// one reads it with [`get_for_update`](Self::get_for_update), locking
+    /// it and comparing with its latest value.
+    pub fn set_skip_noop_writes(&mut self, enabled: bool) {
+        self.skip_noop_writes = enabled;
+    }
+
+    /// Whether `value` is the value `key` already has for this transaction,
+    /// locking the key if so and not mutated yet.
+    fn is_noop_put(&mut self, key: &[u8], encoded: &[u8], value: &[u8]) -> Result<bool> {
+        let mutated = self.write_set.is_written(key);
+        let current = if self.pessimistic {
+            block_until(self.deadline, self.inner.get_for_update(encoded.to_vec()))?
+        } else {
+            block_until(self.deadline, self.inner.get(encoded.to_vec()))?
+        }
+        .map(|current| self.namespace.decode_value(current));
+        let current = self.open_chunked(key, current)?;
+        self.record_value_read(key, &current);
+        if self.pessimistic {
+            self.write_set.record(key, MutationKind::Lock, 0);
+        }
+        if current.as_deref() != Some(value) {
+            return Ok(false);
+        }
+        if !mutated && !self.pessimistic {
+            block_until(self.deadline, self.inner.lock_keys(vec![encoded.to_vec()]))?;
+            self.write_set.record(key, MutationKind::Lock, 0);
+        }
+        Ok(true)
+    }
+
+    /// Checks a put of `value_len` bytes into `key` against the size limits,
+    /// a value to be chunked only by the keys of its chunks.
+    fn check_put_size(&self, key: &[u8], encoded_len: usize, value_len: usize) -> Result<()> {
+        match self.size_limits.chunk_bytes() {
+            Some(chunk_bytes) if value_len > chunk_bytes => {
+                let last = chunk_key(key, ((value_len - 1) / chunk_bytes) as u32);
+                let encoded_len = encoded_len + last.len() - key.len();
+                self.size_limits.check(&last, encoded_len, MANIFEST_LEN)
+            }
+            _ => self.size_limits.check(key, encoded_len, value_len),
+        }
+    }
+
+    /// Buffers `value` as chunks of `chunk_bytes` and their manifest at
+    /// `key`, deleting the chunks of the value it replaces past its own.
+    fn put_chunked(
+        &mut self,
+        key: &[u8],
+        encoded: Vec<u8>,
+        value: &[u8],
+        chunk_bytes: usize,
+    ) -> Result<()> {
+        let manifest = Manifest::of(value, chunk_bytes);
+        self.delete_chunks(key, manifest.chunks)?;
+        for (n, chunk) in value.chunks(chunk_bytes).enumerate() {
+            let chunk_key = chunk_key(key, n as u32);
+            block_until(
+                self.deadline,
+                self.inner
+                    .put(self.namespace.encode(&chunk_key), chunk.to_vec()),
+            )?;
+            self.write_set
+                .record(&chunk_key, MutationKind::Put, chunk.len());
+        }
+        block_until(self.deadline, self.inner.put(encoded, manifest.encode()))?;
+        self.write_set.record(key, MutationKind::Put, MANIFEST_LEN);
+        Ok(())
+    }
+
+    /// Buffers deletes of the chunks from number `from` on of the value of
+    /// `key`, when it is chunked.
+    fn delete_chunks(&mut self, key: &[u8], from: u32) -> Result<()> {
+        let manifest = block_until(self.deadline, self.inner.get(self.namespace.encode(key)))?
+            .as_deref()
+            .and_then(Manifest::parse);
+        let chunks = match manifest {
+            Some(manifest) => manifest.chunks,
+            None => return Ok(()),
+        };
+        for n in from..chunks {
+            let chunk_key = chunk_key(key, n);
+            block_until(
+                self.deadline,
+                self.inner.delete(self.namespace.encode(&chunk_key)),
+            )?;
+            self.write_set.record(&chunk_key, MutationKind::Delete, 0);
+        }
+        Ok(())
+    }
+
+    /// Puts `value` into `key` and returns the value it replaces, read by
+    /// `get_for_update` in a pessimistic transaction, so that the answer
+    /// holds until commit. In an optimistic one the put makes commit fail
+    /// on any change committed since the start, which keeps it just as
+    /// valid. The write is checked before the read, so a put that would be
+    /// refused reads nothing; the read and the put are one operation of the
+    /// transaction, made in one go unless the transaction chunks values, as
+    /// the chunks of the previous value then decide what to write.
+    pub fn put_get_prev(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Value>> {
+        self.logged(
+            OpKind::Put,
+            key,
+            |_| value.len() as u64,
+            |txn| {
+                txn.check_active()?;
+                txn.check_writable()?;
+                let encoded = txn.namespace.encode_key(key)?;
+                txn.check_put_size(key, encoded.len(), value.len())?;
+                txn.check_memory_limit(key, MutationKind::Put, value.len())?;
+                let pessimistic = txn.pessimistic;
+                let put_now = !txn.chunking();
+                let put = put_now.then(|| txn.namespace.encode_value(value));
+                let inner = &mut txn.inner;
+                let previous = block_until(txn.deadline, async {
+                    let previous = if pessimistic {
+                        inner.get_for_update(encoded.clone()).await?
+                    } else {
+                        inner.get(encoded.clone()).await?
+                    };
+                    if let Some(put) = put {
+                        inner.put(encoded.clone(), put).await?;
+                    }
+                    Ok(previous)
+                })?
+                .map(|previous| txn.namespace.decode_value(previous));
+                let previous = txn.open_chunked(key, previous)?;
+                txn.record_value_read(key, &previous);
+                if put_now {
+                    txn.write_set.record(key, MutationKind::Put, value.len());
+                } else {
+                    txn.write_value(key, encoded, value)?;
+                }
+                Ok(previous)
+            },
+        )
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.logged(
+            OpKind::Delete,
+            key,
+            |_| 0,
+            |txn| {
+                txn.check_active()?;
+                txn.check_writable()?;
+                let encoded = txn.namespace.encode_key(key)?;
+                txn.size_limits.check(key, encoded.len(), 0)?;
+                txn.check_memory_limit(key, MutationKind::Delete, 0)?;
+                if txn.chunking() {
+                    txn.delete_chunks(key, 0)?;
+                }
+                block_until(txn.deadline, txn.inner.delete(encoded))?;
+                txn.write_set.record(key, MutationKind::Delete, 0);
+                Ok(())
+            },
+        )
+    }
+
+    /// Makes commit fail with a write conflict if any of `keys` is changed by
+    /// another transaction, without reading or writing their values.
+    ///
+    /// An optimistic transaction buffers a lock mutation on each key, which
+    /// prewrite checks against the writes committed since the start, like a
+    /// put would. A pessimistic transaction locks the keys right away, so
+    /// concurrent writers wait for it instead. No read is needed for either:
+    /// TiKV has no read of a key's version alone, and a get would fetch the
+    /// value.
+    pub fn assert_unchanged<'a>(&mut self, keys: impl IntoIterator<Item = &'a [u8]>) -> Result<()> {
+        self.logged(
+            OpKind::AssertUnchanged,
+            &[],
+            |_| 0,
+            |txn| {
+                txn.check_active()?;
+                txn.check_writable()?;
+                let keys = keys.into_iter().collect::<Vec<_>>();
+                let encoded = keys
+                    .iter()
+                    .map(|key| txn.namespace.encode_key(key))
+                    .collect::<Result<Vec<_>>>()?;
+                for key in &keys {
+                    txn.check_memory_limit(key, MutationKind::Lock, 0)?;
+                }
+                block_until(txn.deadline, txn.inner.lock_keys(encoded))?;
+                for key in keys {
+                    txn.write_set.record(key, MutationKind::Lock, 0);
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// The keys this transaction has put or deleted, in key order, each with
+    /// its latest mutation; locks are left out. It stays available after
+    /// commit, e.g. to invalidate caches, and also after a rollback or a
+    /// failed commit, when nothing was written: check
+    /// [`state`](Self::state).
+    ///
+    /// The keys come from the write set kept for the memory limit, so they
+    /// take no memory of their own beyond the returned copy.
+    pub fn written_keys(&self) -> Vec<(Key, WriteType)> {
+        self.write_set
+            .range(Bound::Unbounded, Bound::Unbounded)
+            .filter_map(|(key, kind)| {
+                let write_type = match kind {
+                    MutationKind::Put => WriteType::Put,
+                    MutationKind::Delete => WriteType::Delete,
+                    MutationKind::Lock => return None,
+                };
+                Some((key.to_vec().into(), write_type))
+            })
+            .collect()
+    }
+
+    /// Releases the pessimistic locks this transaction holds on `keys`,
+    /// leaving it otherwise usable, e.g. to abandon one branch of the work
+    /// after a failed `get_for_update`. Keys it holds no lock on are
+    /// skipped.
+    ///
+    /// The pinned tikv-client sends its pessimistic rollback request only
+    /// internally, on a failed lock, and its mutation buffer cannot drop a
+    /// key, so releasing a held lock always fails for now; the transaction
+    /// is left unchanged.
+    pub fn pessimistic_rollback_keys<'a>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<()> {
+        self.check_active()?;
+        if !self.pessimistic {
+            bail!("only a pessimistic transaction holds locks to roll back");
+        }
+        let held = keys
+            .into_iter()
+            .filter(|key| self.write_set.contains(key))
+            .count();
+        if held > 0 {
+            return Err(unsupported(
+                Capability::PessimisticRollback,
+                format!("releasing the locks on {} keys", held),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Caps the bytes of keys and values this transaction may buffer: a
+    /// mutation that would go over fails with
+    /// [`Error::TransactionTooLarge`] and is not buffered. `None` removes the
+    /// cap. Mutations already buffered are kept even if over a new cap.
+    pub fn set_memory_limit(&mut self, limit: Option<u64>) {
+        self.memory_limit = limit;
+    }
+
+    /// The bytes of keys and values currently buffered, as checked against
+    /// the memory limit. A key mutated several times counts once.
+    pub fn buffered_bytes(&self) -> u64 {
+        self.write_set.bytes()
+    }
+
+    fn check_memory_limit(&self, key: &[u8], kind: MutationKind, value_len: usize) -> Result<()> {
+        if let Some(limit) = self.memory_limit {
+            let size = self.write_set.bytes_after(key, kind, value_len);
+            if size > limit {
+                return Err(Error::TransactionTooLarge { size, limit }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts `value` into `key` only if its current value is `expected`,
+    /// failing with [`Error::PreconditionFailed`] and buffering nothing
+    /// otherwise.
+    ///
+    /// The check holds at commit time too: a pessimistic transaction locks
+    /// the key while reading it, and in an optimistic one the write to the
+    /// key makes commit fail on any change committed since the start.
+    pub fn put_if_equals(&mut self, key: &[u8], expected: &[u8], value: &[u8]) -> Result<()> {
+        self.check_equals(key, expected)?;
+        self.put(key, value)
+    }
+
+    /// Deletes `key` only if its current value is `expected`, with the same
+    /// guarantees as [`put_if_equals`](Self::put_if_equals).
+    pub fn delete_if_equals(&mut self, key: &[u8], expected: &[u8]) -> Result<()> {
+        self.check_equals(key, expected)?;
+        self.delete(key)
+    }
+
+    fn check_equals(&mut self, key: &[u8], expected: &[u8]) -> Result<()> {
+        let current = if self.pessimistic {
+            self.get_for_update(key)?
+        } else {
+            self.get(key)?
+        };
+        if current.as_deref() != Some(expected) {
+            return Err(Error::PreconditionFailed {
+                key: key.to_owned(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Whether nothing has been buffered yet: no put, no delete and no lock.
+    pub fn is_read_only(&self) -> bool {
+        self.write_set.is_empty()
+    }
+
+    /// Commits and returns the commit version, or 0 for a read-only
+    /// transaction, whose commit sends no request to TiKV. When the
+    /// operation log is on, a failed commit carries a summary of it.
+    ///
+    /// With an audit log set on the client, the commit returns once its
+    /// records are written; failing to write them fails the commit with
+    /// [`Error::AuditFailed`], though it did commit.
+    pub fn commit(&mut self) -> Result<u64> {
+        self.logged(
+            OpKind::Commit,
+            &[],
+            |_| 0,
+            |txn| {
+                txn.check_active()?;
+                txn.check_empty_commit()?;
+                txn.commit_started = true;
+                let result = if txn.read_only {
+                    Ok(0)
+                } else if txn.commit_stats && !txn.write_set.is_empty() {
+                    txn.commit_with_stats()
+                } else {
+                    if !txn.write_set.is_empty() {
+                        txn.metrics.record_prewrite();
+                    }
+                    block_until(txn.deadline, txn.inner.commit())
+                        .map(|commit_ts| commit_ts.map_or(0, |ts| ts.version()))
+                };
+                txn.finish_commit(result)
+                    .and_then(|commit_ts| txn.audit_commit(commit_ts))
+            },
+        )
+        .map_err(|e| match &*self.record.op_log() {
+            Some(op_log) => e.context(op_log.summary()),
+            None => e,
+        })
+    }
+
+    /// Applies the empty commit check of strict mode to a commit.
+    fn check_empty_commit(&self) -> Result<()> {
+        if self.read_only || !self.write_set.is_empty() {
+            return Ok(());
+        }
+        match self.empty_commits {
+            EmptyCommits::Allow => Ok(()),
+            EmptyCommits::Warn => {
+                warn!(
+                    "committing transaction {}, which buffered nothing",
+                    self.record.describe()
+                );
+                Ok(())
+            }
+            EmptyCommits::Reject => Err(Error::EmptyCommit.into()),
+        }
+    }
+
+    /// Applies the buffered puts and deletes in independent transactions of
+    /// up to `keys_per_txn` keys each, in key order, for writes too large for
+    /// one transaction that can be applied non-atomically, e.g. idempotent
+    /// rebuilds. The transaction itself is then rolled back and ends up
+    /// committed at the version of the last chunk.
+    ///
+    /// A chunk that fails to commit stops the whole operation with an
+    /// [`Error::ChunkFailed`] context, giving the first key of the chunk:
+    /// every key before it is committed, none from it on. Pessimistic
+    /// transactions holding locks are refused, since their locks would
+    /// block the chunks. The chunks are optimistic transactions with the
+    /// default transaction options the client had when this one began.
+    pub fn commit_chunked(&mut self, keys_per_txn: u32) -> Result<ChunkedCommitResult> {
+        self.logged(
+            OpKind::Commit,
+            &[],
+            |_| 0,
+            |txn| txn.commit_chunks(keys_per_txn),
+        )
+    }
+
+    fn commit_chunks(&mut self, keys_per_txn: u32) -> Result<ChunkedCommitResult> {
+        self.check_active()?;
+        if keys_per_txn == 0 {
+            bail!("keys_per_txn must be positive");
+        }
+        if self.pessimistic && !self.write_set.is_empty() {
+            bail!("a chunked commit cannot run on a pessimistic transaction holding locks");
+        }
+        let mut mutations = Vec::new();
+        for (key, kind) in self.write_set.range(Bound::Unbounded, Bound::Unbounded) {
+            let encoded = self.namespace.encode(key);
+            match kind {
+                MutationKind::Put => {
+                    // Served from the buffer of tikv-client, without a request,
+                    // already in its stored form.
+                    let value = block_until(self.deadline, self.inner.get(encoded.clone()))?
+                        .unwrap_or_default();
+                    mutations.push((encoded, Some(value)));
+                }
+                MutationKind::Delete => mutations.push((encoded, None)),
+                MutationKind::Lock => {}
+            }
+        }
+        let mut result = ChunkedCommitResult::default();
+        for chunk in mutations.chunks(keys_per_txn as usize) {
+            match self.commit_chunk(chunk) {
+                Ok(commit_ts) => {
+                    // From the first chunk on, the transaction is partly
+                    // committed and the two-phase functions must not run.
+                    self.commit_started = true;
+                    result.chunks += 1;
+                    result.last_commit_ts = commit_ts;
+                    if let Err(e) = self.audit_chunk(chunk, commit_ts) {
+                        let _ = block_on(self.inner.rollback());
+                        self.state = TransactionState::Failed;
+                        return Err(e);
+                    }
+                }
+                Err(e) => {
+                    let _ = block_on(self.inner.rollback());
+                    self.state = TransactionState::Failed;
+                    let first_key = self.namespace.decode(chunk[0].0.clone().into());
+                    return Err(e.context(Error::ChunkFailed {
+                        chunk: result.chunks,
+                        first_key: first_key.into(),
+                        last_commit_ts: result.last_commit_ts,
+                    }));
+                }
+            }
+        }
+        if !self.read_only {
+            block_on(self.inner.rollback())?;
+        }
+        self.finish_commit(Ok(result.last_commit_ts))?;
+        Ok(result)
+    }
+
+    /// Writes the records of a chunk committed at `commit_ts` to the audit
+    /// log, each chunk being a commit of its own.
+    fn audit_chunk(&self, chunk: &[(Vec<u8>, Option<Value>)], commit_ts: u64) -> Result<()> {
+        let audit_log = match &self.audit_log {
+            Some(audit_log) => audit_log,
+            None => return Ok(()),
+        };
+        let mutations = chunk.iter().map(|(key, value)| AuditMutation {
+            key,
+            value_len: value.as_ref().map(|value| value.len() as u64),
+        });
+        audit_log
+            .write_commit(commit_ts, mutations)
+            .map_err(|e| e.context(Error::AuditFailed { commit_ts }))
+    }
+
+    /// Commits one chunk of [`commit_chunked`](Self::commit_chunked) in a
+    /// fresh optimistic transaction and returns its commit version.
+    fn commit_chunk(&self, chunk: &[(Vec<u8>, Option<Value>)]) -> Result<u64> {
+        let timestamp = block_until(self.deadline, self.client.current_timestamp())?;
+        let mut txn = self
+            .client
+            .new_transaction_with_options(timestamp, self.chunk_options.clone());
+        let result = block_until(self.deadline, async {
+            for (key, value) in chunk {
+                match value {
+                    Some(value) => txn.put(key.clone(), value.clone()).await?,
+                    None => txn.delete(key.clone()).await?,
+                }
+            }
+            self.metrics.record_prewrite();
+            txn.commit().await
+        });
+        match result {
+            Ok(commit_ts) => Ok(commit_ts.map_or(0, |ts| ts.version())),
+            Err(e) => {
+                let _ = block_on(txn.rollback());
+                Err(e)
+            }
+        }
+    }
+
+    /// Makes [`commit`](Self::commit) record how long each of its phases
+    /// takes, see [`last_commit_stats`](Self::last_commit_stats).
+    ///
+    /// With stats enabled the wrapper drives the two-phase commit itself,
+    /// with the same requests as
+    /// [`prewrite_primary`](Self::prewrite_primary),
+    /// [`commit_primary`](Self::commit_primary) and
+    /// [`commit_secondary`](Self::commit_secondary), plus the timestamp
+    /// fetch timed as [`CommitStats::get_commit_ts`]; read-only transactions
+    /// still commit as usual and record nothing.
+    pub fn set_commit_stats(&mut self, enabled: bool) {
+        self.commit_stats = enabled;
+    }
+
+    /// The phase timings of the last commit made with stats enabled.
+    pub fn last_commit_stats(&self) -> Option<CommitStats> {
+        self.last_commit_stats
+    }
+
+    /// How the transaction was committed, by [`commit`](Self::commit) or
+    /// [`commit_primary`](Self::commit_primary); fails until one succeeded.
+    pub fn commit_info(&self) -> Result<CommitInfo> {
+        match self.commit_info {
+            Some(commit_info) => Ok(commit_info),
+            None => bail!(
+                "the transaction has not been committed (state={:?})",
+                self.state
+            ),
+        }
+    }
+
+    fn commit_with_stats(&mut self) -> Result<u64> {
+        let start = Instant::now();
+        self.metrics.record_prewrite();
+        block_until(self.deadline, self.inner.prewrite_primary(None))?;
+        let prewrite = start.elapsed();
+        block_until(self.deadline, self.client.current_timestamp())?;
+        let get_commit_ts = start.elapsed() - prewrite;
+        let commit_ts = block_until(self.deadline, self.inner.commit_primary())?.version();
+        let commit_primary = start.elapsed() - prewrite - get_commit_ts;
+        self.commit_secondaries(commit_ts);
+        let total = start.elapsed();
+        self.last_commit_stats = Some(CommitStats {
+            prewrite,
+            get_commit_ts,
+            commit_primary,
+            commit_secondary: total - prewrite - get_commit_ts - commit_primary,
+            total,
+            mutation_count: self.write_set.len() as u64,
+        });
+        Ok(commit_ts)
+    }
+
+    pub fn rollback(&mut self) -> Result<()> {
+        self.logged(
+            OpKind::Rollback,
+            &[],
+            |_| 0,
+            |txn| {
+                if txn.state != TransactionState::Failed {
+                    txn.check_active()?;
+                }
+                if !txn.read_only {
+                    block_on(txn.inner.rollback())?;
+                }
+                txn.state = TransactionState::RolledBack;
+                txn.record.finish();
+                Ok(())
+            },
+        )
+    }
+
+    /// Prewrites the primary key of an externally driven two-phase commit.
+    ///
+    /// When `primary_key` is `None` the client picks one from the buffered
+    /// mutations. Returns the primary key and the start version.
+    ///
+    /// A transaction with no buffered mutation has nothing to prewrite: this
+    /// then sends no request and returns an empty key and version 0, and the
+    /// rest of the sequence is just as free.
+    ///
+    /// From this call on the transaction is in
+    /// [`TransactionState::ExternalTwoPhase`].
+    pub fn prewrite_primary(&mut self, primary_key: Option<&[u8]>) -> Result<(Key, u64)> {
+        self.check_two_phase()?;
+        if self.primary_prewritten {
+            bail!("the primary key was already prewritten");
+        }
+        self.state = TransactionState::ExternalTwoPhase;
+        if self.write_set.is_empty() {
+            self.primary_prewritten = true;
+            return Ok((Key::from(Vec::new()), 0));
+        }
+        let start = Instant::now();
+        let primary_key = primary_key.map(|key| self.namespace.encode(key).into());
+        self.metrics.record_prewrite();
+        let result = block_until(self.deadline, self.inner.prewrite_primary(primary_key));
+        debug!("prewrite primary time {:?}", start.elapsed());
+        let result = self.noting_conflicts(result);
+        let (key, ts) = self.two_phase_step(result)?;
+        self.primary_prewritten = true;
+        Ok((self.namespace.decode(key), ts.version()))
+    }
+
+    /// Like [`prewrite_primary`](Self::prewrite_primary), but with the primary
+    /// key picked deterministically by `selection`.
+    pub fn prewrite_primary_with_strategy(
+        &mut self,
+        selection: PrimarySelection,
+    ) -> Result<(Key, u64)> {
+        if self.write_set.is_empty() {
+            return self.prewrite_primary(None);
+        }
+        let primary_key = match selection {
+            PrimarySelection::FirstWritten => self.write_set.first(),
+            PrimarySelection::SmallestKey => self.write_set.smallest(),
+            PrimarySelection::LargestKey => self.write_set.largest(),
+            PrimarySelection::Explicit(key) => Some(key),
+        };
+        let primary_key = match primary_key {
+            Some(key) => key.to_owned(),
+            None => bail!("no buffered mutation to pick a primary key from"),
+        };
+        self.prewrite_primary(Some(&primary_key))
+    }
+
+    /// Prewrites the buffered mutations as secondaries of `primary_key`,
+    /// prewritten by another transaction started at `start_ts`.
+    ///
+    /// A secondary without buffered mutations is usually a bug of the
+    /// coordinator, so it fails unless `allow_empty` is set, in which case
+    /// nothing is sent and the result counts no mutation.
+    ///
+    /// From this call on the transaction is in
+    /// [`TransactionState::ExternalTwoPhase`].
+    pub fn prewrite_secondary(
+        &mut self,
+        primary_key: &[u8],
+        start_ts: u64,
+        allow_empty: bool,
+    ) -> Result<SecondaryPrewriteResult> {
+        self.check_two_phase()?;
+        if self.write_set.is_empty() && !allow_empty {
+            bail!("no buffered mutation to prewrite as a secondary");
+        }
+        self.state = TransactionState::ExternalTwoPhase;
+        if self.write_set.is_empty() {
+            return Ok(SecondaryPrewriteResult::default());
+        }
+        let start = Instant::now();
+        self.metrics.record_prewrite();
+        let result = block_until(
+            self.deadline,
+            self.inner.prewrite_secondary(
+                self.namespace.encode(primary_key).into(),
+                Timestamp::from_version(start_ts),
+            ),
+        );
+        let result = self.noting_conflicts(result);
+        self.two_phase_step(result)?;
+        debug!("prewrite secondary time {:?}", start.elapsed());
+        Ok(SecondaryPrewriteResult {
+            mutation_count: self.write_set.len() as u64,
+            region_count: 0,
+        })
+    }
+
+    /// Commits the primary key prewritten by
+    /// [`prewrite_primary`](Self::prewrite_primary) and returns the commit
+    /// version; this is what commits the transaction as a whole. Returns 0,
+    /// without sending any request, when there is no buffered mutation, and
+    /// otherwise fails, leaving the transaction as it was, before the primary
+    /// was prewritten.
+    pub fn commit_primary(&mut self) -> Result<u64> {
+        self.check_two_phase()?;
+        if self.read_only {
+            return self.finish_commit(Ok(0));
+        }
+        if self.write_set.is_empty() {
+            // A local commit that sends nothing, so free of the deadline.
+            let result = block_on(self.inner.commit()).map(|_| 0);
+            return self.finish_commit(result);
+        }
+        if !self.primary_prewritten {
+            bail!("the primary key was not prewritten");
+        }
+        let start = Instant::now();
+        let result = block_until(self.deadline, self.inner.commit_primary());
+        debug!("commit primary time {:?}", start.elapsed());
+        let result = self.noting_conflicts(result);
+        self.finish_commit(result.map(|commit_ts| commit_ts.version()))
+            .and_then(|commit_ts| self.audit_commit(commit_ts))
+    }
+
+    /// Writes the buffered puts and deletes, committed at `commit_ts`, to
+    /// the audit log if there is one, and passes `commit_ts` through.
+    fn audit_commit(&self, commit_ts: u64) -> Result<u64> {
+        let audit_log = match &self.audit_log {
+            Some(audit_log) if !self.read_only && commit_ts != 0 => audit_log,
+            _ => return Ok(commit_ts),
+        };
+        let keys = self
+            .write_set
+            .written()
+            .map(|(key, kind, value_len)| (self.namespace.encode(key), kind, value_len))
+            .collect::<Vec<_>>();
+        let mutations = keys.iter().map(|(key, kind, value_len)| AuditMutation {
+            key,
+            value_len: match kind {
+                MutationKind::Put => Some(*value_len),
+                _ => None,
+            },
+        });
+        audit_log
+            .write_commit(commit_ts, mutations)
+            .map_err(|e| e.context(Error::AuditFailed { commit_ts }))?;
+        Ok(commit_ts)
+    }
+
+    /// Moves to the state `result` of a commit leads to, counting the writes
+    /// of a successful one in the metrics of the client.
+    fn finish_commit(&mut self, result: Result<u64>) -> Result<u64> {
+        self.state = match result {
+            Ok(commit_ts) => {
+                let protocol = if self.write_set.is_empty() {
+                    CommitProtocol::ReadOnly
+                } else {
+                    CommitProtocol::TwoPhase
+                };
+                self.commit_info = Some(CommitInfo {
+                    protocol,
+                    commit_ts,
+                });
+                let (keys, bytes) = self.write_set.writes();
+                self.metrics.record_write(keys, bytes);
+                TransactionState::Committed
+            }
+            Err(_) => TransactionState::Failed,
+        };
+        if self.state == TransactionState::Committed {
+            self.record.finish();
+        }
+        result
+    }
+
+    /// Commits the secondaries at `commit_ts`, best effort, once the commit
+    /// is decided. This is the one call still made once
+    /// [`commit_primary`](Self::commit_primary) has committed the
+    /// transaction; on a transaction that only prewrote secondaries of
+    /// another one with [`prewrite_secondary`](Self::prewrite_secondary), it
+    /// is what moves it to [`TransactionState::Committed`], counting its
+    /// writes. Sends nothing without buffered mutations, and fails on any
+    /// other transaction, or for a commit version of 0 with mutations.
+    pub fn commit_secondary(&mut self, commit_ts: u64) -> Result<()> {
+        if self.commit_started {
+            return Err(Error::CommitStarted.into());
+        }
+        let secondary_only = match self.state {
+            TransactionState::Committed if self.primary_prewritten || self.write_set.is_empty() => {
+                false
+            }
+            TransactionState::ExternalTwoPhase if !self.primary_prewritten => true,
+            TransactionState::ExternalTwoPhase => bail!("the primary key was not committed"),
+            TransactionState::Active => bail!("no key was prewritten"),
+            state => return Err(Error::TransactionFinished { state }.into()),
+        };
+        if self.write_set.is_empty() {
+            if secondary_only {
+                let result = if self.read_only {
+                    Ok(0)
+                } else {
+                    block_on(self.inner.commit()).map(|_| 0)
+                };
+                self.finish_commit(result)?;
+            }
+            return Ok(());
+        }
+        if commit_ts == 0 {
+            bail!("commit version 0 commits no secondary");
+        }
+        let start = Instant::now();
+        self.commit_secondaries(commit_ts);
+        debug!("commit secondary time {:?}", start.elapsed());
+        if secondary_only {
+            self.finish_commit(Ok(commit_ts))
+                .and_then(|commit_ts| self.audit_commit(commit_ts))?;
+        }
+        Ok(())
+    }
+
+    /// Commits the secondaries at `commit_ts` until the deadline, if any;
+    /// those left are resolved by the readers meeting their locks.
+    fn commit_secondaries(&mut self, commit_ts: u64) {
+        let _ = block_until(self.deadline, async {
+            self.inner
+                .commit_secondary(Timestamp::from_version(commit_ts))
+                .await;
+            Ok(())
+        });
+    }
+}
+
+fn value_len(value: &Option<Value>) -> u64 {
+    value.as_ref().map_or(0, |value| value.len() as u64)
+}
+
+fn bound_key(bound: Bound<&[u8]>) -> &[u8] {
+    match bound {
+        Bound::Included(key) | Bound::Excluded(key) => key,
+        Bound::Unbounded => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{cluster_client, deadline_in};
+    use super::super::{is_retryable, ConflictKind, OversizedValues, TxnOptions};
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn get_and_lock_if_present_leaves_absent_keys_unlocked() {
+        let client = cluster_client("get_and_lock_absent");
+        let mut first = client.begin_pessimistic().unwrap();
+        assert_eq!(first.get_and_lock_if_present(b"k").unwrap(), None);
+
+        // The key is not locked, so a concurrent creator does not wait on
+        // the first transaction.
+        let mut second = client.begin_pessimistic().unwrap();
+        second.set_deadline(deadline_in(2_000));
+        second.put(b"k", b"second").unwrap();
+        second.commit().unwrap();
+
+        // Created after the start of the first transaction, the key is
+        // still absent to it and not locked by it, until it writes it.
+        assert_eq!(first.get_and_lock_if_present(b"k").unwrap(), None);
+        first.put(b"k", b"first").unwrap();
+        first.commit().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn get_and_lock_if_present_locks_present_keys() {
+        let client = cluster_client("get_and_lock_present");
+        let mut setup = client.begin_optimistic().unwrap();
+        setup.put(b"k", b"v").unwrap();
+        setup.commit().unwrap();
+
+        let mut first = client.begin_pessimistic().unwrap();
+        assert_eq!(
+            first.get_and_lock_if_present(b"k").unwrap(),
+            Some(b"v".to_vec())
+        );
+        let mut second = client.begin_pessimistic().unwrap();
+        second.set_deadline(deadline_in(1_000));
+        assert!(second.get_and_lock_if_present(b"k").is_err());
+        second.rollback().unwrap();
+
+        first.put(b"k", b"first").unwrap();
+        first.commit().unwrap();
+        let mut third = client.begin_pessimistic().unwrap();
+        assert_eq!(
+            third.get_and_lock_if_present(b"k").unwrap(),
+            Some(b"first".to_vec())
+        );
+        third.rollback().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn batch_gets_return_the_same_sorted_pairs_whatever_the_key_order() {
+        let client = cluster_client("batch_get_sorted");
+        let keys = (0..20u8)
+            .map(|i| vec![b'k', i * 7 % 20])
+            .collect::<Vec<_>>();
+        let mut setup = client.begin_optimistic().unwrap();
+        for key in &keys {
+            setup.put(key, key).unwrap();
+        }
+        setup.commit().unwrap();
+
+        let mut sorted = keys.clone();
+        sorted.sort();
+        let expected = sorted
+            .iter()
+            .map(|key| KvPair(key.clone().into(), key.clone()))
+            .collect::<Vec<_>>();
+        let mut reversed = sorted.clone();
+        reversed.reverse();
+        let absent = b"absent".to_vec();
+        for order in [&keys, &sorted, &reversed].iter() {
+            let order = order.iter().chain(Some(&absent)).map(Vec::as_slice);
+            let mut txn = client.begin_optimistic().unwrap();
+            // Half the keys come from the write buffer.
+            for key in sorted.iter().step_by(2) {
+                txn.put(key, key).unwrap();
+            }
+            assert_eq!(txn.batch_get(order.clone()).unwrap(), expected);
+            txn.rollback().unwrap();
+            let mut snapshot = client.snapshot().unwrap();
+            assert_eq!(snapshot.batch_get(order).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn commit_stats_time_every_phase() {
+        let client = cluster_client("commit_stats");
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.set_commit_stats(true);
+        txn.put(b"a", b"v").unwrap();
+        txn.put(b"b", b"v").unwrap();
+        assert_eq!(txn.last_commit_stats(), None);
+        let commit_ts = txn.commit().unwrap();
+        let stats = txn.last_commit_stats().unwrap();
+        assert_eq!(stats.mutation_count, 2);
+        assert!(stats.prewrite > Duration::default());
+        assert!(stats.get_commit_ts > Duration::default());
+        assert!(stats.commit_primary > Duration::default());
+        assert_eq!(
+            stats.prewrite + stats.get_commit_ts + stats.commit_primary + stats.commit_secondary,
+            stats.total
+        );
+        let mut snapshot = client.snapshot_at(commit_ts).unwrap();
+        assert_eq!(snapshot.get(b"b").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn read_only_commits_send_no_prewrite() {
+        let client = cluster_client("read_only_commit");
+        let mut setup = client.begin_optimistic().unwrap();
+        setup.put(b"k", b"v").unwrap();
+        setup.commit().unwrap();
+
+        let prewrites = client.metrics().prewrites;
+        let mut txn = client.begin_optimistic().unwrap();
+        assert_eq!(txn.get(b"k").unwrap(), Some(b"v".to_vec()));
+        txn.get_for_update(b"k").unwrap();
+        txn.scan(Bound::Unbounded, Bound::Unbounded, 0).unwrap();
+        assert!(txn.is_read_only());
+        assert_eq!(txn.commit().unwrap(), 0);
+        let mut with_stats = client.begin_optimistic().unwrap();
+        with_stats.set_commit_stats(true);
+        with_stats.get(b"k").unwrap();
+        assert_eq!(with_stats.commit().unwrap(), 0);
+        assert_eq!(client.metrics().prewrites, prewrites);
+
+        let writes: [fn(&mut Transaction) -> Result<()>; 2] =
+            [|txn| txn.put(b"k", b"w"), |txn| txn.delete(b"k")];
+        for write in writes.iter() {
+            let mut txn = client.begin_optimistic().unwrap();
+            write(&mut txn).unwrap();
+            assert!(!txn.is_read_only());
+            assert_ne!(txn.commit().unwrap(), 0);
+        }
+        let mut locking = client.begin_pessimistic().unwrap();
+        locking.get_for_update(b"k").unwrap();
+        assert!(!locking.is_read_only());
+        locking.commit().unwrap();
+        assert_eq!(client.metrics().prewrites, prewrites + 3);
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn read_committed_reads_see_later_commits_and_own_writes() {
+        let client = cluster_client("read_committed");
+        let mut setup = client.begin_optimistic().unwrap();
+        setup.put(b"k/committed", b"old").unwrap();
+        setup.put(b"k/deleted", b"old").unwrap();
+        setup.commit().unwrap();
+
+        let range = (Bound::Included(&b"k/"[..]), Bound::Excluded(&b"k0"[..]));
+        let keys = [&b"k/committed"[..], b"k/deleted", b"k/later", b"k/own"];
+        for &isolation in [
+            IsolationLevel::SnapshotIsolation,
+            IsolationLevel::ReadCommitted,
+        ]
+        .iter()
+        {
+            let mut txn = client.begin_pessimistic_with_isolation(isolation).unwrap();
+            assert_eq!(txn.isolation(), isolation);
+            txn.put(b"k/own", b"own").unwrap();
+            txn.delete(b"k/deleted").unwrap();
+            // Committed after the transaction started.
+            let mut other = client.begin_optimistic().unwrap();
+            other.put(b"k/committed", b"new").unwrap();
+            other.put(b"k/later", b"new").unwrap();
+            other.commit().unwrap();
+
+            let (committed, later) = match isolation {
+                IsolationLevel::ReadCommitted => (&b"new"[..], Some(b"new".to_vec())),
+                _ => (&b"old"[..], None),
+            };
+            assert_eq!(txn.get(b"k/committed").unwrap(), Some(committed.to_vec()));
+            assert_eq!(txn.get(b"k/later").unwrap(), later);
+            assert_eq!(txn.get(b"k/own").unwrap(), Some(b"own".to_vec()));
+            assert_eq!(txn.get(b"k/deleted").unwrap(), None);
+            let mut expected = vec![KvPair(b"k/committed".to_vec().into(), committed.to_vec())];
+            if let Some(later) = later {
+                expected.push(KvPair(b"k/later".to_vec().into(), later));
+            }
+            expected.push(KvPair(b"k/own".to_vec().into(), b"own".to_vec()));
+            assert_eq!(txn.batch_get(keys.iter().copied()).unwrap(), expected);
+            assert_eq!(txn.scan(range.0, range.1, 0).unwrap(), expected);
+            txn.rollback().unwrap();
+
+            let mut reset = client.begin_optimistic().unwrap();
+            reset.put(b"k/committed", b"old").unwrap();
+            reset.delete(b"k/later").unwrap();
+            reset.commit().unwrap();
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn only_writes_to_asserted_keys_abort_an_optimistic_commit() {
+        let client = cluster_client("assert_unchanged");
+        let mut setup = client.begin_optimistic().unwrap();
+        setup.put(b"k/asserted", b"v").unwrap();
+        setup.commit().unwrap();
+
+        for &(written, conflicts) in [(&b"k/unrelated"[..], false), (b"k/asserted", true)].iter() {
+            let mut txn = client.begin_optimistic().unwrap();
+            txn.assert_unchanged(vec![&b"k/asserted"[..], b"k/absent"])
+                .unwrap();
+            txn.put(b"k/own", b"v").unwrap();
+            let mut other = client.begin_optimistic().unwrap();
+            other.put(written, b"w").unwrap();
+            other.commit().unwrap();
+            match txn.commit() {
+                Ok(_) => assert!(!conflicts),
+                Err(e) => assert!(conflicts && is_retryable(&e), "{:?}", e),
+            }
+        }
+        let mut snapshot = client.snapshot().unwrap();
+        assert_eq!(snapshot.get(b"k/asserted").unwrap(), Some(b"w".to_vec()));
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn a_pessimistic_transaction_locks_asserted_keys() {
+        let client = cluster_client("assert_unchanged_pessimistic");
+        let mut txn = client.begin_pessimistic().unwrap();
+        txn.assert_unchanged(vec![&b"k/asserted"[..]]).unwrap();
+        assert!(!txn.is_read_only());
+
+        let mut unrelated = client.begin_pessimistic().unwrap();
+        unrelated.set_deadline(deadline_in(2_000));
+        unrelated.put(b"k/unrelated", b"w").unwrap();
+        unrelated.commit().unwrap();
+        let mut blocked = client.begin_pessimistic().unwrap();
+        blocked.set_deadline(deadline_in(1_000));
+        assert!(blocked.put(b"k/asserted", b"w").is_err());
+        blocked.rollback().unwrap();
+
+        txn.put(b"k/own", b"v").unwrap();
+        txn.commit().unwrap();
+        let mut snapshot = client.snapshot().unwrap();
+        assert_eq!(snapshot.get(b"k/asserted").unwrap(), None);
+        assert_eq!(snapshot.get(b"k/unrelated").unwrap(), Some(b"w".to_vec()));
+    }
+
+    fn is_precondition_failed(e: &Error) -> bool {
+        matches!(e, Error::PreconditionFailed { key } if key == b"k")
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn conditional_writes_check_the_current_value_and_buffer_nothing_on_a_mismatch() {
+        let client = cluster_client("if_equals");
+        for &pessimistic in [false, true].iter() {
+            let begin = || {
+                if pessimistic {
+                    client.begin_pessimistic()
+                } else {
+                    client.begin_optimistic()
+                }
+            };
+            let mut txn = begin().unwrap();
+            assert_fails_with(txn.put_if_equals(b"k", b"a", b"b"), is_precondition_failed);
+            txn.put(b"k", b"a").unwrap();
+            txn.commit().unwrap();
+
+            let mut txn = begin().unwrap();
+            assert_fails_with(txn.put_if_equals(b"k", b"b", b"c"), is_precondition_failed);
+            assert_fails_with(txn.delete_if_equals(b"k", b"b"), is_precondition_failed);
+            // Only a pessimistic check locks what it read.
+            assert_eq!(txn.is_read_only(), !pessimistic);
+            txn.commit().unwrap();
+            let mut snapshot = client.snapshot().unwrap();
+            assert_eq!(snapshot.get(b"k").unwrap(), Some(b"a".to_vec()));
+
+            let mut txn = begin().unwrap();
+            txn.put_if_equals(b"k", b"a", b"b").unwrap();
+            // The buffered value is the one checked from then on.
+            txn.delete_if_equals(b"k", b"b").unwrap();
+            txn.commit().unwrap();
+            let mut snapshot = client.snapshot().unwrap();
+            assert_eq!(snapshot.get(b"k").unwrap(), None);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn a_change_after_an_optimistic_check_aborts_the_commit() {
+        let client = cluster_client("if_equals_conflict");
+        let mut setup = client.begin_optimistic().unwrap();
+        setup.put(b"k", b"a").unwrap();
+        setup.commit().unwrap();
+
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.delete_if_equals(b"k", b"a").unwrap();
+        let mut other = client.begin_optimistic().unwrap();
+        other.put(b"k", b"c").unwrap();
+        other.commit().unwrap();
+        let e = txn.commit().unwrap_err();
+        assert!(is_retryable(&e), "{:?}", e);
+        let mut snapshot = client.snapshot().unwrap();
+        assert_eq!(snapshot.get(b"k").unwrap(), Some(b"c".to_vec()));
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn limited_batch_gets_resume_where_the_budget_ran_out() {
+        let client = cluster_client("batch_get_limited");
+        let mut setup = client.begin_optimistic().unwrap();
+        for key in [&b"k/a"[..], b"k/b", b"k/c"].iter() {
+            setup.put(key, &[0; 7]).unwrap();
+        }
+        setup.commit().unwrap();
+
+        // Pairs of 10 bytes, in the order of the keys, the absent one
+        // taking none of the budget.
+        let keys = [&b"k/c"[..], b"k/absent", b"k/a", b"k/b"];
+        let pair = |key: &[u8]| KvPair(key.to_owned().into(), vec![0; 7]);
+        let mut txn = client.begin_optimistic().unwrap();
+        let mut snapshot = client.snapshot().unwrap();
+        for result in [
+            txn.batch_get_limited(&keys, 25).unwrap(),
+            snapshot.batch_get_limited(&keys, 25).unwrap(),
+        ]
+        .iter()
+        {
+            assert_eq!(result.kv_pairs, [pair(b"k/c"), pair(b"k/a")]);
+            assert!(!result.completed);
+            assert_eq!(result.next_index, 3);
+        }
+        for result in [
+            txn.batch_get_limited(&keys[3..], 25).unwrap(),
+            snapshot.batch_get_limited(&keys[3..], 25).unwrap(),
+        ]
+        .iter()
+        {
+            assert_eq!(result.kv_pairs, [pair(b"k/b")]);
+            assert!(result.completed);
+            assert_eq!(result.next_index, 1);
+        }
+        // A pair over the whole budget is still returned, alone.
+        let result = txn.batch_get_limited(&keys, 1).unwrap();
+        assert_eq!(result.kv_pairs, [pair(b"k/c")]);
+        assert_eq!(result.next_index, 1);
+        txn.rollback().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn conflict_reports_list_the_conflicts_and_locks_met() {
+        let client = cluster_client("conflict_report");
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.put(b"k/conflict", b"v").unwrap();
+        let mut other = client.begin_optimistic().unwrap();
+        other.put(b"k/conflict", b"w").unwrap();
+        other.commit().unwrap();
+        assert!(txn.conflict_report().is_empty());
+        assert!(txn.commit().is_err());
+        let report = txn.conflict_report();
+        assert_eq!(report.len(), 1, "{:?}", report);
+        assert_eq!(report[0].key, b"k/conflict");
+        assert_eq!(report[0].kind, ConflictKind::WriteConflict);
+
+        let mut holder = client.begin_optimistic().unwrap();
+        holder.put(b"k/locked", b"v").unwrap();
+        holder.prewrite_primary(None).unwrap();
+        client.set_default_txn_options(TxnOptions {
+            fail_fast_on_locks: true,
+            ..TxnOptions::default()
+        });
+        let mut reader = client.begin_optimistic().unwrap();
+        assert!(reader.get(b"k/locked").is_err());
+        assert_eq!(
+            reader.conflict_report(),
+            [ConflictEntry {
+                key: b"k/locked".to_vec(),
+                kind: ConflictKind::Locked,
+                other_start_ts: holder.start_version(),
+            }]
+        );
+        reader.rollback().unwrap();
+        holder.commit_primary().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn oversized_values_are_rejected_or_chunked_as_configured() {
+        let mut client = cluster_client("oversized_values");
+        let limited = |oversized_values| SizeLimits {
+            max_key_bytes: None,
+            max_value_bytes: Some(4),
+            oversized_values,
+        };
+        client.set_size_limits(limited(OversizedValues::Reject));
+        let mut txn = client.begin_optimistic().unwrap();
+        assert_fails_with(txn.put(b"k", b"too long"), |e| {
+            matches!(e, Error::EntryTooLarge { .. })
+        });
+        txn.put(b"k", b"four").unwrap();
+        txn.rollback().unwrap();
+
+        client.set_size_limits(limited(OversizedValues::Chunk));
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.put(b"k/big", b"abcdefghij").unwrap();
+        txn.put(b"k/small", b"v").unwrap();
+        txn.commit().unwrap();
+
+        let range = (Bound::Included(&b"k/"[..]), Bound::Excluded(&b"k0"[..]));
+        let big = KvPair(b"k/big".to_vec().into(), b"abcdefghij".to_vec());
+        let small = KvPair(b"k/small".to_vec().into(), b"v".to_vec());
+        let mut snapshot = client.snapshot().unwrap();
+        assert_eq!(snapshot.get(b"k/big").unwrap(), Some(big.1.clone()));
+        assert_eq!(
+            snapshot.scan(range.0, range.1, 0).unwrap(),
+            [big.clone(), small.clone()]
+        );
+        assert_eq!(snapshot.scan(range.0, range.1, 1).unwrap(), [big.clone()]);
+
+        // The manifest is the unit of conflicts.
+        let mut first = client.begin_optimistic().unwrap();
+        first.put(b"k/big", b"0123456789").unwrap();
+        let mut second = client.begin_optimistic().unwrap();
+        second.put(b"k/big", b"9876543210").unwrap();
+        second.commit().unwrap();
+        let e = first.commit().unwrap_err();
+        assert!(is_retryable(&e), "{:?}", e);
+
+        // A delete takes the chunks with it.
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.delete(b"k/big").unwrap();
+        txn.commit().unwrap();
+        client.set_size_limits(SizeLimits::UNLIMITED);
+        let mut raw = client.snapshot().unwrap();
+        assert_eq!(raw.scan(range.0, range.1, 0).unwrap(), [small]);
+    }
+
+    fn assert_fails_with(result: Result<impl std::fmt::Debug>, expected: fn(&Error) -> bool) {
+        let e = result.unwrap_err();
+        assert!(
+            e.downcast_ref::<Error>().map_or(false, expected),
+            "unexpected error: {:#}",
+            e
+        );
+    }
+
+    fn is_external(e: &Error) -> bool {
+        matches!(e, Error::ExternalTwoPhase)
+    }
+
+    fn is_finished(e: &Error) -> bool {
+        matches!(e, Error::TransactionFinished { .. })
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn a_skipped_noop_put_still_conflicts() {
+        let client = cluster_client("skip_noop_conflict");
+        let mut setup = client.begin_optimistic().unwrap();
+        setup.put(b"k", b"v").unwrap();
+        setup.commit().unwrap();
+
+        let skipped = client.metrics().skipped_noop_writes;
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.set_skip_noop_writes(true);
+        txn.put(b"k", b"v").unwrap();
+        assert_eq!(client.metrics().skipped_noop_writes, skipped + 1);
+        assert!(txn.written_keys().is_empty());
+
+        // The key stays locked at commit, so a write committed in between
+        // fails the transaction as if it had put the key.
+        let mut other = client.begin_optimistic().unwrap();
+        other.put(b"k", b"w").unwrap();
+        other.commit().unwrap();
+        let e = txn.commit().unwrap_err();
+        assert!(is_retryable(&e), "{:?}", e);
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn a_skipped_noop_put_of_a_pessimistic_transaction_locks_the_key() {
+        let client = cluster_client("skip_noop_pessimistic");
+        let mut setup = client.begin_optimistic().unwrap();
+        setup.put(b"k", b"v").unwrap();
+        setup.commit().unwrap();
+
+        let mut txn = client.begin_pessimistic().unwrap();
+        txn.set_skip_noop_writes(true);
+        txn.put(b"k", b"v").unwrap();
+        assert!(txn.written_keys().is_empty());
+
+        let mut other = client.begin_pessimistic().unwrap();
+        other.set_deadline(deadline_in(1_000));
+        assert!(other.put(b"k", b"w").is_err());
+        other.rollback().unwrap();
+        txn.commit().unwrap();
+
+        // A put of another value is not skipped.
+        let mut txn = client.begin_pessimistic().unwrap();
+        txn.set_skip_noop_writes(true);
+        txn.put(b"k", b"w").unwrap();
+        assert_eq!(
+            txn.written_keys(),
+            [(Key::from(b"k".to_vec()), WriteType::Put)]
+        );
+        txn.commit().unwrap();
+        let mut snapshot = client.snapshot().unwrap();
+        assert_eq!(snapshot.get(b"k").unwrap(), Some(b"w".to_vec()));
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn two_phase_of_an_empty_transaction_sends_nothing() {
+        let client = cluster_client("two_phase_empty");
+        for strategy in [None, Some(PrimarySelection::SmallestKey)].iter() {
+            let mut txn = client.begin_optimistic().unwrap();
+            // A deadline already past fails whatever would send a request.
+            txn.set_deadline(Some(1));
+            let prewritten = match strategy {
+                Some(strategy) => txn.prewrite_primary_with_strategy(*strategy),
+                None => txn.prewrite_primary(None),
+            };
+            assert_eq!(prewritten.unwrap(), (Key::from(Vec::new()), 0));
+            assert_eq!(txn.state(), TransactionState::ExternalTwoPhase);
+            assert_eq!(txn.commit_primary().unwrap(), 0);
+            txn.commit_secondary(0).unwrap();
+            assert_eq!(txn.state(), TransactionState::Committed);
+            let info = txn.commit_info().unwrap();
+            assert_eq!(info.protocol, CommitProtocol::ReadOnly);
+            assert_eq!(info.commit_ts, 0);
+        }
+
+        // Nor does committing the primary of an empty transaction right away.
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.set_deadline(Some(1));
+        assert_eq!(txn.commit_primary().unwrap(), 0);
+        assert_eq!(txn.state(), TransactionState::Committed);
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn two_phase_of_a_single_key_commits_it_at_the_commit_version() {
+        let client = cluster_client("two_phase_single");
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.put(b"k", b"v").unwrap();
+        let (primary, start_ts) = txn.prewrite_primary(None).unwrap();
+        assert_eq!(primary, Key::from(b"k".to_vec()));
+        assert_eq!(start_ts, txn.start_version());
+        let commit_ts = txn.commit_primary().unwrap();
+        assert!(commit_ts > start_ts);
+        txn.commit_secondary(commit_ts).unwrap();
+        assert_eq!(txn.state(), TransactionState::Committed);
+        assert_eq!(
+            txn.commit_info().unwrap().protocol,
+            CommitProtocol::TwoPhase
+        );
+
+        let mut before = client.snapshot_at(commit_ts - 1).unwrap();
+        assert_eq!(before.get(b"k").unwrap(), None);
+        let mut at = client.snapshot_at(commit_ts).unwrap();
+        assert_eq!(at.get(b"k").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn two_phase_rejects_illegal_orderings() {
+        let client = cluster_client("two_phase_orderings");
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.put(b"k", b"v").unwrap();
+        assert!(txn.commit_primary().is_err());
+        assert!(txn.commit_secondary(1).is_err());
+        assert_eq!(txn.state(), TransactionState::Active);
+
+        let (primary, start_ts) = txn.prewrite_primary(None).unwrap();
+        assert_eq!(primary, Key::from(b"k".to_vec()));
+        assert_eq!(txn.state(), TransactionState::ExternalTwoPhase);
+        assert!(txn.commit_secondary(start_ts + 1).is_err());
+        assert_fails_with(txn.commit(), is_external);
+        assert_fails_with(txn.rollback(), is_external);
+        assert_fails_with(txn.put(b"k2", b"v"), is_external);
+        assert!(txn.prewrite_primary(None).is_err());
+
+        let commit_ts = txn.commit_primary().unwrap();
+        assert!(commit_ts > start_ts);
+        assert_eq!(txn.state(), TransactionState::Committed);
+        assert_fails_with(txn.commit_primary(), is_finished);
+        assert_fails_with(txn.prewrite_primary(None), is_finished);
+        assert_fails_with(txn.prewrite_secondary(b"k", start_ts, false), is_finished);
+        assert_fails_with(txn.commit(), is_finished);
+        assert!(txn.commit_secondary(0).is_err());
+        txn.commit_secondary(commit_ts).unwrap();
+
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.put(b"k", b"v2").unwrap();
+        txn.commit().unwrap();
+        assert_fails_with(txn.prewrite_primary(None), |e| {
+            matches!(e, Error::CommitStarted)
+        });
+        assert_fails_with(txn.commit_primary(), |e| matches!(e, Error::CommitStarted));
+        assert_fails_with(txn.commit_secondary(1), |e| {
+            matches!(e, Error::CommitStarted)
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn participants_of_a_two_phase_commit_are_visible_together_at_the_commit_version() {
+        let client = cluster_client("two_phase_participants");
+        let written_keys = client.metrics().written_keys;
+        let mut primary = client.begin_optimistic().unwrap();
+        primary.put(b"p", b"v").unwrap();
+        primary.put(b"p2", b"v").unwrap();
+        let (primary_key, start_ts) = primary.prewrite_primary(Some(&b"p"[..])).unwrap();
+        let primary_key: Vec<u8> = primary_key.into();
+
+        let participant_keys: [&[u8]; 3] = [b"s0", b"s1", b"s2"];
+        let mut participants = participant_keys
+            .iter()
+            .map(|key| {
+                let mut txn = client.begin_optimistic().unwrap();
+                txn.put(key, b"v").unwrap();
+                let prewritten = txn.prewrite_secondary(&primary_key, start_ts, false);
+                assert_eq!(prewritten.unwrap().mutation_count, 1);
+                txn
+            })
+            .collect::<Vec<_>>();
+        let mut empty = client.begin_optimistic().unwrap();
+        empty
+            .prewrite_secondary(&primary_key, start_ts, true)
+            .unwrap();
+        assert_eq!(client.active_transaction_count(), 5);
+
+        let commit_ts = primary.commit_primary().unwrap();
+        primary.commit_secondary(commit_ts).unwrap();
+        // Committed by the primary, the secondaries still locked are
+        // resolved by the readers.
+        let mut before = client.snapshot_at(commit_ts - 1).unwrap();
+        let mut at = client.snapshot_at(commit_ts).unwrap();
+        for key in participant_keys.iter().chain(&[&b"p"[..], b"p2"]) {
+            assert_eq!(before.get(key).unwrap(), None, "{:?}", key);
+            assert_eq!(at.get(key).unwrap(), Some(b"v".to_vec()), "{:?}", key);
+        }
+
+        for txn in participants.iter_mut().chain(Some(&mut empty)) {
+            assert_eq!(txn.state(), TransactionState::ExternalTwoPhase);
+            txn.commit_secondary(commit_ts).unwrap();
+            assert_eq!(txn.state(), TransactionState::Committed);
+        }
+        assert_eq!(participants[0].commit_info().unwrap().commit_ts, commit_ts);
+        assert_eq!(empty.commit_info().unwrap().commit_ts, 0);
+        assert_fails_with(participants[0].commit_secondary(commit_ts), is_finished);
+        assert_eq!(client.active_transaction_count(), 0);
+        assert_eq!(client.metrics().written_keys, written_keys + 5);
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn two_phase_rolls_back_after_a_failed_step() {
+        let client = cluster_client("two_phase_failed");
+        let mut txn = client.begin_optimistic().unwrap();
+        txn.put(b"k", b"v").unwrap();
+
+        // A write committed since the start conflicts with the prewrite.
+        let mut other = client.begin_optimistic().unwrap();
+        other.put(b"k", b"other").unwrap();
+        other.commit().unwrap();
+
+        assert!(txn.prewrite_primary(None).is_err());
+        assert_eq!(txn.state(), TransactionState::Failed);
+        assert_fails_with(txn.commit_primary(), is_finished);
+        assert_fails_with(txn.commit(), is_finished);
+        txn.rollback().unwrap();
+        assert_eq!(txn.state(), TransactionState::RolledBack);
+        assert_fails_with(txn.rollback(), is_finished);
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn for_update_ts_is_unsupported_by_the_pinned_client() {
+        let client = cluster_client("for_update_ts");
+        let is_unsupported = |e: &Error| {
+            matches!(
+                e,
+                Error::Unsupported {
+                    capability: Capability::ForUpdateTs,
+                    ..
+                }
+            )
+        };
+        let mut txn = client.begin_pessimistic().unwrap();
+        assert_fails_with(txn.for_update_ts(), is_unsupported);
+        assert_fails_with(txn.refresh_for_update_ts(), is_unsupported);
+        txn.rollback().unwrap();
+
+        // An optimistic transaction has none, whatever the capabilities.
+        let mut txn = client.begin_optimistic().unwrap();
+        assert!(txn
+            .for_update_ts()
+            .unwrap_err()
+            .downcast_ref::<Error>()
+            .is_none());
+        assert!(txn
+            .refresh_for_update_ts()
+            .unwrap_err()
+            .downcast_ref::<Error>()
+            .is_none());
+        txn.rollback().unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a TiKV cluster, see cluster_client"]
+    fn pessimistic_rollback_keys_skips_keys_not_locked() {
+        let client = cluster_client("pessimistic_rollback_keys");
+        let mut txn = client.begin_pessimistic().unwrap();
+        txn.pessimistic_rollback_keys(vec![&b"a"[..], b"b"])
+            .unwrap();
+        txn.get_for_update(b"a").unwrap();
+        assert_fails_with(txn.pessimistic_rollback_keys(vec![&b"a"[..], b"b"]), |e| {
+            matches!(
+                e,
+                Error::Unsupported {
+                    capability: Capability::PessimisticRollback,
+                    ..
+                }
+            )
+        });
+        // The failed release left the lock and the transaction as they were.
+        assert_eq!(txn.state(), TransactionState::Active);
+        txn.pessimistic_rollback_keys(vec![&b"b"[..]]).unwrap();
+        txn.rollback().unwrap();
+
+        let mut txn = client.begin_optimistic().unwrap();
+        assert!(txn.pessimistic_rollback_keys(vec![&b"a"[..]]).is_err());
+        txn.rollback().unwrap();
+    }
+}
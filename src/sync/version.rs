@@ -0,0 +1,58 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! TSO versions: the physical time in milliseconds since the Unix epoch,
+//! shifted left by [`LOGICAL_BITS`], plus a logical counter in the low bits.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use chrono::{TimeZone, Utc};
+
+/// The bits of a version holding its logical counter.
+pub const LOGICAL_BITS: u32 = 18;
+
+const MS_PER_YEAR: u64 = 365 * 24 * 60 * 60 * 1000 + 6 * 60 * 60 * 1000;
+
+/// The first version of the millisecond `ms` since the Unix epoch, e.g. to
+/// read as of a wall-clock time. Milliseconds past what a version holds,
+/// after the year 4199, saturate.
+pub fn version_from_unix_ms(ms: u64) -> u64 {
+    ms.min(u64::MAX >> LOGICAL_BITS) << LOGICAL_BITS
+}
+
+/// The physical part of `version`, in milliseconds since the Unix epoch.
+pub fn unix_ms_of_version(version: u64) -> u64 {
+    version >> LOGICAL_BITS
+}
+
+/// Fails unless the physical part of `version`, the `what` of a call, is
+/// within `within_years` of now, naming what the value would be read as
+/// milliseconds instead.
+pub(crate) fn check_version(what: &str, version: u64, within_years: u32) -> Result<()> {
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let physical_ms = unix_ms_of_version(version);
+    let distance = if physical_ms > now_ms {
+        physical_ms - now_ms
+    } else {
+        now_ms - physical_ms
+    };
+    if distance <= within_years as u64 * MS_PER_YEAR {
+        return Ok(());
+    }
+    bail!(
+        "implausible version: {} {} is at {} as a TSO version, more than {} years from now; \
+         as Unix milliseconds it would be {}, which version_from_unix_ms converts",
+        what,
+        version,
+        format_ms(physical_ms),
+        within_years,
+        format_ms(version)
+    )
+}
+
+fn format_ms(ms: u64) -> String {
+    match Utc.timestamp_millis_opt(ms as i64).single() {
+        Some(time) if ms <= i64::MAX as u64 => time.to_rfc3339(),
+        _ => "out of range".to_owned(),
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::ops::Bound;
+
+/// The kind of mutation buffered for a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutationKind {
+    Put,
+    Delete,
+    /// A lock taken without writing, e.g. by a pessimistic `get_for_update`.
+    Lock,
+}
+
+/// The keys a transaction has buffered mutations for, with the size of each
+/// mutation, kept alongside tikv-client's own buffer which cannot be
+/// inspected. Values are not kept, only their lengths.
+#[derive(Default)]
+pub(crate) struct WriteSet {
+    mutations: BTreeMap<Vec<u8>, (MutationKind, u64)>,
+    first: Option<Vec<u8>>,
+    bytes: u64,
+}
+
+impl WriteSet {
+    /// The buffered key and value bytes; a key mutated several times counts
+    /// once, with its latest value.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// The number of keys with a buffered mutation.
+    pub fn len(&self) -> usize {
+        self.mutations.len()
+    }
+
+    /// The number of keys with a buffered put or delete, and their key and
+    /// value bytes; locks are left out.
+    pub fn writes(&self) -> (u64, u64) {
+        self.mutations
+            .values()
+            .filter(|&&(kind, _)| kind != MutationKind::Lock)
+            .fold((0, 0), |(keys, bytes), &(_, size)| (keys + 1, bytes + size))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mutations.is_empty()
+    }
+
+    /// Whether `key` has a buffered mutation, a lock included.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.mutations.contains_key(key)
+    }
+
+    /// Whether `key` has a buffered put or delete.
+    pub fn is_written(&self, key: &[u8]) -> bool {
+        matches!(
+            self.mutations.get(key),
+            Some((MutationKind::Put, _)) | Some((MutationKind::Delete, _))
+        )
+    }
+
+    /// The mutated keys of a non-empty range, in key order.
+    pub fn range<'a>(
+        &'a self,
+        start: Bound<&'a [u8]>,
+        end: Bound<&'a [u8]>,
+    ) -> impl Iterator<Item = (&'a [u8], MutationKind)> {
+        self.mutations
+            .range::<[u8], _>((start, end))
+            .map(|(key, &(kind, _))| (key.as_slice(), kind))
+    }
+
+    /// What [`bytes`](Self::bytes) would be after recording the mutation.
+    pub fn bytes_after(&self, key: &[u8], kind: MutationKind, value_len: usize) -> u64 {
+        match self.mutations.get(key) {
+            Some(_) if kind == MutationKind::Lock => self.bytes,
+            Some(&(_, size)) => self.bytes - size + mutation_size(key, value_len),
+            None => self.bytes + mutation_size(key, value_len),
+        }
+    }
+
+    /// Records a mutation of `key` carrying `value_len` bytes of value, zero
+    /// for anything but a put. Locking a key that is already mutated leaves
+    /// the mutation in place, as tikv-client's buffer does.
+    pub fn record(&mut self, key: &[u8], kind: MutationKind, value_len: usize) {
+        let size = mutation_size(key, value_len);
+        match self.mutations.entry(key.to_owned()) {
+            Entry::Occupied(_) if kind == MutationKind::Lock => {}
+            Entry::Occupied(mut entry) => {
+                self.bytes = self.bytes - entry.get().1 + size;
+                entry.insert((kind, size));
+            }
+            Entry::Vacant(entry) => {
+                if self.first.is_none() {
+                    self.first = Some(entry.key().clone());
+                }
+                self.bytes += size;
+                entry.insert((kind, size));
+            }
+        }
+    }
+
+    /// The buffered puts and deletes in key order, with the length of the
+    /// value of each; locks are left out.
+    pub fn written(&self) -> impl Iterator<Item = (&[u8], MutationKind, u64)> {
+        self.mutations
+            .iter()
+            .filter(|(_, &(kind, _))| kind != MutationKind::Lock)
+            .map(|(key, &(kind, size))| (key.as_slice(), kind, size - key.len() as u64))
+    }
+
+    /// The key mutated first.
+    pub fn first(&self) -> Option<&[u8]> {
+        self.first.as_deref()
+    }
+
+    pub fn smallest(&self) -> Option<&[u8]> {
+        self.mutations.keys().next().map(Vec::as_slice)
+    }
+
+    pub fn largest(&self) -> Option<&[u8]> {
+        self.mutations.keys().next_back().map(Vec::as_slice)
+    }
+}
+
+fn mutation_size(key: &[u8], value_len: usize) -> u64 {
+    (key.len() + value_len) as u64
+}
@@ -7,21 +7,164 @@ using ::rust::cxxbridge1::Box;
 
 namespace tikv_client {
 
+namespace {
+
+::rust::Vec<std::uint8_t> to_rust_vec(const std::string &value) {
+  ::rust::Vec<std::uint8_t> result;
+  result.reserve(value.size());
+  for (auto c : value) {
+    result.push_back(static_cast<std::uint8_t>(c));
+  }
+  return result;
+}
+
+std::optional<std::string> to_optional(const OptionalValue &val) {
+  if (val.is_none) {
+    return std::nullopt;
+  } else {
+    return std::string{val.value.begin(), val.value.end()};
+  }
+}
+
+std::vector<KvPair> to_kv_pairs(const ::rust::Vec<::KvPair> &kv_pairs) {
+  std::vector<KvPair> result;
+  result.reserve(kv_pairs.size());
+  for (auto iter = kv_pairs.begin(); iter != kv_pairs.end(); ++iter) {
+    result.emplace_back(
+        std::string{(iter->key).begin(), (iter->key).end()},
+        std::string{(iter->value).begin(), (iter->value).end()});
+  }
+  return result;
+}
+
+class FunctionRmwCallback : public tikv_client_glue::RmwCallback {
+public:
+  using Function = std::function<std::optional<std::string>(
+      const std::optional<std::string> &)>;
+
+  explicit FunctionRmwCallback(const Function &modify) : _modify(modify) {}
+
+  RmwDecision modify(const OptionalValue &current) const override {
+    auto value = _modify(to_optional(current));
+    RmwDecision decision{};
+    decision.abort = !value;
+    if (value) {
+      decision.value = to_rust_vec(*value);
+    }
+    return decision;
+  }
+
+private:
+  const Function &_modify;
+};
+
+class FunctionTransactionCallback
+    : public tikv_client_glue::TransactionCallback {
+public:
+  using Function = std::function<bool(tikv_client_glue::Transaction &)>;
+
+  explicit FunctionTransactionCallback(const Function &ops) : _ops(ops) {}
+
+  bool run(tikv_client_glue::Transaction &transaction) const override {
+    return _ops(transaction);
+  }
+
+private:
+  const Function &_ops;
+};
+
+// Unlike the callbacks above, which only live for the call they are passed
+// to, this one is kept by the client, so it owns its function.
+class FunctionPressureCallback : public tikv_client_glue::PressureCallback {
+public:
+  using Function = std::function<void(PressureLevel)>;
+
+  explicit FunctionPressureCallback(Function on_pressure)
+      : _on_pressure(std::move(on_pressure)) {}
+
+  void on_pressure(PressureLevel level) const override { _on_pressure(level); }
+
+private:
+  Function _on_pressure;
+};
+
+class FunctionReadTsProvider : public tikv_client_glue::ReadTsProvider {
+public:
+  using Function = std::function<uint64_t()>;
+
+  explicit FunctionReadTsProvider(Function read_ts)
+      : _read_ts(std::move(read_ts)) {}
+
+  uint64_t read_ts() const override { return _read_ts(); }
+
+private:
+  Function _read_ts;
+};
+
+class FunctionFailoverConfirmation
+    : public tikv_client_glue::FailoverConfirmation {
+public:
+  using Function = std::function<bool(uint32_t, uint32_t)>;
+
+  explicit FunctionFailoverConfirmation(Function confirm)
+      : _confirm(std::move(confirm)) {}
+
+  bool confirm_failover(uint32_t consecutive_failures,
+                        uint32_t error_percent) const override {
+    return _confirm(consecutive_failures, error_percent);
+  }
+
+private:
+  Function _confirm;
+};
+
+PartialOutcome to_partial_outcome(const ::PartialOutcome &outcome) {
+  return PartialOutcome{
+      outcome.succeeded_items, outcome.failed_items,
+      std::string{outcome.first_error.begin(), outcome.first_error.end()},
+      std::string{outcome.resume_token.begin(), outcome.resume_token.end()}};
+}
+
+ScanResult to_scan_result(const ::ScanResult &result) {
+  return ScanResult{to_kv_pairs(result.pairs), result.truncated,
+                    std::string{result.last_key.begin(), result.last_key.end()}};
+}
+
+} // namespace
+
 KvPair::KvPair(std::string &&key, std::string &&value)
     : key(std::move(key)), value(std::move(value)) {}
 
 TransactionClient::TransactionClient(
     const std::vector<std::string> &pd_endpoints, const std::string &log_path,
-    uint32_t grpc_timeout)
-    : _client(tikv_client_glue::transaction_client_new(pd_endpoints, log_path,
-                                                       grpc_timeout)) {}
+    uint32_t grpc_timeout, uint32_t max_live_snapshots)
+    : _client(tikv_client_glue::transaction_client_new(
+          pd_endpoints, log_path, grpc_timeout, max_live_snapshots)) {}
 
 TransactionClient::TransactionClient(
     const std::vector<std::string> &pd_endpoints, const std::string &log_path,
     const std::string &ca_path, const std::string &cert_path,
-    const std::string &key_path, uint32_t timeout)
+    const std::string &key_path, uint32_t timeout, Priority priority,
+    uint32_t max_live_snapshots)
     : _client(tikv_client_glue::transaction_client_new_with_config(
-          pd_endpoints, log_path, ca_path, cert_path, key_path, timeout)) {}
+          pd_endpoints, log_path, ca_path, cert_path, key_path, timeout,
+          priority, max_live_snapshots)) {}
+
+TransactionClient::TransactionClient(
+    Box<tikv_client_glue::TransactionClient> client)
+    : _client(std::move(client)) {}
+
+TransactionClient TransactionClient::with_prefix(const std::string &prefix) {
+  return TransactionClient(transaction_client_with_prefix(*_client, prefix));
+}
+
+TransactionClient TransactionClient::in_keyspace(
+    const std::vector<std::string> &pd_endpoints, const std::string &log_path,
+    const std::string &keyspace, uint32_t grpc_timeout,
+    uint32_t max_live_snapshots) {
+  return TransactionClient(tikv_client_glue::transaction_client_new_in_keyspace(
+      pd_endpoints, log_path, keyspace, grpc_timeout, max_live_snapshots));
+}
 
 Transaction TransactionClient::begin() {
   return Transaction(transaction_client_begin(*_client));
@@ -35,14 +178,54 @@ std::shared_ptr<Transaction> TransactionClient::new_optimistic_transaction(uint3
   return std::make_shared<Transaction>(transaction_client_begin_optimistic_with_option(*_client, retry_limit));
 }
 
+Transaction TransactionClient::begin_read_only() {
+  return Transaction(transaction_client_begin_read_only(*_client));
+}
+
 Transaction TransactionClient::begin_pessimistic() {
   return Transaction(transaction_client_begin_pessimistic(*_client));
 }
 
+Transaction
+TransactionClient::begin_pessimistic(const PessimisticOptions &options) {
+  return Transaction(
+      transaction_client_begin_pessimistic_with_options(*_client, options));
+}
+
+Transaction TransactionClient::begin_pessimistic_with_backoff(uint64_t base_ms,
+                                                              uint64_t max_ms,
+                                                              uint32_t attempts) {
+  return Transaction(transaction_client_begin_pessimistic_with_backoff(
+      *_client, base_ms, max_ms, attempts));
+}
+
+Transaction TransactionClient::begin_pessimistic(IsolationLevel isolation) {
+  return Transaction(
+      transaction_client_begin_pessimistic_with_isolation(*_client, isolation));
+}
+
 std::shared_ptr<Snapshot> TransactionClient::snapshot() {
   return std::make_shared<Snapshot>(snapshot_new(*_client));
 }
 
+std::shared_ptr<Snapshot> TransactionClient::snapshot_fail_fast() {
+  return std::make_shared<Snapshot>(snapshot_new_fail_fast(*_client));
+}
+
+std::shared_ptr<Snapshot> TransactionClient::snapshot_pinned() {
+  return std::make_shared<Snapshot>(snapshot_new_pinned(*_client));
+}
+
+void TransactionClient::set_read_ts_provider(
+    std::function<uint64_t()> provider) {
+  client_set_read_ts_provider(
+      *_client, std::make_unique<FunctionReadTsProvider>(std::move(provider)));
+}
+
+void TransactionClient::clear_read_ts_provider() {
+  client_set_read_ts_provider(*_client, nullptr);
+}
+
 std::shared_ptr<Snapshot> TransactionClient::snapshot(uint64_t timestamp) {
   return std::make_shared<Snapshot>(
       snapshot_new_with_timestamp(*_client, timestamp));
@@ -56,6 +239,267 @@ void TransactionClient::gc(uint64_t safe_point) {
   client_gc(*_client, safe_point);
 }
 
+std::string TransactionClient::current_pd() {
+  auto endpoint = client_current_pd(*_client);
+  return std::string{endpoint.begin(), endpoint.end()};
+}
+
+uint64_t TransactionClient::pd_leader_changes() {
+  return client_pd_leader_changes(*_client);
+}
+
+void TransactionClient::set_size_limits(uint64_t max_key_bytes,
+                                        uint64_t max_value_bytes) {
+  client_set_size_limits(*_client, max_key_bytes, max_value_bytes);
+}
+
+void TransactionClient::set_strict_mode(uint32_t max_active_transactions,
+                                        EmptyCommits empty_commits,
+                                        uint64_t report_age_ms) {
+  client_set_strict_mode(*_client, max_active_transactions, empty_commits,
+                         report_age_ms);
+}
+
+void TransactionClient::clear_strict_mode() {
+  client_clear_strict_mode(*_client);
+}
+
+uint64_t TransactionClient::active_transaction_count() const {
+  return client_active_transaction_count(*_client);
+}
+
+SnapshotStats TransactionClient::snapshot_stats() const {
+  return client_snapshot_stats(*_client);
+}
+
+void TransactionClient::set_oversized_values(OversizedValues policy) {
+  client_set_oversized_values(*_client, policy);
+}
+
+void TransactionClient::set_default_txn_options(const TxnOptions &options) {
+  client_set_default_txn_options(*_client, options);
+}
+
+TxnOptions TransactionClient::default_txn_options() {
+  return client_get_default_txn_options(*_client);
+}
+
+void TransactionClient::set_guard_reserved(bool guard) {
+  client_set_guard_reserved(*_client, guard);
+}
+
+void TransactionClient::set_strict_versions(uint32_t within_years) {
+  client_set_strict_versions(*_client, within_years);
+}
+
+void TransactionClient::set_timestamp_retry(uint32_t max_attempts,
+                                            uint64_t budget_ms) {
+  client_set_timestamp_retry(*_client, max_attempts, budget_ms);
+}
+
+void TransactionClient::set_pressure_callback(
+    std::function<void(PressureLevel)> callback, uint64_t high_watermark,
+    uint64_t low_watermark) {
+  client_set_pressure_callback(
+      *_client, std::make_unique<FunctionPressureCallback>(std::move(callback)),
+      high_watermark, low_watermark);
+}
+
+void TransactionClient::clear_pressure_callback() {
+  client_set_pressure_callback(*_client, nullptr, 0, 0);
+}
+
+void TransactionClient::set_audit_log(const std::string &path,
+                                      const std::string &client_tag,
+                                      uint64_t max_file_bytes,
+                                      uint32_t max_rotated_files,
+                                      AuditFsync fsync) {
+  client_set_audit_log(*_client, path, client_tag, max_file_bytes,
+                       max_rotated_files, fsync);
+}
+
+void TransactionClient::clear_audit_log() { client_clear_audit_log(*_client); }
+
+void TransactionClient::set_mirror(const TransactionClient &mirror,
+                                   uint32_t sample_rate_percent,
+                                   uint32_t ops_mask) {
+  client_set_mirror(*_client, *mirror._client, sample_rate_percent, ops_mask);
+}
+
+void TransactionClient::clear_mirror() { client_clear_mirror(*_client); }
+
+void TransactionClient::set_snapshot_read_retry(uint32_t max_attempts,
+                                                uint64_t base_delay_ms,
+                                                uint64_t max_delay_ms) {
+  client_set_snapshot_read_retry(*_client, max_attempts, base_delay_ms,
+                                 max_delay_ms);
+}
+
+void TransactionClient::set_compression(Compression compression,
+                                        uint64_t min_value_bytes) {
+  client_set_compression(*_client, compression, min_value_bytes);
+}
+
+void TransactionClient::set_allow_empty_keys(bool allow) {
+  client_set_allow_empty_keys(*_client, allow);
+}
+
+::rust::Vec<CheckItem> TransactionClient::self_check(uint64_t timeout_ms,
+                                                     bool write_probe) {
+  return client_self_check(*_client, timeout_ms, write_probe);
+}
+
+MetricsSnapshot TransactionClient::metrics() { return client_metrics(*_client); }
+
+void TransactionClient::reset_metrics() { client_reset_metrics(*_client); }
+
+void TransactionClient::set_log_failure_mode(LogFailureMode mode) {
+  client_set_log_failure_mode(*_client, mode);
+}
+
+void TransactionClient::set_region_error_warn_threshold(uint64_t per_minute) {
+  client_set_region_error_warn_threshold(*_client, per_minute);
+}
+
+BulkWriter TransactionClient::bulk_write(uint32_t keys_per_txn,
+                                         uint32_t concurrency) {
+  return BulkWriter(client_bulk_write(*_client, keys_per_txn, concurrency));
+}
+
+::rust::Vec<StoreMetric> TransactionClient::store_metrics() {
+  return client_store_metrics(*_client);
+}
+
+ClockSkewReport TransactionClient::check_clock_skew(uint64_t max_skew_ms) {
+  return client_check_clock_skew(*_client, max_skew_ms);
+}
+
+FinalizeResult TransactionClient::finalize_commit(const std::string &primary_key,
+                                                  uint64_t start_ts,
+                                                  uint64_t commit_ts) {
+  return client_finalize_commit(*_client, primary_key, start_ts, commit_ts);
+}
+
+SafepointGuard TransactionClient::register_service_safepoint(
+    const std::string &service_id, uint64_t safepoint_ts, uint64_t ttl_secs) {
+  return SafepointGuard(client_register_service_safepoint(
+      *_client, service_id, safepoint_ts, ttl_secs));
+}
+
+std::string TransactionClient::read_modify_write(
+    const std::string &key,
+    const std::function<std::optional<std::string>(
+        const std::optional<std::string> &)> &modify,
+    uint32_t max_retries) {
+  FunctionRmwCallback callback(modify);
+  auto value = client_rmw(*_client, key, callback, max_retries);
+  return std::string{value.begin(), value.end()};
+}
+
+uint64_t TransactionClient::run_optimistic(
+    const std::function<bool(tikv_client_glue::Transaction &)> &ops,
+    uint32_t max_attempts, uint64_t backoff_base_ms, uint64_t backoff_max_ms) {
+  FunctionTransactionCallback callback(ops);
+  return client_run_optimistic(*_client, callback, max_attempts,
+                               backoff_base_ms, backoff_max_ms);
+}
+
+uint64_t TransactionClient::delete_range_chunked(
+    const std::string &start, Bound start_bound, const std::string &end,
+    Bound end_bound, uint32_t keys_per_txn, bool pessimistic) {
+  return client_delete_range_chunked(*_client, start, start_bound, end,
+                                     end_bound, keys_per_txn, pessimistic);
+}
+
+PartialOutcome TransactionClient::delete_range_partial(
+    const std::string &start, Bound start_bound, const std::string &end,
+    Bound end_bound, uint32_t keys_per_txn, bool pessimistic) {
+  return to_partial_outcome(client_delete_range_partial(
+      *_client, start, start_bound, end, end_bound, keys_per_txn, pessimistic));
+}
+
+uint64_t TransactionClient::copy_range(Snapshot &source,
+                                       const std::string &start,
+                                       Bound start_bound,
+                                       const std::string &end, Bound end_bound,
+                                       uint32_t keys_per_txn,
+                                       uint32_t concurrency) {
+  return client_copy_range(*_client, *source._snapshot, start, start_bound, end,
+                           end_bound, keys_per_txn, concurrency);
+}
+
+PartialOutcome TransactionClient::copy_range_partial(
+    Snapshot &source, const std::string &start, Bound start_bound,
+    const std::string &end, Bound end_bound, uint32_t keys_per_txn,
+    uint32_t concurrency) {
+  return to_partial_outcome(
+      client_copy_range_partial(*_client, *source._snapshot, start, start_bound,
+                                end, end_bound, keys_per_txn, concurrency));
+}
+
+std::vector<DiffEntry> TransactionClient::range_diff(
+    const std::string &start, Bound start_bound, const std::string &end,
+    Bound end_bound, uint64_t old_version, uint64_t new_version,
+    uint64_t limit) {
+  auto diff = client_range_diff(*_client, start, start_bound, end, end_bound,
+                                old_version, new_version, limit);
+  std::vector<DiffEntry> result;
+  result.reserve(diff.size());
+  for (auto iter = diff.begin(); iter != diff.end(); ++iter) {
+    result.push_back(DiffEntry{
+        std::string{(iter->key).begin(), (iter->key).end()}, iter->change,
+        iter->new_value_len});
+  }
+  return result;
+}
+
+std::vector<MvccVersion>
+TransactionClient::get_mvcc_versions(const std::string &key,
+                                     uint32_t max_versions) {
+  auto versions = client_get_mvcc_versions(*_client, key, max_versions);
+  std::vector<MvccVersion> result;
+  result.reserve(versions.size());
+  for (auto iter = versions.begin(); iter != versions.end(); ++iter) {
+    std::optional<std::string> value;
+    if (iter->write_type == WriteType::Put) {
+      value = std::string{(iter->value).begin(), (iter->value).end()};
+    }
+    result.push_back(MvccVersion{iter->commit_ts, iter->write_type,
+                                 std::move(value)});
+  }
+  return result;
+}
+
+std::vector<VersionedValue>
+TransactionClient::multi_version_get(const std::string &key,
+                                     const std::vector<uint64_t> &timestamps) {
+  auto values = client_multi_version_get(*_client, key, timestamps);
+  std::vector<VersionedValue> result;
+  result.reserve(values.size());
+  for (auto iter = values.begin(); iter != values.end(); ++iter) {
+    result.push_back(VersionedValue{to_optional(iter->value),
+                                    std::string(iter->error)});
+  }
+  return result;
+}
+
+uint32_t
+TransactionClient::presplit_for_keys(const std::vector<std::string> &sample_keys,
+                                     bool scatter) {
+  return client_presplit_for_keys(*_client, sample_keys, scatter);
+}
+
+uint64_t TransactionClient::import_file(const std::string &file_path,
+                                        uint32_t keys_per_txn) {
+  return client_import_file(*_client, file_path, keys_per_txn);
+}
+
+uint64_t TransactionClient::purge_expired(const std::string &prefix,
+                                          uint64_t now,
+                                          uint32_t keys_per_txn) {
+  return client_purge_expired(*_client, prefix, now, keys_per_txn);
+}
+
 Transaction::Transaction(Box<tikv_client_glue::Transaction> txn)
     : _txn(std::move(txn)) {}
 
@@ -68,6 +512,26 @@ std::optional<std::string> Transaction::get(const std::string &key) {
   }
 }
 
+TracedValue Transaction::get_traced(const std::string &key) {
+  auto traced = transaction_get_traced(*_txn, key);
+  std::optional<std::string> value;
+  if (!traced.is_none) {
+    value = std::string{traced.value.begin(), traced.value.end()};
+  }
+  return TracedValue{std::move(value), static_cast<ValueSource>(traced.source),
+                     traced.elapsed_us, traced.region_id};
+}
+
+uint64_t Transaction::start_version() {
+  return transaction_start_version(*_txn);
+}
+
+Snapshot Transaction::fork_snapshot(const TransactionClient &client,
+                                    bool include_buffered) {
+  return Snapshot(
+      transaction_fork_snapshot(*_txn, *client._client, include_buffered));
+}
+
 std::optional<std::string> Transaction::get_for_update(const std::string &key) {
   auto val = transaction_get_for_update(*_txn, key);
   if (val.is_none) {
@@ -77,6 +541,32 @@ std::optional<std::string> Transaction::get_for_update(const std::string &key) {
   }
 }
 
+std::optional<std::string>
+Transaction::get_and_lock_if_present(const std::string &key) {
+  auto val = transaction_get_and_lock_if_present(*_txn, key);
+  if (val.is_none) {
+    return std::nullopt;
+  } else {
+    return std::string{val.value.begin(), val.value.end()};
+  }
+}
+
+std::optional<uint64_t>
+Transaction::get_value_length(const std::string &key) {
+  auto len = transaction_get_value_length(*_txn, key);
+  if (len.is_none) {
+    return std::nullopt;
+  } else {
+    return len.length;
+  }
+}
+
+GetIntoResult Transaction::get_into(const std::string &key, uint8_t *buf,
+                                    size_t buf_len) {
+  return transaction_get_into(*_txn, key,
+                              ::rust::Slice<uint8_t>(buf, buf_len));
+}
+
 std::vector<KvPair>
 Transaction::batch_get(const std::vector<std::string> &keys) {
   auto kv_pairs = transaction_batch_get(*_txn, keys);
@@ -90,6 +580,14 @@ Transaction::batch_get(const std::vector<std::string> &keys) {
   return result;
 }
 
+BatchGetLimitedResult
+Transaction::batch_get_limited(const std::vector<std::string> &keys,
+                               uint64_t max_bytes) {
+  auto result = transaction_batch_get_limited(*_txn, keys, max_bytes);
+  return BatchGetLimitedResult{to_kv_pairs(result.pairs), result.completed,
+                               result.next_index};
+}
+
 std::vector<KvPair>
 Transaction::batch_get_for_update(const std::vector<std::string> &keys) {
   auto kv_pairs = transaction_batch_get_for_update(*_txn, keys);
@@ -103,9 +601,45 @@ Transaction::batch_get_for_update(const std::vector<std::string> &keys) {
   return result;
 }
 
+std::vector<WrittenKey> Transaction::written_keys() {
+  auto written = transaction_written_keys(*_txn);
+  std::vector<WrittenKey> result;
+  result.reserve(written.size());
+  for (auto iter = written.begin(); iter != written.end(); ++iter) {
+    result.push_back(WrittenKey{
+        std::string{(iter->key).begin(), (iter->key).end()}, iter->write_type});
+  }
+  return result;
+}
+
+std::vector<ConflictEntry> Transaction::conflict_report() {
+  auto entries = transaction_conflict_report(*_txn);
+  std::vector<ConflictEntry> result;
+  result.reserve(entries.size());
+  for (auto iter = entries.begin(); iter != entries.end(); ++iter) {
+    result.push_back(ConflictEntry{
+        std::string{(iter->key).begin(), (iter->key).end()},
+        static_cast<ConflictKind>(iter->kind), iter->other_start_ts});
+  }
+  return result;
+}
+
+void Transaction::pessimistic_rollback_keys(
+    const std::vector<std::string> &keys) {
+  transaction_pessimistic_rollback_keys(*_txn, keys);
+}
+
+uint64_t Transaction::region_span(const TransactionClient &client) {
+  return transaction_region_span(*_txn, *client._client);
+}
+
+void Transaction::assert_unchanged(const std::vector<std::string> &keys) {
+  transaction_assert_unchanged(*_txn, keys);
+}
+
 std::vector<KvPair> Transaction::scan(const std::string &start,
                                       Bound start_bound, const std::string &end,
-                                      Bound end_bound, std::uint32_t limit) {
+                                      Bound end_bound, std::uint64_t limit) {
   auto kv_pairs =
       transaction_scan(*_txn, start, start_bound, end, end_bound, limit);
   std::vector<KvPair> result;
@@ -122,7 +656,7 @@ std::vector<std::string> Transaction::scan_keys(const std::string &start,
                                                 Bound start_bound,
                                                 const std::string &end,
                                                 Bound end_bound,
-                                                std::uint32_t limit) {
+                                                std::uint64_t limit) {
   auto keys =
       transaction_scan_keys(*_txn, start, start_bound, end, end_bound, limit);
   std::vector<std::string> result;
@@ -133,10 +667,60 @@ std::vector<std::string> Transaction::scan_keys(const std::string &start,
   return result;
 }
 
+ScanResult Transaction::scan_with_truncation(const std::string &start,
+                                             Bound start_bound,
+                                             const std::string &end,
+                                             Bound end_bound,
+                                             std::uint64_t limit) {
+  return to_scan_result(transaction_scan_with_truncation(
+      *_txn, start, start_bound, end, end_bound, limit));
+}
+
+std::vector<KvPair>
+Transaction::scan_with_codec(const std::string &start, Bound start_bound,
+                             const std::string &end, Bound end_bound,
+                             std::uint64_t limit, bool bounds_are_decoded,
+                             bool decode_keys) {
+  return to_kv_pairs(transaction_scan_with_codec(*_txn, start, start_bound, end,
+                                                 end_bound, limit,
+                                                 bounds_are_decoded,
+                                                 decode_keys));
+}
+
+PackedScanResult Transaction::scan_packed(const std::string &start,
+                                          Bound start_bound,
+                                          const std::string &end,
+                                          Bound end_bound,
+                                          std::uint64_t limit) {
+  return transaction_scan_packed(*_txn, start, start_bound, end, end_bound,
+                                 limit);
+}
+
 void Transaction::put(const std::string &key, const std::string &value) {
   transaction_put(*_txn, key, value);
 }
 
+std::optional<std::string> Transaction::put_get_prev(const std::string &key,
+                                                     const std::string &value) {
+  auto val = transaction_put_get_prev(*_txn, key, value);
+  if (val.is_none) {
+    return std::nullopt;
+  } else {
+    return std::string{val.value.begin(), val.value.end()};
+  }
+}
+
+void Transaction::put_with_expiry(const std::string &key,
+                                  const std::string &value,
+                                  uint64_t expire_at) {
+  transaction_put_with_expiry(*_txn, key, value, expire_at);
+}
+
+std::optional<std::string> Transaction::get_with_expiry(const std::string &key,
+                                                        uint64_t now) {
+  return to_optional(transaction_get_with_expiry(*_txn, key, now));
+}
+
 void Transaction::batch_put(const std::vector<KvPair> &kvs) {
   for (auto iter = kvs.begin(); iter != kvs.end(); ++iter) {
     transaction_put(*_txn, iter->key, iter->value);
@@ -147,7 +731,82 @@ void Transaction::remove(const std::string &key) {
   transaction_delete(*_txn, key);
 }
 
-void Transaction::commit() { transaction_commit(*_txn); }
+void Transaction::put_if_equals(const std::string &key,
+                                const std::string &expected,
+                                const std::string &new_value) {
+  transaction_put_if_equals(*_txn, key, expected, new_value);
+}
+
+void Transaction::remove_if_equals(const std::string &key,
+                                   const std::string &expected) {
+  transaction_delete_if_equals(*_txn, key, expected);
+}
+
+void Transaction::set_memory_limit(uint64_t bytes) {
+  transaction_set_memory_limit(*_txn, bytes);
+}
+
+uint64_t Transaction::buffered_bytes() const {
+  return transaction_buffered_bytes(*_txn);
+}
+
+bool Transaction::is_readonly() const {
+  return transaction_is_readonly(*_txn);
+}
+
+TransactionState Transaction::state() const {
+  return static_cast<TransactionState>(transaction_state(*_txn));
+}
+
+void Transaction::set_priority(Priority priority) {
+  transaction_set_priority(*_txn, priority);
+}
+
+uint64_t Transaction::for_update_ts() const {
+  return transaction_for_update_ts(*_txn);
+}
+
+uint64_t Transaction::refresh_for_update_ts() {
+  return transaction_refresh_for_update_ts(*_txn);
+}
+
+uint64_t Transaction::commit() { return transaction_commit(*_txn); }
+
+void Transaction::set_commit_stats(bool enabled) {
+  transaction_set_commit_stats(*_txn, enabled);
+}
+
+void Transaction::enable_op_log(uint32_t max_entries) {
+  transaction_enable_op_log(*_txn, max_entries);
+}
+
+::rust::Vec<OpLogEntry> Transaction::dump_op_log() const {
+  return transaction_dump_op_log(*_txn);
+}
+
+void Transaction::set_tag(const std::string &tag) {
+  transaction_set_tag(*_txn, tag);
+}
+
+void Transaction::set_deadline(uint64_t deadline_unix_ms) {
+  transaction_set_deadline(*_txn, deadline_unix_ms);
+}
+
+void Transaction::enable_skip_noop_writes() {
+  transaction_enable_skip_noop_writes(*_txn);
+}
+
+ChunkedCommitResult Transaction::commit_chunked(uint32_t keys_per_txn) {
+  return transaction_commit_chunked(*_txn, keys_per_txn);
+}
+
+CommitInfo Transaction::commit_info() const {
+  return transaction_commit_info(*_txn);
+}
+
+CommitStats Transaction::last_commit_stats() const {
+  return transaction_last_commit_stats(*_txn);
+}
 void Transaction::rollback() { transaction_rollback(*_txn); }
 
 std::pair<std::string, uint64_t>
@@ -157,9 +816,20 @@ Transaction::prewrite_primary(const std::string &primary_key) {
                         ret.version);
 }
 
-void Transaction::prewrite_secondary(const std::string &primary_key,
-                                     uint64_t start_ts) {
-  transaction_prewrite_secondary(*_txn, primary_key, start_ts);
+std::pair<std::string, uint64_t>
+Transaction::prewrite_primary(PrimarySelection strategy,
+                              const std::string &primary_key) {
+  auto ret = transaction_prewrite_primary_with_strategy(*_txn, strategy,
+                                                        primary_key);
+  return std::make_pair(std::string{ret.key.begin(), ret.key.end()},
+                        ret.version);
+}
+
+SecondaryPrewriteResult
+Transaction::prewrite_secondary(const std::string &primary_key,
+                                uint64_t start_ts, bool allow_empty) {
+  return transaction_prewrite_secondary(*_txn, primary_key, start_ts,
+                                        allow_empty);
 }
 
 uint64_t Transaction::commit_primary() {
@@ -170,6 +840,29 @@ void Transaction::commit_secondary(uint64_t commit_ts) {
   transaction_commit_secondary(*_txn, commit_ts);
 }
 
+SafepointGuard::SafepointGuard(Box<tikv_client_glue::SafepointGuard> guard)
+    : _guard(std::move(guard)) {}
+
+bool SafepointGuard::healthy() const { return safepoint_guard_healthy(*_guard); }
+
+void SafepointGuard::release() { safepoint_guard_release(*_guard); }
+
+BulkWriter::BulkWriter(Box<tikv_client_glue::BulkWriter> writer)
+    : _writer(std::move(writer)) {}
+
+void BulkWriter::push(const std::vector<std::string> &keys,
+                      const std::vector<std::string> &values) {
+  bulk_write_push(*_writer, keys, values);
+}
+
+BulkWriteProgress BulkWriter::progress() const {
+  return bulk_write_progress(*_writer);
+}
+
+::rust::Vec<BulkWriteFailure> BulkWriter::finish() {
+  return bulk_write_finish(*_writer);
+}
+
 Snapshot::Snapshot(Box<tikv_client_glue::Snapshot> snapshot)
     : _snapshot(std::move(snapshot)) {}
 
@@ -182,6 +875,25 @@ std::optional<std::string> Snapshot::get(const std::string &key) {
   }
 }
 
+void Snapshot::set_priority(Priority priority) {
+  snapshot_set_priority(*_snapshot, priority);
+}
+
+std::optional<uint64_t> Snapshot::get_value_length(const std::string &key) {
+  auto len = snapshot_get_value_length(*_snapshot, key);
+  if (len.is_none) {
+    return std::nullopt;
+  } else {
+    return len.length;
+  }
+}
+
+GetIntoResult Snapshot::get_into(const std::string &key, uint8_t *buf,
+                                 size_t buf_len) {
+  return snapshot_get_into(*_snapshot, key,
+                           ::rust::Slice<uint8_t>(buf, buf_len));
+}
+
 std::map<std::string, std::string>
 Snapshot::batch_get(const std::vector<std::string> &keys) {
   auto kv_pairs = snapshot_batch_get(*_snapshot, keys);
@@ -193,9 +905,50 @@ Snapshot::batch_get(const std::vector<std::string> &keys) {
   return result;
 }
 
+BatchGetLimitedResult
+Snapshot::batch_get_limited(const std::vector<std::string> &keys,
+                            uint64_t max_bytes) {
+  auto result = snapshot_batch_get_limited(*_snapshot, keys, max_bytes);
+  return BatchGetLimitedResult{to_kv_pairs(result.pairs), result.completed,
+                               result.next_index};
+}
+
+std::vector<std::optional<std::string>>
+Snapshot::smart_get(const std::vector<std::string> &keys) {
+  auto values = snapshot_smart_get(*_snapshot, keys);
+  std::vector<std::optional<std::string>> result;
+  result.reserve(values.size());
+  for (auto iter = values.begin(); iter != values.end(); ++iter) {
+    result.push_back(to_optional(*iter));
+  }
+  return result;
+}
+
+std::vector<std::optional<std::string>>
+Snapshot::batch_get_hashes(const std::vector<std::string> &keys,
+                           HashAlgorithm algo) {
+  auto hashes = snapshot_batch_get_hashes(*_snapshot, keys,
+                                          static_cast<uint8_t>(algo));
+  std::vector<std::optional<std::string>> result;
+  result.reserve(hashes.size());
+  for (auto iter = hashes.begin(); iter != hashes.end(); ++iter) {
+    if (iter->is_none) {
+      result.push_back(std::nullopt);
+    } else {
+      result.push_back(std::string{iter->hash.begin(), iter->hash.end()});
+    }
+  }
+  return result;
+}
+
+void Snapshot::set_smart_get_options(uint32_t density_percent,
+                                     uint32_t min_run_keys) {
+  snapshot_set_smart_get_options(*_snapshot, density_percent, min_run_keys);
+}
+
 std::vector<KvPair> Snapshot::scan(const std::string &start, Bound start_bound,
                                    const std::string &end, Bound end_bound,
-                                   std::uint32_t limit) {
+                                   std::uint64_t limit) {
   auto kv_pairs =
       snapshot_scan(*_snapshot, start, start_bound, end, end_bound, limit);
   std::vector<KvPair> result;
@@ -208,11 +961,118 @@ std::vector<KvPair> Snapshot::scan(const std::string &start, Bound start_bound,
   return result;
 }
 
+std::vector<std::pair<std::string, uint64_t>>
+Snapshot::scan_key_sizes(const std::string &start, Bound start_bound,
+                         const std::string &end, Bound end_bound,
+                         std::uint64_t limit) {
+  auto key_sizes = snapshot_scan_key_sizes(*_snapshot, start, start_bound, end,
+                                           end_bound, limit);
+  std::vector<std::pair<std::string, uint64_t>> result;
+  result.reserve(key_sizes.size());
+  for (auto iter = key_sizes.begin(); iter != key_sizes.end(); ++iter) {
+    result.emplace_back(std::string{(iter->key).begin(), (iter->key).end()},
+                        iter->value_len);
+  }
+  return result;
+}
+
+ScanResult Snapshot::scan_with_truncation(const std::string &start,
+                                          Bound start_bound,
+                                          const std::string &end,
+                                          Bound end_bound,
+                                          std::uint64_t limit) {
+  return to_scan_result(snapshot_scan_with_truncation(
+      *_snapshot, start, start_bound, end, end_bound, limit));
+}
+
+std::vector<KvPair>
+Snapshot::scan_with_codec(const std::string &start, Bound start_bound,
+                          const std::string &end, Bound end_bound,
+                          std::uint64_t limit, bool bounds_are_decoded,
+                          bool decode_keys) {
+  return to_kv_pairs(snapshot_scan_with_codec(*_snapshot, start, start_bound,
+                                              end, end_bound, limit,
+                                              bounds_are_decoded, decode_keys));
+}
+
+ScanUntilResult Snapshot::scan_until(const std::string &start,
+                                     Bound start_bound, const std::string &end,
+                                     Bound end_bound, std::uint64_t limit,
+                                     uint64_t deadline_ms) {
+  auto result = snapshot_scan_until(*_snapshot, start, start_bound, end,
+                                    end_bound, limit, deadline_ms);
+  return ScanUntilResult{
+      to_kv_pairs(result.pairs), result.completed,
+      std::string{result.resume_key.begin(), result.resume_key.end()}};
+}
+
+void Snapshot::prefetch(const std::vector<std::string> &keys) {
+  snapshot_prefetch(*_snapshot, keys);
+}
+
+void Snapshot::set_prefetch_cache_bytes(uint64_t bytes) {
+  snapshot_set_prefetch_cache_bytes(*_snapshot, bytes);
+}
+
+PrefetchStats Snapshot::prefetch_stats() {
+  return snapshot_prefetch_stats(*_snapshot);
+}
+
+std::optional<std::string> Snapshot::get_raw(const std::string &key) {
+  auto val = snapshot_get_raw(*_snapshot, key);
+  if (val.is_none) {
+    return std::nullopt;
+  } else {
+    return std::string{val.value.begin(), val.value.end()};
+  }
+}
+
+std::vector<KvPair> Snapshot::scan_raw(const std::string &start,
+                                       Bound start_bound,
+                                       const std::string &end, Bound end_bound,
+                                       std::uint64_t limit) {
+  return to_kv_pairs(
+      snapshot_scan_raw(*_snapshot, start, start_bound, end, end_bound, limit));
+}
+
+SkippingScanResult Snapshot::scan_skipping_oversized(const std::string &start,
+                                                    Bound start_bound,
+                                                    const std::string &end,
+                                                    Bound end_bound,
+                                                    std::uint64_t limit) {
+  auto result = snapshot_scan_skipping_oversized(*_snapshot, start, start_bound,
+                                                 end, end_bound, limit);
+  SkippingScanResult skipping{to_kv_pairs(result.pairs), {}};
+  skipping.skipped.reserve(result.skipped.size());
+  for (const auto &key : result.skipped) {
+    skipping.skipped.emplace_back(key.key.begin(), key.key.end());
+  }
+  return skipping;
+}
+
+PackedScanResult Snapshot::scan_packed(const std::string &start,
+                                       Bound start_bound,
+                                       const std::string &end,
+                                       Bound end_bound, std::uint64_t limit) {
+  return snapshot_scan_packed(*_snapshot, start, start_bound, end, end_bound,
+                              limit);
+}
+
+std::pair<std::vector<KvPair>, std::vector<KvPair>> Snapshot::scan_pair(
+    const std::string &start_a, Bound start_a_bound, const std::string &end_a,
+    Bound end_a_bound, const std::string &start_b, Bound start_b_bound,
+    const std::string &end_b, Bound end_b_bound, std::uint64_t limit) {
+  auto pair = snapshot_scan_pair(*_snapshot, start_a, start_a_bound, end_a,
+                                 end_a_bound, start_b, start_b_bound, end_b,
+                                 end_b_bound, limit);
+  return {to_kv_pairs(pair.a), to_kv_pairs(pair.b)};
+}
+
 std::vector<std::string> Snapshot::scan_keys(const std::string &start,
                                              Bound start_bound,
                                              const std::string &end,
                                              Bound end_bound,
-                                             std::uint32_t limit) {
+                                             std::uint64_t limit) {
   auto keys =
       snapshot_scan_keys(*_snapshot, start, start_bound, end, end_bound, limit);
   std::vector<std::string> result;
@@ -223,4 +1083,235 @@ std::vector<std::string> Snapshot::scan_keys(const std::string &start,
   return result;
 }
 
+ChecksumResult Snapshot::checksum_range(const std::string &start,
+                                       Bound start_bound,
+                                       const std::string &end,
+                                       Bound end_bound) {
+  return snapshot_checksum_range(*_snapshot, start, start_bound, end,
+                                 end_bound);
+}
+
+uint64_t Snapshot::export_range(const std::string &start, Bound start_bound,
+                                const std::string &end, Bound end_bound,
+                                const std::string &file_path) {
+  return snapshot_export_range(*_snapshot, start, start_bound, end, end_bound,
+                               file_path);
+}
+
+RoutedTransaction::RoutedTransaction(
+    Box<tikv_client_glue::RoutedTransaction> txn)
+    : _txn(std::move(txn)) {}
+
+std::optional<std::string> RoutedTransaction::get(const std::string &key) {
+  return to_optional(routed_transaction_get(*_txn, key));
+}
+
+void RoutedTransaction::put(const std::string &key, const std::string &value) {
+  routed_transaction_put(*_txn, key, value);
+}
+
+void RoutedTransaction::remove(const std::string &key) {
+  routed_transaction_delete(*_txn, key);
+}
+
+std::vector<KvPair> RoutedTransaction::scan(const std::string &start,
+                                            Bound start_bound,
+                                            const std::string &end,
+                                            Bound end_bound,
+                                            std::uint64_t limit) {
+  return to_kv_pairs(routed_transaction_scan(*_txn, start, start_bound, end,
+                                             end_bound, limit));
+}
+
+uint64_t RoutedTransaction::commit() { return routed_transaction_commit(*_txn); }
+
+void RoutedTransaction::rollback() { routed_transaction_rollback(*_txn); }
+
+RoutedSnapshot::RoutedSnapshot(
+    Box<tikv_client_glue::RoutedSnapshot> snapshot)
+    : _snapshot(std::move(snapshot)) {}
+
+std::optional<std::string> RoutedSnapshot::get(const std::string &key) {
+  return to_optional(routed_snapshot_get(*_snapshot, key));
+}
+
+std::map<std::string, std::string>
+RoutedSnapshot::batch_get(const std::vector<std::string> &keys) {
+  auto kv_pairs = routed_snapshot_batch_get(*_snapshot, keys);
+  std::map<std::string, std::string> result;
+  for (auto iter = kv_pairs.begin(); iter != kv_pairs.end(); ++iter) {
+    result[std::string{(iter->key).begin(), (iter->key).end()}] =
+        std::string{(iter->value).begin(), (iter->value).end()};
+  }
+  return result;
+}
+
+std::vector<KvPair> RoutedSnapshot::scan(const std::string &start,
+                                         Bound start_bound,
+                                         const std::string &end,
+                                         Bound end_bound, std::uint64_t limit) {
+  return to_kv_pairs(routed_snapshot_scan(*_snapshot, start, start_bound, end,
+                                          end_bound, limit));
+}
+
+RoutedClient::RoutedClient(bool split_scans)
+    : _client(routed_client_new(split_scans)) {}
+
+void RoutedClient::add_route(const std::string &prefix,
+                             TransactionClient &&client) {
+  routed_client_add_route(*_client, prefix, std::move(client._client));
+}
+
+RoutedTransaction RoutedClient::begin() {
+  return RoutedTransaction(routed_client_begin(*_client, false));
+}
+
+RoutedTransaction RoutedClient::begin_pessimistic() {
+  return RoutedTransaction(routed_client_begin(*_client, true));
+}
+
+RoutedSnapshot RoutedClient::snapshot() {
+  return RoutedSnapshot(routed_client_snapshot(*_client));
+}
+
+FailoverClient::FailoverClient(TransactionClient &&primary,
+                               TransactionClient &&standby,
+                               const FailoverPolicy &policy,
+                               std::function<bool(uint32_t, uint32_t)> confirm)
+    : _client(failover_pair_new(
+          std::move(primary._client), std::move(standby._client), policy,
+          confirm ? std::make_unique<FunctionFailoverConfirmation>(
+                        std::move(confirm))
+                  : nullptr)) {}
+
+Transaction FailoverClient::begin() {
+  return Transaction(failover_begin(*_client, false));
+}
+
+Transaction FailoverClient::begin_pessimistic() {
+  return Transaction(failover_begin(*_client, true));
+}
+
+std::shared_ptr<Snapshot> FailoverClient::snapshot() {
+  return std::make_shared<Snapshot>(failover_snapshot(*_client));
+}
+
+void FailoverClient::switch_to(FailoverTarget target) {
+  failover_switch(*_client, static_cast<uint8_t>(target));
+}
+
+FailoverStatus FailoverClient::status() const {
+  auto status = failover_status(*_client);
+  return FailoverStatus{
+      static_cast<FailoverTarget>(status.target),
+      status.switches,
+      status.consecutive_failures,
+      status.window_operations,
+      status.window_failures,
+      std::string{status.last_reason.begin(), status.last_reason.end()}};
+}
+
+void shutdown_runtime() { tikv_client_glue::shutdown_runtime(); }
+
+std::vector<Capability> capabilities() {
+  auto ids = tikv_client_glue::client_capabilities();
+  std::vector<Capability> result;
+  result.reserve(ids.size());
+  for (auto iter = ids.begin(); iter != ids.end(); ++iter) {
+    result.push_back(static_cast<Capability>(*iter));
+  }
+  return result;
+}
+
+bool has_capability(Capability capability) {
+  for (auto enabled : capabilities()) {
+    if (enabled == capability) {
+      return true;
+    }
+  }
+  return false;
+}
+
+std::string format_key_hex(const std::string &key) {
+  return std::string(tikv_client_glue::format_key_hex(key));
+}
+
+std::string parse_key_hex(const std::string &text) {
+  auto key = tikv_client_glue::parse_key_hex(text);
+  return std::string{key.begin(), key.end()};
+}
+
+uint64_t version_from_unix_ms(uint64_t ms) {
+  return tikv_client_glue::version_from_unix_ms(ms);
+}
+
+uint64_t unix_ms_of_version(uint64_t version) {
+  return tikv_client_glue::unix_ms_of_version(version);
+}
+
+std::string hash_kv_pairs(const std::vector<KvPair> &pairs) {
+  std::vector<std::string> keys;
+  std::vector<std::string> values;
+  keys.reserve(pairs.size());
+  values.reserve(pairs.size());
+  for (const auto &pair : pairs) {
+    keys.push_back(pair.key);
+    values.push_back(pair.value);
+  }
+  auto hash = tikv_client_glue::hash_kv_pairs(keys, values);
+  return std::string{hash.begin(), hash.end()};
+}
+
+std::string hash_kv_pairs(const ScanResult &result) {
+  return hash_kv_pairs(result.pairs);
+}
+
+std::string encode_u64_asc(uint64_t value) {
+  auto encoded = tikv_client_glue::encode_u64_asc(value);
+  return std::string{encoded.begin(), encoded.end()};
+}
+
+std::string encode_u64_desc(uint64_t value) {
+  auto encoded = tikv_client_glue::encode_u64_desc(value);
+  return std::string{encoded.begin(), encoded.end()};
+}
+
+std::string encode_i64_asc(int64_t value) {
+  auto encoded = tikv_client_glue::encode_i64_asc(value);
+  return std::string{encoded.begin(), encoded.end()};
+}
+
+std::string encode_i64_desc(int64_t value) {
+  auto encoded = tikv_client_glue::encode_i64_desc(value);
+  return std::string{encoded.begin(), encoded.end()};
+}
+
+uint64_t decode_u64_asc(const std::string &data) {
+  return tikv_client_glue::decode_u64_asc(data);
+}
+
+uint64_t decode_u64_desc(const std::string &data) {
+  return tikv_client_glue::decode_u64_desc(data);
+}
+
+int64_t decode_i64_asc(const std::string &data) {
+  return tikv_client_glue::decode_i64_asc(data);
+}
+
+int64_t decode_i64_desc(const std::string &data) {
+  return tikv_client_glue::decode_i64_desc(data);
+}
+
+std::string encode_bytes(const std::string &data) {
+  auto encoded = tikv_client_glue::encode_bytes(data);
+  return std::string{encoded.begin(), encoded.end()};
+}
+
+std::pair<std::string, size_t> decode_bytes(const std::string &data) {
+  auto decoded = tikv_client_glue::decode_bytes(data);
+  return std::make_pair(
+      std::string{decoded.value.begin(), decoded.value.end()},
+      static_cast<size_t>(decoded.consumed));
+}
+
 } // namespace tikv_client